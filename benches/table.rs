@@ -0,0 +1,139 @@
+//! Microbenchmarks for table access. Run with `cargo bench --bench table`.
+
+use std::time::Instant;
+
+use piccolo::{Closure, Executor, Lua, StaticError, Table, Value};
+
+fn main() -> Result<(), StaticError> {
+    field_lookup()?;
+    table_lookup();
+    integer_keys();
+    Ok(())
+}
+
+// Returns the number of seconds taken to run `f`.
+fn time(f: impl FnOnce()) -> f64 {
+    let start = Instant::now();
+    f();
+    start.elapsed().as_secs_f64()
+}
+
+// Field lookups with constant keys, which use the inline cache, compared with the same lookups
+// using keys held in registers, which do not.
+fn field_lookup() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let mut run = |source: &str| -> Result<f64, StaticError> {
+        let executor = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, source.as_bytes())?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        let mut res = Ok(());
+        let elapsed = time(|| res = lua.execute::<()>(&executor));
+        res.map(|()| elapsed)
+    };
+
+    let setup = r#"
+        local t = {}
+        for i = 1, 64 do
+            t["field" .. i] = i
+        end
+        t.x, t.y, t.z = 1, 2, 3
+        local sum = 0
+        local x, y, z = "x", "y", "z"
+    "#;
+    let cached = run(&format!(
+        "{setup} for i = 1, 1000000 do sum = sum + t.x + t.y + t.z end"
+    ))?;
+    let uncached = run(&format!(
+        "{setup} for i = 1, 1000000 do sum = sum + t[x] + t[y] + t[z] end"
+    ))?;
+    println!("field lookup: constant keys: {cached:.3}s, register keys: {uncached:.3}s");
+    Ok(())
+}
+
+// The throughput of table lookups with string and integer keys in the map part.
+fn table_lookup() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        const KEYS: i64 = 1_000;
+        const ROUNDS: i64 = 1_000;
+
+        let table = Table::new(&ctx);
+        let strings: Vec<Value> = (0..KEYS)
+            .map(|i| ctx.intern(format!("key{i}").as_bytes()).into())
+            .collect();
+        for (i, &key) in strings.iter().enumerate() {
+            table.set(ctx, key, i as i64).unwrap();
+        }
+        // Negative integers are never in the array part.
+        for i in 0..KEYS {
+            table.set(ctx, -i - 1, i).unwrap();
+        }
+
+        let string_time = time(|| {
+            for _ in 0..ROUNDS {
+                for &key in &strings {
+                    assert!(!table.get_value(key).is_nil());
+                }
+            }
+        });
+
+        let integer_time = time(|| {
+            for _ in 0..ROUNDS {
+                for i in 0..KEYS {
+                    assert!(!table.get_value(Value::Integer(-i - 1)).is_nil());
+                }
+            }
+        });
+
+        let lookups = (KEYS * ROUNDS) as f64;
+        println!(
+            "table lookup: string keys: {:.1}M lookups/s, integer keys: {:.1}M lookups/s",
+            lookups / string_time / 1e6,
+            lookups / integer_time / 1e6,
+        );
+    });
+}
+
+// Reading and writing integer keys in the array part compared with the map part.
+fn integer_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        const KEYS: i64 = 1_000;
+        const ROUNDS: i64 = 1_000;
+
+        let array = Table::new(&ctx);
+        let map = Table::new(&ctx);
+        for i in 1..=KEYS {
+            array.set(ctx, i, i).unwrap();
+            map.set(ctx, i * 1_000_003, i).unwrap();
+        }
+
+        let array_time = time(|| {
+            for _ in 0..ROUNDS {
+                for i in 1..=KEYS {
+                    array.set(ctx, i, array.get(ctx, i)).unwrap();
+                }
+            }
+        });
+
+        let map_time = time(|| {
+            for _ in 0..ROUNDS {
+                for i in 1..=KEYS {
+                    let key = i * 1_000_003;
+                    map.set(ctx, key, map.get(ctx, key)).unwrap();
+                }
+            }
+        });
+
+        let accesses = (KEYS * ROUNDS) as f64;
+        println!(
+            "integer keys: array part: {:.1}M get/set pairs/s, map part: {:.1}M get/set pairs/s",
+            accesses / array_time / 1e6,
+            accesses / map_time / 1e6,
+        );
+    });
+}
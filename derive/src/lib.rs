@@ -0,0 +1,220 @@
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam, Generics, Ident,
+    Lifetime, LifetimeParam, LitStr, Type,
+};
+
+/// Derive `IntoValue` for a struct with named fields, converting it into a new table with one key
+/// per field.
+///
+/// Fields are converted with their own `IntoValue` impls, so a field which converts to `nil` (such
+/// as `None`) is left out of the table. The key for a field is its name, unless renamed with
+/// `#[lua(rename = "key")]`.
+#[proc_macro_derive(IntoValue, attributes(lua))]
+pub fn derive_into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match into_value(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Derive `FromValue` for a struct with named fields, reading each field from the key of the same
+/// name in a table.
+///
+/// Fields are converted with their own `FromValue` impls, so `Option` fields may be missing. A
+/// field marked `#[lua(default)]` uses `Default::default()` when its key is missing (or `nil`), and
+/// the key for a field can be changed with `#[lua(rename = "key")]`.
+///
+/// A field which is missing or has the wrong type is reported as a `TypeError` expecting
+/// `field 'key'`, found the type of the value at that key.
+#[proc_macro_derive(FromValue, attributes(lua))]
+pub fn derive_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match from_value(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    key: LitStr,
+    default: bool,
+}
+
+fn into_value(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = parse_fields(&input)?;
+    let (generics, gc) = with_gc_lifetime(&input.generics);
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+    for field in &fields {
+        let ty = &field.ty;
+        where_clause
+            .predicates
+            .push(parse_quote!(#ty: ::piccolo::IntoValue<#gc>));
+    }
+
+    let name = &input.ident;
+    let (ctx, table) = (local("ctx"), local("table"));
+    let sets = fields.iter().map(|f| {
+        let ident = &f.ident;
+        let key = &f.key;
+        quote! {
+            #table
+                .set(#ctx, #key, self.#ident)
+                .expect("string keys are always valid");
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::piccolo::IntoValue<#gc> for #name #ty_generics #where_clause {
+            fn into_value(self, #ctx: ::piccolo::Context<#gc>) -> ::piccolo::Value<#gc> {
+                let #table = ::piccolo::Table::new(&#ctx);
+                #(#sets)*
+                #table.into()
+            }
+        }
+    })
+}
+
+fn from_value(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = parse_fields(&input)?;
+    let (generics, gc) = with_gc_lifetime(&input.generics);
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+    for field in &fields {
+        let ty = &field.ty;
+        where_clause
+            .predicates
+            .push(parse_quote!(#ty: ::piccolo::FromValue<#gc>));
+        if field.default {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::core::default::Default));
+        }
+    }
+
+    let name = &input.ident;
+    let (ctx, table, value) = (local("ctx"), local("table"), local("value"));
+    let gets = fields.iter().map(|f| {
+        let ident = &f.ident;
+        let ty = &f.ty;
+        let key = &f.key;
+        let field = format!("field '{}'", key.value());
+        let convert = quote! {
+            <#ty as ::piccolo::FromValue<#gc>>::from_value(#ctx, #value).map_err(|_| {
+                ::piccolo::TypeError {
+                    expected: #field,
+                    found: #value.type_name(),
+                }
+            })?
+        };
+        let get = if f.default {
+            quote! {
+                if #value.is_nil() {
+                    ::core::default::Default::default()
+                } else {
+                    #convert
+                }
+            }
+        } else {
+            convert
+        };
+        quote! {
+            #ident: {
+                let #value = #table.get(#ctx, #key);
+                #get
+            },
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::piccolo::FromValue<#gc> for #name #ty_generics #where_clause {
+            fn from_value(
+                #ctx: ::piccolo::Context<#gc>,
+                #value: ::piccolo::Value<#gc>,
+            ) -> ::core::result::Result<Self, ::piccolo::TypeError> {
+                let ::piccolo::Value::Table(#table) = #value else {
+                    return ::core::result::Result::Err(::piccolo::TypeError {
+                        expected: "table",
+                        found: #value.type_name(),
+                    });
+                };
+                ::core::result::Result::Ok(#name { #(#gets)* })
+            }
+        }
+    })
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "only structs with named fields are supported",
+            ))
+        }
+    };
+
+    fields
+        .iter()
+        .map(|field| {
+            let ident = field.ident.clone().unwrap();
+            let mut key = LitStr::new(&ident.to_string(), ident.span());
+            let mut default = false;
+            for attr in field.attrs.iter().filter(|a| a.path().is_ident("lua")) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("rename") {
+                        key = meta.value()?.parse()?;
+                        Ok(())
+                    } else if meta.path.is_ident("default") {
+                        default = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("unsupported lua attribute"))
+                    }
+                })?;
+            }
+            Ok(Field {
+                ident,
+                ty: field.ty.clone(),
+                key,
+                default,
+            })
+        })
+        .collect()
+}
+
+// An identifier for a variable in generated code, which can never conflict with a field name.
+fn local(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+// Returns the generics for the impl along with the `'gc` lifetime to use. If the struct has a
+// lifetime parameter, the first one is used as `'gc`, otherwise a new `'gc` parameter is added.
+fn with_gc_lifetime(generics: &Generics) -> (Generics, Lifetime) {
+    if let Some(lifetime) = generics.lifetimes().next() {
+        return (generics.clone(), lifetime.lifetime.clone());
+    }
+
+    let gc = Lifetime::new("'gc", Span::call_site());
+    let mut generics = generics.clone();
+    generics
+        .params
+        .insert(0, GenericParam::Lifetime(LifetimeParam::new(gc.clone())));
+    (generics, gc)
+}
@@ -64,10 +64,13 @@ fn run_repl(lua: &mut Lua) -> Result<(), Box<dyn StdError>> {
                     if !read_empty
                         && matches!(
                             err.downcast::<PrototypeError>(),
-                            Some(PrototypeError::Parser(ParseError {
-                                kind: ParseErrorKind::EndOfStream { .. },
+                            Some(PrototypeError::Parser {
+                                error: ParseError {
+                                    kind: ParseErrorKind::EndOfStream { .. },
+                                    ..
+                                },
                                 ..
-                            }))
+                            })
                         ) =>
                 {
                     prompt = ">> ";
@@ -110,11 +113,11 @@ fn main() -> Result<(), Box<dyn StdError>> {
     let file = io::buffered_read(File::open(file_name)?)?;
 
     let executor = lua.try_enter(|ctx| {
-        let closure = Closure::load(ctx, Some(file_name.as_str()), file)?;
+        let closure = Closure::load(ctx, Some(&format!("@{file_name}")), file)?;
         Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
     })?;
 
-    lua.execute(&executor)?;
+    lua.execute::<()>(&executor)?;
 
     if matches.contains_id("repl") {
         run_repl(&mut lua)?;
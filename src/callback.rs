@@ -1,27 +1,51 @@
 use std::{
     fmt,
+    future::Future,
     hash::{Hash, Hasher},
     ops,
+    pin::Pin,
+    ptr,
+    task::{self, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 use allocator_api2::boxed;
 use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
 
-use crate::{Context, Error, Execution, Function, Stack, Thread};
+use crate::{Context, Error, Execution, FromMultiValue, Function, IntoMultiValue, Stack, Thread};
 
+/// What a callback does after it has finished running.
+///
+/// This is the only return type of a callback: it can return immediately, call a function, yield,
+/// resume a thread, or continue as a `Sequence`. The `then` continuation of every variant is polled
+/// with the results, which is how a callback does more work after a call or yield. Sequences
+/// themselves return the analogous `SequencePoll`.
 #[derive(Collect)]
 #[collect(no_drop)]
 pub enum CallbackReturn<'gc> {
+    /// Return the values in the stack to the caller.
     Return,
+    /// Continue running the given sequence with the stack unchanged.
     Sequence(BoxSequence<'gc>),
+    /// Yield the values in the stack from the current coroutine, or resume `to_thread` with them
+    /// if it is set.
+    ///
+    /// When the coroutine is resumed, the resume arguments are placed on the stack and `then` is
+    /// polled with them, so a callback can receive the values passed to the next resume by
+    /// providing a continuation. If `then` is `None`, the resume arguments are instead returned to
+    /// the caller of the callback, the same as `coroutine.yield` in PUC-Rio Lua. If the coroutine
+    /// is resumed with an error, `Sequence::error` is called on the continuation instead.
     Yield {
         to_thread: Option<Thread<'gc>>,
         then: Option<BoxSequence<'gc>>,
     },
+    /// Call `function` with the values in the stack as arguments. Its results are returned to the
+    /// caller, or given to `then` if it is set.
     Call {
         function: Function<'gc>,
         then: Option<BoxSequence<'gc>>,
     },
+    /// Resume `thread` with the values in the stack as arguments. The values it yields or returns
+    /// are returned to the caller, or given to `then` if it is set.
     Resume {
         thread: Thread<'gc>,
         then: Option<BoxSequence<'gc>>,
@@ -103,6 +127,23 @@ impl<'gc> Callback<'gc> {
         Self::from_fn_with(mc, (), move |_, ctx, exec, stack| call(ctx, exec, stack))
     }
 
+    /// Create a callback from a function which takes typed arguments and returns typed results.
+    ///
+    /// Arguments are converted with `FromMultiValue` and results with `IntoMultiValue`. If an
+    /// argument fails to convert, a `BadArgument` error naming its position is raised.
+    pub fn from_fn_typed<A, R, F>(mc: &Mutation<'gc>, call: F) -> Callback<'gc>
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: 'static + Fn(Context<'gc>, A) -> Result<R, Error<'gc>>,
+    {
+        Self::from_fn(mc, move |ctx, _, mut stack| {
+            let ret = call(ctx, stack.consume_args(ctx)?)?;
+            stack.replace(ctx, ret);
+            Ok(CallbackReturn::Return)
+        })
+    }
+
     pub fn from_fn_with<R, F>(mc: &Mutation<'gc>, root: R, call: F) -> Callback<'gc>
     where
         R: 'gc + Collect,
@@ -276,4 +317,260 @@ impl<'gc> BoxSequence<'gc> {
         let b = unsafe { boxed::Box::from_raw_in(ptr as *mut dyn Sequence, alloc) };
         Self(b)
     }
+
+    /// Create a sequence which awaits a host future and returns its output to the caller.
+    ///
+    /// While the future is pending, the sequence interrupts the current `Fuel` so that
+    /// `Executor::step` returns control to the host, and the future is polled again on the next
+    /// step. The future is polled with a no-op waker, so the host is responsible for stepping the
+    /// executor again once the future can make progress.
+    ///
+    /// Since nothing waits for the waker, `Lua::finish` and `Lua::execute` poll a pending future in
+    /// a busy loop until it is ready. A host awaiting futures which may stay pending for a while
+    /// should step the executor itself, and only step it again once the future can make progress.
+    pub fn from_future<F, R, E>(mc: &Mutation<'gc>, future: F) -> Self
+    where
+        F: Future<Output = Result<R, E>> + 'static,
+        R: IntoMultiValue<'gc>,
+        E: Into<Error<'gc>>,
+    {
+        #[derive(Collect)]
+        #[collect(require_static)]
+        struct FutureSequence<F>(Pin<Box<F>>);
+
+        impl<'gc, F, R, E> Sequence<'gc> for FutureSequence<F>
+        where
+            F: Future<Output = Result<R, E>> + 'static,
+            R: IntoMultiValue<'gc>,
+            E: Into<Error<'gc>>,
+        {
+            fn poll(
+                &mut self,
+                ctx: Context<'gc>,
+                mut exec: Execution<'gc, '_>,
+                mut stack: Stack<'gc, '_>,
+            ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                let waker = noop_waker();
+                match self.0.as_mut().poll(&mut task::Context::from_waker(&waker)) {
+                    Poll::Pending => {
+                        exec.fuel().interrupt();
+                        Ok(SequencePoll::Pending)
+                    }
+                    Poll::Ready(Ok(ret)) => {
+                        stack.replace(ctx, ret);
+                        Ok(SequencePoll::Return)
+                    }
+                    Poll::Ready(Err(err)) => Err(err.into()),
+                }
+            }
+        }
+
+        Self::new(mc, FutureSequence(Box::pin(future)))
+    }
+}
+
+impl<'gc> BoxSequence<'gc> {
+    /// Run this sequence, then call `f` with the values it returned to transform them before they
+    /// are returned to the caller.
+    pub fn map<F>(self, mc: &Mutation<'gc>, f: F) -> Self
+    where
+        F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+    {
+        #[derive(Collect)]
+        #[collect(no_drop)]
+        struct Map<F>(#[collect(require_static)] F);
+
+        impl<'gc, F> Then<'gc> for Map<F>
+        where
+            F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+        {
+            fn then(
+                self,
+                ctx: Context<'gc>,
+                stack: Stack<'gc, '_>,
+            ) -> Result<Next<'gc>, Error<'gc>> {
+                (self.0)(ctx, stack)?;
+                Ok(Next::Return)
+            }
+        }
+
+        Self::chain(mc, self, Map(f))
+    }
+
+    /// Run this sequence, then run the sequence returned by `f` with the values this sequence
+    /// returned.
+    pub fn and_then<F>(self, mc: &Mutation<'gc>, f: F) -> Self
+    where
+        F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<BoxSequence<'gc>, Error<'gc>>,
+    {
+        #[derive(Collect)]
+        #[collect(no_drop)]
+        struct AndThen<F>(#[collect(require_static)] F);
+
+        impl<'gc, F> Then<'gc> for AndThen<F>
+        where
+            F: 'static
+                + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<BoxSequence<'gc>, Error<'gc>>,
+        {
+            fn then(
+                self,
+                ctx: Context<'gc>,
+                stack: Stack<'gc, '_>,
+            ) -> Result<Next<'gc>, Error<'gc>> {
+                Ok(Next::Sequence((self.0)(ctx, stack)?))
+            }
+        }
+
+        Self::chain(mc, self, AndThen(f))
+    }
+
+    /// Run this sequence, then tail call `function` with the values this sequence returned as
+    /// arguments.
+    pub fn then_call(self, mc: &Mutation<'gc>, function: Function<'gc>) -> Self {
+        #[derive(Collect)]
+        #[collect(no_drop)]
+        struct ThenCall<'gc>(Function<'gc>);
+
+        impl<'gc> Then<'gc> for ThenCall<'gc> {
+            fn then(self, _: Context<'gc>, _: Stack<'gc, '_>) -> Result<Next<'gc>, Error<'gc>> {
+                Ok(Next::Call(self.0))
+            }
+        }
+
+        Self::chain(mc, self, ThenCall(function))
+    }
+
+    fn chain(mc: &Mutation<'gc>, sequence: BoxSequence<'gc>, then: impl Then<'gc> + 'gc) -> Self {
+        Self::new(
+            mc,
+            Chain {
+                sequence: Some(sequence),
+                then: Some(then),
+            },
+        )
+    }
+}
+
+// What a `Chain` should do once its inner sequence has finished.
+enum Next<'gc> {
+    Return,
+    Sequence(BoxSequence<'gc>),
+    Call(Function<'gc>),
+}
+
+trait Then<'gc>: Collect {
+    fn then(self, ctx: Context<'gc>, stack: Stack<'gc, '_>) -> Result<Next<'gc>, Error<'gc>>;
+}
+
+// Runs an inner sequence to completion, then runs a `Then` action with its results.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Chain<'gc, T> {
+    // `None` once the inner sequence has finished, including when it has finished by making a
+    // tail call or tail yield.
+    sequence: Option<BoxSequence<'gc>>,
+    then: Option<T>,
+}
+
+impl<'gc, T: Then<'gc>> Chain<'gc, T> {
+    fn advance(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+        mut poll: Option<SequencePoll<'gc>>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        loop {
+            match poll {
+                None | Some(SequencePoll::Return) => {}
+                Some(poll) => return Ok(self.forward(poll)),
+            }
+
+            self.sequence = None;
+            let Some(then) = self.then.take() else {
+                return Ok(SequencePoll::Return);
+            };
+            match then.then(ctx, stack.sub_stack(0))? {
+                Next::Return => return Ok(SequencePoll::Return),
+                Next::Call(function) => {
+                    return Ok(SequencePoll::Call {
+                        function,
+                        is_tail: true,
+                    })
+                }
+                Next::Sequence(mut sequence) => {
+                    poll = Some(sequence.poll(ctx, exec.reborrow(), stack.sub_stack(0))?);
+                    self.sequence = Some(sequence);
+                }
+            }
+        }
+    }
+
+    // Forward a non-returning poll result of the inner sequence. If the inner sequence finishes
+    // with a tail call or tail yield while there is still an action to run, it must no longer be
+    // a tail operation for us.
+    fn forward(&mut self, poll: SequencePoll<'gc>) -> SequencePoll<'gc> {
+        let keep_tail = |chain: &mut Self, is_tail: bool| {
+            if is_tail {
+                chain.sequence = None;
+            }
+            is_tail && chain.then.is_none()
+        };
+
+        match poll {
+            SequencePoll::Yield { to_thread, is_tail } => SequencePoll::Yield {
+                to_thread,
+                is_tail: keep_tail(self, is_tail),
+            },
+            SequencePoll::Call { function, is_tail } => SequencePoll::Call {
+                function,
+                is_tail: keep_tail(self, is_tail),
+            },
+            SequencePoll::Resume { thread, is_tail } => SequencePoll::Resume {
+                thread,
+                is_tail: keep_tail(self, is_tail),
+            },
+            poll => poll,
+        }
+    }
+}
+
+impl<'gc, T: Then<'gc>> Sequence<'gc> for Chain<'gc, T> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let poll = match &mut self.sequence {
+            Some(sequence) => Some(sequence.poll(ctx, exec.reborrow(), stack.sub_stack(0))?),
+            None => None,
+        };
+        self.advance(ctx, exec, stack, poll)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        match &mut self.sequence {
+            Some(sequence) => {
+                let poll = sequence.error(ctx, exec.reborrow(), error, stack.sub_stack(0))?;
+                self.advance(ctx, exec, stack, Some(poll))
+            }
+            // The error came from a tail operation of the finished inner sequence.
+            None => Err(error),
+        }
+    }
+}
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(ptr::null(), &VTABLE);
+    // SAFETY: All of the vtable functions are no-ops, which trivially upholds the `RawWaker`
+    // contract.
+    unsafe { Waker::from_raw(RAW) }
 }
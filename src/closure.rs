@@ -1,6 +1,8 @@
 use std::{
+    cell::Cell,
     hash::{Hash, Hasher},
     io::Read,
+    string::String as StdString,
 };
 
 use allocator_api2::{boxed, vec, SliceExt};
@@ -8,19 +10,70 @@ use gc_arena::{allocator_api::MetricsAlloc, lock::Lock, Collect, Gc, Mutation};
 use thiserror::Error;
 
 use crate::{
-    compiler::{self, CompiledPrototype, FunctionRef, LineNumber},
+    compiler::{self, CompiledPrototype, FunctionRef, LineNumber, LocalVariable},
+    dump::UndumpError,
     opcode::OpCode,
     thread::OpenUpValue,
     types::UpValueDescriptor,
     Constant, Context, String, Table, Value,
 };
 
+/// Derive the short form of a chunk name used in error messages and tracebacks, the same as
+/// `luaO_chunkid` in PUC-Rio Lua.
+///
+/// * `=name` is used verbatim as `name` (truncated if very long).
+/// * `@filename` is a file name, and becomes `filename`. Long file names keep their end, as
+///   `...end`.
+/// * Anything else is the source itself, and becomes `[string "source"]`, which is cut short at
+///   the first newline or if it is too long, as `[string "start..."]`.
+pub fn short_src(chunk_name: &[u8]) -> StdString {
+    // The maximum length of the result, the same as `LUA_IDSIZE - 1` in PUC-Rio Lua.
+    const MAX_LEN: usize = 59;
+    const RETS: &[u8] = b"...";
+    const PRE: &[u8] = b"[string \"";
+    const POS: &[u8] = b"\"]";
+
+    let mut out = Vec::new();
+    if let Some(name) = chunk_name.strip_prefix(b"=") {
+        out.extend_from_slice(&name[..name.len().min(MAX_LEN)]);
+    } else if let Some(name) = chunk_name.strip_prefix(b"@") {
+        if name.len() <= MAX_LEN {
+            out.extend_from_slice(name);
+        } else {
+            out.extend_from_slice(RETS);
+            out.extend_from_slice(&name[name.len() - (MAX_LEN - RETS.len())..]);
+        }
+    } else {
+        let max_len = MAX_LEN - PRE.len() - RETS.len() - POS.len();
+        let newline = chunk_name.iter().position(|&b| b == b'\n');
+        out.extend_from_slice(PRE);
+        if newline.is_none() && chunk_name.len() < max_len {
+            out.extend_from_slice(chunk_name);
+        } else {
+            let len = newline.unwrap_or(chunk_name.len()).min(max_len);
+            out.extend_from_slice(&chunk_name[..len]);
+            out.extend_from_slice(RETS);
+        }
+        out.extend_from_slice(POS);
+    }
+    StdString::from_utf8_lossy(&out).into_owned()
+}
+
+/// An error compiling a chunk, displayed as `short_src:line: message` like PUC-Rio Lua.
 #[derive(Debug, Error)]
 pub enum PrototypeError {
-    #[error(transparent)]
-    Parser(#[from] compiler::ParseError),
-    #[error(transparent)]
-    Compiler(#[from] compiler::CompileError),
+    #[error("{short_src}:{line}: {kind}", line = .error.line_number, kind = .error.kind)]
+    Parser {
+        short_src: StdString,
+        #[source]
+        error: compiler::ParseError,
+    },
+    #[error("{short_src}:{line}: {kind}", line = .error.line_number, kind = .error.kind)]
+    Compiler {
+        short_src: StdString,
+        #[source]
+        error: compiler::CompileError,
+    },
 }
 
 #[derive(Debug, Collect)]
@@ -35,10 +88,27 @@ pub struct FunctionPrototype<'gc> {
     pub opcodes: boxed::Box<[OpCode], MetricsAlloc<'gc>>,
     pub opcode_line_numbers: boxed::Box<[(usize, LineNumber)], MetricsAlloc<'gc>>,
     pub upvalues: boxed::Box<[UpValueDescriptor], MetricsAlloc<'gc>>,
+    pub upvalue_names: boxed::Box<[String<'gc>], MetricsAlloc<'gc>>,
+    pub local_variables: boxed::Box<[LocalVariable<String<'gc>>], MetricsAlloc<'gc>>,
     pub prototypes: boxed::Box<[Gc<'gc, FunctionPrototype<'gc>>], MetricsAlloc<'gc>>,
+    /// A cache slot for every opcode, used by table lookups with constant keys to remember where
+    /// the key was last found. See `FunctionPrototype::new_inline_cache`.
+    pub inline_cache: boxed::Box<[Cell<u32>], MetricsAlloc<'gc>>,
 }
 
 impl<'gc> FunctionPrototype<'gc> {
+    /// Create an empty inline cache for a prototype with `opcode_count` opcodes.
+    ///
+    /// The cache only ever holds hints, so it is always correct to start with an empty one.
+    pub fn new_inline_cache(
+        mc: &Mutation<'gc>,
+        opcode_count: usize,
+    ) -> boxed::Box<[Cell<u32>], MetricsAlloc<'gc>> {
+        let mut cache = vec::Vec::with_capacity_in(opcode_count, MetricsAlloc::new(mc));
+        cache.resize_with(opcode_count, || Cell::new(0));
+        cache.into_boxed_slice()
+    }
+
     pub fn from_compiled(
         mc: &Mutation<'gc>,
         chunk_name: String<'gc>,
@@ -77,6 +147,19 @@ impl<'gc> FunctionPrototype<'gc> {
             let upvalues =
                 SliceExt::to_vec_in(compiled_function.upvalues.as_slice(), alloc.clone());
 
+            let mut upvalue_names = vec::Vec::new_in(alloc.clone());
+            upvalue_names.extend(compiled_function.upvalue_names.iter().map(map_string));
+
+            let mut local_variables = vec::Vec::new_in(alloc.clone());
+            local_variables.extend(compiled_function.local_variables.iter().map(|l| {
+                LocalVariable {
+                    name: map_string(&l.name),
+                    register: l.register,
+                    start_pc: l.start_pc,
+                    end_pc: l.end_pc,
+                }
+            }));
+
             let mut prototypes = vec::Vec::new_in(alloc);
             prototypes.extend(
                 compiled_function
@@ -94,10 +177,13 @@ impl<'gc> FunctionPrototype<'gc> {
                 fixed_params: compiled_function.fixed_params,
                 has_varargs: compiled_function.has_varargs,
                 stack_size: compiled_function.stack_size,
+                inline_cache: FunctionPrototype::new_inline_cache(mc, opcodes.len()),
                 constants: constants.into_boxed_slice(),
                 opcodes: opcodes.into_boxed_slice(),
                 opcode_line_numbers: opcode_line_numbers.into_boxed_slice(),
                 upvalues: upvalues.into_boxed_slice(),
+                upvalue_names: upvalue_names.into_boxed_slice(),
+                local_variables: local_variables.into_boxed_slice(),
                 prototypes: prototypes.into_boxed_slice(),
             }
         }
@@ -123,8 +209,17 @@ impl<'gc> FunctionPrototype<'gc> {
 
         let interner = Interner(ctx);
 
-        let chunk = compiler::parse_chunk(source, interner)?;
-        let compiled_function = compiler::compile_chunk(&chunk, interner)?;
+        let chunk =
+            compiler::parse_chunk(source, interner).map_err(|error| PrototypeError::Parser {
+                short_src: short_src(source_name.as_bytes()),
+                error,
+            })?;
+        let compiled_function = compiler::compile_chunk(&chunk, interner).map_err(|error| {
+            PrototypeError::Compiler {
+                short_src: short_src(source_name.as_bytes()),
+                error,
+            }
+        })?;
 
         Ok(FunctionPrototype::from_compiled(
             &ctx,
@@ -132,6 +227,83 @@ impl<'gc> FunctionPrototype<'gc> {
             &compiled_function,
         ))
     }
+
+    /// The chunk name in the short form used in error messages and tracebacks, the same as
+    /// PUC-Rio Lua's `short_src`, see `short_src`.
+    pub fn short_src(&self) -> StdString {
+        short_src(self.chunk_name.as_bytes())
+    }
+
+    /// The source line number of the opcode at index `pc`.
+    pub fn line_number(&self, pc: usize) -> LineNumber {
+        match self
+            .opcode_line_numbers
+            .binary_search_by_key(&pc, |(opi, _)| *opi)
+        {
+            Ok(i) => self.opcode_line_numbers[i].1,
+            Err(0) => LineNumber(0),
+            Err(i) => self.opcode_line_numbers[i - 1].1,
+        }
+    }
+
+    /// The `n`th (starting at 1) local variable that is in scope at the opcode index `pc`.
+    pub fn local_variable(&self, pc: usize, n: usize) -> Option<&LocalVariable<String<'gc>>> {
+        self.local_variables
+            .iter()
+            .filter(|l| l.start_pc <= pc && pc < l.end_pc)
+            .nth(n.checked_sub(1)?)
+    }
+
+    /// The number of fixed (named) parameters, not including any varargs.
+    pub fn param_count(&self) -> usize {
+        self.fixed_params.into()
+    }
+
+    /// Whether the function accepts varargs (`...`).
+    pub fn is_vararg(&self) -> bool {
+        self.has_varargs
+    }
+
+    /// The number of upvalues captured by closures of this prototype.
+    pub fn upvalue_count(&self) -> usize {
+        self.upvalues.len()
+    }
+
+    /// The names of each upvalue, in upvalue index order.
+    pub fn upvalue_names(&self) -> &[String<'gc>] {
+        &self.upvalue_names
+    }
+
+    /// The constant table, indexed by the constant operands of the opcodes.
+    pub fn constants(&self) -> &[Constant<String<'gc>>] {
+        &self.constants
+    }
+
+    /// The prototypes of the functions defined directly inside this one.
+    pub fn prototypes(&self) -> &[Gc<'gc, FunctionPrototype<'gc>>] {
+        &self.prototypes
+    }
+
+    /// The source line on which the function definition starts, or `None` for a main chunk.
+    pub fn line_defined(&self) -> Option<LineNumber> {
+        match self.reference {
+            FunctionRef::Named(_, line) | FunctionRef::Expression(line) => Some(line),
+            FunctionRef::Chunk => None,
+        }
+    }
+
+    /// The last source line of the function, or `None` for a main chunk.
+    ///
+    /// This is the line of the final opcode, which is at the end of the function definition.
+    pub fn last_line_defined(&self) -> Option<LineNumber> {
+        let line_defined = self.line_defined()?;
+        Some(
+            self.opcode_line_numbers
+                .last()
+                .map(|&(_, line)| line)
+                .unwrap_or(line_defined),
+        )
+    }
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -173,7 +345,7 @@ impl<'gc> UpValue<'gc> {
 #[collect(no_drop)]
 pub struct ClosureInner<'gc> {
     proto: Gc<'gc, FunctionPrototype<'gc>>,
-    upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    upvalues: boxed::Box<[Lock<UpValue<'gc>>], MetricsAlloc<'gc>>,
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -216,16 +388,22 @@ impl<'gc> Closure<'gc> {
             if proto.upvalues.len() > 1 || proto.upvalues[0] != UpValueDescriptor::Environment {
                 return Err(ClosureError::HasUpValues);
             } else if let Some(environment) = environment {
-                upvalues.push(UpValue(Gc::new(
+                upvalues.push(Lock::new(UpValue(Gc::new(
                     mc,
                     Lock::new(UpValueState::Closed(Value::Table(environment))),
-                )));
+                ))));
             } else {
                 return Err(ClosureError::RequiresEnv);
             }
         }
 
-        Ok(Closure(Gc::new(mc, ClosureInner { proto, upvalues })))
+        Ok(Closure(Gc::new(
+            mc,
+            ClosureInner {
+                proto,
+                upvalues: upvalues.into_boxed_slice(),
+            },
+        )))
     }
 
     pub fn from_parts(
@@ -233,7 +411,15 @@ impl<'gc> Closure<'gc> {
         proto: Gc<'gc, FunctionPrototype<'gc>>,
         upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
     ) -> Self {
-        Self(Gc::new(mc, ClosureInner { proto, upvalues }))
+        let mut locked = vec::Vec::with_capacity_in(upvalues.len(), MetricsAlloc::new(mc));
+        locked.extend(upvalues.into_iter().map(Lock::new));
+        Self(Gc::new(
+            mc,
+            ClosureInner {
+                proto,
+                upvalues: locked.into_boxed_slice(),
+            },
+        ))
     }
 
     pub fn from_inner(inner: Gc<'gc, ClosureInner<'gc>>) -> Self {
@@ -245,6 +431,10 @@ impl<'gc> Closure<'gc> {
     }
 
     /// Compile a top-level closure from source, using the globals table as the `_ENV` table.
+    ///
+    /// The chunk name is used in error messages in the same way as PUC-Rio Lua, see `short_src`.
+    /// Names of files should be given as `@filename`, other names as `=name`. If no name is
+    /// given, the chunk is called `=<anonymous>`.
     pub fn load(
         ctx: Context<'gc>,
         name: Option<&str>,
@@ -260,15 +450,44 @@ impl<'gc> Closure<'gc> {
         source: impl Read,
         env: Table<'gc>,
     ) -> Result<Closure<'gc>, PrototypeError> {
-        let proto = FunctionPrototype::compile(ctx, name.unwrap_or("<anonymous>"), source)?;
+        let proto = FunctionPrototype::compile(ctx, name.unwrap_or("=<anonymous>"), source)?;
         Ok(Closure::new(&ctx, proto, Some(env)).unwrap())
     }
 
+    /// Load a top-level closure from a binary chunk created by [`FunctionPrototype::dump`].
+    ///
+    /// The first upvalue of the closure (if any) is set to the `env` table, the same as for a main
+    /// chunk, and any other upvalues are initialized to `nil`.
+    pub fn load_binary(
+        ctx: Context<'gc>,
+        chunk: &[u8],
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, UndumpError> {
+        let proto = Gc::new(&ctx, FunctionPrototype::undump(ctx, chunk)?);
+        let mut upvalues = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        for i in 0..proto.upvalues.len() {
+            let value = if i == 0 { env.into() } else { Value::Nil };
+            upvalues.push(UpValue::new(&ctx, UpValueState::Closed(value)));
+        }
+        Ok(Closure::from_parts(&ctx, proto, upvalues))
+    }
+
     pub fn prototype(self) -> Gc<'gc, FunctionPrototype<'gc>> {
         self.0.proto
     }
 
-    pub fn upvalues(self) -> &'gc [UpValue<'gc>] {
+    pub fn upvalues(self) -> &'gc [Lock<UpValue<'gc>>] {
         &Gc::as_ref(self.0).upvalues
     }
+
+    /// Replace the upvalue at index `n` with another upvalue, so that this closure shares it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not a valid upvalue index for this closure.
+    pub fn set_upvalue(self, mc: &Mutation<'gc>, n: usize, upvalue: UpValue<'gc>) {
+        let inner = Gc::write(mc, self.0);
+        // SAFETY: The write barrier has been triggered for the closure that owns the lock.
+        unsafe { inner.upvalues[n].as_cell() }.set(upvalue);
+    }
 }
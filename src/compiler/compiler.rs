@@ -1,6 +1,8 @@
 use std::{
+    borrow::Cow,
     collections::{hash_map, VecDeque},
     fmt, iter, mem,
+    string::String as StdString,
 };
 
 use ahash::HashMap;
@@ -14,6 +16,7 @@ use crate::{
         ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor,
         UpValueIndex, VarCount,
     },
+    value::display_float,
     Constant,
 };
 
@@ -21,22 +24,24 @@ use super::{
     lexer::LineNumber,
     operators::{
         categorize_binop, comparison_binop_const_fold, comparison_binop_operation,
-        simple_binop_const_fold, simple_binop_operation, unop_const_fold, unop_operation,
-        BinOpCategory, ComparisonBinOp, ShortCircuitBinOp, SimpleBinOp,
+        short_circuit_binop_const_fold, simple_binop_const_fold, simple_binop_operation,
+        unop_const_fold, unop_operation, BinOpCategory, ComparisonBinOp, ShortCircuitBinOp,
+        SimpleBinOp,
     },
     parser::{
         AssignmentStatement, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk,
         ConstructorField, Expression, FieldSuffix, ForStatement, FunctionCallStatement,
-        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalFunctionStatement,
-        LocalStatement, PrimaryExpression, RecordKey, RepeatStatement, ReturnStatement,
-        SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor,
-        UnaryOperator, WhileStatement,
+        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalAttribute,
+        LocalFunctionStatement, LocalStatement, PrimaryExpression, RecordKey, RepeatStatement,
+        ReturnStatement, SimpleExpression, Statement, SuffixPart, SuffixedExpression,
+        TableConstructor, UnaryOperator, WhileStatement,
     },
+    peephole,
     register_allocator::RegisterAllocator,
     StringInterner,
 };
 
-#[derive(Debug, Copy, Clone, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum CompileErrorKind {
     #[error("insufficient available registers")]
     Registers,
@@ -48,17 +53,21 @@ pub enum CompileErrorKind {
     Functions,
     #[error("too many constants")]
     Constants,
-    #[error("label defined multiple times")]
-    DuplicateLabel,
-    #[error("goto target label not found")]
-    GotoInvalid,
-    #[error("jump into scope of new local variable")]
-    JumpLocal,
+    #[error("label '{0}' already defined")]
+    DuplicateLabel(StdString),
+    #[error("no visible label '{0}' for goto")]
+    GotoInvalid(StdString),
+    #[error("break outside a loop")]
+    BreakOutsideLoop,
+    #[error("<goto {label}> jumps into the scope of local '{local}'")]
+    JumpLocal { label: StdString, local: StdString },
     #[error("jump offset overflow")]
     JumpOverflow,
+    #[error("attempt to assign to const variable '{0}'")]
+    AssignToConst(StdString),
 }
 
-#[derive(Debug, Copy, Clone, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("compiler error at line {line_number}: {kind}")]
 pub struct CompileError {
     pub kind: CompileErrorKind,
@@ -101,6 +110,18 @@ impl<S> FunctionRef<S> {
     }
 }
 
+/// Debug information about a named local variable.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct LocalVariable<S> {
+    pub name: S,
+    pub register: RegisterIndex,
+    /// The index of the first opcode for which the variable is in scope.
+    pub start_pc: usize,
+    /// The index of the first opcode after the variable goes out of scope.
+    pub end_pc: usize,
+}
+
 #[derive(Debug, Clone, Collect)]
 #[collect(no_drop)]
 pub struct CompiledPrototype<S> {
@@ -115,6 +136,10 @@ pub struct CompiledPrototype<S> {
     /// Stored in sorted opcode index order with redundant entries removed.
     pub opcode_line_numbers: Vec<(usize, LineNumber)>,
     pub upvalues: Vec<UpValueDescriptor>,
+    /// The names of each upvalue, in the same order as `upvalues`.
+    pub upvalue_names: Vec<S>,
+    /// Every named local variable in the function, in order of declaration.
+    pub local_variables: Vec<LocalVariable<S>>,
     pub prototypes: Vec<Box<CompiledPrototype<S>>>,
 }
 
@@ -137,6 +162,17 @@ impl<S> CompiledPrototype<S> {
                 opcodes: this.opcodes,
                 opcode_line_numbers: this.opcode_line_numbers,
                 upvalues: this.upvalues,
+                upvalue_names: this.upvalue_names.into_iter().map(f).collect(),
+                local_variables: this
+                    .local_variables
+                    .into_iter()
+                    .map(|l| LocalVariable {
+                        name: f(l.name),
+                        register: l.register,
+                        start_pc: l.start_pc,
+                        end_pc: l.end_pc,
+                    })
+                    .collect(),
                 prototypes: this
                     .prototypes
                     .into_iter()
@@ -151,8 +187,18 @@ impl<S> CompiledPrototype<S> {
 pub fn compile_chunk<S: StringInterner>(
     chunk: &Chunk<S::String>,
     create_string: S,
+) -> Result<CompiledPrototype<S::String>, CompileError> {
+    compile(chunk, create_string, true)
+}
+
+// Compile a chunk, optionally skipping the peephole pass over the finished operations.
+pub(super) fn compile<S: StringInterner>(
+    chunk: &Chunk<S::String>,
+    create_string: S,
+    optimize: bool,
 ) -> Result<CompiledPrototype<S::String>, CompileError> {
     let mut compiler = Compiler {
+        optimize,
         string_interner: create_string,
         current_function: CompilerFunction::start(FunctionRef::Chunk, &[], true).unwrap(),
         upper_functions: Vec::new(),
@@ -165,11 +211,12 @@ pub fn compile_chunk<S: StringInterner>(
     let line_number = compiler.current_function.current_line_number;
     compiler
         .current_function
-        .finish()
+        .finish(optimize)
         .map_err(|kind| CompileError { kind, line_number })
 }
 
 struct Compiler<S: StringInterner> {
+    optimize: bool,
     string_interner: S,
     current_function: CompilerFunction<S::String>,
     upper_functions: Vec<CompilerFunction<S::String>>,
@@ -188,7 +235,9 @@ struct CompilerFunction<S> {
 
     has_varargs: bool,
     fixed_params: u8,
-    locals: Vec<(S, RegisterIndex)>,
+    // Every local variable in scope, along with whether it is const.
+    locals: Vec<(S, RegisterIndex, bool)>,
+    local_variables: Vec<LocalVariable<S>>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
@@ -244,6 +293,8 @@ enum ExprDescriptor<S> {
         args: Vec<ExprDescriptor<S>>,
     },
     Concat(VecDeque<ExprDescriptor<S>>),
+    // A parenthesized multi-value expression, which is always truncated to a single value.
+    Truncated(Box<ExprDescriptor<S>>),
 }
 
 #[derive(Debug)]
@@ -286,6 +337,14 @@ where
 
 impl<S> Eq for JumpLabel<S> where S: AsRef<[u8]> {}
 
+fn label_name<S: AsRef<[u8]>>(label: &JumpLabel<S>) -> StdString {
+    match label {
+        JumpLabel::Unique(i) => format!("<unique {i}>"),
+        JumpLabel::Named(name) => StdString::from_utf8_lossy(name.as_ref()).into_owned(),
+        JumpLabel::Break => "break".to_owned(),
+    }
+}
+
 #[derive(Debug)]
 struct BlockDescriptor {
     // The index of the first local variable in this block. All locals above this will be freed when
@@ -296,6 +355,16 @@ struct BlockDescriptor {
     bottom_jump_target: usize,
     // True if any lower function has an upvalue reference to variables in this block
     owns_upvalues: bool,
+    // True if this block declares any to-be-closed variables
+    owns_to_be_closed: bool,
+}
+
+impl BlockDescriptor {
+    // To-be-closed variables are closed by the same operations that close upvalues, so a block
+    // with either must be closed when it is exited.
+    fn needs_close(&self) -> bool {
+        self.owns_upvalues || self.owns_to_be_closed
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -340,16 +409,19 @@ impl<S: StringInterner> Compiler<S> {
             stack_bottom: self.current_function.register_allocator.stack_top(),
             bottom_jump_target: self.current_function.jump_targets.len(),
             owns_upvalues: false,
+            owns_to_be_closed: false,
         });
     }
 
     fn exit_block(&mut self) -> Result<(), CompileErrorKind> {
         let last_block = self.current_function.blocks.pop().unwrap();
 
-        while let Some((_, last)) = self.current_function.locals.last() {
+        while let Some((_, last, _)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
-                self.current_function.register_allocator.free(*last);
+                let last = *last;
+                self.current_function.register_allocator.free(last);
                 self.current_function.locals.pop();
+                self.current_function.end_local(last);
             } else {
                 break;
             }
@@ -358,7 +430,7 @@ impl<S: StringInterner> Compiler<S> {
             .jump_targets
             .drain(last_block.bottom_jump_target..);
 
-        if last_block.owns_upvalues && !self.current_function.blocks.is_empty() {
+        if last_block.needs_close() && !self.current_function.blocks.is_empty() {
             self.current_function.operations.push(Operation::Jump {
                 offset: 0,
                 close_upvalues: u8::try_from(last_block.stack_bottom)
@@ -380,7 +452,7 @@ impl<S: StringInterner> Compiler<S> {
                     pending_jump.stack_top >= self.current_function.register_allocator.stack_top()
                 );
                 pending_jump.stack_top = self.current_function.register_allocator.stack_top();
-                pending_jump.close_upvalues |= last_block.owns_upvalues;
+                pending_jump.close_upvalues |= last_block.needs_close();
             }
         }
 
@@ -466,8 +538,14 @@ impl<S: StringInterner> Compiler<S> {
             .collect::<Result<Vec<_>, CompileErrorKind>>()?;
 
         // A return of a single function call is a tail call, and this is the only thing
-        // in Lua that is considered a tail call
-        if returns.len() == 1 {
+        // in Lua that is considered a tail call. To-be-closed variables must be closed after the
+        // call returns, so there are no tail calls in their scope.
+        let has_to_be_closed = self
+            .current_function
+            .blocks
+            .iter()
+            .any(|b| b.owns_to_be_closed);
+        if returns.len() == 1 && !has_to_be_closed {
             match returns.pop().unwrap() {
                 ExprDescriptor::FunctionCall { func, args } => {
                     self.call_function(*func, args, CallMode::TailCall)?;
@@ -481,6 +559,15 @@ impl<S: StringInterner> Compiler<S> {
                     self.call_method(*table, *method, args, CallMode::TailCall)?;
                     return Ok(());
                 }
+                ExprDescriptor::Variable(VariableDescriptor::Local(register)) => {
+                    // A single local can be returned from its own register without first being
+                    // moved to the top of the stack.
+                    self.current_function.operations.push(Operation::Return {
+                        start: register,
+                        count: VarCount::constant(1),
+                    });
+                    return Ok(());
+                }
                 other => {
                     returns.push(other);
                 }
@@ -576,7 +663,7 @@ impl<S: StringInterner> Compiler<S> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompileErrorKind::Registers)?;
-                self.current_function.locals.push((name.clone(), loop_var));
+                self.current_function.declare_local(name.clone(), loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
@@ -619,32 +706,56 @@ impl<S: StringInterner> Compiler<S> {
             } => {
                 let loop_label = self.unique_jump_label();
 
-                assert!(arguments.len() >= 1);
-                let base = if arguments.len() == 1 {
-                    let args = self.expression(&arguments[0])?;
-                    self.expr_push_count(args, 3)?
-                } else {
-                    let iterator = self.expression(&arguments[0])?;
-                    let top = self.expr_discharge(iterator, ExprDestination::PushNew)?;
-
-                    let state = if let Some(state) = arguments.get(1) {
-                        self.expression(state)?
-                    } else {
-                        ExprDescriptor::Constant(Constant::Nil)
-                    };
-                    self.expr_discharge(state, ExprDestination::PushNew)?;
+                // The closing value is a to-be-closed variable of the whole loop, so it must be in
+                // the outer loop block. Its register is reserved first, so that the iterator
+                // function, state, and control value can be directly followed by the loop
+                // variables.
+                self.enter_block();
+                let close_reg = self
+                    .current_function
+                    .register_allocator
+                    .push(1)
+                    .ok_or(CompileErrorKind::Registers)?;
 
-                    let control = if let Some(control) = arguments.get(2) {
-                        self.expression(control)?
+                // The argument list is adjusted to four values: the iterator function, the
+                // iterator state, the initial control value, and the closing value. Any values past
+                // the closing value are evaluated and then discarded.
+                assert!(arguments.len() >= 1);
+                let mut base = None;
+                for (i, argument) in arguments.iter().enumerate() {
+                    let expr = self.expression(argument)?;
+                    if i >= 4 {
+                        let reg = self.expr_discharge(expr, ExprDestination::AllocateNew)?;
+                        self.current_function.register_allocator.free(reg);
+                    } else if i == arguments.len() - 1 {
+                        let dest = self.expr_push_count(expr, 4 - i as u8)?;
+                        base.get_or_insert(dest);
                     } else {
-                        ExprDescriptor::Constant(Constant::Nil)
-                    };
-                    self.expr_discharge(control, ExprDestination::PushNew)?;
+                        let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
+                        base.get_or_insert(reg);
+                    }
+                }
+                let base = base.unwrap();
 
-                    top
-                };
+                let closing = RegisterIndex(base.0 + 3);
+                self.current_function.operations.push(Operation::Move {
+                    dest: close_reg,
+                    source: closing,
+                });
+                self.current_function
+                    .register_allocator
+                    .pop_to(closing.0 as u16);
+                let name = self.string_interner.intern(b"(for state)");
+                self.current_function.declare_local(name, close_reg);
+                self.current_function
+                    .operations
+                    .push(Operation::ToBeClosed { value: close_reg });
+                self.current_function
+                    .blocks
+                    .last_mut()
+                    .unwrap()
+                    .owns_to_be_closed = true;
 
-                self.enter_block();
                 self.enter_block();
 
                 let name_count = names
@@ -658,8 +769,7 @@ impl<S: StringInterner> Compiler<S> {
                     .ok_or(CompileErrorKind::Registers)?;
                 for i in 0..name_count {
                     self.current_function
-                        .locals
-                        .push((names[i as usize].clone(), RegisterIndex(names_reg.0 + i)));
+                        .declare_local(names[i as usize].clone(), RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label.clone())?;
@@ -692,7 +802,7 @@ impl<S: StringInterner> Compiler<S> {
 
                 self.current_function
                     .register_allocator
-                    .pop_to(base.0 as u16);
+                    .pop_to(close_reg.0 as u16);
             }
         }
         Ok(())
@@ -830,10 +940,10 @@ impl<S: StringInterner> Compiler<S> {
                 .operations
                 .push(Operation::LoadNil { dest, count });
             for i in 0..name_len {
-                self.current_function.locals.push((
+                self.current_function.declare_local(
                     local_statement.names[i].clone(),
                     RegisterIndex(dest.0 + i as u8),
-                ));
+                );
             }
         } else {
             for i in 0..val_len {
@@ -849,20 +959,39 @@ impl<S: StringInterner> Compiler<S> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.current_function.declare_local(
                             local_statement.names[val_len - 1 + j as usize].clone(),
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
                     self.current_function
-                        .locals
-                        .push((local_statement.names[i].clone(), reg));
+                        .declare_local(local_statement.names[i].clone(), reg);
                 }
             }
         }
 
+        let first_local = self.current_function.locals.len() - name_len;
+        for (i, attribute) in local_statement.attributes.iter().enumerate() {
+            // To-be-closed variables are also const.
+            if attribute.is_some() {
+                self.current_function.locals[first_local + i].2 = true;
+            }
+
+            if *attribute == Some(LocalAttribute::Close) {
+                let (_, value, _) = self.current_function.locals[first_local + i];
+                self.current_function
+                    .operations
+                    .push(Operation::ToBeClosed { value });
+                self.current_function
+                    .blocks
+                    .last_mut()
+                    .unwrap()
+                    .owns_to_be_closed = true;
+            }
+        }
+
         Ok(())
     }
 
@@ -917,6 +1046,11 @@ impl<S: StringInterner> Compiler<S> {
             expr: ExprDescriptor<S::String>,
         ) -> Result<(), CompileErrorKind> {
             match target {
+                AssignmentTarget::Name(name) if this.is_const_variable(name) => {
+                    return Err(CompileErrorKind::AssignToConst(
+                        StdString::from_utf8_lossy(name.as_ref()).into_owned(),
+                    ));
+                }
                 AssignmentTarget::Name(name) => match this.find_variable(name.clone())? {
                     VariableDescriptor::Local(dest) => {
                         this.expr_discharge(expr, ExprDestination::Register(dest))?;
@@ -993,8 +1127,7 @@ impl<S: StringInterner> Compiler<S> {
             .push(1)
             .ok_or(CompileErrorKind::Registers)?;
         self.current_function
-            .locals
-            .push((local_function.name.clone(), dest));
+            .declare_local(local_function.name.clone(), dest);
 
         let proto = self.new_prototype(
             FunctionRef::Named(
@@ -1158,7 +1291,12 @@ impl<S: StringInterner> Compiler<S> {
             PrimaryExpression::Name(name) => {
                 Ok(ExprDescriptor::Variable(self.find_variable(name.clone())?))
             }
-            PrimaryExpression::GroupedExpression(expr) => self.expression(expr),
+            PrimaryExpression::GroupedExpression(expr) => Ok(match self.expression(expr)? {
+                expr @ (ExprDescriptor::FunctionCall { .. }
+                | ExprDescriptor::MethodCall { .. }
+                | ExprDescriptor::VarArgs) => ExprDescriptor::Truncated(Box::new(expr)),
+                expr => expr,
+            }),
         }
     }
 
@@ -1214,32 +1352,75 @@ impl<S: StringInterner> Compiler<S> {
                 })
             }
 
-            BinOpCategory::ShortCircuit(op) => Ok(ExprDescriptor::ShortCircuitBinOp {
-                left: Box::new(left),
-                op,
-                right: Box::new(right),
-            }),
-
-            BinOpCategory::Concat => Ok(match (left, right) {
-                (ExprDescriptor::Concat(mut left), ExprDescriptor::Concat(right)) => {
-                    left.extend(right);
-                    ExprDescriptor::Concat(left)
-                }
-                (ExprDescriptor::Concat(mut left), right) => {
-                    left.push_back(right);
-                    ExprDescriptor::Concat(left)
-                }
-                (left, ExprDescriptor::Concat(mut right)) => {
-                    right.push_front(left);
-                    ExprDescriptor::Concat(right)
+            BinOpCategory::ShortCircuit(op) => {
+                if let ExprDescriptor::Constant(c) = &left {
+                    if short_circuit_binop_const_fold(op, c) {
+                        return Ok(left);
+                    }
+                    // The right hand side is only ever a single value.
+                    return Ok(match right {
+                        expr @ (ExprDescriptor::FunctionCall { .. }
+                        | ExprDescriptor::MethodCall { .. }
+                        | ExprDescriptor::VarArgs) => ExprDescriptor::Truncated(Box::new(expr)),
+                        expr => expr,
+                    });
                 }
-                (left, right) => {
-                    let mut exprs = VecDeque::new();
-                    exprs.push_back(left);
-                    exprs.push_back(right);
-                    ExprDescriptor::Concat(exprs)
+                Ok(ExprDescriptor::ShortCircuitBinOp {
+                    left: Box::new(left),
+                    op,
+                    right: Box::new(right),
+                })
+            }
+
+            BinOpCategory::Concat => {
+                let exprs = match (left, right) {
+                    (ExprDescriptor::Concat(mut left), ExprDescriptor::Concat(right)) => {
+                        left.extend(right);
+                        left
+                    }
+                    (ExprDescriptor::Concat(mut left), right) => {
+                        left.push_back(right);
+                        left
+                    }
+                    (left, ExprDescriptor::Concat(mut right)) => {
+                        right.push_front(left);
+                        right
+                    }
+                    (left, right) => {
+                        let mut exprs = VecDeque::new();
+                        exprs.push_back(left);
+                        exprs.push_back(right);
+                        exprs
+                    }
+                };
+                Ok(self.concat_const_fold(exprs))
+            }
+        }
+    }
+
+    // Concatenate every run of adjacent string and number constants at compile time.
+    fn concat_const_fold(
+        &mut self,
+        exprs: VecDeque<ExprDescriptor<S::String>>,
+    ) -> ExprDescriptor<S::String> {
+        let mut folded = VecDeque::with_capacity(exprs.len());
+        for expr in exprs {
+            if let (Some(ExprDescriptor::Constant(left)), ExprDescriptor::Constant(right)) =
+                (folded.back_mut(), &expr)
+            {
+                if let (Some(a), Some(b)) = (concat_bytes(left), concat_bytes(right)) {
+                    let s = self.string_interner.intern(&[a, b].concat());
+                    *left = Constant::String(s);
+                    continue;
                 }
-            }),
+            }
+            folded.push_back(expr);
+        }
+
+        if folded.len() == 1 {
+            folded.pop_front().unwrap()
+        } else {
+            ExprDescriptor::Concat(folded)
         }
     }
 
@@ -1260,7 +1441,7 @@ impl<S: StringInterner> Compiler<S> {
             &mut self.current_function,
             self.upper_functions.pop().unwrap(),
         )
-        .finish()?;
+        .finish(self.optimize)?;
         self.current_function.functions.push(proto);
         Ok(PrototypeIndex(
             (self.current_function.functions.len() - 1)
@@ -1269,6 +1450,16 @@ impl<S: StringInterner> Compiler<S> {
         ))
     }
 
+    // Returns true if the given name refers to a const local variable, either in the current
+    // function or captured as an upvalue from an upper function.
+    fn is_const_variable(&self, name: &S::String) -> bool {
+        iter::once(&self.current_function)
+            .chain(self.upper_functions.iter().rev())
+            .flat_map(|function| function.locals.iter().rev())
+            .find(|(local_name, _, _)| name.as_ref() == local_name.as_ref())
+            .is_some_and(|&(_, _, is_const)| is_const)
+    }
+
     fn find_variable(
         &mut self,
         name: S::String,
@@ -1289,7 +1480,7 @@ impl<S: StringInterner> Compiler<S> {
 
         for i in (0..=current_function).rev() {
             for j in (0..get_function(self, i).locals.len()).rev() {
-                let (local_name, register) = get_function(self, i).locals[j].clone();
+                let (local_name, register, _) = get_function(self, i).locals[j].clone();
                 if name.as_ref() == local_name.as_ref() {
                     if i == current_function {
                         return Ok(VariableDescriptor::Local(register));
@@ -1390,7 +1581,7 @@ impl<S: StringInterner> Compiler<S> {
                 assert!(jump_target.block_index <= current_block_index);
                 let needs_close_upvalues = jump_target.stack_top < current_stack_top
                     && (jump_target.block_index..=current_block_index)
-                        .any(|i| self.current_function.blocks[i].owns_upvalues);
+                        .any(|i| self.current_function.blocks[i].needs_close());
 
                 self.current_function.operations.push(Operation::Jump {
                     offset: jump_offset(jmp_inst, jump_target.instruction)
@@ -1437,7 +1628,7 @@ impl<S: StringInterner> Compiler<S> {
             if jump_target.block_index < current_block_index {
                 break;
             } else if jump_target.label == jump_label {
-                return Err(CompileErrorKind::DuplicateLabel);
+                return Err(CompileErrorKind::DuplicateLabel(label_name(&jump_label)));
             }
         }
 
@@ -1465,7 +1656,18 @@ impl<S: StringInterner> Compiler<S> {
         for pending_jump in resolving_jumps {
             assert!(pending_jump.stack_top <= current_stack_top);
             if pending_jump.stack_top < current_stack_top {
-                return Err(CompileErrorKind::JumpLocal);
+                // Report the first local variable whose scope the jump would enter.
+                let local = self
+                    .current_function
+                    .locals
+                    .iter()
+                    .find(|(_, reg, _)| u16::from(reg.0) >= pending_jump.stack_top)
+                    .map(|(name, _, _)| StdString::from_utf8_lossy(name.as_ref()).into_owned())
+                    .unwrap_or_default();
+                return Err(CompileErrorKind::JumpLocal {
+                    label: label_name(&jump_label),
+                    local,
+                });
             }
 
             match &mut self.current_function.operations[pending_jump.instruction] {
@@ -2173,7 +2375,12 @@ impl<S: StringInterner> Compiler<S> {
 
             ExprDescriptor::Concat(mut exprs) => {
                 assert!(!exprs.is_empty());
-                let dest = new_destination(self, dest)?;
+                // Unless the result must go in an existing register, it is placed in the register
+                // of the first operand rather than in a newly allocated one.
+                let dest = match dest {
+                    ExprDestination::Register(dest) => Some(dest),
+                    ExprDestination::AllocateNew | ExprDestination::PushNew => None,
+                };
                 let source =
                     self.expr_discharge(exprs.pop_front().unwrap(), ExprDestination::PushNew)?;
                 let mut count = 1;
@@ -2195,15 +2402,24 @@ impl<S: StringInterner> Compiler<S> {
                     }
                 }
                 self.current_function.operations.push(Operation::Concat {
-                    dest,
+                    dest: dest.unwrap_or(source),
                     source,
                     count,
                 });
-                self.current_function
-                    .register_allocator
-                    .pop_to(source.0 as u16);
-                dest
+                if let Some(dest) = dest {
+                    self.current_function
+                        .register_allocator
+                        .pop_to(source.0 as u16);
+                    dest
+                } else {
+                    self.current_function
+                        .register_allocator
+                        .pop_to(source.0 as u16 + 1);
+                    source
+                }
             }
+
+            ExprDescriptor::Truncated(expr) => self.expr_discharge(*expr, dest)?,
         };
 
         Ok(result)
@@ -2390,6 +2606,7 @@ impl<S: Clone> CompilerFunction<S> {
             has_varargs: false,
             fixed_params: 0,
             locals: Vec::new(),
+            local_variables: Vec::new(),
             blocks: Vec::new(),
             unique_jump_id: 0,
             jump_targets: Vec::new(),
@@ -2409,21 +2626,47 @@ impl<S: Clone> CompilerFunction<S> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize].clone(), RegisterIndex(i)));
+            function.declare_local(parameters[i as usize].clone(), RegisterIndex(i));
         }
         Ok(function)
     }
 
-    fn finish(mut self) -> Result<CompiledPrototype<S>, CompileErrorKind> {
+    // Declare a new local variable in the given register, in scope from the next operation.
+    fn declare_local(&mut self, name: S, register: RegisterIndex) {
+        self.local_variables.push(LocalVariable {
+            name: name.clone(),
+            register,
+            start_pc: self.operations.len(),
+            end_pc: usize::MAX,
+        });
+        self.locals.push((name, register, false));
+    }
+
+    // Mark the most recently declared local variable in the given register as going out of scope
+    // at the next operation.
+    fn end_local(&mut self, register: RegisterIndex) {
+        if let Some(local) = self
+            .local_variables
+            .iter_mut()
+            .rev()
+            .find(|l| l.register == register && l.end_pc == usize::MAX)
+        {
+            local.end_pc = self.operations.len();
+        }
+    }
+
+    fn finish(mut self, optimize: bool) -> Result<CompiledPrototype<S>, CompileErrorKind>
+    where
+        S: AsRef<[u8]>,
+    {
         self.operations.push(Operation::Return {
             start: RegisterIndex(0),
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
+        while let Some((_, r, _)) = self.locals.pop() {
             self.register_allocator.free(r);
+            self.end_local(r);
         }
         assert_eq!(
             self.register_allocator.stack_top(),
@@ -2431,11 +2674,21 @@ impl<S: Clone> CompilerFunction<S> {
             "register leak detected"
         );
 
-        if !self.pending_jumps.is_empty() {
-            return Err(CompileErrorKind::GotoInvalid);
+        if let Some(pending_jump) = self.pending_jumps.first() {
+            return Err(match &pending_jump.target {
+                JumpLabel::Break => CompileErrorKind::BreakOutsideLoop,
+                label => CompileErrorKind::GotoInvalid(label_name(label)),
+            });
         }
 
         let mut operation_lines = self.operation_lines;
+        if optimize {
+            peephole::optimize(
+                &mut self.operations,
+                &mut operation_lines,
+                &mut self.local_variables,
+            );
+        }
         operation_lines.dedup_by(|(next_opi, next_ln), (curr_opi, curr_ln)| {
             if *curr_opi == *next_opi {
                 *curr_ln = *next_ln;
@@ -2461,6 +2714,8 @@ impl<S: Clone> CompilerFunction<S> {
                 .collect(),
             opcode_line_numbers: operation_lines,
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
+            upvalue_names: self.upvalues.into_iter().map(|(n, _)| n).collect(),
+            local_variables: self.local_variables,
             prototypes: self.functions.into_iter().map(|f| Box::new(f)).collect(),
         })
     }
@@ -2472,10 +2727,21 @@ impl<S: Clone> CompilerFunction<S> {
     }
 }
 
-fn jump_offset(source: usize, target: usize) -> Option<i16> {
+pub(super) fn jump_offset(source: usize, target: usize) -> Option<i16> {
     if target > source {
         (target - (source + 1)).try_into().ok()
     } else {
         ((source + 1) - target).try_into().ok().map(|i: i16| -i)
     }
 }
+
+// The bytes a constant contributes to a concatenation, numbers are converted the same way as at
+// runtime.
+fn concat_bytes<S: AsRef<[u8]>>(constant: &Constant<S>) -> Option<Cow<'_, [u8]>> {
+    match constant {
+        Constant::String(s) => Some(Cow::Borrowed(s.as_ref())),
+        Constant::Integer(i) => Some(Cow::Owned(i.to_string().into_bytes())),
+        Constant::Number(n) => Some(Cow::Owned(display_float(*n).into_bytes())),
+        Constant::Nil | Constant::Boolean(_) => None,
+    }
+}
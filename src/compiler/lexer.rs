@@ -233,8 +233,10 @@ pub enum LexError {
     InvalidLongStringDelimiter,
     #[error("unfinished long string")]
     UnfinishedLongString,
-    #[error("malformed number")]
-    BadNumber,
+    #[error("unfinished long comment")]
+    UnfinishedLongComment,
+    #[error("malformed number near '{0}'")]
+    BadNumber(String),
     #[error("IO Error: {0}")]
     IOError(#[from] io::Error),
 }
@@ -604,7 +606,9 @@ where
                     }
 
                     b'\n' | b'\r' => {
-                        self.read_line_end(true)?;
+                        // An escaped line ending is read as a single '\n'.
+                        self.read_line_end(false)?;
+                        self.string_buffer.push(b'\n');
                     }
 
                     b'x' => {
@@ -628,14 +632,23 @@ where
                         self.advance(2);
 
                         let mut u: u32 = 0;
+                        let mut digits = 0;
                         loop {
                             if let Some(c) = self.peek(0)? {
-                                if c == b'}' {
+                                if c == b'}' && digits > 0 {
                                     self.advance(1);
                                     break;
                                 } else if let Some(h) = from_hex_digit(c) {
+                                    // Checked for every digit, so that a long escape cannot wrap
+                                    // around to a valid code point.
                                     u = (u << 4) | h as u32;
+                                    if u > char::MAX as u32 {
+                                        return Err(LexError::EscapeUnicodeInvalid);
+                                    }
+                                    digits += 1;
                                     self.advance(1);
+                                } else if digits == 0 {
+                                    return Err(LexError::HexDigitExpected);
                                 } else {
                                     return Err(LexError::EscapeUnicodeEnd);
                                 }
@@ -696,7 +709,10 @@ where
     }
 
     // Read a [=*[...]=*] sequence with matching numbers of '='. If `into_string` is true, writes
-    // the contained string into the string buffer.
+    // the contained string into the string buffer, otherwise this is a long comment.
+    //
+    // The contents are not processed for escapes, but a line ending immediately after the opening
+    // delimiter is skipped.
     fn read_long_string(&mut self, into_string: bool) -> Result<(), LexError> {
         assert_eq!(self.peek(0).unwrap().unwrap(), b'[');
         self.advance(1);
@@ -716,16 +732,26 @@ where
         }
         self.advance(1);
 
+        if matches!(self.peek(0)?, Some(b'\n' | b'\r')) {
+            self.read_line_end(false)?;
+        }
+
         loop {
             let c = if let Some(c) = self.peek(0)? {
                 c
-            } else {
+            } else if into_string {
                 return Err(LexError::UnfinishedLongString);
+            } else {
+                return Err(LexError::UnfinishedLongComment);
             };
 
             match c {
                 b'\n' | b'\r' => {
-                    self.read_line_end(into_string)?;
+                    // Every line ending is read as a single '\n'.
+                    self.read_line_end(false)?;
+                    if into_string {
+                        self.string_buffer.push(b'\n');
+                    }
                 }
 
                 b']' => {
@@ -766,6 +792,10 @@ where
     // Reads a hex or decimal integer or floating point identifier. Allows decimal integers (123),
     // hex integers (0xdeadbeef), decimal floating point with optional exponent and exponent sign
     // (3.21e+1), and hex floats with optional exponent and exponent sign (0xe.2fp-1c).
+    //
+    // Like PUC-Rio Lua, every character that could be part of a numeral is read before converting
+    // it, so something like `3..2` or `12abc` is a single malformed number. Decimal integers which
+    // do not fit in an `i64` are read as floats, and hex integers wrap around.
     fn read_numeral(&mut self) -> Result<Token<S::String>, LexError> {
         let p1 = self.peek(0).unwrap().unwrap();
         assert!(p1 == b'.' || is_digit(p1));
@@ -781,43 +811,35 @@ where
         }
 
         let mut has_radix = false;
+        let mut has_exp = false;
         while let Some(c) = self.peek(0)? {
-            if c == b'.' && !has_radix {
-                self.string_buffer.push(b'.');
-                has_radix = true;
+            let is_exp = if is_hex {
+                c == b'p' || c == b'P'
+            } else {
+                c == b'e' || c == b'E'
+            };
+            if is_exp {
+                self.string_buffer.push(c);
+                has_exp = true;
                 self.advance(1);
-            } else if (!is_hex && is_digit(c)) || (is_hex && is_hex_digit(c)) {
+                if let Some(sign @ (b'+' | b'-')) = self.peek(0)? {
+                    self.string_buffer.push(sign);
+                    self.advance(1);
+                }
+            } else if is_hex_digit(c) || c == b'.' {
                 self.string_buffer.push(c);
+                has_radix |= c == b'.';
                 self.advance(1);
             } else {
                 break;
             }
         }
 
-        let mut has_exp = false;
-        if let Some(exp_begin) = self.peek(0)? {
-            if (is_hex && (exp_begin == b'p' || exp_begin == b'P'))
-                || (!is_hex && (exp_begin == b'e' || exp_begin == b'E'))
-            {
-                self.string_buffer.push(exp_begin);
-                has_exp = true;
+        // A numeral touching a letter is malformed.
+        if let Some(c) = self.peek(0)? {
+            if is_alpha(c) {
+                self.string_buffer.push(c);
                 self.advance(1);
-
-                if let Some(sign) = self.peek(0)? {
-                    if sign == b'+' || sign == b'-' {
-                        self.string_buffer.push(sign);
-                        self.advance(1);
-                    }
-                }
-
-                while let Some(c) = self.peek(0)? {
-                    if is_digit(c) {
-                        self.string_buffer.push(c);
-                        self.advance(1);
-                    } else {
-                        break;
-                    }
-                }
             }
         }
 
@@ -826,20 +848,20 @@ where
                 if let Some(i) = read_hex_integer(&self.string_buffer) {
                     return Ok(Token::Integer(i));
                 }
-            }
-            if let Some(i) = read_dec_integer(&self.string_buffer) {
+            } else if let Some(i) = read_dec_integer(&self.string_buffer) {
                 return Ok(Token::Integer(i));
             }
         }
 
-        Ok(Token::Float(
-            if is_hex {
-                read_hex_float(&self.string_buffer)
-            } else {
-                read_dec_float(&self.string_buffer)
-            }
-            .ok_or(LexError::BadNumber)?,
-        ))
+        if is_hex {
+            read_hex_float(&self.string_buffer)
+        } else {
+            read_dec_float(&self.string_buffer)
+        }
+        .map(Token::Float)
+        .ok_or_else(|| {
+            LexError::BadNumber(String::from_utf8_lossy(&self.string_buffer).into_owned())
+        })
     }
 
     fn peek(&mut self, n: usize) -> Result<Option<u8>, LexError> {
@@ -889,6 +911,10 @@ pub fn read_integer(s: &[u8]) -> Option<i64> {
 pub fn read_dec_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
 
+    if s.is_empty() {
+        return None;
+    }
+
     let mut i: i64 = 0;
     for &c in s {
         let d = from_digit(c)? as i64;
@@ -905,18 +931,19 @@ pub fn read_dec_integer(s: &[u8]) -> Option<i64> {
 pub fn read_hex_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
 
-    if s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
+    if s.len() < 3 || s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
         return None;
     }
 
+    // Hex integers wrap around rather than overflowing.
     let mut i: i64 = 0;
     for &c in &s[2..] {
         let d = from_hex_digit(c)? as i64;
-        i = i.checked_mul(16)?.checked_add(d)?;
+        i = i.wrapping_mul(16).wrapping_add(d);
     }
 
     if is_neg {
-        i = i.checked_neg()?;
+        i = i.wrapping_neg();
     }
 
     Some(i)
@@ -927,6 +954,13 @@ pub fn read_float(s: &[u8]) -> Option<f64> {
 }
 
 pub fn read_dec_float(s: &[u8]) -> Option<f64> {
+    // Rust's float parsing also accepts words like "inf" and "NaN", which are not Lua numerals.
+    if !s
+        .iter()
+        .all(|&c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+    {
+        return None;
+    }
     let s = str::from_utf8(s).ok()?;
     str::parse(s).ok()
 }
@@ -1194,6 +1228,31 @@ mod tests {
                 str_token(" [=] [==] another long string [==] [=] "),
             ],
         );
+
+        // A line ending right after the opening delimiter is skipped, but only the first one.
+        test_tokens_lines(
+            "[[\nfirst]] [==[\r\n\r\nsecond\\n]=]]==] [[\n\rthird]]\n[[]]",
+            &[
+                (str_token("first"), 0),
+                (str_token("\nsecond\\n]=]"), 1),
+                (str_token("third"), 3),
+                (str_token(""), 5),
+            ],
+        );
+    }
+
+    #[test]
+    fn unfinished_long_brackets() {
+        for (source, expected) in [
+            ("[[ abc", "unfinished long string"),
+            ("[==[ abc ]=]", "unfinished long string"),
+            ("--[[ abc", "unfinished long comment"),
+            ("--[=[ abc ]]", "unfinished long comment"),
+        ] {
+            let mut lexer = Lexer::new(source.as_bytes(), BasicInterner::default());
+            let err = lexer.read_token().unwrap_err();
+            assert_eq!(err.to_string(), expected, "{source:?}");
+        }
     }
 
     #[test]
@@ -1219,6 +1278,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn string_escapes() {
+        let bytes_token = |b: &[u8]| Token::String(b.to_vec().into_boxed_slice().into());
+        test_tokens(
+            r#"
+            "\u{1F600}\u{7ff}\u{0041}"
+            "\255\0\0659"
+            "\xff\x4A"
+            "a\z
+
+               b"
+            "\a\b\f\v\r\n\t"
+        "#,
+            &[
+                str_token("\u{1F600}\u{7ff}A"),
+                bytes_token(b"\xff\x00\x419"),
+                bytes_token(b"\xff\x4a"),
+                str_token("ab"),
+                str_token("\x07\x08\x0c\x0b\r\n\t"),
+            ],
+        );
+
+        // An escaped line ending is always a single '\n'.
+        test_tokens("\"a\\\r\nb\\\n\rc\"", &[str_token("a\nb\nc")]);
+
+        for (source, expected) in [
+            (r#""\256""#, LexError::EscapeDecimalTooLarge),
+            (r#""\u{}""#, LexError::HexDigitExpected),
+            (r#""\u{110000}""#, LexError::EscapeUnicodeInvalid),
+            (r#""\u{100000000041}""#, LexError::EscapeUnicodeInvalid),
+            (r#""\u{41""#, LexError::EscapeUnicodeEnd),
+            (r#""\u41""#, LexError::EscapeUnicodeStart),
+            (r#""\x4""#, LexError::HexDigitExpected),
+            (r#""\q""#, LexError::InvalidEscape),
+        ] {
+            let mut lexer = Lexer::new(source.as_bytes(), BasicInterner::default());
+            let err = lexer.read_token().unwrap_err();
+            assert_eq!(err.to_string(), expected.to_string(), "{source}");
+        }
+    }
+
     #[test]
     fn numerals() {
         test_tokens(
@@ -1234,6 +1334,12 @@ mod tests {
             0x99999999999999999999999999999999p999999999999999999999999999999
             9223372036854775807
             9223372036854775808
+            0xff
+            0x1p4
+            0x1.8p3
+            0xffffffffffffffff
+            0x10000000000000001
+            100000000000000000000
         "#,
             &[
                 Token::Integer(0xdeadbeef),
@@ -1247,10 +1353,31 @@ mod tests {
                 Token::Float(f64::INFINITY),
                 Token::Integer(9223372036854775807),
                 Token::Float(9223372036854775808.0),
+                Token::Integer(255),
+                Token::Float(16.0),
+                Token::Float(12.0),
+                Token::Integer(-1),
+                Token::Integer(1),
+                Token::Float(1e20),
             ],
         );
     }
 
+    #[test]
+    fn malformed_numerals() {
+        for source in [
+            "0x", "0xp1", "1e", "1e+", "0x1p", "3..2", "1.2.3", "12abc", "0x1g",
+        ] {
+            let mut lexer = Lexer::new(source.as_bytes(), BasicInterner::default());
+            match lexer.read_token() {
+                Err(err @ LexError::BadNumber(_)) => {
+                    assert_eq!(err.to_string(), format!("malformed number near '{source}'"));
+                }
+                res => panic!("expected a malformed number for {source:?}, got {res:?}"),
+            }
+        }
+    }
+
     #[test]
     fn words() {
         test_tokens(
@@ -3,10 +3,14 @@ pub mod interning;
 pub mod lexer;
 mod operators;
 pub mod parser;
+mod peephole;
 mod register_allocator;
 
 pub use self::{
-    compiler::{compile_chunk, CompileError, CompileErrorKind, CompiledPrototype, FunctionRef},
+    compiler::{
+        compile_chunk, CompileError, CompileErrorKind, CompiledPrototype, FunctionRef,
+        LocalVariable,
+    },
     interning::StringInterner,
     lexer::LineNumber,
     parser::parse_chunk,
@@ -112,7 +112,11 @@ pub fn simple_binop_const_fold<S: AsRef<[u8]>>(
         SimpleBinOp::Pow => left.exponentiate(right),
         SimpleBinOp::Div => left.float_divide(right),
         SimpleBinOp::IDiv => left.floor_divide(right),
-        _ => None,
+        SimpleBinOp::BitAnd => left.bitwise_and(right),
+        SimpleBinOp::BitOr => left.bitwise_or(right),
+        SimpleBinOp::BitXor => left.bitwise_xor(right),
+        SimpleBinOp::ShiftLeft => left.shift_left(right),
+        SimpleBinOp::ShiftRight => left.shift_right(right),
     }
 }
 
@@ -143,15 +147,16 @@ pub fn comparison_binop_operation(
             left,
             right,
         },
-        ComparisonBinOp::GreaterThan => Operation::LessEq {
-            skip_if: !skip_if,
-            left,
-            right,
+        // `a > b` is `b < a` rather than `not (a <= b)`, which differ when either is NaN.
+        ComparisonBinOp::GreaterThan => Operation::Less {
+            skip_if,
+            left: right,
+            right: left,
         },
-        ComparisonBinOp::GreaterEqual => Operation::Less {
-            skip_if: !skip_if,
-            left,
-            right,
+        ComparisonBinOp::GreaterEqual => Operation::LessEq {
+            skip_if,
+            left: right,
+            right: left,
         },
     }
 }
@@ -161,17 +166,34 @@ pub fn comparison_binop_const_fold<S: AsRef<[u8]>>(
     left: &Constant<S>,
     right: &Constant<S>,
 ) -> Option<Constant<S>> {
-    match comparison_binop {
-        ComparisonBinOp::Equal => Some(Constant::Boolean(left.is_equal(right))),
-        ComparisonBinOp::LessThan => match left.less_than(right) {
-            Some(a) => Some(Constant::Boolean(a)),
-            _ => None,
-        },
-        ComparisonBinOp::LessEqual => match left.less_equal(right) {
-            Some(a) => Some(Constant::Boolean(a)),
-            _ => None,
-        },
-        _ => None,
+    // Ordering a number against a string is an error, so only numbers with numbers and strings with
+    // strings are folded.
+    let is_number = |c: &Constant<S>| matches!(c, Constant::Integer(_) | Constant::Number(_));
+    let ordered = match (left, right) {
+        (Constant::String(_), Constant::String(_)) => true,
+        (a, b) => is_number(a) && is_number(b),
+    };
+
+    Some(Constant::Boolean(match comparison_binop {
+        ComparisonBinOp::Equal => left.is_equal(right),
+        ComparisonBinOp::NotEqual => !left.is_equal(right),
+        _ if !ordered => return None,
+        ComparisonBinOp::LessThan => left.less_than(right)?,
+        ComparisonBinOp::LessEqual => left.less_equal(right)?,
+        ComparisonBinOp::GreaterThan => right.less_than(left)?,
+        ComparisonBinOp::GreaterEqual => right.less_equal(left)?,
+    }))
+}
+
+// Returns true if `and` / `or` with the given constant left hand side always evaluates to the left
+// hand side, otherwise it always evaluates to the right hand side.
+pub fn short_circuit_binop_const_fold<S>(
+    short_circuit_binop: ShortCircuitBinOp,
+    left: &Constant<S>,
+) -> bool {
+    match short_circuit_binop {
+        ShortCircuitBinOp::And => !left.to_bool(),
+        ShortCircuitBinOp::Or => left.to_bool(),
     }
 }
 
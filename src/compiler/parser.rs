@@ -143,9 +143,19 @@ pub struct LocalFunctionStatement<S> {
 #[derive(Debug, Clone)]
 pub struct LocalStatement<S> {
     pub names: Vec<S>,
+    /// The attribute of each name in `names`, if any.
+    pub attributes: Vec<Option<LocalAttribute>>,
     pub values: Vec<Expression<S>>,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LocalAttribute {
+    /// `<const>`, the variable cannot be assigned to after its declaration.
+    Const,
+    /// `<close>`, the value's `__close` metamethod is called when the variable goes out of scope.
+    Close,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum BinaryOperator {
     Add,
@@ -295,6 +305,10 @@ pub enum ParseErrorKind {
     ExpressionNotStatement,
     #[error("recursion limit reached")]
     RecursionLimit,
+    #[error("unknown attribute '{0}'")]
+    UnknownAttribute(String),
+    #[error("multiple to-be-closed variables in local list")]
+    MultipleToBeClosed,
     #[error(transparent)]
     LexError(#[from] LexError),
 }
@@ -592,10 +606,25 @@ where
     fn parse_local_statement(&mut self) -> Result<LocalStatement<S::String>, ParseError> {
         self.expect_next(Token::Local)?;
         let mut names = Vec::new();
+        let mut attributes = Vec::new();
         names.push(self.expect_name()?.inner);
+        attributes.push(self.parse_local_attribute()?);
         while self.check_ahead(0, Token::Comma)? {
             self.take_next()?;
             names.push(self.expect_name()?.inner);
+            attributes.push(self.parse_local_attribute()?);
+        }
+
+        if attributes
+            .iter()
+            .filter(|&&a| a == Some(LocalAttribute::Close))
+            .count()
+            > 1
+        {
+            return Err(ParseError {
+                kind: ParseErrorKind::MultipleToBeClosed,
+                line_number: self.lexer.line_number(),
+            });
         }
 
         let values = if self.check_ahead(0, Token::Assign)? {
@@ -605,7 +634,34 @@ where
             Vec::new()
         };
 
-        Ok(LocalStatement { names, values })
+        Ok(LocalStatement {
+            names,
+            attributes,
+            values,
+        })
+    }
+
+    // Parse an optional `<attrib>` following a local variable name.
+    fn parse_local_attribute(&mut self) -> Result<Option<LocalAttribute>, ParseError> {
+        if !self.check_ahead(0, Token::LessThan)? {
+            return Ok(None);
+        }
+        self.take_next()?;
+        let name = self.expect_name()?;
+        let attribute = match name.inner.as_ref() {
+            b"const" => LocalAttribute::Const,
+            b"close" => LocalAttribute::Close,
+            other => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::UnknownAttribute(
+                        String::from_utf8_lossy(other).into_owned(),
+                    ),
+                    line_number: name.line_number,
+                })
+            }
+        };
+        self.expect_next(Token::GreaterThan)?;
+        Ok(Some(attribute))
     }
 
     fn parse_label_statement(&mut self) -> Result<LabelStatement<S::String>, ParseError> {
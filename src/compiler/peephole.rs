@@ -0,0 +1,297 @@
+use crate::{
+    opcode::Operation,
+    types::{Opt254, RegisterIndex},
+};
+
+use super::{compiler::jump_offset, lexer::LineNumber, LocalVariable};
+
+/// Simplify the operations of a finished function.
+///
+/// Chains of jumps are collapsed into a single jump, jumps to the immediately following operation
+/// are removed, and adjacent `LoadNil` operations are merged. All jump offsets, line numbers and
+/// local variable ranges are adjusted to match the new operation indexes.
+pub fn optimize<S>(
+    operations: &mut Vec<Operation>,
+    operation_lines: &mut [(usize, LineNumber)],
+    local_variables: &mut [LocalVariable<S>],
+) {
+    loop {
+        thread_jumps(operations);
+        let removed = find_removable(operations);
+        if !removed.contains(&true) {
+            break;
+        }
+        remove_operations(operations, &removed, operation_lines, local_variables);
+    }
+}
+
+// Redirect every `Jump` whose target is another `Jump` to the final destination of the chain.
+fn thread_jumps(operations: &mut [Operation]) {
+    for i in 0..operations.len() {
+        let Operation::Jump {
+            offset,
+            close_upvalues,
+        } = operations[i]
+        else {
+            continue;
+        };
+
+        let mut target = add_offset(i, offset);
+        let mut close = close_upvalues;
+        // Bounding the number of steps prevents looping forever on a cycle of jumps that does
+        // not include this one.
+        for _ in 0..operations.len() {
+            if target == i {
+                break;
+            }
+            let Some(&Operation::Jump {
+                offset: next_offset,
+                close_upvalues: next_close,
+            }) = operations.get(target)
+            else {
+                break;
+            };
+            let next_target = add_offset(target, next_offset);
+            if jump_offset(i, next_target).is_none() {
+                break;
+            }
+            target = next_target;
+            close = combine_close(close, next_close);
+        }
+
+        operations[i] = Operation::Jump {
+            offset: jump_offset(i, target).unwrap(),
+            close_upvalues: close,
+        };
+    }
+}
+
+// Mark every operation which can be removed without changing the behavior of the function,
+// merging `LoadNil` operations into the preceding `LoadNil` as necessary.
+fn find_removable(operations: &mut [Operation]) -> Vec<bool> {
+    let mut is_target = vec![false; operations.len() + 1];
+    for (i, op) in operations.iter().enumerate() {
+        if let Some(target) = jump_target(i, op) {
+            is_target[target] = true;
+            if let Operation::NumericForPrep { .. } = op {
+                // The loop is exited by skipping past the target `NumericForLoop`.
+                is_target[target + 1] = true;
+            }
+        }
+    }
+
+    let mut removed = vec![false; operations.len()];
+    for i in 0..operations.len() {
+        if removed[i] {
+            continue;
+        }
+        // The operation following a skipping operation must stay where it is, and must not grow
+        // to cover more than it did.
+        if i > 0 && can_skip(&operations[i - 1]) {
+            continue;
+        }
+
+        match operations[i] {
+            Operation::Jump {
+                offset: 0,
+                close_upvalues,
+            } if close_upvalues.is_none() => {
+                removed[i] = true;
+            }
+            Operation::LoadNil { dest, count } => {
+                let Some(&Operation::LoadNil {
+                    dest: next_dest,
+                    count: next_count,
+                }) = operations.get(i + 1)
+                else {
+                    continue;
+                };
+                if is_target[i + 1] {
+                    continue;
+                }
+
+                let start = dest.0.min(next_dest.0) as usize;
+                let end = (dest.0 as usize + count as usize)
+                    .max(next_dest.0 as usize + next_count as usize);
+                let overlaps = next_dest.0 as usize <= dest.0 as usize + count as usize
+                    && dest.0 as usize <= next_dest.0 as usize + next_count as usize;
+                if let (true, Ok(count)) = (overlaps, u8::try_from(end - start)) {
+                    operations[i] = Operation::LoadNil {
+                        dest: RegisterIndex(start as u8),
+                        count,
+                    };
+                    removed[i + 1] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    removed
+}
+
+// Remove every marked operation, fixing up all of the operation indexes which refer to them.
+fn remove_operations<S>(
+    operations: &mut Vec<Operation>,
+    removed: &[bool],
+    operation_lines: &mut [(usize, LineNumber)],
+    local_variables: &mut [LocalVariable<S>],
+) {
+    // The new index of every operation, removed operations are mapped to the index of the next
+    // operation which is kept.
+    let mut new_index = Vec::with_capacity(operations.len() + 1);
+    let mut kept = 0;
+    for &r in removed {
+        new_index.push(kept);
+        if !r {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let mut new_operations = Vec::with_capacity(kept);
+    for (i, &op) in operations.iter().enumerate() {
+        if removed[i] {
+            continue;
+        }
+        let mut op = op;
+        if let Some(target) = jump_target(i, &op) {
+            set_jump_target(new_operations.len(), &mut op, new_index[target]);
+        }
+        new_operations.push(op);
+    }
+    *operations = new_operations;
+
+    for (opi, _) in operation_lines {
+        *opi = new_index[*opi];
+    }
+
+    for local in local_variables {
+        local.start_pc = new_index[local.start_pc];
+        if local.end_pc != usize::MAX {
+            local.end_pc = new_index[local.end_pc];
+        }
+    }
+}
+
+// The operation index that the given operation jumps to, if it is a jump.
+//
+// For `NumericForPrep` this is the index of the matching `NumericForLoop`.
+fn jump_target(index: usize, op: &Operation) -> Option<usize> {
+    match *op {
+        Operation::Jump { offset, .. }
+        | Operation::NumericForPrep { jump: offset, .. }
+        | Operation::NumericForLoop { jump: offset, .. }
+        | Operation::GenericForLoop { jump: offset, .. } => Some(add_offset(index, offset)),
+        _ => None,
+    }
+}
+
+// Moving operations closer together can never overflow an offset, so this panics if the target
+// is out of range.
+fn set_jump_target(index: usize, op: &mut Operation, target: usize) {
+    let new_offset = jump_offset(index, target).expect("jump offset overflow");
+    match op {
+        Operation::Jump { offset, .. }
+        | Operation::NumericForPrep { jump: offset, .. }
+        | Operation::NumericForLoop { jump: offset, .. }
+        | Operation::GenericForLoop { jump: offset, .. } => *offset = new_offset,
+        _ => panic!("operation is not a jump"),
+    }
+}
+
+// Whether the operation may skip the operation following it.
+fn can_skip(op: &Operation) -> bool {
+    matches!(
+        op,
+        Operation::LoadBool {
+            skip_next: true,
+            ..
+        } | Operation::Test { .. }
+            | Operation::TestSet { .. }
+            | Operation::Eq { .. }
+            | Operation::Less { .. }
+            | Operation::LessEq { .. }
+    )
+}
+
+// Jumping first with `a` and then with `b` closes every upvalue >= either of them, in reverse
+// order, which is the same as closing everything >= the lower of the two.
+fn combine_close(a: Opt254, b: Opt254) -> Opt254 {
+    match (a.to_u8(), b.to_u8()) {
+        (Some(a), Some(b)) => Opt254::some(a.min(b)),
+        (Some(_), None) => a,
+        (None, _) => b,
+    }
+}
+
+fn add_offset(index: usize, offset: i16) -> usize {
+    (index as isize + 1 + offset as isize) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{interning::BasicInterner, parse_chunk, CompiledPrototype};
+
+    use super::{super::compiler::compile, *};
+
+    fn compile_source(source: &str, optimize: bool) -> Vec<Operation> {
+        let mut interner = BasicInterner::default();
+        let chunk = parse_chunk(source.as_bytes(), &mut interner).unwrap();
+        let proto: CompiledPrototype<_> = compile(&chunk, &mut interner, optimize).unwrap();
+        proto.opcodes.iter().map(|op| op.decode()).collect()
+    }
+
+    fn count_jumps(ops: &[Operation]) -> usize {
+        ops.iter()
+            .filter(|op| matches!(op, Operation::Jump { .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_if_elseif_jumps() {
+        let source = r#"
+            local a, b = ...
+            if a == 1 then
+                b = 1
+            elseif a == 2 then
+                if b then
+                    b = 2
+                else
+                    b = 3
+                end
+            elseif a == 3 then
+                b = 4
+            else
+            end
+            return b
+        "#;
+
+        let unoptimized = compile_source(source, false);
+        let optimized = compile_source(source, true);
+        assert!(
+            count_jumps(&optimized) < count_jumps(&unoptimized),
+            "{optimized:?} has as many jumps as {unoptimized:?}"
+        );
+
+        // No jump should target another unconditional jump or the following operation.
+        for (i, op) in optimized.iter().enumerate() {
+            if let Operation::Jump { offset, .. } = *op {
+                assert_ne!(offset, 0);
+                assert!(!matches!(
+                    optimized[add_offset(i, offset)],
+                    Operation::Jump { .. }
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_load_nil() {
+        let ops = compile_source("local a; local b; local c; return a, b, c", true);
+        let load_nils = ops
+            .iter()
+            .filter(|op| matches!(op, Operation::LoadNil { .. }))
+            .count();
+        assert_eq!(load_nils, 1, "{ops:?}");
+    }
+}
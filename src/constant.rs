@@ -49,40 +49,44 @@ impl<S> Constant<S> {
 }
 
 impl<S: AsRef<[u8]>> Constant<S> {
+    /// Interprets Numbers, Integers, and Strings as an Integer or a Number, if possible.
+    ///
+    /// Strings are converted following the Lua rules for string coercion: the string must contain
+    /// a numeral as accepted by the lexer, optionally preceded by a sign and surrounded by
+    /// whitespace. Integer numerals that do not fit in an `i64` are converted to a Number.
+    pub fn to_numeric(&self) -> Option<Self> {
+        match self {
+            &Self::Integer(a) => Some(Self::Integer(a)),
+            &Self::Number(a) => Some(Self::Number(a)),
+            Self::String(a) => {
+                let s = trim_whitespace(a.as_ref());
+                if let Some(i) = read_integer(s) {
+                    Some(Self::Integer(i))
+                } else {
+                    read_float(s).map(Self::Number)
+                }
+            }
+            _ => None,
+        }
+    }
+
     /// Interprets Numbers, Integers, and Strings as a Number, if possible.
     pub fn to_number(&self) -> Option<f64> {
-        match self {
-            &Self::Integer(a) => Some(a as f64),
-            &Self::Number(a) => Some(a),
-            Self::String(a) => read_float(a.as_ref()),
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(a as f64),
+            Self::Number(a) => Some(a),
             _ => None,
         }
     }
 
     /// Interprets Numbers, Integers, and Strings as an Integer, if possible.
+    ///
+    /// Numbers (and Strings containing floating point numerals) are only converted if they have an
+    /// exact integer representation.
     pub fn to_integer(&self) -> Option<i64> {
-        match self {
-            &Self::Integer(a) => Some(a),
-            &Self::Number(a) => {
-                if ((a as i64) as f64) == a {
-                    Some(a as i64)
-                } else {
-                    None
-                }
-            }
-            Self::String(a) => {
-                if let Some(i) = read_integer(a.as_ref()) {
-                    Some(i)
-                } else if let Some(n) = read_float(a.as_ref()) {
-                    if ((n as i64) as f64) == n {
-                        Some(n as i64)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(a),
+            Self::Number(a) => float_to_integer(a),
             _ => None,
         }
     }
@@ -90,22 +94,22 @@ impl<S: AsRef<[u8]>> Constant<S> {
     // Mathematical operators
 
     pub fn add(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_add(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_add(b)),
             (a, b) => Self::Number(a.to_number()? + b.to_number()?),
         })
     }
 
     pub fn subtract(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_sub(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_sub(b)),
             (a, b) => Self::Number(a.to_number()? - b.to_number()?),
         })
     }
 
     pub fn multiply(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_mul(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_mul(b)),
             (a, b) => Self::Number(a.to_number()? * b.to_number()?),
         })
     }
@@ -118,12 +122,19 @@ impl<S: AsRef<[u8]>> Constant<S> {
     /// This operation returns an Integer only if both arguments are Integers. Rounding is towards
     /// negative infinity.
     pub fn floor_divide(&self, rhs: &Self) -> Option<Self> {
-        match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => {
+        match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => {
                 if b == 0 {
                     None
                 } else {
-                    Some(Self::Integer(a.wrapping_div(b)))
+                    // Rust's integer division truncates, so adjust the quotient to round towards
+                    // negative infinity when the operands have different signs.
+                    let q = a.wrapping_div(b);
+                    if a.wrapping_rem(b) != 0 && (a ^ b) < 0 {
+                        Some(Self::Integer(q - 1))
+                    } else {
+                        Some(Self::Integer(q))
+                    }
                 }
             }
             (a, b) => Some(Self::Number((a.to_number()? / b.to_number()?).floor())),
@@ -133,17 +144,31 @@ impl<S: AsRef<[u8]>> Constant<S> {
     /// Computes the Lua modulus (`%`) operator. This is unlike Rust's `%` operator which computes
     /// the remainder.
     pub fn modulo(&self, rhs: &Self) -> Option<Self> {
-        match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => {
+        match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => {
                 if b == 0 {
                     None
                 } else {
-                    Some(Self::Integer(((a % b) + b) % b))
+                    // The remainder has the sign of `a`, so adjust it to have the sign of `b`.
+                    // `wrapping_rem` makes `i64::MIN % -1` zero rather than overflowing.
+                    let m = a.wrapping_rem(b);
+                    if m != 0 && (m ^ b) < 0 {
+                        Some(Self::Integer(m + b))
+                    } else {
+                        Some(Self::Integer(m))
+                    }
                 }
             }
             (a, b) => {
                 let (a, b) = (a.to_number()?, b.to_number()?);
-                Some(Self::Number(((a % b) + b) % b))
+                // Like PUC-Rio Lua, adjust the remainder rather than computing
+                // `a - floor(a / b) * b`, which is imprecise and makes `a % inf` NaN.
+                let m = a % b;
+                if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+                    Some(Self::Number(m + b))
+                } else {
+                    Some(Self::Number(m))
+                }
             }
         }
     }
@@ -154,10 +179,10 @@ impl<S: AsRef<[u8]>> Constant<S> {
     }
 
     pub fn negate(&self) -> Option<Self> {
-        match self {
-            &Self::Integer(a) => Some(Self::Integer(a.wrapping_neg())),
-            &Self::Number(a) => Some(Self::Number(-a)),
-            s => s.to_number().map(|x| Self::Number(-x)),
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(Self::Integer(a.wrapping_neg())),
+            Self::Number(a) => Some(Self::Number(-a)),
+            _ => None,
         }
     }
 
@@ -179,14 +204,20 @@ impl<S: AsRef<[u8]>> Constant<S> {
         Some(Self::Integer(self.to_integer()? ^ rhs.to_integer()?))
     }
 
+    /// Shifts are logical, shifting by 64 or more bits in either direction results in 0, and
+    /// shifting by a negative amount shifts in the opposite direction.
     pub fn shift_left(&self, rhs: &Self) -> Option<Self> {
-        Some(Self::Integer(self.to_integer()? << rhs.to_integer()?))
+        Some(Self::Integer(shift_left(
+            self.to_integer()?,
+            rhs.to_integer()?,
+        )))
     }
 
     pub fn shift_right(&self, rhs: &Self) -> Option<Self> {
-        Some(Self::Integer(
-            (self.to_integer()? as u64 >> rhs.to_integer()? as u64) as i64,
-        ))
+        Some(Self::Integer(shift_left(
+            self.to_integer()?,
+            rhs.to_integer()?.wrapping_neg(),
+        )))
     }
 
     // Comparison operators
@@ -229,6 +260,40 @@ impl<S: AsRef<[u8]>> Constant<S> {
     }
 }
 
+/// Converts a float to an integer, if it has an exact integer representation.
+pub fn float_to_integer(n: f64) -> Option<i64> {
+    // -2^63 is exactly representable as an `i64`, but 2^63 is not. Casting a float to an `i64`
+    // saturates, so the range must be checked explicitly.
+    const LIMIT: f64 = 9223372036854775808.0;
+    if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+// The Lua `<<` operator, a negative shift is a logical right shift.
+fn shift_left(a: i64, shift: i64) -> i64 {
+    if shift <= -64 || shift >= 64 {
+        0
+    } else if shift >= 0 {
+        ((a as u64) << shift) as i64
+    } else {
+        ((a as u64) >> -shift) as i64
+    }
+}
+
+// Strips the characters considered whitespace by C `isspace` from both ends of a string.
+pub(crate) fn trim_whitespace(s: &[u8]) -> &[u8] {
+    let is_space = |c: &u8| matches!(c, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r');
+    let start = s.iter().position(|c| !is_space(c)).unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|c| !is_space(c))
+        .map_or(start, |i| i + 1);
+    &s[start..end]
+}
+
 impl<S: AsRef<[u8]>> PartialEq for Constant<S> {
     fn eq(&self, other: &Self) -> bool {
         self.is_equal(other)
@@ -295,3 +360,90 @@ impl<S: AsRef<[u8]>> Hash for IdenticalConstant<S> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Constant<&str> {
+        Constant::String(s)
+    }
+
+    #[test]
+    fn test_to_numeric() {
+        assert!(matches!(
+            string("0x10").to_numeric(),
+            Some(Constant::Integer(16))
+        ));
+        assert!(matches!(
+            string("-0x10").to_numeric(),
+            Some(Constant::Integer(-16))
+        ));
+        assert!(matches!(string("  3.5  ").to_numeric(), Some(Constant::Number(n)) if n == 3.5));
+        assert!(matches!(
+            string("\t42\n").to_numeric(),
+            Some(Constant::Integer(42))
+        ));
+        assert!(matches!(string("1e2").to_numeric(), Some(Constant::Number(n)) if n == 100.0));
+        assert!(matches!(
+            string("9223372036854775808").to_numeric(),
+            Some(Constant::Number(n)) if n == 9223372036854775808.0
+        ));
+
+        for s in ["", "   ", "abc", "1a", "0x", "-", "3 5", "inf", "nan", "1e"] {
+            assert!(
+                string(s).to_numeric().is_none(),
+                "{:?} should not coerce",
+                s
+            );
+        }
+
+        assert!(Constant::<&str>::Nil.to_numeric().is_none());
+        assert!(Constant::<&str>::Boolean(true).to_numeric().is_none());
+    }
+
+    #[test]
+    fn test_to_integer() {
+        assert_eq!(string("0x10").to_integer(), Some(16));
+        assert_eq!(string("  3.0  ").to_integer(), Some(3));
+        assert_eq!(string("  3.5  ").to_integer(), None);
+        assert_eq!(string("abc").to_integer(), None);
+        assert_eq!(string("").to_integer(), None);
+        assert_eq!(Constant::<&str>::Number(-2.0).to_integer(), Some(-2));
+        assert_eq!(Constant::<&str>::Number(0.5).to_integer(), None);
+    }
+
+    #[test]
+    fn test_to_number() {
+        assert_eq!(string("0x10").to_number(), Some(16.0));
+        assert_eq!(string("  3.5  ").to_number(), Some(3.5));
+        assert_eq!(string("3.5x").to_number(), None);
+        assert_eq!(Constant::<&str>::Integer(7).to_number(), Some(7.0));
+    }
+
+    #[test]
+    fn test_arithmetic_coercion() {
+        assert!(matches!(
+            string("10").add(&Constant::Integer(1)),
+            Some(Constant::Integer(11))
+        ));
+        assert!(matches!(
+            string(" 0x10 ").multiply(&string("2")),
+            Some(Constant::Integer(32))
+        ));
+        assert!(matches!(string("2").negate(), Some(Constant::Integer(-2))));
+        assert!(matches!(
+            string("1.5").subtract(&Constant::Integer(1)),
+            Some(Constant::Number(n)) if n == 0.5
+        ));
+        assert!(string("abc").add(&Constant::Integer(1)).is_none());
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert!(!Constant::<&str>::Nil.to_bool());
+        assert!(!Constant::<&str>::Boolean(false).to_bool());
+        assert!(Constant::<&str>::Integer(0).to_bool());
+        assert!(string("").to_bool());
+    }
+}
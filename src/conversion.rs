@@ -1,7 +1,8 @@
 use std::{array, iter, ops, string::String as StdString, vec};
 
 use crate::{
-    Callback, Closure, Context, Function, String, Table, Thread, TypeError, UserData, Value,
+    Callback, Closure, Context, Function, LightUserData, String, Table, Thread, TypeError,
+    UserData, Value,
 };
 
 pub trait IntoValue<'gc> {
@@ -31,6 +32,7 @@ impl_into!(
     Thread<'gc>,
     Value<'gc>,
     UserData<'gc>,
+    LightUserData,
 );
 
 macro_rules! impl_int_into {
@@ -82,6 +84,7 @@ impl_copy_into!(
     Thread<'gc>,
     Value<'gc>,
     UserData<'gc>,
+    LightUserData,
 );
 
 impl<'gc> IntoValue<'gc> for &'static str {
@@ -176,13 +179,12 @@ impl<'gc, T: FromValue<'gc>> FromValue<'gc> for Option<T> {
 impl<'gc, T: FromValue<'gc>> FromValue<'gc> for Vec<T> {
     fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
         if let Value::Table(table) = value {
-            (1..=table.length())
-                .into_iter()
+            (1..=table.len())
                 .map(|i| T::from_value(ctx, table.get(ctx, i)))
                 .collect()
         } else {
             Err(TypeError {
-                expected: "sequence",
+                expected: "table",
                 found: value.type_name(),
             })
         }
@@ -202,7 +204,7 @@ impl<'gc, T: FromValue<'gc>, const N: usize> FromValue<'gc> for [T; N] {
             Ok(res.map(|r| r.unwrap()))
         } else {
             Err(TypeError {
-                expected: "sequence",
+                expected: "table",
                 found: value.type_name(),
             })
         }
@@ -222,13 +224,13 @@ macro_rules! impl_int_from {
                             Ok(i)
                         } else {
                             Err(TypeError {
-                                expected: stringify!($i),
+                                expected: "integer",
                                 found: "integer out of range",
                             })
                         }
                     } else {
                         Err(TypeError {
-                            expected: stringify!($i),
+                            expected: "integer",
                             found: value.type_name(),
                         })
                     }
@@ -251,7 +253,7 @@ macro_rules! impl_float_from {
                         Ok(n as $f)
                     } else {
                         Err(TypeError {
-                            expected: stringify!($f),
+                            expected: "number",
                             found: value.type_name(),
                         })
                     }
@@ -263,7 +265,7 @@ macro_rules! impl_float_from {
 impl_float_from!(f32, f64);
 
 macro_rules! impl_from {
-    ($([$e:ident $t:ty]),* $(,)?) => {
+    ($([$e:ident $t:ty, $n:literal]),* $(,)?) => {
         $(
             impl<'gc> FromValue<'gc> for $t {
                 fn from_value(
@@ -274,7 +276,7 @@ macro_rules! impl_from {
                         Value::$e(a) => Ok(a),
                         _ => {
                             Err(TypeError {
-                                expected: stringify!($e),
+                                expected: $n,
                                 found: value.type_name(),
                             })
                         }
@@ -285,12 +287,13 @@ macro_rules! impl_from {
     };
 }
 impl_from! {
-    [Boolean bool],
-    [String String<'gc>],
-    [Table Table<'gc>],
-    [Function Function<'gc>],
-    [Thread Thread<'gc>],
-    [UserData UserData<'gc>],
+    [Boolean bool, "boolean"],
+    [String String<'gc>, "string"],
+    [Table Table<'gc>, "table"],
+    [Function Function<'gc>, "function"],
+    [Thread Thread<'gc>, "thread"],
+    [UserData UserData<'gc>, "userdata"],
+    [LightUserData LightUserData, "userdata"],
 }
 
 impl<'gc> FromValue<'gc> for Closure<'gc> {
@@ -329,15 +332,15 @@ impl<'gc> FromValue<'gc> for StdString {
     fn from_value(_: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
         let Value::String(str) = value else {
             return Err(TypeError {
-                expected: "String",
+                expected: "string",
                 found: value.type_name(),
             });
         };
 
         let Ok(str) = str.to_str() else {
             return Err(TypeError {
-                expected: "UTF-8 String",
-                found: "non-UTF-8 String",
+                expected: "UTF-8 string",
+                found: "non-UTF-8 string",
             });
         };
 
@@ -0,0 +1,782 @@
+//! A binary format for compiled function prototypes, used by `string.dump` and for loading binary
+//! chunks.
+//!
+//! The format is specific to piccolo and is versioned: every dump starts with [`SIGNATURE`]
+//! followed by [`FORMAT_VERSION`], and chunks with any other version are rejected rather than
+//! misparsed. Multi-byte values are stored little-endian.
+//!
+//! Loaded chunks are checked for structural consistency: every register, constant, upvalue,
+//! prototype and jump target referenced by an opcode must exist, and variable results must be
+//! consumed by the following opcode. This is enough to keep malformed bytecode from accessing
+//! outside of its own stack frame, but the meaning of the bytecode is not otherwise verified, so
+//! only load binary chunks from trusted sources.
+
+use allocator_api2::vec;
+use gc_arena::{allocator_api::MetricsAlloc, Gc};
+use thiserror::Error;
+
+use crate::{
+    compiler::{FunctionRef, LineNumber, LocalVariable},
+    opcode::{OpCode, Operation, RCIndex},
+    types::{
+        ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor,
+        UpValueIndex, VarCount,
+    },
+    Constant, Context, FunctionPrototype, String,
+};
+
+/// The bytes that every binary chunk starts with.
+///
+/// The first byte is the same escape character that starts PUC-Rio Lua binary chunks, so a chunk
+/// can be identified as binary by its first byte alone.
+pub const SIGNATURE: &[u8] = b"\x1bLua\x00piccolo";
+
+/// The version of the binary format, incremented whenever the format or the meaning of any opcode
+/// changes.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Returns true if `chunk` looks like a binary chunk rather than source text.
+pub fn is_binary_chunk(chunk: &[u8]) -> bool {
+    chunk.first() == SIGNATURE.first()
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum UndumpError {
+    #[error("not a precompiled chunk")]
+    BadSignature,
+    #[error("precompiled chunk has format version {found}, expected version {expected}")]
+    VersionMismatch { found: u8, expected: u8 },
+    #[error("truncated precompiled chunk")]
+    Truncated,
+    #[error("malformed precompiled chunk: {0}")]
+    Malformed(&'static str),
+}
+
+impl<'gc> FunctionPrototype<'gc> {
+    /// Serialize this prototype and all of its nested prototypes as a binary chunk.
+    ///
+    /// If `strip` is true, debug information (line numbers, local variable and upvalue names, and
+    /// the chunk name) is omitted.
+    pub fn dump(&self, strip: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(SIGNATURE);
+        out.push(FORMAT_VERSION);
+        if strip {
+            write_bytes(&mut out, b"=?");
+        } else {
+            write_bytes(&mut out, self.chunk_name.as_bytes());
+        }
+        write_prototype(&mut out, self, strip);
+        out
+    }
+
+    /// Deserialize a prototype from a binary chunk created by [`FunctionPrototype::dump`].
+    pub fn undump(ctx: Context<'gc>, chunk: &[u8]) -> Result<Self, UndumpError> {
+        let mut reader = Reader { bytes: chunk };
+        if reader.take(SIGNATURE.len())? != SIGNATURE {
+            return Err(UndumpError::BadSignature);
+        }
+        let version = reader.byte()?;
+        if version != FORMAT_VERSION {
+            return Err(UndumpError::VersionMismatch {
+                found: version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        let chunk_name = reader.string(ctx)?;
+        let proto = read_prototype(ctx, &mut reader, chunk_name, None)?;
+        if !reader.bytes.is_empty() {
+            return Err(UndumpError::Malformed("trailing data"));
+        }
+        Ok(proto)
+    }
+}
+
+fn write_prototype(out: &mut Vec<u8>, proto: &FunctionPrototype<'_>, strip: bool) {
+    match proto.reference {
+        FunctionRef::Chunk => out.push(0),
+        FunctionRef::Named(name, line) => {
+            out.push(1);
+            write_bytes(out, name.as_bytes());
+            line.0.write(out);
+        }
+        FunctionRef::Expression(line) => {
+            out.push(2);
+            line.0.write(out);
+        }
+    }
+    proto.fixed_params.write(out);
+    proto.has_varargs.write(out);
+    proto.stack_size.write(out);
+
+    write_len(out, proto.constants.len());
+    for constant in proto.constants.iter() {
+        match constant {
+            Constant::Nil => out.push(0),
+            Constant::Boolean(b) => {
+                out.push(1);
+                b.write(out);
+            }
+            Constant::Integer(i) => {
+                out.push(2);
+                out.extend(i.to_le_bytes());
+            }
+            Constant::Number(n) => {
+                out.push(3);
+                out.extend(n.to_bits().to_le_bytes());
+            }
+            Constant::String(s) => {
+                out.push(4);
+                write_bytes(out, s.as_bytes());
+            }
+        }
+    }
+
+    write_len(out, proto.upvalues.len());
+    for &upvalue in proto.upvalues.iter() {
+        match upvalue {
+            UpValueDescriptor::Environment => out.push(0),
+            UpValueDescriptor::ParentLocal(register) => {
+                out.push(1);
+                register.write(out);
+            }
+            UpValueDescriptor::Outer(index) => {
+                out.push(2);
+                index.write(out);
+            }
+        }
+    }
+
+    write_len(out, proto.prototypes.len());
+    for nested in proto.prototypes.iter() {
+        write_prototype(out, nested, strip);
+    }
+
+    write_len(out, proto.opcodes.len());
+    for opcode in proto.opcodes.iter() {
+        write_operation(out, opcode.decode());
+    }
+
+    if strip {
+        write_len(out, 0);
+        write_len(out, 0);
+        write_len(out, 0);
+    } else {
+        write_len(out, proto.upvalue_names.len());
+        for name in proto.upvalue_names.iter() {
+            write_bytes(out, name.as_bytes());
+        }
+
+        write_len(out, proto.opcode_line_numbers.len());
+        for &(pc, line) in proto.opcode_line_numbers.iter() {
+            (pc as u64).write(out);
+            line.0.write(out);
+        }
+
+        write_len(out, proto.local_variables.len());
+        for local in proto.local_variables.iter() {
+            write_bytes(out, local.name.as_bytes());
+            local.register.write(out);
+            (local.start_pc as u64).write(out);
+            (local.end_pc as u64).write(out);
+        }
+    }
+}
+
+// The registers and upvalues of the function enclosing a nested prototype, which the upvalue
+// descriptors of the nested prototype refer to.
+#[derive(Copy, Clone)]
+struct Enclosing {
+    stack_size: usize,
+    upvalue_count: usize,
+}
+
+fn read_prototype<'gc>(
+    ctx: Context<'gc>,
+    reader: &mut Reader<'_>,
+    chunk_name: String<'gc>,
+    enclosing: Option<Enclosing>,
+) -> Result<FunctionPrototype<'gc>, UndumpError> {
+    let alloc = MetricsAlloc::new(&ctx);
+
+    let reference = match reader.byte()? {
+        0 => FunctionRef::Chunk,
+        1 => FunctionRef::Named(reader.string(ctx)?, LineNumber(reader.read()?)),
+        2 => FunctionRef::Expression(LineNumber(reader.read()?)),
+        _ => return Err(UndumpError::Malformed("bad function reference")),
+    };
+    let fixed_params = reader.read()?;
+    let has_varargs = reader.read()?;
+    let stack_size: u16 = reader.read()?;
+
+    let len = reader.len()?;
+    let mut constants = vec::Vec::new_in(alloc.clone());
+    for _ in 0..len {
+        constants.push(match reader.byte()? {
+            0 => Constant::Nil,
+            1 => Constant::Boolean(reader.read()?),
+            2 => Constant::Integer(i64::from_le_bytes(reader.array()?)),
+            3 => Constant::Number(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            4 => Constant::String(reader.string(ctx)?),
+            _ => return Err(UndumpError::Malformed("bad constant")),
+        });
+    }
+
+    let len = reader.len()?;
+    let mut upvalues = vec::Vec::new_in(alloc.clone());
+    for _ in 0..len {
+        let descriptor = match reader.byte()? {
+            0 => UpValueDescriptor::Environment,
+            1 => UpValueDescriptor::ParentLocal(reader.read()?),
+            2 => UpValueDescriptor::Outer(reader.read()?),
+            _ => return Err(UndumpError::Malformed("bad upvalue descriptor")),
+        };
+        // The upvalues of a top-level function are initialized by the loader rather than
+        // captured, so only nested prototypes have descriptors to check.
+        if let Some(enclosing) = enclosing {
+            match descriptor {
+                UpValueDescriptor::ParentLocal(RegisterIndex(r))
+                    if usize::from(r) >= enclosing.stack_size =>
+                {
+                    return Err(UndumpError::Malformed("register out of range"));
+                }
+                UpValueDescriptor::Outer(UpValueIndex(i))
+                    if usize::from(i) >= enclosing.upvalue_count =>
+                {
+                    return Err(UndumpError::Malformed("upvalue index out of range"));
+                }
+                _ => {}
+            }
+        }
+        upvalues.push(descriptor);
+    }
+    let this = Enclosing {
+        stack_size: stack_size.into(),
+        upvalue_count: upvalues.len(),
+    };
+
+    let len = reader.len()?;
+    let mut prototypes = vec::Vec::new_in(alloc.clone());
+    for _ in 0..len {
+        prototypes.push(Gc::new(
+            &ctx,
+            read_prototype(ctx, reader, chunk_name, Some(this))?,
+        ));
+    }
+
+    let len = reader.len()?;
+    let mut opcodes = vec::Vec::new_in(alloc.clone());
+    let mut variable_start = None;
+    for _ in 0..len {
+        let operation = read_operation(reader)?;
+        check_operation(
+            operation,
+            opcodes.len(),
+            len,
+            constants.len(),
+            upvalues.len(),
+            prototypes.len(),
+            this.stack_size,
+        )?;
+        if let Some(start) = variable_start {
+            check_variable_consumer(operation, start)?;
+        }
+        variable_start = variable_results(operation);
+        opcodes.push(OpCode::encode(operation));
+    }
+    if !matches!(
+        opcodes.last().map(|op| op.decode()),
+        Some(Operation::Return { .. })
+    ) {
+        return Err(UndumpError::Malformed(
+            "function does not end with a return",
+        ));
+    }
+
+    let len = reader.len()?;
+    let mut upvalue_names = vec::Vec::new_in(alloc.clone());
+    for _ in 0..len {
+        upvalue_names.push(reader.string(ctx)?);
+    }
+    // Stripped chunks have no upvalue names, but they are expected to exist for every upvalue.
+    if upvalue_names.len() > upvalues.len() {
+        return Err(UndumpError::Malformed("too many upvalue names"));
+    }
+    while upvalue_names.len() < upvalues.len() {
+        upvalue_names.push(ctx.intern_static(b"(no name)"));
+    }
+
+    let len = reader.len()?;
+    let mut opcode_line_numbers = vec::Vec::new_in(alloc.clone());
+    for _ in 0..len {
+        opcode_line_numbers.push((reader.index()?, LineNumber(reader.read()?)));
+    }
+    if opcode_line_numbers.windows(2).any(|w| w[0].0 >= w[1].0) {
+        return Err(UndumpError::Malformed("unsorted line numbers"));
+    }
+
+    let len = reader.len()?;
+    let mut local_variables = vec::Vec::new_in(alloc);
+    for _ in 0..len {
+        let local = LocalVariable {
+            name: reader.string(ctx)?,
+            register: reader.read()?,
+            start_pc: reader.index()?,
+            end_pc: reader.index()?,
+        };
+        if usize::from(local.register.0) >= this.stack_size {
+            return Err(UndumpError::Malformed("register out of range"));
+        }
+        local_variables.push(local);
+    }
+
+    Ok(FunctionPrototype {
+        chunk_name,
+        reference,
+        fixed_params,
+        has_varargs,
+        stack_size,
+        inline_cache: FunctionPrototype::new_inline_cache(&ctx, opcodes.len()),
+        constants: constants.into_boxed_slice(),
+        opcodes: opcodes.into_boxed_slice(),
+        opcode_line_numbers: opcode_line_numbers.into_boxed_slice(),
+        upvalues: upvalues.into_boxed_slice(),
+        upvalue_names: upvalue_names.into_boxed_slice(),
+        local_variables: local_variables.into_boxed_slice(),
+        prototypes: prototypes.into_boxed_slice(),
+    })
+}
+
+// Check that every register, constant, upvalue, and prototype used by the operation at `pc`
+// exists, and that any jump lands inside the function.
+//
+// Operations with a variable count only use the registers up to their first variable one here, the
+// rest of the stack is checked by `check_variable_consumer`.
+fn check_operation(
+    operation: Operation,
+    pc: usize,
+    opcode_count: usize,
+    constant_count: usize,
+    upvalue_count: usize,
+    prototype_count: usize,
+    stack_size: usize,
+) -> Result<(), UndumpError> {
+    // Check that the `count` registers starting at `start` are all inside the stack frame.
+    let registers = |RegisterIndex(start), count: usize| {
+        if usize::from(start) + count <= stack_size {
+            Ok(())
+        } else {
+            Err(UndumpError::Malformed("register out of range"))
+        }
+    };
+    let register = |index| registers(index, 1);
+    let constant_count_of = |count: VarCount| count.to_constant().map_or(0, usize::from);
+    let constant = |index: usize| {
+        if index < constant_count {
+            Ok(())
+        } else {
+            Err(UndumpError::Malformed("constant index out of range"))
+        }
+    };
+    let rc = |index: RCIndex| match index {
+        RCIndex::Register(index) => register(index),
+        RCIndex::Constant(ConstantIndex8(index)) => constant(index.into()),
+    };
+    let upvalue = |UpValueIndex(index)| {
+        if usize::from(index) < upvalue_count {
+            Ok(())
+        } else {
+            Err(UndumpError::Malformed("upvalue index out of range"))
+        }
+    };
+    // Jumps are relative to the following opcode.
+    let jump = |offset: i16, extra: isize| {
+        let target = pc as isize + 1 + isize::from(offset) + extra;
+        if target >= 0 && (target as usize) < opcode_count {
+            Ok(())
+        } else {
+            Err(UndumpError::Malformed("jump target out of range"))
+        }
+    };
+
+    match operation {
+        Operation::Move { dest, source }
+        | Operation::Length { dest, source }
+        | Operation::Not { dest, source }
+        | Operation::Minus { dest, source }
+        | Operation::BitNot { dest, source } => register(dest).and(register(source)),
+        Operation::LoadConstant {
+            dest,
+            constant: ConstantIndex16(index),
+        } => register(dest).and(constant(index.into())),
+        Operation::LoadBool { dest, .. } | Operation::NewTable { dest, .. } => register(dest),
+        Operation::LoadNil { dest, count } => registers(dest, count.into()),
+        Operation::GetTable { dest, table, key } => {
+            register(dest).and(register(table)).and(rc(key))
+        }
+        Operation::SetTable { table, key, value } => register(table).and(rc(key)).and(rc(value)),
+        Operation::GetUpTable { dest, table, key } => {
+            register(dest).and(upvalue(table)).and(rc(key))
+        }
+        Operation::SetUpTable { table, key, value } => upvalue(table).and(rc(key)).and(rc(value)),
+        Operation::SetList { base, count } => registers(base, 2 + constant_count_of(count)),
+        Operation::Call {
+            func,
+            args,
+            returns,
+        } => registers(func, 1 + constant_count_of(args))
+            .and(registers(func, constant_count_of(returns))),
+        Operation::TailCall { func, args } => registers(func, 1 + constant_count_of(args)),
+        Operation::Return { start, count } | Operation::VarArgs { dest: start, count } => {
+            registers(start, constant_count_of(count))
+        }
+        Operation::ToBeClosed { value } | Operation::Test { value, .. } => register(value),
+        Operation::TestSet { dest, value, .. } => register(dest).and(register(value)),
+        Operation::GetUpValue { dest, source } => register(dest).and(upvalue(source)),
+        Operation::SetUpValue { dest, source } => upvalue(dest).and(register(source)),
+        Operation::Closure {
+            dest,
+            proto: PrototypeIndex(index),
+        } => {
+            if usize::from(index) < prototype_count {
+                register(dest)
+            } else {
+                Err(UndumpError::Malformed("prototype index out of range"))
+            }
+        }
+        Operation::Jump {
+            offset,
+            close_upvalues,
+        } => match close_upvalues.to_u8() {
+            Some(r) => registers(RegisterIndex(r), 0),
+            None => Ok(()),
+        }
+        .and(jump(offset, 0)),
+        Operation::NumericForPrep { base, jump: offset } => registers(base, 4).and(jump(offset, 1)),
+        Operation::NumericForLoop { base, jump: offset } => registers(base, 4).and(jump(offset, 0)),
+        Operation::GenericForCall { base, var_count } => {
+            registers(base, 3 + usize::from(var_count))
+        }
+        Operation::GenericForLoop { base, jump: offset } => registers(base, 2).and(jump(offset, 0)),
+        Operation::Method { base, table, key } => {
+            registers(base, 2).and(register(table)).and(rc(key))
+        }
+        Operation::Concat {
+            dest,
+            source,
+            count,
+        } => register(dest).and(registers(source, count.into())),
+        Operation::Eq { left, right, .. }
+        | Operation::Less { left, right, .. }
+        | Operation::LessEq { left, right, .. } => rc(left).and(rc(right)),
+        Operation::Add { dest, left, right }
+        | Operation::Sub { dest, left, right }
+        | Operation::Mul { dest, left, right }
+        | Operation::Div { dest, left, right }
+        | Operation::IDiv { dest, left, right }
+        | Operation::Mod { dest, left, right }
+        | Operation::Pow { dest, left, right }
+        | Operation::BitAnd { dest, left, right }
+        | Operation::BitOr { dest, left, right }
+        | Operation::BitXor { dest, left, right }
+        | Operation::ShiftLeft { dest, left, right }
+        | Operation::ShiftRight { dest, left, right } => {
+            register(dest).and(rc(left)).and(rc(right))
+        }
+    }
+}
+
+// If the operation leaves a variable number of results on top of the stack, returns the register
+// that the results start at.
+fn variable_results(operation: Operation) -> Option<u8> {
+    match operation {
+        Operation::Call { func, returns, .. } if returns.is_variable() => Some(func.0),
+        Operation::VarArgs { dest, count } if count.is_variable() => Some(dest.0),
+        _ => None,
+    }
+}
+
+// Check that the operation following one with variable results consumes all of them, starting at or
+// below the register `start` where the results begin.
+//
+// While the stack is variable the registers above the results do not exist, so executing anything
+// else would access them.
+fn check_variable_consumer(operation: Operation, start: u8) -> Result<(), UndumpError> {
+    let consumed = match operation {
+        Operation::Call { func, args, .. } | Operation::TailCall { func, args } => {
+            args.is_variable() && func.0 < start
+        }
+        Operation::Return {
+            start: first,
+            count,
+        } => count.is_variable() && first.0 <= start,
+        Operation::SetList { base, count } => {
+            count.is_variable() && usize::from(base.0) + 2 <= usize::from(start)
+        }
+        _ => false,
+    };
+    if consumed {
+        Ok(())
+    } else {
+        Err(UndumpError::Malformed("variable results are not consumed"))
+    }
+}
+
+// Defines `write_operation` and `read_operation`, giving each operation variant a stable tag.
+//
+// Tags must never be reused or reordered without incrementing `FORMAT_VERSION`.
+macro_rules! operations {
+    ($($tag:literal => $variant:ident { $($field:ident),* $(,)? }),* $(,)?) => {
+        fn write_operation(out: &mut Vec<u8>, operation: Operation) {
+            match operation {
+                $(Operation::$variant { $($field),* } => {
+                    out.push($tag);
+                    $($field.write(out);)*
+                })*
+            }
+        }
+
+        fn read_operation(reader: &mut Reader<'_>) -> Result<Operation, UndumpError> {
+            Ok(match reader.byte()? {
+                $($tag => Operation::$variant { $($field: reader.read()?),* },)*
+                _ => return Err(UndumpError::Malformed("unknown opcode")),
+            })
+        }
+    };
+}
+
+operations! {
+    0 => Move { dest, source },
+    1 => LoadConstant { dest, constant },
+    2 => LoadBool { dest, value, skip_next },
+    3 => LoadNil { dest, count },
+    4 => NewTable { dest, array_size, map_size },
+    5 => GetTable { dest, table, key },
+    6 => SetTable { table, key, value },
+    7 => GetUpTable { dest, table, key },
+    8 => SetUpTable { table, key, value },
+    9 => SetList { base, count },
+    10 => Call { func, args, returns },
+    11 => TailCall { func, args },
+    12 => Return { start, count },
+    13 => VarArgs { dest, count },
+    14 => ToBeClosed { value },
+    15 => Jump { offset, close_upvalues },
+    16 => Test { value, is_true },
+    17 => TestSet { dest, value, is_true },
+    18 => Closure { dest, proto },
+    19 => NumericForPrep { base, jump },
+    20 => NumericForLoop { base, jump },
+    21 => GenericForCall { base, var_count },
+    22 => GenericForLoop { base, jump },
+    23 => Method { base, table, key },
+    24 => Concat { dest, source, count },
+    25 => GetUpValue { dest, source },
+    26 => SetUpValue { dest, source },
+    27 => Length { dest, source },
+    28 => Eq { skip_if, left, right },
+    29 => Less { skip_if, left, right },
+    30 => LessEq { skip_if, left, right },
+    31 => Not { dest, source },
+    32 => Minus { dest, source },
+    33 => Add { dest, left, right },
+    34 => Sub { dest, left, right },
+    35 => Mul { dest, left, right },
+    36 => Div { dest, left, right },
+    37 => IDiv { dest, left, right },
+    38 => Mod { dest, left, right },
+    39 => Pow { dest, left, right },
+    40 => BitAnd { dest, left, right },
+    41 => BitOr { dest, left, right },
+    42 => BitXor { dest, left, right },
+    43 => ShiftLeft { dest, left, right },
+    44 => ShiftRight { dest, left, right },
+    45 => BitNot { dest, source },
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    (len as u64).write(out);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len());
+    out.extend(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], UndumpError> {
+        if len > self.bytes.len() {
+            return Err(UndumpError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], UndumpError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, UndumpError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read<T: Encode>(&mut self) -> Result<T, UndumpError> {
+        T::read(self)
+    }
+
+    fn len(&mut self) -> Result<usize, UndumpError> {
+        let len: u64 = self.read()?;
+        // Every element takes at least one byte, so this also rejects lengths which would cause
+        // absurd allocations.
+        match usize::try_from(len) {
+            Ok(len) if len <= self.bytes.len() => Ok(len),
+            _ => Err(UndumpError::Truncated),
+        }
+    }
+
+    fn index(&mut self) -> Result<usize, UndumpError> {
+        let index: u64 = self.read()?;
+        usize::try_from(index).map_err(|_| UndumpError::Malformed("index out of range"))
+    }
+
+    fn string<'gc>(&mut self, ctx: Context<'gc>) -> Result<String<'gc>, UndumpError> {
+        let len = self.len()?;
+        Ok(ctx.intern(self.take(len)?))
+    }
+}
+
+// A value with a fixed binary encoding.
+trait Encode: Sized {
+    fn write(&self, out: &mut Vec<u8>);
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError>;
+}
+
+impl Encode for u8 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        reader.byte()
+    }
+}
+
+impl Encode for bool {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        match reader.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(UndumpError::Malformed("bad boolean")),
+        }
+    }
+}
+
+impl Encode for u16 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        Ok(u16::from_le_bytes(reader.array()?))
+    }
+}
+
+impl Encode for i16 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        Ok(i16::from_le_bytes(reader.array()?))
+    }
+}
+
+impl Encode for u64 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        Ok(u64::from_le_bytes(reader.array()?))
+    }
+}
+
+macro_rules! encode_newtype {
+    ($($ty:ident($inner:ty)),* $(,)?) => {
+        $(impl Encode for $ty {
+            fn write(&self, out: &mut Vec<u8>) {
+                self.0.write(out);
+            }
+
+            fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+                Ok($ty(<$inner>::read(reader)?))
+            }
+        })*
+    };
+}
+
+encode_newtype! {
+    RegisterIndex(u8),
+    ConstantIndex8(u8),
+    ConstantIndex16(u16),
+    UpValueIndex(u8),
+    PrototypeIndex(u8),
+}
+
+impl Encode for Opt254 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(self.to_u8().unwrap_or(255));
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        Ok(match reader.byte()? {
+            255 => Opt254::none(),
+            v => Opt254::some(v),
+        })
+    }
+}
+
+impl Encode for VarCount {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(self.to_constant().unwrap_or(255));
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        Ok(match reader.byte()? {
+            255 => VarCount::variable(),
+            v => VarCount::constant(v),
+        })
+    }
+}
+
+impl Encode for RCIndex {
+    fn write(&self, out: &mut Vec<u8>) {
+        match *self {
+            RCIndex::Register(register) => {
+                out.push(0);
+                register.write(out);
+            }
+            RCIndex::Constant(constant) => {
+                out.push(1);
+                constant.write(out);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, UndumpError> {
+        match reader.byte()? {
+            0 => Ok(RCIndex::Register(reader.read()?)),
+            1 => Ok(RCIndex::Constant(reader.read()?)),
+            _ => Err(UndumpError::Malformed("bad operand")),
+        }
+    }
+}
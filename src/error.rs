@@ -3,7 +3,10 @@ use std::{error::Error as StdError, fmt, string::String as StdString, sync::Arc}
 use gc_arena::{Collect, Rootable};
 use thiserror::Error;
 
-use crate::{Callback, CallbackReturn, Context, MetaMethod, Singleton, Table, UserData, Value};
+use crate::{
+    compiler::LineNumber, Callback, CallbackReturn, Context, MetaMethod, Singleton, Table,
+    UserData, Value,
+};
 
 #[derive(Debug, Clone, Copy, Error)]
 #[error("type error, expected {expected}, found {found}")]
@@ -12,6 +15,18 @@ pub struct TypeError {
     pub found: &'static str,
 }
 
+/// A callback argument had the wrong type.
+///
+/// `index` is the 1-based position of the argument, the same as it would be reported by PUC-Rio
+/// Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("bad argument #{index} ({expected} expected, got {found})")]
+pub struct BadArgument {
+    pub index: usize,
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct LuaError<'gc>(pub Value<'gc>);
@@ -35,12 +50,41 @@ impl<'gc> LuaError<'gc> {
 }
 
 #[derive(Debug, Clone, Error)]
-#[error("{0}")]
-pub struct StaticLuaError(StdString);
+pub struct StaticLuaError {
+    message: StdString,
+    traceback: Option<StdString>,
+}
+
+impl fmt::Display for StaticLuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{traceback}")?;
+        }
+        Ok(())
+    }
+}
 
 impl<'gc> From<LuaError<'gc>> for StaticLuaError {
     fn from(error: LuaError<'gc>) -> Self {
-        Self(error.to_string())
+        Self {
+            message: error.to_string(),
+            traceback: None,
+        }
+    }
+}
+
+impl StaticLuaError {
+    /// Attach a traceback of the point where the error was raised.
+    pub fn with_traceback(self, traceback: StdString) -> Self {
+        Self {
+            traceback: Some(traceback),
+            ..self
+        }
+    }
+
+    pub fn traceback(&self) -> Option<&str> {
+        self.traceback.as_deref()
     }
 }
 
@@ -78,6 +122,66 @@ impl RuntimeError {
     {
         self.0.downcast_ref::<E>()
     }
+
+    /// Take the wrapped error if it is of type `E` and this is the only reference to it, otherwise
+    /// returns `self` unchanged.
+    pub fn into_downcast<E>(self) -> Result<E, Self>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        if !self.is::<E>() {
+            return Err(self);
+        }
+        match Arc::try_unwrap(self.0) {
+            Ok(err) => err.downcast::<E>().map_err(|err| Self(Arc::new(err))),
+            Err(err) => Err(Self(err)),
+        }
+    }
+
+    /// Prefix the error message with a source location, in the form `chunk_name:line: message`.
+    ///
+    /// The original error is still available through `RuntimeError::downcast`.
+    pub fn with_location(self, chunk_name: &str, line: LineNumber) -> Self {
+        match Arc::try_unwrap(self.0) {
+            Ok(err) => {
+                let message = format!("{chunk_name}:{line}: {err}");
+                Self(Arc::new(err.context(message)))
+            }
+            Err(err) => Self(err),
+        }
+    }
+
+    /// Attach a traceback of the point where the error was raised. The traceback is appended to
+    /// the error message, the same as the standalone PUC-Rio Lua interpreter does.
+    ///
+    /// The original error is still available through `RuntimeError::downcast`.
+    pub fn with_traceback(self, traceback: StdString) -> Self {
+        match Arc::try_unwrap(self.0) {
+            Ok(err) => {
+                let message = format!("{err}\n{traceback}");
+                Self(Arc::new(err.context(ErrorTraceback { message, traceback })))
+            }
+            Err(err) => Self(err),
+        }
+    }
+
+    pub fn traceback(&self) -> Option<&str> {
+        self.0
+            .downcast_ref::<ErrorTraceback>()
+            .map(|t| t.traceback.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct ErrorTraceback {
+    message: StdString,
+    traceback: StdString,
+}
+
+impl fmt::Display for ErrorTraceback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
 impl AsRef<dyn StdError + 'static> for RuntimeError {
@@ -86,11 +190,53 @@ impl AsRef<dyn StdError + 'static> for RuntimeError {
     }
 }
 
+/// An error wrapped with a descriptive Lua value, created by `Error::with_context`.
+#[derive(Debug, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ContextError<'gc> {
+    pub context: Value<'gc>,
+    pub cause: Box<Error<'gc>>,
+}
+
+impl<'gc> fmt::Display for ContextError<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.cause)
+    }
+}
+
+impl<'gc> ContextError<'gc> {
+    pub fn to_static(&self) -> StaticContextError {
+        StaticContextError {
+            context: self.context.to_string(),
+            cause: Box::new(self.cause.to_static()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticContextError {
+    pub context: StdString,
+    pub cause: Box<StaticError>,
+}
+
+impl fmt::Display for StaticContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.cause)
+    }
+}
+
+impl StdError for StaticContextError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.cause)
+    }
+}
+
 #[derive(Debug, Clone, Collect)]
 #[collect(no_drop)]
 pub enum Error<'gc> {
     Lua(LuaError<'gc>),
     Runtime(RuntimeError),
+    Context(ContextError<'gc>),
 }
 
 impl<'gc> fmt::Display for Error<'gc> {
@@ -98,6 +244,7 @@ impl<'gc> fmt::Display for Error<'gc> {
         match self {
             Error::Lua(err) => write!(f, "lua error: {}", err),
             Error::Runtime(err) => write!(f, "runtime error: {}", err),
+            Error::Context(err) => write!(f, "{}", err),
         }
     }
 }
@@ -135,11 +282,101 @@ impl<'gc> Error<'gc> {
             if let Ok(err) = ud.downcast_static::<RuntimeError>() {
                 return Error::Runtime(err.clone());
             }
+            if let Ok(err) = ud.downcast::<Rootable![ContextError<'_>]>() {
+                return Error::Context(err.clone());
+            }
         }
 
         Error::Lua(value.into())
     }
 
+    /// Convert a Rust error into a Lua error whose value is the interned `Display` message of the
+    /// error.
+    ///
+    /// Rust errors can also be converted with `?` through the `From` impl, which keeps the error as
+    /// a `RuntimeError` that can later be downcast. This is instead useful when Lua code should
+    /// see a plain string, for example to compare the message or concatenate it.
+    pub fn from_std(ctx: Context<'gc>, error: impl StdError) -> Self {
+        Error::Lua(LuaError(ctx.intern(error.to_string().as_bytes()).into()))
+    }
+
+    /// Wrap this error with a descriptive value, keeping the original error as the cause.
+    ///
+    /// When converted to a Lua value, the resulting error becomes a userdata whose `__tostring`
+    /// renders the whole chain in the form `context: cause`.
+    pub fn with_context(self, context: impl Into<Value<'gc>>) -> Self {
+        Error::Context(ContextError {
+            context: context.into(),
+            cause: Box::new(self),
+        })
+    }
+
+    /// The error this error was wrapped around with `Error::with_context`, if any.
+    pub fn cause(&self) -> Option<&Error<'gc>> {
+        match self {
+            Error::Context(err) => Some(&err.cause),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this is a `RuntimeError` wrapping a Rust error of type `E`, possibly under
+    /// any number of `Error::with_context` layers.
+    pub fn is<E>(&self) -> bool
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        self.downcast_ref::<E>().is_some()
+    }
+
+    /// Get a reference to the Rust error this error was created from, if it was converted from an
+    /// error of type `E` (for example by `?` in a callback).
+    ///
+    /// Errors keep their type when passing through Lua code, so this also finds an error raised by
+    /// a callback, caught by `pcall` and then raised again. Any `Error::with_context` layers are
+    /// looked through.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            Error::Lua(_) => None,
+            Error::Runtime(err) => err.downcast::<E>(),
+            Error::Context(err) => err.cause.downcast_ref::<E>(),
+        }
+    }
+
+    /// Take the Rust error of type `E` that this error was created from.
+    ///
+    /// Returns the error unchanged if it does not wrap an `E`, or if the underlying `RuntimeError`
+    /// has been cloned and is still shared, in which case `Error::downcast_ref` can be used
+    /// instead.
+    pub fn downcast<E>(self) -> Result<E, Self>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            Error::Runtime(err) => err.into_downcast::<E>().map_err(Error::Runtime),
+            Error::Context(ContextError { context, cause }) => {
+                cause.downcast::<E>().map_err(|cause| {
+                    Error::Context(ContextError {
+                        context,
+                        cause: Box::new(cause),
+                    })
+                })
+            }
+            err => Err(err),
+        }
+    }
+
+    // The error message as it is rendered for Lua code, without the error kind prefix.
+    fn message(&self) -> StdString {
+        match self {
+            Error::Lua(err) => err.to_string(),
+            Error::Runtime(err) => err.to_string(),
+            Error::Context(err) => format!("{}: {}", err.context, err.cause.message()),
+        }
+    }
+
     pub fn to_value(&self, ctx: Context<'gc>) -> Value<'gc> {
         match self {
             Error::Lua(err) => err.0,
@@ -171,6 +408,34 @@ impl<'gc> Error<'gc> {
                 ud.set_metatable(&ctx, Some(ctx.singleton::<Rootable![UDMeta<'_>]>().0));
                 ud.into()
             }
+            Error::Context(err) => {
+                #[derive(Copy, Clone, Collect)]
+                #[collect(no_drop)]
+                struct UDMeta<'gc>(Table<'gc>);
+
+                impl<'gc> Singleton<'gc> for UDMeta<'gc> {
+                    fn create(ctx: Context<'gc>) -> Self {
+                        let table = Table::new(&ctx);
+                        table
+                            .set(
+                                ctx,
+                                MetaMethod::ToString,
+                                Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                                    let ud = stack.consume::<UserData>(ctx)?;
+                                    let error = ud.downcast::<Rootable![ContextError<'_>]>()?;
+                                    stack.replace(ctx, Error::Context(error.clone()).message());
+                                    Ok(CallbackReturn::Return)
+                                }),
+                            )
+                            .unwrap();
+                        Self(table)
+                    }
+                }
+
+                let ud = UserData::new::<Rootable![ContextError<'_>]>(&ctx, err.clone());
+                ud.set_metatable(&ctx, Some(ctx.singleton::<Rootable![UDMeta<'_>]>().0));
+                ud.into()
+            }
         }
     }
 
@@ -187,6 +452,7 @@ impl<'gc> Error<'gc> {
 pub enum StaticError {
     Lua(StaticLuaError),
     Runtime(RuntimeError),
+    Context(StaticContextError),
 }
 
 impl fmt::Display for StaticError {
@@ -194,6 +460,70 @@ impl fmt::Display for StaticError {
         match self {
             StaticError::Lua(err) => write!(f, "lua error: {err}"),
             StaticError::Runtime(err) => write!(f, "runtime error: {err}"),
+            StaticError::Context(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl StaticError {
+    /// Attach a traceback of the point where the error was raised.
+    pub fn with_traceback(self, traceback: StdString) -> Self {
+        match self {
+            StaticError::Lua(err) => StaticError::Lua(err.with_traceback(traceback)),
+            StaticError::Runtime(err) => StaticError::Runtime(err.with_traceback(traceback)),
+            StaticError::Context(err) => StaticError::Context(StaticContextError {
+                cause: Box::new(err.cause.with_traceback(traceback)),
+                ..err
+            }),
+        }
+    }
+
+    /// Returns true if this error wraps a Rust error of type `E`, see `Error::is`.
+    pub fn is<E>(&self) -> bool
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        self.downcast_ref::<E>().is_some()
+    }
+
+    /// Get a reference to the Rust error of type `E` this error was created from, see
+    /// `Error::downcast_ref`.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            StaticError::Lua(_) => None,
+            StaticError::Runtime(err) => err.downcast::<E>(),
+            StaticError::Context(err) => err.cause.downcast_ref::<E>(),
+        }
+    }
+
+    /// Take the Rust error of type `E` this error was created from, see `Error::downcast`.
+    pub fn downcast<E>(self) -> Result<E, Self>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            StaticError::Runtime(err) => err.into_downcast::<E>().map_err(StaticError::Runtime),
+            StaticError::Context(StaticContextError { context, cause }) => {
+                cause.downcast::<E>().map_err(|cause| {
+                    StaticError::Context(StaticContextError {
+                        context,
+                        cause: Box::new(cause),
+                    })
+                })
+            }
+            err => Err(err),
+        }
+    }
+
+    /// The traceback attached to this error, if any.
+    pub fn traceback(&self) -> Option<&str> {
+        match self {
+            StaticError::Lua(err) => err.traceback(),
+            StaticError::Runtime(err) => err.traceback(),
+            StaticError::Context(err) => err.cause.traceback(),
         }
     }
 }
@@ -203,6 +533,7 @@ impl StdError for StaticError {
         match self {
             StaticError::Lua(err) => Some(err),
             StaticError::Runtime(err) => Some(err.as_ref()),
+            StaticError::Context(err) => Some(err),
         }
     }
 }
@@ -224,6 +555,7 @@ impl<'gc> From<Error<'gc>> for StaticError {
         match err {
             Error::Lua(err) => err.to_static().into(),
             Error::Runtime(e) => e.into(),
+            Error::Context(err) => StaticError::Context(err.to_static()),
         }
     }
 }
@@ -1,6 +1,14 @@
 use gc_arena::{lock::RefLock, Collect, Finalization, Gc, GcWeak, Mutation};
 
-use crate::{thread::ThreadInner, Thread};
+use crate::{
+    table::TableInner,
+    thread::{ThreadInner, ThreadStacks},
+    userdata::UserDataInner,
+    Function, Table, Thread, UserData, Value,
+};
+
+// The maximum number of collected threads whose stacks are kept for reuse.
+const THREAD_POOL_SIZE: usize = 32;
 
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -12,20 +20,177 @@ impl<'gc> Finalizers<'gc> {
     }
 
     pub(crate) fn register_thread(&self, mc: &Mutation<'gc>, ptr: Gc<'gc, ThreadInner<'gc>>) {
-        self.0.borrow_mut(mc).threads.push(Gc::downgrade(ptr));
+        let mut state = self.0.borrow_mut(mc);
+        if state.finalized {
+            // Like userdata, threads allocated after finalization are held strongly until the next
+            // finalization.
+            state.young_threads.push(Thread::from_inner(ptr));
+        } else {
+            state.threads.push(Gc::downgrade(ptr));
+        }
+    }
+
+    // Take the stacks of a collected thread for a new thread to use, if there are any.
+    pub(crate) fn take_thread_stacks(&self, mc: &Mutation<'gc>) -> Option<ThreadStacks<'gc>> {
+        if self.0.borrow().thread_pool.is_empty() {
+            return None;
+        }
+        let mut state = self.0.borrow_mut(mc);
+        state.reused_threads += 1;
+        state.thread_pool.pop()
+    }
+
+    /// The number of threads which have been created reusing the stacks of a collected thread.
+    pub fn reused_threads(&self) -> usize {
+        self.0.borrow().reused_threads
     }
 
-    pub(crate) fn finalize(&self, fc: &Finalization<'gc>) {
+    /// Register a userdata to have its `__gc` metamethod called when it becomes unreachable.
+    ///
+    /// The `__gc` metamethod is looked up in the userdata's metatable once it is unreachable, and
+    /// called with the userdata as its only argument. The userdata is then freed normally, it is
+    /// not finalized again even if the finalizer stores it somewhere.
+    pub fn register_userdata(&self, mc: &Mutation<'gc>, userdata: UserData<'gc>) {
+        let mut state = self.0.borrow_mut(mc);
+        if state.finalized {
+            // Objects allocated after finalization in the current cycle may be freed when the
+            // cycle finishes without ever being seen as dead, so they are held strongly until the
+            // next finalization.
+            state.young_userdata.push(userdata);
+        } else {
+            state.userdata.push(Gc::downgrade(userdata.into_inner()));
+        }
+    }
+
+    // Register a table which has become weak or has had entries set to nil, so that its dead
+    // entries are removed whenever the table is finalized.
+    pub(crate) fn register_weak_table(&self, mc: &Mutation<'gc>, table: Table<'gc>) {
+        let mut state = self.0.borrow_mut(mc);
+        if state.finalized {
+            // Like userdata, tables allocated after finalization are held strongly until the end of
+            // the cycle. Until then the table is also traced strongly, since its dead entries have
+            // not been removed.
+            table.set_traced_strongly(mc, true);
+            state.young_tables.push(table);
+        } else {
+            state.weak_tables.push(Gc::downgrade(table.into_inner()));
+        }
+    }
+
+    // Take every userdata that has become unreachable and is waiting for its finalizer to be
+    // called.
+    pub(crate) fn take_pending(&self, mc: &Mutation<'gc>) -> Vec<UserData<'gc>> {
+        if self.0.borrow().pending.is_empty() {
+            Vec::new()
+        } else {
+            std::mem::take(&mut self.0.borrow_mut(mc).pending)
+        }
+    }
+
+    // Returns false if anything was resurrected, in which case marking must be completed and this
+    // must be called again before the collection cycle can finish.
+    //
+    // Threads and weak tables are only finalized once nothing more is resurrected, since objects
+    // which are reachable only from resurrected objects are not marked until marking resumes.
+    pub(crate) fn finalize(&self, fc: &Finalization<'gc>) -> bool {
         let mut state = self.0.borrow_mut(fc);
+        let state = &mut *state;
+
+        // The values of weak key tables whose keys are alive are resurrected before anything else,
+        // since they are reachable and must not be finalized.
+        let mut resurrected = false;
+        for &ptr in &state.weak_tables {
+            if !ptr.is_dead(fc) {
+                Table::from_inner(ptr.upgrade(fc).unwrap()).resurrect_values(
+                    |value| is_dead(fc, value),
+                    |value| {
+                        resurrect(fc, value);
+                        resurrected = true;
+                    },
+                );
+            }
+        }
+        if resurrected {
+            return false;
+        }
+
+        // Userdata are resurrected first, so that anything they reference is kept alive until
+        // their finalizers have run.
+        let pending = state.pending.len();
+        state.userdata.retain(|&ptr| {
+            let ptr = ptr.upgrade(fc).expect("userdata finalization was missed");
+            if Gc::is_dead(fc, ptr) {
+                Gc::resurrect(fc, ptr);
+                state.pending.push(UserData::from_inner(ptr));
+                false
+            } else {
+                true
+            }
+        });
+        if state.pending.len() > pending {
+            return false;
+        }
+
         state.threads.retain(|&ptr| {
             let ptr = ptr.upgrade(fc).expect("thread finalization was missed");
             if Gc::is_dead(fc, ptr) {
-                Thread::from_inner(ptr).reset(fc).unwrap();
+                // Resetting the thread closes its upvalues, and leaves nothing in the stacks that
+                // are kept.
+                let stacks = Thread::from_inner(ptr).take_stacks(fc);
+                if state.thread_pool.len() < THREAD_POOL_SIZE {
+                    state.thread_pool.push(stacks);
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        // Dead weak tables are freed along with everything in them, live weak tables have their
+        // dead entries removed before the objects in them are freed.
+        state.weak_tables.retain(|&ptr| {
+            if ptr.is_dead(fc) {
                 false
             } else {
+                let table = Table::from_inner(ptr.upgrade(fc).unwrap());
+                table.remove_dead(fc, |value| is_dead(fc, value));
                 true
             }
         });
+
+        state.threads.extend(
+            state
+                .young_threads
+                .drain(..)
+                .map(|thread| Gc::downgrade(thread.into_inner())),
+        );
+        state.userdata.extend(
+            state
+                .young_userdata
+                .drain(..)
+                .map(|ud| Gc::downgrade(ud.into_inner())),
+        );
+        state.finalized = true;
+        true
+    }
+
+    // Must be called once the collection cycle that `Finalizers::finalize` was called for has
+    // finished.
+    pub(crate) fn end_cycle(&self, mc: &Mutation<'gc>) {
+        let mut state = self.0.borrow_mut(mc);
+        let state = &mut *state;
+
+        // Every registered table is still alive, dead tables were unregistered during finalization
+        // and young tables are held strongly.
+        for &ptr in &state.weak_tables {
+            Table::from_inner(ptr.upgrade(mc).unwrap()).set_traced_strongly(mc, false);
+        }
+        for table in state.young_tables.drain(..) {
+            table.set_traced_strongly(mc, false);
+            state.weak_tables.push(Gc::downgrade(table.into_inner()));
+        }
+
+        state.finalized = false;
     }
 }
 
@@ -33,4 +198,37 @@ impl<'gc> Finalizers<'gc> {
 #[collect(no_drop)]
 struct FinalizersState<'gc> {
     threads: Vec<GcWeak<'gc, ThreadInner<'gc>>>,
+    young_threads: Vec<Thread<'gc>>,
+    thread_pool: Vec<ThreadStacks<'gc>>,
+    reused_threads: usize,
+    userdata: Vec<GcWeak<'gc, UserDataInner<'gc>>>,
+    young_userdata: Vec<UserData<'gc>>,
+    pending: Vec<UserData<'gc>>,
+    weak_tables: Vec<GcWeak<'gc, TableInner<'gc>>>,
+    young_tables: Vec<Table<'gc>>,
+    finalized: bool,
+}
+
+// Returns true if the value is an object which was not reached during marking.
+fn is_dead<'gc>(fc: &Finalization<'gc>, value: Value<'gc>) -> bool {
+    match value {
+        Value::Table(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::Function(Function::Closure(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Function(Function::Callback(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Thread(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::UserData(u) => Gc::is_dead(fc, u.into_inner()),
+        _ => false,
+    }
+}
+
+// Keep an object which was not reached during marking alive, along with everything it refers to.
+fn resurrect<'gc>(fc: &Finalization<'gc>, value: Value<'gc>) {
+    match value {
+        Value::Table(t) => Gc::resurrect(fc, t.into_inner()),
+        Value::Function(Function::Closure(c)) => Gc::resurrect(fc, c.into_inner()),
+        Value::Function(Function::Callback(c)) => Gc::resurrect(fc, c.into_inner()),
+        Value::Thread(t) => Gc::resurrect(fc, t.into_inner()),
+        Value::UserData(u) => Gc::resurrect(fc, u.into_inner()),
+        _ => {}
+    }
 }
@@ -5,6 +5,11 @@ use crate::{
     Sequence, SequencePoll, Stack,
 };
 
+/// Any callable Lua function value.
+///
+/// A `Function` is either a `Closure`, which is a compiled Lua function together with its
+/// upvalues, or a `Callback`, which is a function implemented in Rust. Both are the Lua type
+/// `function`, and a `Function` can be used anywhere a value is expected.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Collect)]
 #[collect(no_drop)]
 pub enum Function<'gc> {
@@ -25,6 +30,48 @@ impl<'gc> From<Callback<'gc>> for Function<'gc> {
 }
 
 impl<'gc> Function<'gc> {
+    /// Create a `Function` from a Rust callback.
+    pub fn callback(callback: Callback<'gc>) -> Self {
+        Self::Callback(callback)
+    }
+
+    /// Create a `Function` from a Rust callback function, see `Callback::from_fn`.
+    pub fn from_fn<F>(mc: &Mutation<'gc>, call: F) -> Self
+    where
+        F: 'static
+            + Fn(
+                Context<'gc>,
+                Execution<'gc, '_>,
+                Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        Self::Callback(Callback::from_fn(mc, call))
+    }
+
+    /// Create a `Function` from a Rust callback function which captures garbage collected data,
+    /// see `Callback::from_fn_with`.
+    pub fn from_fn_with<R, F>(mc: &Mutation<'gc>, root: R, call: F) -> Self
+    where
+        R: 'gc + Collect,
+        F: 'static
+            + Fn(
+                &R,
+                Context<'gc>,
+                Execution<'gc, '_>,
+                Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        Self::Callback(Callback::from_fn_with(mc, root, call))
+    }
+
+    /// Returns the address of the closure or callback, for identification purposes only.
+    pub fn as_ptr(self) -> *const () {
+        match self {
+            Function::Closure(c) => Gc::as_ptr(c.into_inner()) as *const (),
+            Function::Callback(c) => Gc::as_ptr(c.into_inner()) as *const (),
+        }
+    }
+
     pub fn compose<I>(mc: &Mutation<'gc>, functions: I) -> Self
     where
         I: AsRef<[Function<'gc>]> + Collect + 'gc,
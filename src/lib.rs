@@ -4,6 +4,7 @@ pub mod closure;
 pub mod compiler;
 pub mod constant;
 pub mod conversion;
+pub mod dump;
 pub mod error;
 pub mod finalizers;
 pub mod fuel;
@@ -28,23 +29,27 @@ pub use self::{
     closure::{Closure, ClosureError, FunctionPrototype, PrototypeError},
     constant::Constant,
     conversion::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Variadic},
-    error::{Error, RuntimeError, StaticError, TypeError},
+    dump::UndumpError,
+    error::{BadArgument, Error, RuntimeError, StaticError, TypeError},
     finalizers::Finalizers,
     fuel::Fuel,
     function::Function,
-    lua::{Context, Lua},
+    lua::{Context, GcControl, Lua},
     meta_ops::MetaMethod,
     registry::{
         Registry, Singleton, StashedCallback, StashedClosure, StashedExecutor, StashedFunction,
         StashedTable, StashedThread, StashedUserData, StaticValue,
     },
     stack::Stack,
-    string::{BadConcatType, String},
-    table::{InvalidTableKey, Table},
+    string::{BadConcatType, String, StringBuilder},
+    table::{InvalidTableKey, PathError, Table},
     thread::{
-        BadExecutorMode, BadThreadMode, Execution, Executor, ExecutorMode, Thread, ThreadMode,
-        VMError,
+        BadExecutorMode, BadThreadMode, Execution, Executor, ExecutorMode, FrameInfo, Hook,
+        HookMask, StepResult, Thread, ThreadMode, VMError,
     },
-    userdata::{BadUserDataType, UserData},
+    userdata::{BadUserDataType, LightUserData, UserData},
     value::Value,
 };
+
+#[cfg(feature = "derive")]
+pub use piccolo_derive::{FromValue, IntoValue};
@@ -1,14 +1,19 @@
-use std::ops;
+use std::{cell::Cell, io::Read, mem, ops};
 
 use gc_arena::{metrics::Metrics, Arena, Collect, CollectionPhase, Mutation, Root, Rootable};
 
 use crate::{
     finalizers::Finalizers,
+    meta_ops::{self, MetaMethod},
     registry::{Fetchable, Stashable},
-    stdlib::{load_base, load_coroutine, load_io, load_math, load_string, load_table},
+    stdlib::{
+        load_base, load_coroutine, load_debug, load_file_functions, load_io, load_math, load_os,
+        load_package, load_string, load_table, load_utf8,
+    },
     string::InternedStringSet,
-    Error, FromMultiValue, Fuel, IntoValue, InvalidTableKey, Registry, Singleton, StashedExecutor,
-    StaticError, String, Table, Value,
+    Closure, Error, Executor, FromMultiValue, Fuel, Function, IntoValue, InvalidTableKey, Registry,
+    RuntimeError, Singleton, StashedExecutor, StashedFunction, StaticError, String, Table, VMError,
+    Value,
 };
 
 #[derive(Copy, Clone)]
@@ -34,6 +39,26 @@ impl<'gc> Context<'gc> {
         self.state.finalizers
     }
 
+    /// Requests to the garbage collector that will be handled once the arena is exited.
+    pub fn gc_control(self) -> &'gc GcControl {
+        &self.state.gc_control
+    }
+
+    /// Checks whether allocating `size` more bytes would exceed the limit set with
+    /// `GcControl::set_memory_limit`.
+    ///
+    /// The limit is otherwise only checked in-between VM instructions and callbacks, so code that
+    /// allocates a large buffer at once should call this first. Garbage that has not been collected
+    /// yet still counts towards the total.
+    pub fn check_allocation(self, size: usize) -> Result<(), VMError> {
+        match self.state.gc_control.memory_limit() {
+            Some(limit) if self.metrics().total_allocation().saturating_add(size) > limit => {
+                Err(VMError::NotEnoughMemory)
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Calls `ctx.globals().set(ctx, key, value)`.
     pub fn set_global<K: IntoValue<'gc>, V: IntoValue<'gc>>(
         self,
@@ -48,6 +73,20 @@ impl<'gc> Context<'gc> {
         self.state.globals.get(self, key)
     }
 
+    /// Calls `ctx.registry().table().set(ctx, key, value)`.
+    pub fn set_registry<K: IntoValue<'gc>, V: IntoValue<'gc>>(
+        self,
+        key: K,
+        value: V,
+    ) -> Result<Value<'gc>, InvalidTableKey> {
+        self.state.registry.table().set(self, key, value)
+    }
+
+    /// Calls `ctx.registry().table().get(ctx, key)`.
+    pub fn get_registry<K: IntoValue<'gc>>(self, key: K) -> Value<'gc> {
+        self.state.registry.table().get(self, key)
+    }
+
     /// Calls `ctx.registry().singleton::<S>(ctx)`.
     pub fn singleton<S>(self) -> &'gc Root<'gc, S>
     where
@@ -86,9 +125,155 @@ impl<'gc> ops::Deref for Context<'gc> {
     }
 }
 
+/// Control over the garbage collector from *inside* the arena.
+///
+/// Garbage collection can only take place in-between calls to `Lua::enter`, so code running
+/// inside the arena (such as the `collectgarbage` stdlib function) cannot collect garbage directly.
+/// Instead, requests are recorded here, and `Lua::enter` acts on them before returning.
+#[derive(Debug, Default, Collect)]
+#[collect(require_static)]
+pub struct GcControl {
+    stopped: Cell<bool>,
+    collect_requested: Cell<bool>,
+    step_requested: Cell<bool>,
+    memory_limit: Cell<Option<usize>>,
+    emergency_requested: Cell<bool>,
+    emergency_collected: Cell<bool>,
+    memory_state: Cell<MemoryState>,
+    finalizing: Cell<bool>,
+    cycles: Cell<u64>,
+}
+
+// Progress through one episode of allocated memory being over the limit.
+#[derive(Debug, Default, Copy, Clone)]
+enum MemoryState {
+    // Allocated memory is within the limit, or an error has not been raised yet.
+    #[default]
+    Within,
+    // The memory error has been raised, and once it has been handled another full collection will
+    // be done to free anything left behind by the unwinding.
+    Raised,
+    // Memory is still over the limit after handling the error, but no more errors are raised until
+    // it is back within the limit.
+    Quiet,
+}
+
+/// The result of checking the total allocated memory against the limit set with
+/// `GcControl::set_memory_limit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum MemoryCheck {
+    /// Allocated memory is within the limit.
+    Ok,
+    /// Allocated memory is over the limit, and a full collection has been requested to see if
+    /// enough garbage can be freed. The caller should exit the arena so that the collection can
+    /// take place.
+    NeedsCollection,
+    /// Allocated memory is still over the limit after a full collection, and the caller should
+    /// raise an error. This is only returned once until allocated memory is back within the limit.
+    ///
+    /// The caller must not check memory while it is handling an error, so that the error can
+    /// finish unwinding.
+    Exceeded,
+}
+
+impl GcControl {
+    /// Request a full collection cycle.
+    pub fn collect(&self) {
+        self.collect_requested.set(true);
+    }
+
+    /// Request that some garbage collection work be done, even if the collector is stopped or not
+    /// enough allocation debt has built up.
+    pub fn step(&self) {
+        self.step_requested.set(true);
+    }
+
+    /// Stop automatic garbage collection. Explicit `collect` and `step` requests are still
+    /// honored.
+    pub fn stop(&self) {
+        self.stopped.set(true);
+    }
+
+    /// Restart automatic garbage collection after a call to `GcControl::stop`.
+    pub fn restart(&self) {
+        self.stopped.set(false);
+    }
+
+    /// Returns false if automatic garbage collection has been stopped.
+    pub fn is_running(&self) -> bool {
+        !self.stopped.get()
+    }
+
+    /// The number of garbage collection cycles which have finished.
+    pub fn cycles(&self) -> u64 {
+        self.cycles.get()
+    }
+
+    /// Set a limit on the total memory (as reported by `Lua::total_memory`) that running Lua code
+    /// may allocate, or `None` to remove the limit.
+    ///
+    /// The limit is checked by `Executor::step` in-between VM instructions and callbacks. When the
+    /// limit is first exceeded, `Executor::step` returns early so that a full collection can free
+    /// any garbage. If the limit is still exceeded after this, a catchable "not enough memory"
+    /// error is raised in the running thread. The error is raised only once each time the limit is
+    /// exceeded, so that it can unwind and free memory without being raised again.
+    ///
+    /// Because of this, a single allocation made by a callback can exceed the limit by an
+    /// arbitrary amount before it is noticed. The stdlib calls `Context::check_allocation` before
+    /// allocating large buffers, and custom callbacks that do so should as well.
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.memory_limit.set(limit);
+    }
+
+    /// Returns the limit set with `GcControl::set_memory_limit`, if any.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit.get()
+    }
+
+    pub(crate) fn check_memory(&self, total_memory: usize) -> MemoryCheck {
+        if self.finalizing.get() {
+            // Finalizers run outside of `Lua::enter`, where no collection can be done, so they are
+            // exempt from the limit.
+            return MemoryCheck::Ok;
+        }
+
+        match self.memory_limit.get() {
+            Some(limit) if total_memory > limit => {
+                let collected = self.emergency_collected.take();
+                match self.memory_state.get() {
+                    MemoryState::Within if collected => {
+                        self.memory_state.set(MemoryState::Raised);
+                        MemoryCheck::Exceeded
+                    }
+                    MemoryState::Raised if collected => {
+                        self.memory_state.set(MemoryState::Quiet);
+                        MemoryCheck::Ok
+                    }
+                    MemoryState::Within | MemoryState::Raised => {
+                        self.emergency_requested.set(true);
+                        MemoryCheck::NeedsCollection
+                    }
+                    MemoryState::Quiet => MemoryCheck::Ok,
+                }
+            }
+            _ => {
+                self.emergency_collected.set(false);
+                self.memory_state.set(MemoryState::Within);
+                MemoryCheck::Ok
+            }
+        }
+    }
+}
+
+/// The error recorded for a `__gc` metamethod which ran out of `Lua::FINALIZER_FUEL`.
+#[derive(Debug, thiserror::Error)]
+#[error("finalizer did not finish and was aborted")]
+struct FinalizerAborted;
+
 pub struct Lua {
     arena: Arena<Rootable![State<'_>]>,
     finalized: bool,
+    finalizer_errors: Vec<StaticError>,
 }
 
 impl Default for Lua {
@@ -103,6 +288,7 @@ impl Lua {
         Lua {
             arena: Arena::<Rootable![State<'_>]>::new(|mc| State::new(mc)),
             finalized: false,
+            finalizer_errors: Vec::new(),
         }
     }
 
@@ -117,6 +303,9 @@ impl Lua {
     pub fn full() -> Self {
         let mut lua = Lua::core();
         lua.load_io();
+        lua.load_os(true);
+        lua.load_debug();
+        lua.load_package();
         lua
     }
 
@@ -128,6 +317,7 @@ impl Lua {
     ///   - `load_math`
     ///   - `load_string`
     ///   - `load_table`
+    ///   - `load_utf8`
     pub fn load_core(&mut self) {
         self.enter(|ctx| {
             load_base(ctx);
@@ -135,13 +325,50 @@ impl Lua {
             load_math(ctx);
             load_string(ctx);
             load_table(ctx);
+            load_utf8(ctx);
         })
     }
 
     /// Load the parts of the stdlib that allow I/O.
+    ///
+    /// Calls:
+    ///   - `load_io`
+    ///   - `load_file_functions`
     pub fn load_io(&mut self) {
         self.enter(|ctx| {
             load_io(ctx);
+            load_file_functions(ctx);
+        })
+    }
+
+    /// Load the `debug` library.
+    ///
+    /// The debug library allows introspection of running code and is not loaded as part of the
+    /// core stdlib.
+    pub fn load_debug(&mut self) {
+        self.enter(|ctx| {
+            load_debug(ctx);
+        })
+    }
+
+    /// Load the `os` library.
+    ///
+    /// If `unrestricted` is false, `os.execute`, `os.exit`, `os.remove`, and `os.rename` will not be
+    /// loaded, so that scripts cannot run shell commands, terminate the host process, or modify the
+    /// file system.
+    pub fn load_os(&mut self, unrestricted: bool) {
+        self.enter(|ctx| {
+            load_os(ctx, unrestricted);
+        })
+    }
+
+    /// Load the `package` library and `require`.
+    ///
+    /// Modules can be loaded from `package.preload` or from Lua files found using `package.path`.
+    /// Any other libraries should be loaded first, so that they are added to `package.loaded`.
+    pub fn load_package(&mut self) {
+        self.enter(|ctx| {
+            load_package(ctx);
         })
     }
 
@@ -155,16 +382,22 @@ impl Lua {
     }
 
     /// Finish the current collection cycle completely, calls `gc_arena::Arena::collect_all()`.
+    ///
+    /// Any `__gc` metamethods of userdata found to be unreachable are called before returning.
     pub fn gc_collect(&mut self) {
-        if !self.finalized {
-            self.arena.mark_all().unwrap().finalize(|fc, root| {
-                root.finalizers.finalize(fc);
-            });
+        while !self.finalized {
+            self.finalized = self
+                .arena
+                .mark_all()
+                .unwrap()
+                .finalize(|fc, root| root.finalizers.finalize(fc));
         }
 
         self.arena.collect_all();
         assert!(self.arena.collection_phase() == CollectionPhase::Sleeping);
-        self.finalized = false;
+        self.end_cycle();
+
+        self.run_finalizers();
     }
 
     pub fn gc_metrics(&self) -> &Metrics {
@@ -188,26 +421,120 @@ impl Lua {
     {
         const COLLECTOR_GRANULARITY: f64 = 1024.0;
 
-        let r = self.arena.mutate(move |mc, state| f(state.ctx(mc)));
-        if self.arena.metrics().allocation_debt() > COLLECTOR_GRANULARITY {
+        let (r, running, collect, step, emergency) = self.arena.mutate(move |mc, state| {
+            let r = f(state.ctx(mc));
+            let control = &state.gc_control;
+            (
+                r,
+                control.is_running(),
+                control.collect_requested.take(),
+                control.step_requested.take(),
+                control.emergency_requested.take(),
+            )
+        });
+
+        if collect || emergency {
+            self.gc_collect();
+            if emergency {
+                self.arena
+                    .mutate(|_, state| state.gc_control.emergency_collected.set(true));
+            }
+        } else if step
+            || (running && self.arena.metrics().allocation_debt() > COLLECTOR_GRANULARITY)
+        {
             if self.finalized {
                 self.arena.collect_debt();
 
                 if self.arena.collection_phase() == CollectionPhase::Sleeping {
-                    self.finalized = false;
+                    self.end_cycle();
                 }
-            } else {
+            } else if self.arena.collection_phase() != CollectionPhase::Sleeping
+                || self.arena.metrics().allocation_debt() > 0.0
+            {
+                // A requested step right after a full collection has no debt to pay off, and must
+                // not try to start marking.
                 if let Some(marked) = self.arena.mark_debt() {
-                    marked.finalize(|fc, root| {
-                        root.finalizers.finalize(fc);
-                    });
-                    self.finalized = true;
+                    self.finalized = marked.finalize(|fc, root| root.finalizers.finalize(fc));
                 }
             }
+
+            self.run_finalizers();
         }
         r
     }
 
+    fn end_cycle(&mut self) {
+        self.finalized = false;
+        self.arena.mutate(|mc, state| {
+            state.finalizers.end_cycle(mc);
+            let cycles = &state.gc_control.cycles;
+            cycles.set(cycles.get() + 1);
+        });
+    }
+
+    /// Take every error raised by a `__gc` metamethod since the last call.
+    ///
+    /// An error in a finalizer does not stop the collection or the remaining finalizers, it is
+    /// only recorded here. Errors accumulate until they are taken, up to a limit of
+    /// `Lua::MAX_FINALIZER_ERRORS`, after which any further errors are dropped.
+    pub fn take_finalizer_errors(&mut self) -> Vec<StaticError> {
+        mem::take(&mut self.finalizer_errors)
+    }
+
+    /// The most finalizer errors that are kept until they are taken with
+    /// `Lua::take_finalizer_errors`.
+    pub const MAX_FINALIZER_ERRORS: usize = 256;
+
+    /// The fuel that each `__gc` metamethod may use before it is aborted.
+    pub const FINALIZER_FUEL: i32 = 1 << 20;
+
+    // Call the `__gc` metamethod of every userdata that was found unreachable during the last
+    // collection.
+    //
+    // Each finalizer is run on its own `Executor` until it finishes or runs out of
+    // `Lua::FINALIZER_FUEL`, so that a finalizer which never finishes (or which waits on something
+    // that can only happen once it returns) cannot hang the collector. Errors raised by finalizers
+    // are recorded for `Lua::take_finalizer_errors`.
+    fn run_finalizers(&mut self) {
+        let errors = self.arena.mutate(|mc, state| {
+            let ctx = state.ctx(mc);
+            ctx.gc_control().finalizing.set(true);
+            let mut errors = Vec::new();
+            for userdata in ctx.finalizers().take_pending(&ctx) {
+                let Some(metatable) = userdata.metatable() else {
+                    continue;
+                };
+
+                let Ok(function) = meta_ops::call(ctx, metatable.get(ctx, MetaMethod::Gc)) else {
+                    continue;
+                };
+
+                let executor = Executor::start(ctx, function, userdata);
+                let mut fuel = Fuel::with(Self::FINALIZER_FUEL);
+                let finished = loop {
+                    if executor.step(ctx, &mut fuel) {
+                        break true;
+                    }
+                    // An interrupt (such as from a pending future) only ends the step, the
+                    // finalizer continues until it is out of fuel.
+                    fuel.clear_interrupt();
+                    if !fuel.should_continue() {
+                        break false;
+                    }
+                };
+                if !finished {
+                    errors.push(RuntimeError::from(FinalizerAborted).into());
+                } else if let Ok(Err(err)) = executor.take_result::<()>(ctx) {
+                    errors.push(err.into_static());
+                }
+            }
+            ctx.gc_control().finalizing.set(false);
+            errors
+        });
+        let room = Self::MAX_FINALIZER_ERRORS.saturating_sub(self.finalizer_errors.len());
+        self.finalizer_errors.extend(errors.into_iter().take(room));
+    }
+
     /// A version of `Lua::enter` that expects failure and also automatically converts `Error` types
     /// into `StaticError`, allowing the error type to escape the arena.
     pub fn try_enter<F, R>(&mut self, f: F) -> Result<R, StaticError>
@@ -217,10 +544,41 @@ impl Lua {
         self.enter(move |ctx| f(ctx).map_err(Error::into_static))
     }
 
+    /// Compile a chunk of Lua source into a function which uses the global table as its `_ENV`.
+    ///
+    /// The returned function is stashed in the registry, so that it can be run later with
+    /// `Lua::call`.
+    pub fn load(
+        &mut self,
+        name: Option<&str>,
+        source: impl Read,
+    ) -> Result<StashedFunction, StaticError> {
+        self.try_enter(|ctx| {
+            let closure = Closure::load(ctx, name, source)?;
+            Ok(ctx.stash(Function::from(closure)))
+        })
+    }
+
+    /// Call the given function with no arguments on a new `Executor`, run it to completion, and
+    /// then take its return values.
+    pub fn call<R: for<'gc> FromMultiValue<'gc>>(
+        &mut self,
+        function: &StashedFunction,
+    ) -> Result<R, StaticError> {
+        let executor = self.enter(|ctx| {
+            let function = ctx.fetch(function);
+            ctx.stash(Executor::start(ctx, function, ()))
+        });
+        self.execute(&executor)
+    }
+
     /// Run the given executor to completion.
     ///
     /// This will periodically exit the arena in order to collect garbage concurrently with running
     /// Lua code.
+    ///
+    /// This never blocks, a sequence which stays pending (such as one created with
+    /// `BoxSequence::from_future`) is polled again immediately, so this spins until it finishes.
     pub fn finish(&mut self, executor: &StashedExecutor) {
         const FUEL_PER_GC: i32 = 4096;
 
@@ -236,23 +594,38 @@ impl Lua {
     /// Run the given executor to completion and then take return values from the returning thread.
     ///
     /// This is equivalent to calling `Lua::finish` on an executor and then calling
-    /// `Executor::take_result` yourself.
+    /// `Executor::take_result` yourself. If the executor has traceback capturing enabled (see
+    /// `Executor::set_capture_traceback`), an uncaught error has its traceback attached.
     pub fn execute<R: for<'gc> FromMultiValue<'gc>>(
         &mut self,
         executor: &StashedExecutor,
     ) -> Result<R, StaticError> {
         self.finish(executor);
-        self.try_enter(|ctx| ctx.fetch(executor).take_result::<R>(ctx)?)
+        self.enter(|ctx| {
+            let executor = ctx.fetch(executor);
+            let traceback = executor.error_traceback();
+            executor
+                .take_result::<R>(ctx)
+                .unwrap_or_else(|err| Err(err.into()))
+                .map_err(|err| {
+                    let err = err.into_static();
+                    match traceback {
+                        Some(traceback) => err.with_traceback(traceback),
+                        None => err,
+                    }
+                })
+        })
     }
 }
 
-#[derive(Copy, Clone, Collect)]
+#[derive(Collect)]
 #[collect(no_drop)]
 struct State<'gc> {
     globals: Table<'gc>,
     registry: Registry<'gc>,
     strings: InternedStringSet<'gc>,
     finalizers: Finalizers<'gc>,
+    gc_control: GcControl,
 }
 
 impl<'gc> State<'gc> {
@@ -262,6 +635,7 @@ impl<'gc> State<'gc> {
             registry: Registry::new(mc),
             strings: InternedStringSet::new(mc),
             finalizers: Finalizers::new(mc),
+            gc_control: GcControl::default(),
         }
     }
 
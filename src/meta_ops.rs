@@ -1,4 +1,6 @@
-use gc_arena::Collect;
+use std::io::Write;
+
+use gc_arena::{Collect, Gc};
 
 use crate::{
     Callback, CallbackReturn, Context, Function, IntoValue, RuntimeError, Table, TypeError,
@@ -15,6 +17,10 @@ pub enum MetaMethod {
     Pairs,
     ToString,
     Eq,
+    Gc,
+    Close,
+    Mode,
+    Name,
 }
 
 impl MetaMethod {
@@ -27,6 +33,10 @@ impl MetaMethod {
             MetaMethod::Pairs => "__pairs",
             MetaMethod::ToString => "__tostring",
             MetaMethod::Eq => "__eq",
+            MetaMethod::Gc => "__gc",
+            MetaMethod::Close => "__close",
+            MetaMethod::Mode => "__mode",
+            MetaMethod::Name => "__name",
         }
     }
 }
@@ -153,7 +163,7 @@ pub fn new_index<'gc>(
             let v = table.get(ctx, key);
             if !v.is_nil() {
                 // If the value is present in the table, then we do not invoke the metamethod.
-                table.set_value(&ctx, key, value)?;
+                table.set_value(ctx, key, value)?;
                 return Ok(None);
             }
 
@@ -166,7 +176,7 @@ pub fn new_index<'gc>(
             if idx.is_nil() {
                 // If we do not have a __newindex metamethod, then just set the table value
                 // directly.
-                table.set_value(&ctx, key, value)?;
+                table.set_value(ctx, key, value)?;
                 return Ok(None);
             }
 
@@ -254,6 +264,36 @@ pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, Type
     }
 }
 
+/// Prepares a call to the `__close` metamethod of a to-be-closed value.
+///
+/// The metamethod is called with the value and the error that caused it to be closed, or `nil` if
+/// it went out of scope normally.
+pub fn close<'gc>(
+    ctx: Context<'gc>,
+    v: Value<'gc>,
+    error: Value<'gc>,
+) -> Result<MetaCall<'gc, 2>, TypeError> {
+    let metamethod = match v {
+        Value::Table(t) => t.metatable(),
+        Value::UserData(ud) => ud.metatable(),
+        _ => None,
+    }
+    .map(|mt| mt.get(ctx, MetaMethod::Close))
+    .unwrap_or_default();
+
+    if metamethod.is_nil() {
+        return Err(TypeError {
+            expected: "closable value",
+            found: v.type_name(),
+        });
+    }
+
+    Ok(MetaCall {
+        function: call(ctx, metamethod)?,
+        args: [v, error],
+    })
+}
+
 pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, TypeError> {
     if let Some(metatable) = match v {
         Value::Table(t) => t.metatable(),
@@ -271,7 +311,7 @@ pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>,
 
     match v {
         Value::String(s) => Ok(MetaResult::Value(s.len().into())),
-        Value::Table(t) => Ok(MetaResult::Value(t.length().into())),
+        Value::Table(t) => Ok(MetaResult::Value(t.len().into())),
         f => Err(TypeError {
             expected: "string or table",
             found: f.type_name(),
@@ -280,9 +320,13 @@ pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>,
 }
 
 pub fn tostring<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, TypeError> {
-    if let Some(metatable) = match v {
-        Value::Table(t) => t.metatable(),
-        Value::UserData(u) => u.metatable(),
+    if let Some((metatable, address)) = match v {
+        Value::Table(t) => t
+            .metatable()
+            .map(|mt| (mt, Gc::as_ptr(t.into_inner()) as *const ())),
+        Value::UserData(u) => u
+            .metatable()
+            .map(|mt| (mt, Gc::as_ptr(u.into_inner()) as *const ())),
         _ => None,
     } {
         let tostring = metatable.get(ctx, MetaMethod::ToString);
@@ -292,6 +336,13 @@ pub fn tostring<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc,
                 args: [v],
             }));
         }
+
+        // Without `__tostring`, a string `__name` replaces the type name before the address.
+        if let Value::String(name) = metatable.get(ctx, MetaMethod::Name) {
+            let mut s = name.as_bytes().to_vec();
+            write!(s, ": {address:p}").unwrap();
+            return Ok(MetaResult::Value(ctx.intern(&s).into()));
+        }
     }
 
     Ok(match v {
@@ -394,5 +445,8 @@ pub fn equal<'gc>(
             }
         }
         (Value::UserData(_), _) => Value::Boolean(false).into(),
+
+        (Value::LightUserData(a), Value::LightUserData(b)) => Value::Boolean(a == b).into(),
+        (Value::LightUserData(_), _) => Value::Boolean(false).into(),
     })
 }
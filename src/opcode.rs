@@ -108,9 +108,17 @@ pub enum Operation {
         dest: RegisterIndex,
         count: VarCount,
     },
+    /// Mark the given register as a to-be-closed variable. The value must be `nil`, `false`, or
+    /// have a `__close` metamethod.
+    ///
+    /// To-be-closed variables are closed in reverse order by `Jump` operations that close upvalues
+    /// at or below their register, by `Return`, and when an error unwinds through the frame.
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     Jump {
         offset: i16,
-        // If set, close upvalues >= `close_upvalues`
+        // If set, close upvalues and to-be-closed variables >= `close_upvalues`
         close_upvalues: Opt254,
     },
     /// Test the register as a boolean, if its boolean value matches `is_true`, skip the next
@@ -133,22 +141,43 @@ pub enum Operation {
     },
     /// Used to set up for a numeric for loop:
     ///
-    /// R(base) -= R(base + 2)
-    /// pc += jump
+    /// if R(base) and R(base + 2) are integers then
+    ///     R(base + 1) = number of iterations after the first
+    /// else
+    ///     R(base), R(base + 1), R(base + 2) = float(R(base)), float(R(base + 1)), float(R(base + 2))
+    /// end
+    /// if the loop runs at least once then
+    ///     R(base + 3) = R(base)
+    /// else
+    ///     pc += jump + 1
+    /// end
+    ///
+    /// A step of zero is an error.
     NumericForPrep {
         base: RegisterIndex,
         jump: i16,
     },
     /// Used to iterate a numeric for loop:
     ///
+    /// For integer loops:
+    ///
+    /// if R(base + 1) > 0 then
+    ///     R(base + 1) -= 1
+    ///     R(base) += R(base + 2)
+    ///     pc += jump
+    ///     R(base + 3) = R(base)
+    /// end
+    ///
+    /// For float loops:
+    ///
     /// R(base) += R(base + 2)
     /// if R(base) <?= R(base + 1) then
     ///     pc += jump
     ///     R(base + 3) = R(base)
     /// end
     ///
-    /// The `<?=` operator here means "less than" if the step (aka R(base + 2)) is positive, and
-    /// "greater than" if the step is negative
+    /// The `<?=` operator here means "less than or equal" if the step (aka R(base + 2)) is
+    /// positive, and "greater than or equal" if the step is negative
     NumericForLoop {
         base: RegisterIndex,
         jump: i16,
@@ -364,6 +393,7 @@ impl OpCode {
             Operation::TailCall { func, args } => OpCodeRepr::TailCall { func, args },
             Operation::Return { start, count } => OpCodeRepr::Return { start, count },
             Operation::VarArgs { dest, count } => OpCodeRepr::VarArgs { dest, count },
+            Operation::ToBeClosed { value } => OpCodeRepr::ToBeClosed { value },
             Operation::Jump {
                 offset,
                 close_upvalues,
@@ -754,6 +784,7 @@ impl OpCode {
             OpCodeRepr::TailCall { func, args } => Operation::TailCall { func, args },
             OpCodeRepr::Return { start, count } => Operation::Return { start, count },
             OpCodeRepr::VarArgs { dest, count } => Operation::VarArgs { dest, count },
+            OpCodeRepr::ToBeClosed { value } => Operation::ToBeClosed { value },
             OpCodeRepr::Jump {
                 offset,
                 close_upvalues,
@@ -1261,6 +1292,9 @@ enum OpCodeRepr {
         dest: RegisterIndex,
         count: VarCount,
     },
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     Jump {
         offset: i16,
         close_upvalues: Opt254,
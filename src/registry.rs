@@ -8,8 +8,8 @@ use gc_arena::{
 use hashbrown::{hash_map, HashMap};
 
 use crate::{
-    any::Any, Callback, Closure, Context, Executor, Function, String, Table, Thread, UserData,
-    Value,
+    any::Any, Callback, Closure, Context, Executor, Function, LightUserData, String, Table, Thread,
+    UserData, Value,
 };
 
 #[derive(Clone)]
@@ -118,6 +118,7 @@ pub enum StaticValue {
     Function(StashedFunction),
     Thread(StashedThread),
     UserData(StashedUserData),
+    LightUserData(LightUserData),
 }
 
 impl StaticValue {
@@ -194,6 +195,12 @@ impl From<StashedUserData> for StaticValue {
     }
 }
 
+impl From<LightUserData> for StaticValue {
+    fn from(v: LightUserData) -> StaticValue {
+        StaticValue::LightUserData(v)
+    }
+}
+
 pub trait Singleton<'gc> {
     fn create(ctx: Context<'gc>) -> Self;
 }
@@ -208,6 +215,7 @@ impl<'gc, T: Default> Singleton<'gc> for T {
 #[collect(no_drop)]
 pub struct Registry<'gc> {
     roots: DynamicRootSet<'gc>,
+    table: Table<'gc>,
     singletons:
         Gc<'gc, RefLock<HashMap<TypeId, Any<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>>>,
 }
@@ -219,6 +227,7 @@ impl<'gc> Registry<'gc> {
 
         Self {
             roots: DynamicRootSet::new(mc),
+            table: Table::new(mc),
             singletons: Gc::new(mc, RefLock::new(singletons)),
         }
     }
@@ -227,6 +236,16 @@ impl<'gc> Registry<'gc> {
         self.roots
     }
 
+    /// A table that is always reachable, for storing values by key.
+    ///
+    /// This is similar to the registry in the PUC-Rio Lua C API. Values stored in it are kept alive
+    /// until they are removed, and can be found again in a later call to `Lua::enter` using the
+    /// same key. To get a handle to a single value without choosing a key, use
+    /// [`Registry::stash`] instead.
+    pub fn table(&self) -> Table<'gc> {
+        self.table
+    }
+
     /// Create an instance of a type that exists at most once per `Lua` instance.
     ///
     /// If the type has already been created, returns the already created instance, otherwise calls
@@ -356,6 +375,7 @@ impl<'gc> Stashable<'gc> for Value<'gc> {
             Value::Function(f) => StaticValue::Function(f.stash(roots, mc)),
             Value::Thread(t) => StaticValue::Thread(t.stash(roots, mc)),
             Value::UserData(u) => StaticValue::UserData(u.stash(roots, mc)),
+            Value::LightUserData(u) => StaticValue::LightUserData(u),
         }
     }
 }
@@ -374,6 +394,7 @@ impl<'gc> Fetchable<'gc> for StaticValue {
             StaticValue::Function(f) => Value::Function(f.fetch(roots)),
             StaticValue::Thread(t) => Value::Thread(t.fetch(roots)),
             StaticValue::UserData(u) => Value::UserData(u.fetch(roots)),
+            StaticValue::LightUserData(u) => Value::LightUserData(*u),
         }
     }
 }
@@ -7,8 +7,16 @@ use std::{
 use allocator_api2::vec;
 use gc_arena::allocator_api::MetricsAlloc;
 
-use crate::{Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, TypeError, Value};
+use crate::{
+    BadArgument, Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, String, Table,
+    TypeError, Value,
+};
 
+/// A view of the part of a thread's value stack that belongs to a single callback or sequence.
+///
+/// When a callback is called, the stack holds its arguments, and whatever is left on the stack
+/// when the callback returns becomes its return values. Values below the bottom of the view
+/// belong to calling frames and are only reachable through the debug methods on `Execution`.
 pub struct Stack<'gc, 'a> {
     values: &'a mut vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     bottom: usize,
@@ -20,6 +28,11 @@ impl<'gc, 'a> Stack<'gc, 'a> {
         Self { values, bottom }
     }
 
+    // The values below the bottom of this view, which belong to the calling frames.
+    pub(crate) fn lower(&mut self) -> &mut [Value<'gc>] {
+        &mut self.values[..self.bottom]
+    }
+
     pub fn sub_stack(&mut self, bottom: usize) -> Stack<'gc, '_> {
         Stack {
             values: self.values,
@@ -34,6 +47,20 @@ impl<'gc, 'a> Stack<'gc, 'a> {
             .unwrap_or_default()
     }
 
+    /// Borrow every value in the stack, without removing them.
+    pub fn args(&self) -> &[Value<'gc>] {
+        &self.values[self.bottom..]
+    }
+
+    /// Replace the entire contents of the stack with the given values.
+    ///
+    /// This is the same as `Stack::replace`, but takes values directly, so that results computed
+    /// from `Stack::args` can be returned without a `Context`.
+    pub fn set_results(&mut self, values: impl IntoIterator<Item = Value<'gc>>) {
+        self.clear();
+        self.values.extend(values);
+    }
+
     pub fn push_back(&mut self, value: Value<'gc>) {
         self.values.push(value);
     }
@@ -114,6 +141,12 @@ impl<'gc, 'a> Stack<'gc, 'a> {
         V::from_value(ctx, self.pop_front())
     }
 
+    /// Move every value out of the stack, leaving it empty.
+    pub fn take(&mut self) -> Vec<Value<'gc>> {
+        self.drain(..).collect()
+    }
+
+    /// Replace the entire contents of the stack with the given values.
     pub fn replace(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
         self.clear();
         self.extend(v.into_multi_value(ctx));
@@ -122,6 +155,83 @@ impl<'gc, 'a> Stack<'gc, 'a> {
     pub fn consume<V: FromMultiValue<'gc>>(&mut self, ctx: Context<'gc>) -> Result<V, TypeError> {
         V::from_multi_value(ctx, self.drain(..))
     }
+
+    /// Like `Stack::consume`, but on failure reports the position of the argument that failed to
+    /// convert.
+    pub fn consume_args<V: FromMultiValue<'gc>>(
+        &mut self,
+        ctx: Context<'gc>,
+    ) -> Result<V, BadArgument> {
+        // Every `FromMultiValue` impl converts each value as soon as it is pulled, including a
+        // trailing `Variadic` which takes the rest of the stack, so the number of values requested
+        // when an error occurs is the position of the bad argument.
+        let mut pulled = 0;
+        let mut values = self.drain(..);
+        V::from_multi_value(
+            ctx,
+            iter::from_fn(|| {
+                pulled += 1;
+                values.next()
+            }),
+        )
+        .map_err(|err| BadArgument {
+            index: pulled.max(1),
+            expected: err.expected,
+            found: err.found,
+        })
+    }
+
+    /// Get the argument at index `i` as an integer.
+    ///
+    /// Numbers with an exact integer representation and strings that can be converted to integers
+    /// are also accepted. Missing arguments are treated as `nil`.
+    pub fn check_integer(&self, i: usize) -> Result<i64, BadArgument> {
+        let v = self.get(i);
+        v.to_integer()
+            .ok_or_else(|| self.bad_argument(i, "integer", v))
+    }
+
+    /// Get the argument at index `i` as a number.
+    ///
+    /// Integers and strings that can be converted to numbers are also accepted. Missing arguments
+    /// are treated as `nil`.
+    pub fn check_number(&self, i: usize) -> Result<f64, BadArgument> {
+        let v = self.get(i);
+        v.to_number()
+            .ok_or_else(|| self.bad_argument(i, "number", v))
+    }
+
+    /// Get the argument at index `i` as a string.
+    ///
+    /// Numbers are also accepted and are converted to strings. Missing arguments are treated as
+    /// `nil`.
+    pub fn check_string(&self, ctx: Context<'gc>, i: usize) -> Result<String<'gc>, BadArgument> {
+        match self.get(i) {
+            Value::String(s) => Ok(s),
+            v @ (Value::Integer(_) | Value::Number(_)) => Ok(ctx.intern(v.to_string().as_bytes())),
+            v => Err(self.bad_argument(i, "string", v)),
+        }
+    }
+
+    /// Get the argument at index `i` as a table. Missing arguments are treated as `nil`.
+    pub fn check_table(&self, i: usize) -> Result<Table<'gc>, BadArgument> {
+        match self.get(i) {
+            Value::Table(t) => Ok(t),
+            v => Err(self.bad_argument(i, "table", v)),
+        }
+    }
+
+    fn bad_argument(&self, i: usize, expected: &'static str, found: Value<'gc>) -> BadArgument {
+        BadArgument {
+            index: i + 1,
+            expected,
+            found: if i < self.len() {
+                found.type_name()
+            } else {
+                "no value"
+            },
+        }
+    }
 }
 
 impl<'gc: 'b, 'a, 'b> IntoIterator for &'b Stack<'gc, 'a> {
@@ -1,13 +1,114 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, Read, Write},
+    rc::Rc,
+    string::String as StdString,
+};
+
 use gc_arena::Collect;
 
 use crate::{
+    constant::trim_whitespace,
+    dump,
     meta_ops::{self, MetaResult},
     table::NextValue,
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, MetaMethod,
-    Sequence, SequencePoll, Stack, String, Table, Value, Variadic,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Function, IntoValue,
+    MetaMethod, Sequence, SequencePoll, Stack, String, Table, Value, Variadic,
 };
 
+use super::io::strerror;
+
+/// Load the base library, with `print` writing to the process stdout.
 pub fn load_base<'gc>(ctx: Context<'gc>) {
+    load_base_with(ctx, io::stdout());
+}
+
+/// Load the base library, with `print` writing to the given output.
+pub fn load_base_with<'gc>(ctx: Context<'gc>, output: impl Write + 'static) {
+    ctx.set_global("_G", ctx.globals()).unwrap();
+
+    #[derive(Clone, Collect)]
+    #[collect(require_static)]
+    struct PrintOutput(Rc<RefCell<dyn Write>>);
+
+    ctx.set_global(
+        "print",
+        Callback::from_fn_with(
+            &ctx,
+            PrintOutput(Rc::new(RefCell::new(output))),
+            |output, ctx, _, mut stack| {
+                #[derive(Debug, Copy, Clone, Eq, PartialEq, Collect)]
+                #[collect(require_static)]
+                enum Mode {
+                    Init,
+                    First,
+                    Rest,
+                }
+
+                #[derive(Collect)]
+                #[collect(no_drop)]
+                struct PrintSeq<'gc> {
+                    output: PrintOutput,
+                    mode: Mode,
+                    values: Vec<Value<'gc>>,
+                }
+
+                impl<'gc> Sequence<'gc> for PrintSeq<'gc> {
+                    fn poll(
+                        &mut self,
+                        ctx: Context<'gc>,
+                        _exec: Execution<'gc, '_>,
+                        mut stack: Stack<'gc, '_>,
+                    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                        let mut output = self.output.0.borrow_mut();
+
+                        if self.mode == Mode::Init {
+                            self.mode = Mode::First;
+                        } else {
+                            self.values.push(stack.get(0));
+                        }
+                        stack.clear();
+
+                        while let Some(value) = self.values.pop() {
+                            match meta_ops::tostring(ctx, value)? {
+                                MetaResult::Value(v) => {
+                                    if self.mode == Mode::First {
+                                        self.mode = Mode::Rest;
+                                    } else {
+                                        output.write_all(&b"\t"[..])?;
+                                    }
+                                    v.display(&mut *output)?
+                                }
+                                MetaResult::Call(call) => {
+                                    stack.extend(call.args);
+                                    return Ok(SequencePoll::Call {
+                                        function: call.function,
+                                        is_tail: false,
+                                    });
+                                }
+                            }
+                        }
+
+                        output.write_all(&b"\n"[..])?;
+                        output.flush()?;
+                        Ok(SequencePoll::Return)
+                    }
+                }
+
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    PrintSeq {
+                        output: output.clone(),
+                        mode: Mode::Init,
+                        values: stack.drain(..).rev().collect(),
+                    },
+                )))
+            },
+        ),
+    )
+    .unwrap();
+
     ctx.set_global(
         "tostring",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
@@ -33,6 +134,36 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
+    ctx.set_global(
+        "tonumber",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            if stack.is_empty() {
+                return Err("bad argument #1 to 'tonumber' (value expected)"
+                    .into_value(ctx)
+                    .into());
+            }
+
+            let result = if stack.get(1).is_nil() {
+                // The same conversion as arithmetic operands.
+                stack.get(0).to_numeric().unwrap_or(Value::Nil)
+            } else {
+                let base = stack.check_integer(1)?;
+                if !(2..=36).contains(&base) {
+                    return Err("bad argument #2 to 'tonumber' (base out of range)"
+                        .into_value(ctx)
+                        .into());
+                }
+                let s = stack.check_string(ctx, 0)?;
+                read_integer_base(s.as_bytes(), base as u32)
+                    .map(Value::Integer)
+                    .unwrap_or(Value::Nil)
+            };
+            stack.replace(ctx, result);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
     ctx.set_global(
         "error",
         Callback::from_fn(&ctx, |_, _, stack| Err(stack.get(0).into())),
@@ -42,7 +173,7 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "assert",
         Callback::from_fn(&ctx, |ctx, _, stack| {
-            if stack.get(0).to_bool() {
+            if stack.get(0).is_truthy() {
                 Ok(CallbackReturn::Return)
             } else if stack.get(1).is_nil() {
                 Err("assertion failed!".into_value(ctx).into())
@@ -94,6 +225,100 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
+    ctx.set_global(
+        "load",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            #[derive(Collect)]
+            #[collect(no_drop)]
+            struct ReadChunk<'gc> {
+                reader: Function<'gc>,
+                started: bool,
+                source: Vec<u8>,
+                name: Option<String<'gc>>,
+                mode: Option<String<'gc>>,
+                env: Option<Table<'gc>>,
+            }
+
+            impl<'gc> Sequence<'gc> for ReadChunk<'gc> {
+                fn poll(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    if self.started {
+                        match stack.get(0) {
+                            Value::Nil => {}
+                            Value::String(s) if s.is_empty() => {}
+                            Value::String(s) => {
+                                self.source.extend(s.as_bytes());
+                                stack.clear();
+                                return Ok(SequencePoll::Call {
+                                    function: self.reader,
+                                    is_tail: false,
+                                });
+                            }
+                            _ => {
+                                stack.replace(
+                                    ctx,
+                                    (Value::Nil, "reader function must return a string"),
+                                );
+                                return Ok(SequencePoll::Return);
+                            }
+                        }
+
+                        let res = load_chunk(ctx, self.name, &self.source, self.mode, self.env);
+                        stack.replace(ctx, res);
+                        Ok(SequencePoll::Return)
+                    } else {
+                        self.started = true;
+                        stack.clear();
+                        Ok(SequencePoll::Call {
+                            function: self.reader,
+                            is_tail: false,
+                        })
+                    }
+                }
+
+                fn error(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    error: Error<'gc>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    stack.replace(ctx, (Value::Nil, error.to_value(ctx)));
+                    Ok(SequencePoll::Return)
+                }
+            }
+
+            let (chunk, name, mode, env): (Value, Option<String>, Option<String>, Option<Table>) =
+                stack.consume(ctx)?;
+
+            match chunk {
+                Value::String(source) => {
+                    // Like PUC-Rio Lua, a string chunk is named after its source by default.
+                    let name = name.or(Some(source));
+                    stack.replace(ctx, load_chunk(ctx, name, &source, mode, env));
+                    Ok(CallbackReturn::Return)
+                }
+                Value::Function(reader) => Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    ReadChunk {
+                        reader,
+                        started: false,
+                        source: Vec::new(),
+                        name,
+                        mode,
+                        env,
+                    },
+                ))),
+                _ => Err("Bad argument to load".into_value(ctx).into()),
+            }
+        }),
+    )
+    .unwrap();
+
     ctx.set_global(
         "type",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
@@ -169,7 +394,7 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         "setmetatable",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let (t, mt): (Table, Option<Table>) = stack.consume(ctx)?;
-            t.set_metatable(&ctx, mt);
+            t.set_metatable(ctx, mt);
             stack.replace(ctx, t);
             Ok(CallbackReturn::Return)
         }),
@@ -271,18 +496,214 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
 
     ctx.set_global(
         "collectgarbage",
-        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
-            match stack.consume::<Option<String>>(ctx)? {
-                Some(arg) if arg == "count" => {
-                    stack.into_back(ctx, ctx.metrics().total_allocation() as f64 / 1024.0);
+        Callback::from_fn(&ctx, move |ctx, mut exec, mut stack| {
+            // Collection cannot happen while inside the arena, so "collect" and "step" only
+            // request work which takes place after the current `Lua::enter` call returns. The
+            // fuel is interrupted so that the work happens before the script continues, weak
+            // tables are cleared by the time "collect" returns, and "step" can tell whether it
+            // finished a cycle.
+            let opt: Option<String> = stack.consume(ctx)?;
+            let control = ctx.gc_control();
+            match opt.as_ref().map(|s| s.as_bytes()).unwrap_or(b"collect") {
+                b"collect" => {
+                    control.collect();
+                    exec.fuel().interrupt();
+                    stack.replace(ctx, 0);
                 }
-                Some(_) => {
+                b"count" => {
+                    let total = ctx.metrics().total_allocation();
+                    stack.replace(ctx, (total as f64 / 1024.0, (total % 1024) as i64));
+                }
+                b"step" => {
+                    #[derive(Collect)]
+                    #[collect(require_static)]
+                    struct StepSeq(u64);
+
+                    impl<'gc> Sequence<'gc> for StepSeq {
+                        fn poll(
+                            &mut self,
+                            ctx: Context<'gc>,
+                            _exec: Execution<'gc, '_>,
+                            mut stack: Stack<'gc, '_>,
+                        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                            stack.replace(ctx, ctx.gc_control().cycles() != self.0);
+                            Ok(SequencePoll::Return)
+                        }
+                    }
+
+                    control.step();
+                    exec.fuel().interrupt();
+                    stack.clear();
+                    let seq = StepSeq(control.cycles());
+                    return Ok(CallbackReturn::Sequence(BoxSequence::new(&ctx, seq)));
+                }
+                b"stop" => {
+                    control.stop();
+                    stack.replace(ctx, 0);
+                }
+                b"restart" => {
+                    control.restart();
+                    stack.replace(ctx, 0);
+                }
+                b"isrunning" => {
+                    stack.replace(ctx, control.is_running());
+                }
+                _ => {
                     return Err("bad argument to 'collectgarbage'".into_value(ctx).into());
                 }
-                None => {}
             }
             Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
 }
+
+/// Load the global `loadfile` and `dofile` functions, reading from the process stdin when no file
+/// name is given.
+///
+/// These read from the filesystem, so unlike the rest of the base library they are not loaded by
+/// `load_base`.
+pub fn load_file_functions<'gc>(ctx: Context<'gc>) {
+    load_file_functions_with(ctx, io::stdin());
+}
+
+/// Load the global `loadfile` and `dofile` functions, reading from the given input when no file
+/// name is given.
+pub fn load_file_functions_with<'gc>(ctx: Context<'gc>, stdin: impl Read + 'static) {
+    #[derive(Clone, Collect)]
+    #[collect(require_static)]
+    struct FileInput(Rc<RefCell<dyn Read>>);
+
+    let stdin = FileInput(Rc::new(RefCell::new(stdin)));
+
+    ctx.set_global(
+        "loadfile",
+        Callback::from_fn_with(&ctx, stdin.clone(), |stdin, ctx, _, mut stack| {
+            let (filename, mode, env): (Option<String>, Option<String>, Option<Table>) =
+                stack.consume(ctx)?;
+            match load_file(ctx, &mut *stdin.0.borrow_mut(), filename, mode, env) {
+                Ok(closure) => stack.replace(ctx, closure),
+                Err(err) => stack.replace(ctx, (Value::Nil, err.into_value(ctx))),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    ctx.set_global(
+        "dofile",
+        Callback::from_fn_with(&ctx, stdin, |stdin, ctx, _, mut stack| {
+            let filename: Option<String> = stack.consume(ctx)?;
+            match load_file(ctx, &mut *stdin.0.borrow_mut(), filename, None, None) {
+                Ok(closure) => Ok(CallbackReturn::Call {
+                    function: closure.into(),
+                    then: None,
+                }),
+                Err(err) => Err(err.into_value(ctx).into()),
+            }
+        }),
+    )
+    .unwrap();
+}
+
+// Read and compile a chunk for `loadfile` and `dofile`, reading from `stdin` if there is no file
+// name.
+//
+// The chunk is named `@filename`, or `=stdin`. If the first line starts with `#` (such as a Unix
+// shebang line), it is skipped.
+fn load_file<'gc>(
+    ctx: Context<'gc>,
+    stdin: &mut dyn Read,
+    filename: Option<String<'gc>>,
+    mode: Option<String<'gc>>,
+    env: Option<Table<'gc>>,
+) -> Result<Closure<'gc>, StdString> {
+    let (name, source) = match filename {
+        Some(filename) => {
+            let filename = filename.to_str_lossy();
+            let source = fs::read(&*filename)
+                .map_err(|err| format!("cannot open {filename}: {}", strerror(&err)))?;
+            (format!("@{filename}"), source)
+        }
+        None => {
+            let mut source = Vec::new();
+            stdin
+                .read_to_end(&mut source)
+                .map_err(|err| format!("cannot read stdin: {}", strerror(&err)))?;
+            ("=stdin".to_owned(), source)
+        }
+    };
+
+    let mut source = &source[..];
+    if source.starts_with(b"#") {
+        // Keep the newline so that line numbers are unchanged.
+        let end = source
+            .iter()
+            .position(|&b| b == b'\n')
+            .unwrap_or(source.len());
+        source = &source[end..];
+    }
+
+    compile_chunk(ctx, &name, source, mode, env)
+}
+
+// Compile a chunk for `load`, returning either the loaded function or `nil` and an error message.
+fn load_chunk<'gc>(
+    ctx: Context<'gc>,
+    name: Option<String<'gc>>,
+    source: &[u8],
+    mode: Option<String<'gc>>,
+    env: Option<Table<'gc>>,
+) -> (Value<'gc>, Value<'gc>) {
+    let name = name.map(|n| n.to_str_lossy());
+    match compile_chunk(ctx, name.as_deref().unwrap_or("=(load)"), source, mode, env) {
+        Ok(closure) => (closure.into(), Value::Nil),
+        Err(err) => (Value::Nil, err.into_value(ctx)),
+    }
+}
+
+// Compile a text chunk or load a binary chunk, if allowed by `mode` (which defaults to "bt").
+fn compile_chunk<'gc>(
+    ctx: Context<'gc>,
+    name: &str,
+    source: &[u8],
+    mode: Option<String<'gc>>,
+    env: Option<Table<'gc>>,
+) -> Result<Closure<'gc>, StdString> {
+    let mode = mode.as_ref().map(|m| m.as_bytes()).unwrap_or(b"bt");
+    let env = env.unwrap_or_else(|| ctx.globals());
+    let is_binary = dump::is_binary_chunk(source);
+    if !mode.contains(if is_binary { &b'b' } else { &b't' }) {
+        return Err(format!(
+            "attempt to load a {} chunk (mode is '{}')",
+            if is_binary { "binary" } else { "text" },
+            StdString::from_utf8_lossy(mode)
+        ));
+    }
+
+    if is_binary {
+        Closure::load_binary(ctx, source, env).map_err(|err| format!("{name}: {err}"))
+    } else {
+        Closure::load_with_env(ctx, Some(name), source, env).map_err(|err| err.to_string())
+    }
+}
+
+// Parse an integer in the given base the same way as `tonumber` with a base argument: surrounding
+// whitespace and a leading `-` are allowed, and the value wraps around on overflow.
+fn read_integer_base(s: &[u8], base: u32) -> Option<i64> {
+    let s = trim_whitespace(s);
+    let (negative, digits) = match s.strip_prefix(b"-") {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut n: i64 = 0;
+    for &c in digits {
+        let digit = (c as char).to_digit(base)?;
+        n = n.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Some(if negative { n.wrapping_neg() } else { n })
+}
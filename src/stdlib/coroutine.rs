@@ -1,8 +1,8 @@
 use gc_arena::Collect;
 
 use crate::{
-    meta_ops, BoxSequence, Callback, CallbackReturn, Context, Execution, Sequence, SequencePoll,
-    Stack, Table, Thread, ThreadMode,
+    meta_ops, BadThreadMode, BoxSequence, Callback, CallbackReturn, Context, Error, Execution,
+    IntoValue, Sequence, SequencePoll, Stack, Table, Thread, ThreadMode, Value,
 };
 
 pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
@@ -76,6 +76,63 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    coroutine
+        .set(
+            ctx,
+            "wrap",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let thread = Thread::new(ctx);
+                thread
+                    .start_suspended(&ctx, meta_ops::call(ctx, stack.get(0))?)
+                    .unwrap();
+                stack.replace(
+                    ctx,
+                    Callback::from_fn_with(&ctx, thread, |thread, _, _, _| {
+                        Ok(CallbackReturn::Resume {
+                            thread: *thread,
+                            then: None,
+                        })
+                    }),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            ctx,
+            "close",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let thread: Thread = stack.consume(ctx)?;
+                let pending = thread.take_to_be_closed(&ctx).unwrap_or_default();
+                match thread.close(&ctx) {
+                    Ok(Ok(())) if !pending.is_empty() => {
+                        return Ok(CallbackReturn::Sequence(BoxSequence::new(
+                            &ctx,
+                            CloseSeq {
+                                pending,
+                                error: None,
+                            },
+                        )));
+                    }
+                    Ok(Ok(())) => stack.replace(ctx, true),
+                    Ok(Err(err)) => stack.replace(ctx, (false, err.to_value(ctx))),
+                    Err(BadThreadMode { found, .. }) => {
+                        return Err(if found == ThreadMode::Running {
+                            "cannot close a running coroutine"
+                        } else {
+                            "cannot close a normal coroutine"
+                        }
+                        .into_value(ctx)
+                        .into())
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     coroutine
         .set(
             ctx,
@@ -100,7 +157,12 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         .set(
             ctx,
             "yield",
-            Callback::from_fn(&ctx, |_, _, _| {
+            Callback::from_fn(&ctx, |ctx, exec, _| {
+                if !exec.is_yieldable() {
+                    return Err("attempt to yield from outside a coroutine"
+                        .into_value(ctx)
+                        .into());
+                }
                 Ok(CallbackReturn::Yield {
                     to_thread: None,
                     then: None,
@@ -135,5 +197,78 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    coroutine
+        .set(
+            ctx,
+            "isyieldable",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                stack.replace(ctx, exec.is_yieldable());
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     ctx.set_global("coroutine", coroutine).unwrap();
 }
+
+// Calls the `__close` metamethods of the pending to-be-closed variables of a closed coroutine, the
+// most recent first. If any of them fail, the rest are called with the first error, which is
+// returned as `false, err`.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct CloseSeq<'gc> {
+    pending: Vec<Value<'gc>>,
+    error: Option<Value<'gc>>,
+}
+
+impl<'gc> CloseSeq<'gc> {
+    fn close_next(
+        &mut self,
+        ctx: Context<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.clear();
+        while let Some(value) = self.pending.pop() {
+            match meta_ops::close(ctx, value, self.error.unwrap_or_default()) {
+                Ok(call) => {
+                    stack.extend(call.args);
+                    return Ok(SequencePoll::Call {
+                        function: call.function,
+                        is_tail: false,
+                    });
+                }
+                Err(err) => {
+                    self.error.get_or_insert(Error::from(err).to_value(ctx));
+                }
+            }
+        }
+
+        match self.error {
+            None => stack.replace(ctx, true),
+            Some(err) => stack.replace(ctx, (false, err)),
+        }
+        Ok(SequencePoll::Return)
+    }
+}
+
+impl<'gc> Sequence<'gc> for CloseSeq<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.close_next(ctx, stack)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.error.get_or_insert(error.to_value(ctx));
+        self.close_next(ctx, stack)
+    }
+}
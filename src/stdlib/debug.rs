@@ -0,0 +1,485 @@
+use std::string::String as StdString;
+
+use gc_arena::Gc;
+
+use crate::{
+    compiler::{FunctionRef, LineNumber},
+    thread::callback_name,
+    Callback, CallbackReturn, Closure, Context, Error, FrameInfo, Function, Hook, HookMask,
+    IntoValue, LightUserData, String, Table, Thread, Value,
+};
+
+pub fn load_debug<'gc>(ctx: Context<'gc>) {
+    let debug = Table::new(&ctx);
+
+    debug
+        .set(
+            ctx,
+            "traceback",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => {
+                        stack.pop_front();
+                        Some(thread)
+                    }
+                    _ => None,
+                };
+                let (msg, level): (Value, Option<i64>) = stack.consume(ctx)?;
+
+                // Like PUC-Rio Lua, non-string messages are returned untouched.
+                let msg = match msg {
+                    Value::Nil => None,
+                    Value::String(s) => Some(s),
+                    Value::Integer(_) | Value::Number(_) => {
+                        Some(ctx.intern(msg.to_string().as_bytes()))
+                    }
+                    v => {
+                        stack.replace(ctx, v);
+                        return Ok(CallbackReturn::Return);
+                    }
+                };
+                let level = level.unwrap_or(1).max(0) as usize;
+
+                let traceback = match thread {
+                    Some(thread) if thread != exec.current_thread().thread => thread
+                        .traceback(ctx, level)
+                        .map_err(|_| "cannot trace a running thread".into_value(ctx))?,
+                    _ => exec.traceback(ctx, level),
+                };
+
+                let mut out = Vec::new();
+                if let Some(msg) = msg {
+                    out.extend(msg.as_bytes());
+                    out.push(b'\n');
+                }
+                out.extend(traceback.as_bytes());
+                stack.replace(ctx, ctx.intern(&out));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getinfo",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => {
+                        stack.pop_front();
+                        Some(thread)
+                    }
+                    _ => None,
+                };
+                let (target, what): (Value, Option<String>) = stack.consume(ctx)?;
+                let what = what.as_ref().map(|s| s.as_bytes()).unwrap_or(b"flnSu");
+
+                let (function, current_line) = match target {
+                    Value::Function(function) => (Some(function), None),
+                    Value::Integer(_) | Value::Number(_) => {
+                        let Some(level) = target.to_integer().filter(|&l| l >= 0) else {
+                            return Err("Bad argument to getinfo".into_value(ctx).into());
+                        };
+                        let level = level as usize;
+
+                        let info = match thread {
+                            Some(thread) if thread != exec.current_thread().thread => thread
+                                .frame_info(level)
+                                .map_err(|_| "cannot inspect a running thread".into_value(ctx))?,
+                            // Level 0 is `getinfo` itself.
+                            _ if level == 0 => Some(FrameInfo::Callback { callback: None }),
+                            _ => exec.frame_info(level),
+                        };
+
+                        match info {
+                            Some(FrameInfo::Lua {
+                                closure,
+                                current_line,
+                            }) => (Some(closure.into()), Some(current_line)),
+                            Some(FrameInfo::Callback { callback }) => {
+                                (callback.map(Function::Callback), None)
+                            }
+                            None => {
+                                stack.replace(ctx, Value::Nil);
+                                return Ok(CallbackReturn::Return);
+                            }
+                        }
+                    }
+                    _ => return Err("Bad argument to getinfo".into_value(ctx).into()),
+                };
+
+                let info = Table::new(&ctx);
+                let proto = match function {
+                    Some(Function::Closure(closure)) => Some(closure.prototype()),
+                    _ => None,
+                };
+
+                for &opt in what {
+                    match opt {
+                        b'S' => {
+                            if let Some(proto) = proto {
+                                let is_main = matches!(proto.reference, FunctionRef::Chunk);
+
+                                info.set(ctx, "source", proto.chunk_name)?;
+                                info.set(ctx, "short_src", proto.short_src())?;
+                                info.set(
+                                    ctx,
+                                    "linedefined",
+                                    proto.line_defined().map(lua_line).unwrap_or(0),
+                                )?;
+                                info.set(
+                                    ctx,
+                                    "lastlinedefined",
+                                    proto.last_line_defined().map(lua_line).unwrap_or(-1),
+                                )?;
+                                info.set(ctx, "what", if is_main { "main" } else { "Lua" })?;
+                            } else {
+                                info.set(ctx, "source", "=[C]")?;
+                                info.set(ctx, "short_src", "[C]")?;
+                                info.set(ctx, "linedefined", -1)?;
+                                info.set(ctx, "lastlinedefined", -1)?;
+                                info.set(ctx, "what", "C")?;
+                            }
+                        }
+                        b'l' => {
+                            info.set(ctx, "currentline", current_line.map(lua_line).unwrap_or(-1))?;
+                        }
+                        b'u' => {
+                            if let Some(proto) = proto {
+                                info.set(ctx, "nups", proto.upvalue_count() as i64)?;
+                                info.set(ctx, "nparams", proto.param_count() as i64)?;
+                                info.set(ctx, "isvararg", proto.is_vararg())?;
+                            } else {
+                                info.set(ctx, "nups", 0)?;
+                                info.set(ctx, "nparams", 0)?;
+                                info.set(ctx, "isvararg", true)?;
+                            }
+                        }
+                        b'n' => {
+                            // Names come from the function definition rather than the call site,
+                            // callbacks are named after the global they are registered under.
+                            let name = match function {
+                                Some(Function::Closure(closure)) => {
+                                    match closure.prototype().reference {
+                                        FunctionRef::Named(name, _) => Some(name),
+                                        _ => None,
+                                    }
+                                }
+                                Some(Function::Callback(callback)) => callback_name(ctx, callback)
+                                    .map(|name| ctx.intern(name.as_bytes())),
+                                None => None,
+                            };
+                            if let Some(name) = name {
+                                info.set(ctx, "name", name)?;
+                                info.set(ctx, "namewhat", "function")?;
+                            } else {
+                                info.set(ctx, "namewhat", "")?;
+                            }
+                        }
+                        b'f' => {
+                            if let Some(function) = function {
+                                info.set(ctx, "func", function)?;
+                            }
+                        }
+                        b't' => {
+                            info.set(ctx, "istailcall", false)?;
+                        }
+                        _ => return Err("invalid option to getinfo".into_value(ctx).into()),
+                    }
+                }
+
+                stack.replace(ctx, info);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getlocal",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => {
+                        stack.pop_front();
+                        Some(thread)
+                    }
+                    _ => None,
+                };
+                let (target, n): (Value, i64) = stack.consume(ctx)?;
+                let n = usize::try_from(n).unwrap_or(0);
+
+                if let Value::Function(function) = target {
+                    // For functions, only the names of parameters are available.
+                    let name = match function {
+                        Function::Closure(closure) => {
+                            let proto = closure.prototype();
+                            proto
+                                .local_variable(0, n)
+                                .filter(|_| n <= proto.fixed_params as usize)
+                                .map(|l| l.name)
+                        }
+                        Function::Callback(_) => None,
+                    };
+                    stack.replace(ctx, name);
+                    return Ok(CallbackReturn::Return);
+                }
+
+                let level = local_level(ctx, target, "getlocal")?;
+                let local = match thread {
+                    Some(thread) if thread != exec.current_thread().thread => {
+                        let local = thread
+                            .local_variable(level, n)
+                            .map_err(|_| "cannot inspect a running thread".into_value(ctx))?;
+                        if local.is_none() && matches!(thread.frame_info(level), Ok(None)) {
+                            return Err(level_out_of_range(ctx));
+                        }
+                        local
+                    }
+                    _ => {
+                        if level != 0 && exec.frame_info(level).is_none() {
+                            return Err(level_out_of_range(ctx));
+                        }
+                        exec.local_variable(&mut stack, level, n)
+                    }
+                };
+
+                match local {
+                    Some((name, value)) => stack.replace(ctx, (name, value)),
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "setlocal",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => {
+                        stack.pop_front();
+                        Some(thread)
+                    }
+                    _ => None,
+                };
+                let (level, n, value): (Value, i64, Value) = stack.consume(ctx)?;
+                let level = local_level(ctx, level, "setlocal")?;
+                let n = usize::try_from(n).unwrap_or(0);
+
+                let name = match thread {
+                    Some(thread) if thread != exec.current_thread().thread => {
+                        let name = thread
+                            .set_local_variable(&ctx, level, n, value)
+                            .map_err(|_| "cannot modify a running thread".into_value(ctx))?;
+                        if name.is_none() && matches!(thread.frame_info(level), Ok(None)) {
+                            return Err(level_out_of_range(ctx));
+                        }
+                        name
+                    }
+                    _ => {
+                        if level != 0 && exec.frame_info(level).is_none() {
+                            return Err(level_out_of_range(ctx));
+                        }
+                        exec.set_local_variable(&mut stack, level, n, value)
+                    }
+                };
+
+                stack.replace(ctx, name);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getupvalue",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let (function, n): (Function, i64) = stack.consume(ctx)?;
+                match upvalue(function, n) {
+                    Some((closure, n)) => {
+                        let name = closure.prototype().upvalue_names[n];
+                        let value = exec.get_upvalue(&ctx, &mut stack, closure.upvalues()[n].get());
+                        stack.replace(ctx, (name, value));
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "setupvalue",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let (function, n, value): (Function, i64, Value) = stack.consume(ctx)?;
+                match upvalue(function, n) {
+                    Some((closure, n)) => {
+                        exec.set_upvalue(&ctx, &mut stack, closure.upvalues()[n].get(), value);
+                        stack.replace(ctx, closure.prototype().upvalue_names[n]);
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "upvalueid",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (function, n): (Function, i64) = stack.consume(ctx)?;
+                let Some((closure, n)) = upvalue(function, n) else {
+                    return Err("invalid upvalue index".into_value(ctx).into());
+                };
+                // As in PUC-Rio Lua, upvalues are identified by a light userdata holding their
+                // address.
+                let id = Gc::as_ptr(closure.upvalues()[n].get().into_inner());
+                stack.replace(ctx, LightUserData::new(id));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "upvaluejoin",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (f1, n1, f2, n2): (Function, i64, Function, i64) = stack.consume(ctx)?;
+                let (Some((f1, n1)), Some((f2, n2))) = (upvalue(f1, n1), upvalue(f2, n2)) else {
+                    return Err("invalid upvalue index".into_value(ctx).into());
+                };
+                f1.set_upvalue(&ctx, n1, f2.upvalues()[n2].get());
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "sethook",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => {
+                        stack.pop_front();
+                        Some(thread)
+                    }
+                    _ => None,
+                };
+                let (function, mask, count): (Option<Function>, Option<String>, Option<i64>) =
+                    stack.consume(ctx)?;
+
+                let hook = match function {
+                    Some(function) => {
+                        let mut hook_mask = HookMask::default();
+                        for &c in mask.as_ref().map(|s| s.as_bytes()).unwrap_or(b"") {
+                            match c {
+                                b'c' => hook_mask.call = true,
+                                b'r' => hook_mask.ret = true,
+                                b'l' => hook_mask.line = true,
+                                _ => {}
+                            }
+                        }
+                        let count = count.unwrap_or(0).clamp(0, u32::MAX as i64) as u32;
+                        if hook_mask == HookMask::default() && count == 0 {
+                            None
+                        } else {
+                            Some(Hook {
+                                function,
+                                mask: hook_mask,
+                                count,
+                            })
+                        }
+                    }
+                    None => None,
+                };
+
+                match thread {
+                    Some(thread) if thread != exec.current_thread().thread => thread
+                        .set_hook(&ctx, hook)
+                        .map_err(|_| "cannot set the hook of a running thread".into_value(ctx))?,
+                    _ => exec.set_hook(hook),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "gethook",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread: Option<Thread> = stack.consume(ctx)?;
+                let hook = match thread {
+                    Some(thread) if thread != exec.current_thread().thread => thread
+                        .hook()
+                        .map_err(|_| "cannot get the hook of a running thread".into_value(ctx))?,
+                    _ => exec.hook(),
+                };
+
+                match hook {
+                    Some(hook) => {
+                        let mut mask = StdString::new();
+                        if hook.mask.call {
+                            mask.push('c');
+                        }
+                        if hook.mask.ret {
+                            mask.push('r');
+                        }
+                        if hook.mask.line {
+                            mask.push('l');
+                        }
+                        stack.replace(
+                            ctx,
+                            (
+                                hook.function,
+                                ctx.intern(mask.as_bytes()),
+                                hook.count as i64,
+                            ),
+                        );
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    ctx.set_global("debug", debug).unwrap();
+}
+
+// Convert a stack level argument to `getlocal` or `setlocal`.
+fn local_level<'gc>(ctx: Context<'gc>, level: Value<'gc>, name: &str) -> Result<usize, Error<'gc>> {
+    level
+        .to_integer()
+        .and_then(|l| usize::try_from(l).ok())
+        .ok_or_else(|| format!("Bad argument to {name}").into_value(ctx).into())
+}
+
+fn level_out_of_range<'gc>(ctx: Context<'gc>) -> Error<'gc> {
+    "level out of range".into_value(ctx).into()
+}
+
+// Find the Lua closure and zero-based index of the `n`th upvalue of a function.
+fn upvalue(function: Function<'_>, n: i64) -> Option<(Closure<'_>, usize)> {
+    let Function::Closure(closure) = function else {
+        return None;
+    };
+    let n = usize::try_from(n).ok()?.checked_sub(1)?;
+    (n < closure.upvalues().len()).then_some((closure, n))
+}
+
+// `LineNumber` is zero-based, Lua line numbers are one-based.
+fn lua_line(line: LineNumber) -> i64 {
+    line.0 as i64 + 1
+}
@@ -1,82 +1,645 @@
-use std::io::{self, Write};
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+};
 
 use gc_arena::Collect;
 
 use crate::{
-    meta_ops::{self, MetaResult},
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Sequence, SequencePoll,
-    Stack, Value,
+    compiler::lexer::{read_float, read_integer},
+    value::format_float,
+    BadArgument, Callback, CallbackReturn, Context, Error, IntoValue, Stack, Table, UserData,
+    VMError, Value, Variadic,
 };
 
+/// Load the `io` library, writing to the process stdout and stderr.
 pub fn load_io<'gc>(ctx: Context<'gc>) {
-    ctx.set_global(
-        "print",
-        Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            #[derive(Debug, Copy, Clone, Eq, PartialEq, Collect)]
-            #[collect(require_static)]
-            enum Mode {
-                Init,
-                First,
-                Rest,
-            }
+    load_io_with(ctx, io::stdout(), io::stderr());
+}
 
-            #[derive(Collect)]
-            #[collect(no_drop)]
-            struct PrintSeq<'gc> {
-                mode: Mode,
-                values: Vec<Value<'gc>>,
-            }
+/// Load the `io` library, using the given writers for `io.stdout` and `io.stderr`.
+///
+/// This allows embedders to capture or redirect the output of `io.write`.
+pub fn load_io_with<'gc>(
+    ctx: Context<'gc>,
+    stdout: impl Write + 'static,
+    stderr: impl Write + 'static,
+) {
+    let io = Table::new(&ctx);
 
-            impl<'gc> Sequence<'gc> for PrintSeq<'gc> {
-                fn poll(
-                    &mut self,
-                    ctx: Context<'gc>,
-                    _exec: Execution<'gc, '_>,
-                    mut stack: Stack<'gc, '_>,
-                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
-                    let mut stdout = io::stdout();
-
-                    if self.mode == Mode::Init {
-                        self.mode = Mode::First;
-                    } else {
-                        self.values.push(stack.get(0));
+    let methods = Table::new(&ctx);
+    methods
+        .set(
+            ctx,
+            "write",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (handle, file) = file_arg(stack.get(0))?;
+                match file.write_values(&stack, 1)? {
+                    Ok(()) => stack.replace(ctx, handle),
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "read",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (_, file) = file_arg(stack.get(0))?;
+                let formats = stack[1..].to_vec();
+                match file.read_values(ctx, &formats)? {
+                    Ok(values) => stack.replace(ctx, Variadic(values)),
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "lines",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (handle, _) = file_arg(stack.get(0))?;
+                let formats = stack[1..].to_vec();
+                stack.replace(ctx, lines_iter(ctx, handle, formats, false));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "seek",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (_, file) = file_arg(stack.get(0))?;
+                let whence = match stack.get(1) {
+                    Value::Nil => "cur".into_value(ctx),
+                    v => v,
+                };
+                let offset = match stack.get(2) {
+                    Value::Nil => 0,
+                    v => v.to_integer().ok_or(BadArgument {
+                        index: 3,
+                        expected: "number",
+                        found: v.type_name(),
+                    })?,
+                };
+                let pos = match whence {
+                    Value::String(s) if s == "set" => {
+                        SeekFrom::Start(u64::try_from(offset).unwrap_or(0))
                     }
-                    stack.clear();
-
-                    while let Some(value) = self.values.pop() {
-                        match meta_ops::tostring(ctx, value)? {
-                            MetaResult::Value(v) => {
-                                if self.mode == Mode::First {
-                                    self.mode = Mode::Rest;
-                                } else {
-                                    stdout.write_all(&b"\t"[..])?;
-                                }
-                                v.display(&mut stdout)?
-                            }
-                            MetaResult::Call(call) => {
-                                stack.extend(call.args);
-                                return Ok(SequencePoll::Call {
-                                    function: call.function,
-                                    is_tail: false,
-                                });
-                            }
-                        }
+                    Value::String(s) if s == "cur" => SeekFrom::Current(offset),
+                    Value::String(s) if s == "end" => SeekFrom::End(offset),
+                    _ => {
+                        return Err("bad argument #2 to 'seek' (invalid option)"
+                            .into_value(ctx)
+                            .into())
                     }
+                };
+                match file.seek(pos)? {
+                    Ok(pos) => stack.replace(ctx, pos as i64),
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "flush",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (handle, file) = file_arg(stack.get(0))?;
+                match file.flush()? {
+                    Ok(()) => stack.replace(ctx, handle),
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let close = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        let (_, file) = file_arg(stack.get(0))?;
+        if file.close()? {
+            stack.replace(ctx, true);
+        } else {
+            stack.replace(ctx, (Value::Nil, "cannot close standard file"));
+        }
+        Ok(CallbackReturn::Return)
+    });
+    methods.set(ctx, "close", close).unwrap();
 
-                    stdout.write_all(&b"\n"[..])?;
-                    stdout.flush()?;
-                    Ok(SequencePoll::Return)
+    let metatable = Table::new(&ctx);
+    metatable.set(ctx, "__index", methods).unwrap();
+    metatable.set(ctx, "__name", "FILE*").unwrap();
+    metatable
+        .set(
+            ctx,
+            "__tostring",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (handle, file) = file_arg(stack.get(0))?;
+                let s = if matches!(*file.state.borrow(), FileState::Closed) {
+                    "file (closed)".to_owned()
+                } else {
+                    format!("file ({:p})", handle.into_inner())
+                };
+                stack.replace(ctx, ctx.intern(s.as_bytes()));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+    // Files are also closed when the userdata is dropped, but `__gc` closes them as soon as they
+    // are collected and allows finalizing them explicitly, the same as PUC-Rio Lua. Unlike
+    // `close`, finalizing a file which is already closed does nothing.
+    metatable
+        .set(
+            ctx,
+            "__gc",
+            Callback::from_fn(&ctx, |_, _, stack| {
+                let (_, file) = file_arg(stack.get(0))?;
+                if !matches!(*file.state.borrow(), FileState::Closed) {
+                    file.close()?;
                 }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let stdout = new_handle(ctx, metatable, FileState::Std(Box::new(stdout)));
+    let stderr = new_handle(ctx, metatable, FileState::Std(Box::new(stderr)));
+
+    io.set(ctx, "stdout", stdout).unwrap();
+    io.set(ctx, "stderr", stderr).unwrap();
+
+    io.set(
+        ctx,
+        "write",
+        Callback::from_fn_with(&ctx, stdout, |stdout, ctx, _, mut stack| {
+            // `io.write` always writes to standard output, there is no way to change the default
+            // output file yet.
+            let file = stdout.downcast_static::<FileHandle>().unwrap();
+            match file.write_values(&stack, 0)? {
+                Ok(()) => stack.replace(ctx, *stdout),
+                Err(err) => stack.replace(ctx, io_error(ctx, err)),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    io.set(
+        ctx,
+        "open",
+        Callback::from_fn_with(&ctx, metatable, |metatable, ctx, _, mut stack| {
+            let (filename, mode): (crate::String, Option<crate::String>) = stack.consume(ctx)?;
+            let Some(options) = open_options(mode.as_ref().map(|m| m.as_bytes()).unwrap_or(b"r"))
+            else {
+                return Err("bad argument #2 to 'open' (invalid mode)"
+                    .into_value(ctx)
+                    .into());
+            };
+
+            let path = filename.to_str_lossy();
+            match options.open(path.as_ref()) {
+                Ok(file) => {
+                    let state = FileState::File(BufReader::new(file));
+                    stack.replace(ctx, new_handle(ctx, *metatable, state));
+                }
+                Err(err) => stack.replace(ctx, file_error(ctx, &path, err)),
             }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
 
-            Ok(CallbackReturn::Sequence(BoxSequence::new(
-                &ctx,
-                PrintSeq {
-                    mode: Mode::Init,
-                    values: stack.drain(..).rev().collect(),
-                },
-            )))
+    io.set(
+        ctx,
+        "lines",
+        Callback::from_fn_with(&ctx, metatable, |metatable, ctx, _, mut stack| {
+            let filename: crate::String = stack.from_front(ctx)?;
+            let path = filename.to_str_lossy();
+            match fs::File::open(path.as_ref()) {
+                Ok(file) => {
+                    let handle = new_handle(ctx, *metatable, FileState::File(BufReader::new(file)));
+                    let formats = stack.drain(..).collect();
+                    stack.replace(ctx, lines_iter(ctx, handle, formats, true));
+                    Ok(CallbackReturn::Return)
+                }
+                Err(err) => Err(file_error(ctx, &path, err).1.into()),
+            }
         }),
     )
     .unwrap();
+
+    ctx.set_global("io", io).unwrap();
+}
+
+fn new_handle<'gc>(ctx: Context<'gc>, metatable: Table<'gc>, state: FileState) -> UserData<'gc> {
+    let handle = UserData::new_static(
+        &ctx,
+        FileHandle {
+            state: RefCell::new(state),
+        },
+    );
+    handle.set_metatable(&ctx, Some(metatable));
+    ctx.finalizers().register_userdata(&ctx, handle);
+    handle
+}
+
+// Check that the given value is a file handle userdata.
+fn file_arg<'gc>(value: Value<'gc>) -> Result<(UserData<'gc>, &'gc FileHandle), BadArgument> {
+    if let Value::UserData(ud) = value {
+        if let Ok(file) = ud.downcast_static::<FileHandle>() {
+            return Ok((ud, file));
+        }
+    }
+    Err(BadArgument {
+        index: 1,
+        expected: "FILE*",
+        found: value.type_name(),
+    })
+}
+
+// Create an iterator function that reads from a file with the given formats.
+fn lines_iter<'gc>(
+    ctx: Context<'gc>,
+    handle: UserData<'gc>,
+    formats: Vec<Value<'gc>>,
+    close_at_eof: bool,
+) -> Callback<'gc> {
+    #[derive(Collect)]
+    #[collect(no_drop)]
+    struct Lines<'gc> {
+        handle: UserData<'gc>,
+        formats: Vec<Value<'gc>>,
+        close_at_eof: bool,
+    }
+
+    Callback::from_fn_with(
+        &ctx,
+        Lines {
+            handle,
+            formats,
+            close_at_eof,
+        },
+        |lines, ctx, _, mut stack| {
+            let file = lines.handle.downcast_static::<FileHandle>().unwrap();
+            let values = match file.read_values(ctx, &lines.formats)? {
+                Ok(values) => values,
+                Err(err) => return Err(Error::from_std(ctx, err)),
+            };
+            if matches!(values.first(), None | Some(Value::Nil)) && lines.close_at_eof {
+                file.close()?;
+            }
+            stack.replace(ctx, Variadic(values));
+            Ok(CallbackReturn::Return)
+        },
+    )
+}
+
+// Convert a Lua `io.open` mode string into file open options.
+fn open_options(mode: &[u8]) -> Option<fs::OpenOptions> {
+    let (mode, plus) = match mode {
+        [m, rest @ ..] => match rest {
+            [] | [b'b'] => (*m, false),
+            [b'+'] | [b'+', b'b'] => (*m, true),
+            _ => return None,
+        },
+        [] => return None,
+    };
+
+    let mut options = fs::OpenOptions::new();
+    match mode {
+        b'r' => options.read(true).write(plus),
+        b'w' => options.write(true).create(true).truncate(true).read(plus),
+        b'a' => options.append(true).create(true).read(plus),
+        _ => return None,
+    };
+    Some(options)
+}
+
+// The message for an I/O error in the same form as C's `strerror`, without the " (os error N)"
+// suffix that Rust adds.
+pub(super) fn strerror(err: &io::Error) -> String {
+    let msg = err.to_string();
+    match err.raw_os_error() {
+        Some(code) => match msg.strip_suffix(&format!(" (os error {code})")) {
+            Some(msg) => msg.to_owned(),
+            None => msg,
+        },
+        None => msg,
+    }
+}
+
+// The results of a failed I/O operation, in the same form as PUC-Rio Lua's `luaL_fileresult`.
+pub(super) fn io_error<'gc>(
+    ctx: Context<'gc>,
+    err: io::Error,
+) -> (Value<'gc>, Value<'gc>, Value<'gc>) {
+    (
+        Value::Nil,
+        ctx.intern(strerror(&err).as_bytes()).into(),
+        err.raw_os_error().map(|e| e as i64).into_value(ctx),
+    )
+}
+
+pub(super) fn file_error<'gc>(
+    ctx: Context<'gc>,
+    path: &str,
+    err: io::Error,
+) -> (Value<'gc>, Value<'gc>, Value<'gc>) {
+    let msg = format!("{path}: {}", strerror(&err));
+    (
+        Value::Nil,
+        ctx.intern(msg.as_bytes()).into(),
+        err.raw_os_error().map(|e| e as i64).into_value(ctx),
+    )
+}
+
+#[derive(Debug, Copy, Clone, thiserror::Error)]
+#[error("attempt to use a closed file")]
+struct ClosedFile;
+
+// The contents of a file handle userdata.
+struct FileHandle {
+    state: RefCell<FileState>,
+}
+
+enum FileState {
+    // A standard output stream, which can only be written to.
+    Std(Box<dyn Write>),
+    File(BufReader<fs::File>),
+    Closed,
+}
+
+// The possible formats for reading from a file.
+enum ReadFormat {
+    Number,
+    Line { keep_newline: bool },
+    All,
+    Count(usize),
+}
+
+impl ReadFormat {
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::String(s) => {
+                // Lua 5.1 required formats to be prefixed with '*', this is still accepted.
+                let s = s.as_bytes();
+                match s.strip_prefix(b"*").unwrap_or(s).first() {
+                    Some(b'n') => Some(ReadFormat::Number),
+                    Some(b'l') => Some(ReadFormat::Line {
+                        keep_newline: false,
+                    }),
+                    Some(b'L') => Some(ReadFormat::Line { keep_newline: true }),
+                    Some(b'a') => Some(ReadFormat::All),
+                    _ => None,
+                }
+            }
+            v => v
+                .to_integer()
+                .map(|i| ReadFormat::Count(usize::try_from(i).unwrap_or(0))),
+        }
+    }
+}
+
+impl FileHandle {
+    // Write every string or number argument starting at `start`, in the same way as the `write`
+    // method of Lua file handles.
+    fn write_values<'gc>(
+        &self,
+        stack: &Stack<'gc, '_>,
+        start: usize,
+    ) -> Result<Result<(), io::Error>, Error<'gc>> {
+        let mut state = self.state.borrow_mut();
+        let writer: &mut dyn Write = match &mut *state {
+            FileState::Std(w) => w,
+            FileState::File(reader) => {
+                // Discard any buffered input so that writes happen at the current position.
+                if let Err(err) = reader
+                    .stream_position()
+                    .and_then(|pos| reader.seek(SeekFrom::Start(pos)))
+                {
+                    return Ok(Err(err));
+                }
+                reader.get_mut()
+            }
+            FileState::Closed => return Err(ClosedFile.into()),
+        };
+
+        for i in start..stack.len() {
+            let res = match stack[i] {
+                Value::String(s) => writer.write_all(s.as_bytes()),
+                Value::Integer(i) => write!(writer, "{i}"),
+                Value::Number(n) => writer.write_all(format_float(n).as_bytes()),
+                v => {
+                    return Err(BadArgument {
+                        index: i + 1,
+                        expected: "string",
+                        found: v.type_name(),
+                    }
+                    .into())
+                }
+            };
+            if let Err(err) = res {
+                return Ok(Err(err));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    // Read a value for each format, stopping after the first format that fails. With no formats,
+    // reads a single line.
+    fn read_values<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        formats: &[Value<'gc>],
+    ) -> Result<Result<Vec<Value<'gc>>, io::Error>, Error<'gc>> {
+        let mut state = self.state.borrow_mut();
+        let reader = match &mut *state {
+            FileState::Std(_) => return Ok(Err(io::Error::from(io::ErrorKind::Unsupported))),
+            FileState::File(reader) => reader,
+            FileState::Closed => return Err(ClosedFile.into()),
+        };
+
+        let default = [Value::Nil];
+        let formats = if formats.is_empty() {
+            &default[..]
+        } else {
+            formats
+        };
+
+        let mut values = Vec::new();
+        for (i, &format) in formats.iter().enumerate() {
+            let format = if format.is_nil() {
+                ReadFormat::Line {
+                    keep_newline: false,
+                }
+            } else {
+                ReadFormat::from_value(format).ok_or_else(|| {
+                    format!("bad argument #{} to 'read' (invalid format)", i + 1).into_value(ctx)
+                })?
+            };
+
+            let value = match read_format(ctx, reader, format) {
+                Ok(value) => value,
+                Err(ReadError::Io(err)) => return Ok(Err(err)),
+                Err(ReadError::Memory(err)) => return Err(err.into()),
+            };
+            values.push(value);
+            if value.is_nil() {
+                break;
+            }
+        }
+        Ok(Ok(values))
+    }
+
+    fn seek<'gc>(&self, pos: SeekFrom) -> Result<Result<u64, io::Error>, Error<'gc>> {
+        match &mut *self.state.borrow_mut() {
+            FileState::Std(_) => Ok(Err(io::Error::from(io::ErrorKind::Unsupported))),
+            FileState::File(reader) => Ok(reader.seek(pos)),
+            FileState::Closed => Err(ClosedFile.into()),
+        }
+    }
+
+    fn flush<'gc>(&self) -> Result<Result<(), io::Error>, Error<'gc>> {
+        match &mut *self.state.borrow_mut() {
+            FileState::Std(w) => Ok(w.flush()),
+            FileState::File(reader) => Ok(reader.get_mut().flush()),
+            FileState::Closed => Err(ClosedFile.into()),
+        }
+    }
+
+    // Close the file, returns false if this is a standard stream, which cannot be closed.
+    fn close<'gc>(&self) -> Result<bool, Error<'gc>> {
+        let mut state = self.state.borrow_mut();
+        match &*state {
+            FileState::Std(_) => Ok(false),
+            FileState::File(_) => {
+                *state = FileState::Closed;
+                Ok(true)
+            }
+            FileState::Closed => Err(ClosedFile.into()),
+        }
+    }
+}
+
+// An I/O error is returned to the caller as `nil, message`, but running out of memory is raised as
+// an error.
+enum ReadError {
+    Io(io::Error),
+    Memory(VMError),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        ReadError::Io(err)
+    }
+}
+
+impl From<VMError> for ReadError {
+    fn from(err: VMError) -> Self {
+        ReadError::Memory(err)
+    }
+}
+
+fn read_format<'gc>(
+    ctx: Context<'gc>,
+    reader: &mut BufReader<fs::File>,
+    format: ReadFormat,
+) -> Result<Value<'gc>, ReadError> {
+    Ok(match format {
+        ReadFormat::Number => {
+            // Skip leading whitespace, then read the longest run of characters that may be part
+            // of a numeral.
+            loop {
+                let buf = reader.fill_buf()?;
+                let skip = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+                let done = skip < buf.len() || buf.is_empty();
+                reader.consume(skip);
+                if done {
+                    break;
+                }
+            }
+
+            const MAX_NUMERAL: usize = 200;
+            let mut numeral = Vec::new();
+            while numeral.len() < MAX_NUMERAL {
+                let buf = reader.fill_buf()?;
+                match buf.first() {
+                    Some(&b) if b.is_ascii_hexdigit() || b"+-.xXpP".contains(&b) => {
+                        numeral.push(b);
+                        reader.consume(1);
+                    }
+                    _ => break,
+                }
+            }
+
+            if let Some(i) = read_integer(&numeral) {
+                Value::Integer(i)
+            } else if let Some(n) = read_float(&numeral) {
+                Value::Number(n)
+            } else {
+                Value::Nil
+            }
+        }
+        ReadFormat::Line { keep_newline } => {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                Value::Nil
+            } else {
+                if !keep_newline && line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+                ctx.intern(&line).into()
+            }
+        }
+        ReadFormat::All => ctx.intern(&read_bytes(ctx, reader, usize::MAX)?).into(),
+        ReadFormat::Count(0) => {
+            // Reading zero bytes is a test for the end of the file.
+            if reader.fill_buf()?.is_empty() {
+                Value::Nil
+            } else {
+                ctx.intern(b"").into()
+            }
+        }
+        ReadFormat::Count(count) => {
+            let bytes = read_bytes(ctx, reader, count)?;
+            if bytes.is_empty() {
+                Value::Nil
+            } else {
+                ctx.intern(&bytes).into()
+            }
+        }
+    })
+}
+
+// Read up to `limit` bytes, checking the memory limit as the buffer grows so that a large file
+// cannot be read in all at once.
+fn read_bytes<'gc>(
+    ctx: Context<'gc>,
+    reader: &mut BufReader<fs::File>,
+    limit: usize,
+) -> Result<Vec<u8>, ReadError> {
+    let mut bytes = Vec::new();
+    while bytes.len() < limit {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = buf.len().min(limit - bytes.len());
+        ctx.check_allocation(bytes.len() + n)?;
+        bytes.extend_from_slice(&buf[..n]);
+        reader.consume(n);
+    }
+    Ok(bytes)
 }
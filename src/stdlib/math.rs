@@ -4,8 +4,8 @@ use gc_arena::Mutation;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 
 use crate::{
-    raw_ops, Callback, CallbackReturn, Context, FromMultiValue, IntoMultiValue, IntoValue, Table,
-    Value, Variadic,
+    raw_ops, value::float_to_lua, Callback, CallbackReturn, Context, FromMultiValue,
+    IntoMultiValue, IntoValue, Table, Value, Variadic,
 };
 
 pub fn load_math<'gc>(ctx: Context<'gc>) {
@@ -25,14 +25,6 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
         })
     }
 
-    fn to_int(v: Value) -> Value {
-        if let Some(i) = v.to_integer() {
-            Value::Integer(i)
-        } else {
-            v
-        }
-    }
-
     let math = Table::new(&ctx);
     let seeded_rng: Rc<RefCell<SmallRng>> = Rc::new(RefCell::new(SmallRng::from_entropy()));
 
@@ -79,7 +71,12 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "ceil",
-        callback("ceil", &ctx, |_, v: f64| Some(to_int(v.ceil().into()))),
+        callback("ceil", &ctx, |_, v: Value| {
+            Some(match v {
+                Value::Integer(i) => Value::Integer(i),
+                v => float_to_lua(v.to_number()?.ceil()),
+            })
+        }),
     )
     .unwrap();
 
@@ -96,31 +93,60 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "exp",
-        callback("exp", &ctx, |_, v: f64| Some(f64::consts::E.powf(v))),
+        callback("exp", &ctx, |_, v: f64| Some(v.exp())),
     )
     .unwrap();
 
     math.set(
         ctx,
         "floor",
-        callback("floor", &ctx, |_, v: f64| Some(to_int(v.floor().into()))),
+        callback("floor", &ctx, |_, v: Value| {
+            Some(match v {
+                Value::Integer(i) => Value::Integer(i),
+                v => float_to_lua(v.to_number()?.floor()),
+            })
+        }),
     )
     .unwrap();
 
     math.set(
         ctx,
         "fmod",
-        callback("fmod", &ctx, |_, (f, g): (f64, f64)| {
-            let result = (f % g).abs();
-            Some(if f < 0.0 { -result } else { result })
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            // Unlike `%`, the remainder is truncated towards zero, the same as C `fmod`.
+            let res = match (stack.get(0), stack.get(1)) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    if b == 0 {
+                        return Err("bad argument #2 to 'fmod' (zero)".into_value(ctx).into());
+                    }
+                    // `wrapping_rem` gives the correct result of 0 for `mininteger % -1`.
+                    Value::Integer(a.wrapping_rem(b))
+                }
+                _ => Value::Number(stack.check_number(0)? % stack.check_number(1)?),
+            };
+            stack.replace(ctx, res);
+            Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
 
     math.set(ctx, "huge", Value::Number(f64::INFINITY)).unwrap();
 
-    math.set(ctx, "log", callback("log", &ctx, |_, v: f64| Some(v.ln())))
-        .unwrap();
+    math.set(
+        ctx,
+        "log",
+        callback("log", &ctx, |_, (v, base): (f64, Option<f64>)| {
+            // The common bases use the dedicated functions, so that e.g. `math.log(8, 2)` is
+            // exactly 3.
+            Some(match base {
+                None => v.ln(),
+                Some(2.0) => v.log2(),
+                Some(10.0) => v.log10(),
+                Some(base) => v.ln() / base.ln(),
+            })
+        }),
+    )
+    .unwrap();
 
     math.set(
         ctx,
@@ -178,7 +204,18 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "modf",
-        callback("modf", &ctx, |_, f: f64| Some((f as i64, f % 1.0))),
+        callback("modf", &ctx, |_, v: Value| {
+            Some(match v {
+                Value::Integer(i) => (Value::Integer(i), Value::Number(0.0)),
+                v => {
+                    // The integral part is always a float, rounded towards zero.
+                    let f = v.to_number()?;
+                    let int = f.trunc();
+                    let frac = if f == int { 0.0 } else { f - int };
+                    (Value::Number(int), Value::Number(frac))
+                }
+            })
+        }),
     )
     .unwrap();
 
@@ -265,8 +302,22 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "ult",
-        callback("ult", &ctx, |_, (a, b): (i64, i64)| {
-            Some(Value::Boolean((a as u64) < (b as u64)))
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            // Both arguments must have an exact integer representation, which are then compared
+            // as if they were unsigned.
+            let check = |i| {
+                stack.check_integer(i).map_err(|err| {
+                    format!(
+                        "bad argument #{} to 'ult' ({} expected, got {})",
+                        err.index, err.expected, err.found
+                    )
+                    .into_value(ctx)
+                })
+            };
+            let a = check(0)? as u64;
+            let b = check(1)? as u64;
+            stack.replace(ctx, a < b);
+            Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
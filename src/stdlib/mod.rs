@@ -1,11 +1,25 @@
 mod base;
 mod coroutine;
+mod debug;
 mod io;
 mod math;
+mod os;
+mod pack;
+mod package;
+mod pattern;
 mod string;
 mod table;
+mod utf8;
 
 pub use self::{
-    base::load_base, coroutine::load_coroutine, io::load_io, math::load_math, string::load_string,
+    base::{load_base, load_base_with, load_file_functions, load_file_functions_with},
+    coroutine::load_coroutine,
+    debug::load_debug,
+    io::{load_io, load_io_with},
+    math::load_math,
+    os::load_os,
+    package::load_package,
+    string::load_string,
     table::load_table,
+    utf8::load_utf8,
 };
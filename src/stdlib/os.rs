@@ -0,0 +1,602 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::{thread_rng, Rng};
+
+use crate::{Callback, CallbackReturn, Context, Error, IntoValue, Table, Value};
+
+use super::io::{file_error, io_error};
+
+/// Load the `os` library.
+///
+/// If `unrestricted` is false, `os.execute`, `os.exit`, `os.remove`, and `os.rename` are not
+/// loaded. Since these run arbitrary shell commands, terminate the whole host process, or modify
+/// the file system, sandboxed environments will usually want to omit them.
+///
+/// Time zone information is not available, so `os.date` and `os.time` always work in UTC, and
+/// `isdst` is always false.
+pub fn load_os<'gc>(ctx: Context<'gc>, unrestricted: bool) {
+    let os = Table::new(&ctx);
+
+    os.set(
+        ctx,
+        "time",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            if stack.get(0).is_nil() {
+                stack.replace(ctx, now());
+                return Ok(CallbackReturn::Return);
+            }
+
+            let table = stack.check_table(0)?;
+            let year = date_field(ctx, table, "year", None)?;
+            let month = date_field(ctx, table, "month", None)?;
+            let day = date_field(ctx, table, "day", None)?;
+            let hour = date_field(ctx, table, "hour", Some(12))?;
+            let min = date_field(ctx, table, "min", Some(0))?;
+            let sec = date_field(ctx, table, "sec", Some(0))?;
+
+            // Out of range fields are normalized the same as `mktime`, so for example month 13 is
+            // January of the next year. Every field fits in a C `int`, so none of this can
+            // overflow.
+            let month = month - 1;
+            let days = days_from_civil(year + month.div_euclid(12), month.rem_euclid(12) + 1, 1)
+                + (day - 1);
+            let time = days * 86400 + hour * 3600 + min * 60 + sec;
+
+            // Update the table with the normalized fields, the same as PUC-Rio Lua.
+            let Some(date) = Date::from_time(time) else {
+                return Err("time result cannot be represented in this installation"
+                    .into_value(ctx)
+                    .into());
+            };
+            date.set_fields(ctx, table)?;
+
+            stack.replace(ctx, time);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "date",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let format = if stack.get(0).is_nil() {
+                &b"%c"[..]
+            } else {
+                stack.check_string(ctx, 0)?.as_bytes()
+            };
+            let time = if stack.get(1).is_nil() {
+                now()
+            } else {
+                stack.check_integer(1)?
+            };
+
+            // Local time is the same as UTC here, so a leading '!' makes no difference.
+            let format = format.strip_prefix(b"!").unwrap_or(format);
+            let Some(date) = Date::from_time(time) else {
+                return Err("date result cannot be represented in this installation"
+                    .into_value(ctx)
+                    .into());
+            };
+
+            if format.starts_with(b"*t") {
+                let table = Table::new(&ctx);
+                date.set_fields(ctx, table)?;
+                stack.replace(ctx, table);
+            } else {
+                let mut out = Vec::new();
+                if let Err(spec) = date.format(&mut out, format) {
+                    return Err(format!(
+                        "bad argument #1 to 'date' (invalid conversion specifier '%{spec}')"
+                    )
+                    .into_value(ctx)
+                    .into());
+                }
+                stack.replace(ctx, ctx.intern(&out));
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "difftime",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let t2 = stack.check_integer(0)?;
+            let t1 = stack.check_integer(1)?;
+            stack.replace(ctx, t2 as f64 - t1 as f64);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "getenv",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let name: Value = stack.consume(ctx)?;
+            let Value::String(name) = name else {
+                return Err("Bad argument to getenv".into_value(ctx).into());
+            };
+            let value = name
+                .to_str()
+                .ok()
+                .and_then(env::var_os)
+                .map(|v| ctx.intern(v.to_string_lossy().as_bytes()));
+            stack.replace(ctx, value);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "tmpname",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let dir = env::temp_dir();
+            let mut rng = thread_rng();
+            loop {
+                let path = dir.join(format!("lua_{:x}_{:016x}", process::id(), rng.gen::<u64>()));
+                // Create the file so that the name is reserved, the same as `mkstemp` in PUC-Rio
+                // Lua.
+                match fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                {
+                    Ok(_) => {
+                        stack.replace(ctx, ctx.intern(path.to_string_lossy().as_bytes()));
+                        return Ok(CallbackReturn::Return);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }),
+    )
+    .unwrap();
+
+    if unrestricted {
+        os.set(
+            ctx,
+            "execute",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                // Running a command gives it the full permissions of the host process, so this is
+                // exactly as dangerous as it is in PUC-Rio Lua.
+                let command: Option<crate::String> = stack.consume(ctx)?;
+                let Some(command) = command else {
+                    // Without a command, report whether a shell is available.
+                    stack.replace(ctx, cfg!(any(unix, windows)));
+                    return Ok(CallbackReturn::Return);
+                };
+
+                match shell_command(&command.to_str_lossy()).status() {
+                    Ok(status) => {
+                        let (reason, code) = exit_status(status);
+                        stack.replace(ctx, (status.success().then_some(true), reason, code));
+                    }
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+        os.set(
+            ctx,
+            "remove",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let filename = stack.check_string(ctx, 0)?;
+                let path = filename.to_str_lossy();
+                // Like C's `remove`, empty directories can be removed as well as files.
+                let res = match fs::symlink_metadata(path.as_ref()) {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir(path.as_ref()),
+                    _ => fs::remove_file(path.as_ref()),
+                };
+                match res {
+                    Ok(()) => stack.replace(ctx, true),
+                    Err(err) => stack.replace(ctx, file_error(ctx, &path, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+        os.set(
+            ctx,
+            "rename",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let from = stack.check_string(ctx, 0)?;
+                let to = stack.check_string(ctx, 1)?;
+                match fs::rename(from.to_str_lossy().as_ref(), to.to_str_lossy().as_ref()) {
+                    Ok(()) => stack.replace(ctx, true),
+                    Err(err) => stack.replace(ctx, io_error(ctx, err)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+        os.set(
+            ctx,
+            "exit",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                // The second `close` argument is accepted for compatibility, but the host process
+                // is always terminated without closing the Lua state.
+                let (code, _close): (Option<Value>, Option<Value>) = stack.consume(ctx)?;
+                let Some(code) = exit_code(code) else {
+                    return Err("bad argument #1 to 'exit'".into_value(ctx).into());
+                };
+                let _ = io::stdout().flush();
+                let _ = io::stderr().flush();
+                process::exit(code)
+            }),
+        )
+        .unwrap();
+    }
+
+    ctx.set_global("os", os).unwrap();
+}
+
+// A command which runs `command` with the system shell, the same as C's `system`.
+fn shell_command(command: &str) -> process::Command {
+    #[cfg(windows)]
+    {
+        let mut cmd = process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = process::Command::new("/bin/sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+// Decompose the status of a finished command into `"exit"` and its exit code, or `"signal"` and the
+// signal that terminated it, the same as PUC-Rio Lua's `luaL_execresult`.
+fn exit_status(status: process::ExitStatus) -> (&'static str, i64) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return ("signal", signal.into());
+        }
+    }
+    ("exit", status.code().unwrap_or(-1).into())
+}
+
+// The current time in seconds since the Unix epoch.
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+// Get an integer field of an `os.time` date table, which must fit in a C `int`.
+fn date_field<'gc>(
+    ctx: Context<'gc>,
+    table: Table<'gc>,
+    name: &'static str,
+    default: Option<i64>,
+) -> Result<i64, Error<'gc>> {
+    let value = table.get(ctx, name);
+    let value = match (value, default) {
+        (Value::Nil, Some(default)) => return Ok(default),
+        (Value::Nil, None) => {
+            return Err(format!("field '{name}' missing in date table")
+                .into_value(ctx)
+                .into())
+        }
+        (value, _) => value,
+    };
+    match value.to_integer() {
+        Some(i) if i32::try_from(i).is_ok() => Ok(i),
+        Some(_) => Err(format!("field '{name}' is out-of-bound")
+            .into_value(ctx)
+            .into()),
+        None => Err(format!("field '{name}' is not an integer")
+            .into_value(ctx)
+            .into()),
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A broken down UTC date, with fields numbered the same as an `os.date("*t")` table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Date {
+    year: i64,
+    /// 1-12
+    month: i64,
+    /// 1-31
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    /// 1-7, where 1 is Sunday
+    wday: i64,
+    /// 1-366
+    yday: i64,
+}
+
+impl Date {
+    /// Break down a time in seconds since the Unix epoch. Returns `None` if the year does not fit
+    /// in a C `int`.
+    fn from_time(time: i64) -> Option<Date> {
+        let days = time.div_euclid(86400);
+        let secs = time.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        i32::try_from(year).ok()?;
+        Some(Date {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            min: secs / 60 % 60,
+            sec: secs % 60,
+            wday: weekday(days) + 1,
+            yday: days - days_from_civil(year, 1, 1) + 1,
+        })
+    }
+
+    fn set_fields<'gc>(&self, ctx: Context<'gc>, table: Table<'gc>) -> Result<(), Error<'gc>> {
+        table.set(ctx, "year", self.year)?;
+        table.set(ctx, "month", self.month)?;
+        table.set(ctx, "day", self.day)?;
+        table.set(ctx, "hour", self.hour)?;
+        table.set(ctx, "min", self.min)?;
+        table.set(ctx, "sec", self.sec)?;
+        table.set(ctx, "wday", self.wday)?;
+        table.set(ctx, "yday", self.yday)?;
+        table.set(ctx, "isdst", false)?;
+        Ok(())
+    }
+
+    // The ISO 8601 week-based year and week number.
+    fn iso_week(&self) -> (i64, i64) {
+        // Monday is 1 and Sunday is 7.
+        let iso_wday = (self.wday + 5) % 7 + 1;
+        let week = (self.yday - iso_wday + 10) / 7;
+        if week < 1 {
+            (self.year - 1, iso_weeks_in_year(self.year - 1))
+        } else if week > iso_weeks_in_year(self.year) {
+            (self.year + 1, 1)
+        } else {
+            (self.year, week)
+        }
+    }
+
+    /// Format the date like C `strftime` in the "C" locale.
+    ///
+    /// Returns the invalid conversion specifier, without the leading '%', on error.
+    fn format(&self, out: &mut Vec<u8>, format: &[u8]) -> Result<(), String> {
+        let mut bytes = format.iter().copied();
+        while let Some(b) = bytes.next() {
+            if b != b'%' {
+                out.push(b);
+                continue;
+            }
+
+            let mut spec = bytes.next().ok_or_else(String::new)?;
+            // The `E` and `O` modifiers select alternative representations, which are the same
+            // as the normal ones in the "C" locale.
+            if spec == b'E' || spec == b'O' {
+                let modified = bytes.next().ok_or_else(|| char::from(spec).to_string())?;
+                let allowed: &[u8] = if spec == b'E' {
+                    b"cCxXyY"
+                } else {
+                    b"deHImMSuUVwWy"
+                };
+                if !allowed.contains(&modified) {
+                    return Err(format!("{}{}", char::from(spec), char::from(modified)));
+                }
+                spec = modified;
+            }
+            self.format_spec(out, spec)?;
+        }
+        Ok(())
+    }
+
+    fn format_spec(&self, out: &mut Vec<u8>, spec: u8) -> Result<(), String> {
+        let weekday = WEEKDAYS[(self.wday - 1) as usize];
+        let month = MONTHS[(self.month - 1) as usize];
+        let hour12 = if self.hour % 12 == 0 {
+            12
+        } else {
+            self.hour % 12
+        };
+        // Writing to a `Vec` cannot fail.
+        let _ = match spec {
+            b'a' => write!(out, "{}", &weekday[..3]),
+            b'A' => write!(out, "{weekday}"),
+            b'b' | b'h' => write!(out, "{}", &month[..3]),
+            b'B' => write!(out, "{month}"),
+            b'c' => return self.format(out, b"%a %b %e %H:%M:%S %Y"),
+            b'C' => write!(out, "{:02}", self.year.div_euclid(100)),
+            b'd' => write!(out, "{:02}", self.day),
+            b'D' | b'x' => return self.format(out, b"%m/%d/%y"),
+            b'e' => write!(out, "{:2}", self.day),
+            b'F' => return self.format(out, b"%Y-%m-%d"),
+            b'g' => write!(out, "{:02}", self.iso_week().0.rem_euclid(100)),
+            b'G' => write!(out, "{}", self.iso_week().0),
+            b'H' => write!(out, "{:02}", self.hour),
+            b'I' => write!(out, "{hour12:02}"),
+            b'j' => write!(out, "{:03}", self.yday),
+            b'm' => write!(out, "{:02}", self.month),
+            b'M' => write!(out, "{:02}", self.min),
+            b'n' => writeln!(out),
+            b'p' => write!(out, "{}", if self.hour < 12 { "AM" } else { "PM" }),
+            b'r' => return self.format(out, b"%I:%M:%S %p"),
+            b'R' => return self.format(out, b"%H:%M"),
+            b'S' => write!(out, "{:02}", self.sec),
+            b't' => write!(out, "\t"),
+            b'T' | b'X' => return self.format(out, b"%H:%M:%S"),
+            b'u' => write!(out, "{}", (self.wday + 5) % 7 + 1),
+            b'U' => write!(out, "{:02}", (self.yday - 1 + 7 - (self.wday - 1)) / 7),
+            b'V' => write!(out, "{:02}", self.iso_week().1),
+            b'w' => write!(out, "{}", self.wday - 1),
+            b'W' => write!(out, "{:02}", (self.yday - 1 + 7 - (self.wday + 5) % 7) / 7),
+            b'y' => write!(out, "{:02}", self.year.rem_euclid(100)),
+            b'Y' => write!(out, "{}", self.year),
+            b'z' => write!(out, "+0000"),
+            b'Z' => write!(out, "UTC"),
+            b'%' => write!(out, "%"),
+            _ => return Err(char::from(spec).to_string()),
+        };
+        Ok(())
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn iso_weeks_in_year(year: i64) -> i64 {
+    // A year has 53 ISO weeks if it starts on a Thursday, or if it is a leap year starting on a
+    // Wednesday.
+    let jan1 = weekday(days_from_civil(year, 1, 1));
+    if jan1 == 4 || (is_leap_year(year) && jan1 == 3) {
+        53
+    } else {
+        52
+    }
+}
+
+// The day of the week of a day number, where 0 is Sunday. The Unix epoch was a Thursday.
+fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+// The number of days since the Unix epoch of the given proleptic Gregorian calendar date, from
+// Howard Hinnant's `days_from_civil` algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The inverse of `days_from_civil`, returning the year, month, and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Convert the argument to `os.exit` into a process exit status.
+///
+/// A missing argument or `true` maps to a successful exit (0), `false` maps to a failing exit (1),
+/// and any value convertible to an integer that fits in an `i32` is used as the exit status
+/// directly.
+fn exit_code(code: Option<Value>) -> Option<i32> {
+    match code {
+        None | Some(Value::Nil) | Some(Value::Boolean(true)) => Some(0),
+        Some(Value::Boolean(false)) => Some(1),
+        Some(v) => v.to_integer().and_then(|i| i32::try_from(i).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(None), Some(0));
+        assert_eq!(exit_code(Some(Value::Nil)), Some(0));
+        assert_eq!(exit_code(Some(Value::Boolean(true))), Some(0));
+        assert_eq!(exit_code(Some(Value::Boolean(false))), Some(1));
+        assert_eq!(exit_code(Some(Value::Integer(3))), Some(3));
+        assert_eq!(exit_code(Some(Value::Number(2.0))), Some(2));
+        assert_eq!(exit_code(Some(Value::Number(2.5))), None);
+        assert_eq!(exit_code(Some(Value::Integer(1 << 32))), None);
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in (-800_000..800_000).step_by(997) {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test_date() {
+        // 2024-02-29 13:14:15 UTC, a Thursday.
+        let date = Date::from_time(1709212455).unwrap();
+        assert_eq!(
+            date,
+            Date {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 13,
+                min: 14,
+                sec: 15,
+                wday: 5,
+                yday: 60,
+            }
+        );
+
+        let mut out = Vec::new();
+        date.format(&mut out, b"%c|%F %T|%j %u %w %U %W %V %G|%I%p|%%")
+            .unwrap();
+        assert_eq!(
+            out,
+            b"Thu Feb 29 13:14:15 2024|2024-02-29 13:14:15|060 4 4 08 09 09 2024|01PM|%"
+        );
+
+        // January 1st 2021 was a Friday in the last ISO week of 2020.
+        let date = Date::from_time(1609459200).unwrap();
+        assert_eq!(date.iso_week(), (2020, 53));
+
+        assert_eq!(date.format(&mut Vec::new(), b"%Q"), Err("Q".to_owned()));
+        assert_eq!(date.format(&mut Vec::new(), b"%Ea"), Err("Ea".to_owned()));
+        assert_eq!(date.format(&mut Vec::new(), b"%"), Err("".to_owned()));
+    }
+}
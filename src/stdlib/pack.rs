@@ -0,0 +1,514 @@
+//! The format mini-language of `string.pack`, `string.unpack`, and `string.packsize`, matching the
+//! behavior of PUC-Rio Lua's `lstrlib.c`.
+
+use std::{
+    ffi::{c_int, c_long, c_short},
+    mem,
+};
+
+use thiserror::Error;
+
+use crate::{BadArgument, Value};
+
+/// The largest integer size (in bytes) that may be given to an `i`, `I`, or `s` option.
+const MAX_INT_SIZE: usize = 16;
+
+/// The size of a Lua integer in bytes.
+const LUA_INT_SIZE: usize = mem::size_of::<i64>();
+
+/// The default maximum alignment set by a `!` option without a size.
+const NATIVE_ALIGN: usize = 8;
+
+/// The length of the longest string that `pack` will create.
+const MAX_PACK_LEN: usize = i32::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PackError {
+    #[error(transparent)]
+    BadArgument(#[from] BadArgument),
+    #[error("invalid format option '{0}'")]
+    InvalidOption(char),
+    #[error("integral size ({0}) out of limits [1,16]")]
+    IntegralSizeOutOfLimits(usize),
+    #[error("missing size for format option 'c'")]
+    MissingCharSize,
+    #[error("invalid next option for option 'X'")]
+    InvalidAlignOption,
+    #[error("format asks for alignment not power of 2")]
+    AlignmentNotPowerOfTwo,
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("unsigned overflow")]
+    UnsignedOverflow,
+    #[error("string longer than given size")]
+    StringTooLong,
+    #[error("string length does not fit in given size")]
+    StringLengthTooLarge,
+    #[error("string contains zeros")]
+    StringContainsZeros,
+    #[error("variable-size format in packsize")]
+    VariableSize,
+    #[error("format result too large")]
+    TooLarge,
+    #[error("resulting string too large")]
+    ResultTooLarge,
+    #[error("initial position out of string")]
+    InitialPositionOutOfString,
+    #[error("data string too short")]
+    DataTooShort,
+    #[error("unfinished string for format 'z'")]
+    UnfinishedString,
+    #[error("{0}-byte integer does not fit into Lua Integer")]
+    IntegerDoesNotFit(usize),
+}
+
+/// A value read by `unpack`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unpacked {
+    Integer(i64),
+    Number(f64),
+    /// A string, as a range of byte indexes into the data string.
+    String(usize, usize),
+}
+
+/// Packs `args` according to `fmt`.
+///
+/// `arg_offset` is the number of callback arguments before `args`, so that argument errors report
+/// the correct position.
+pub fn pack(fmt: &[u8], args: &[Value], arg_offset: usize) -> Result<Vec<u8>, PackError> {
+    let mut parser = Parser::new(fmt);
+    let mut out = Vec::new();
+    let mut arg = 0;
+
+    while let Some((opt, size, align)) = parser.next_details(out.len())? {
+        check_len(&out, align)?;
+        out.resize(out.len() + align, 0);
+
+        match opt {
+            Opt::Int => {
+                let n = check_integer(args, arg, arg_offset)?;
+                if size < LUA_INT_SIZE {
+                    let lim = 1i64 << (size * 8 - 1);
+                    if n < -lim || n >= lim {
+                        return Err(PackError::IntegerOverflow);
+                    }
+                }
+                pack_int(&mut out, n as u64, parser.little, size, n < 0);
+            }
+            Opt::Uint => {
+                let n = check_integer(args, arg, arg_offset)?;
+                if size < LUA_INT_SIZE && (n as u64) >= 1u64 << (size * 8) {
+                    return Err(PackError::UnsignedOverflow);
+                }
+                pack_int(&mut out, n as u64, parser.little, size, false);
+            }
+            Opt::Float => {
+                let n = check_number(args, arg, arg_offset)? as f32;
+                out.extend(if parser.little {
+                    n.to_le_bytes()
+                } else {
+                    n.to_be_bytes()
+                });
+            }
+            Opt::Double => {
+                let n = check_number(args, arg, arg_offset)?;
+                out.extend(if parser.little {
+                    n.to_le_bytes()
+                } else {
+                    n.to_be_bytes()
+                });
+            }
+            Opt::Char => {
+                let s = check_string(args, arg, arg_offset)?;
+                if s.len() > size {
+                    return Err(PackError::StringTooLong);
+                }
+                check_len(&out, size)?;
+                out.extend_from_slice(&s);
+                out.resize(out.len() + size - s.len(), 0);
+            }
+            Opt::String => {
+                let s = check_string(args, arg, arg_offset)?;
+                if size < LUA_INT_SIZE && (s.len() as u64) >= 1u64 << (size * 8) {
+                    return Err(PackError::StringLengthTooLarge);
+                }
+                check_len(&out, size.saturating_add(s.len()))?;
+                pack_int(&mut out, s.len() as u64, parser.little, size, false);
+                out.extend_from_slice(&s);
+            }
+            Opt::ZStr => {
+                let s = check_string(args, arg, arg_offset)?;
+                if s.contains(&0) {
+                    return Err(PackError::StringContainsZeros);
+                }
+                check_len(&out, s.len().saturating_add(1))?;
+                out.extend_from_slice(&s);
+                out.push(0);
+            }
+            Opt::Padding => out.push(0),
+            Opt::PadAlign | Opt::Nop => {}
+        }
+
+        if !matches!(opt, Opt::Padding | Opt::PadAlign | Opt::Nop) {
+            arg += 1;
+        }
+    }
+
+    Ok(out)
+}
+
+// Check that `additional` more bytes can be packed, before they are allocated.
+fn check_len(out: &[u8], additional: usize) -> Result<(), PackError> {
+    match out.len().checked_add(additional) {
+        Some(len) if len <= MAX_PACK_LEN => Ok(()),
+        _ => Err(PackError::ResultTooLarge),
+    }
+}
+
+/// Returns the size of a string packed with `fmt`, which must not contain variable-size options.
+pub fn packsize(fmt: &[u8]) -> Result<usize, PackError> {
+    let mut parser = Parser::new(fmt);
+    let mut total: usize = 0;
+    while let Some((opt, size, align)) = parser.next_details(total)? {
+        if matches!(opt, Opt::String | Opt::ZStr) {
+            return Err(PackError::VariableSize);
+        }
+        total = total
+            .checked_add(align)
+            .and_then(|t| t.checked_add(size))
+            .filter(|&t| t <= i64::MAX as usize)
+            .ok_or(PackError::TooLarge)?;
+    }
+    Ok(total)
+}
+
+/// Unpacks `data` according to `fmt`, starting at the 0-based byte index `pos`.
+///
+/// Returns the unpacked values and the 0-based index of the first unread byte.
+pub fn unpack(
+    fmt: &[u8],
+    data: &[u8],
+    mut pos: usize,
+) -> Result<(Vec<Unpacked>, usize), PackError> {
+    if pos > data.len() {
+        return Err(PackError::InitialPositionOutOfString);
+    }
+
+    let mut parser = Parser::new(fmt);
+    let mut values = Vec::new();
+    while let Some((opt, size, align)) = parser.next_details(pos)? {
+        if !matches!(align.checked_add(size), Some(len) if len <= data.len() - pos) {
+            return Err(PackError::DataTooShort);
+        }
+        pos += align;
+        let bytes = &data[pos..pos + size];
+
+        match opt {
+            Opt::Int | Opt::Uint => {
+                let n = unpack_int(bytes, parser.little, opt == Opt::Int)?;
+                values.push(Unpacked::Integer(n));
+            }
+            Opt::Float => {
+                let bytes = bytes.try_into().unwrap();
+                values.push(Unpacked::Number(
+                    if parser.little {
+                        f32::from_le_bytes(bytes)
+                    } else {
+                        f32::from_be_bytes(bytes)
+                    }
+                    .into(),
+                ));
+            }
+            Opt::Double => {
+                let bytes = bytes.try_into().unwrap();
+                values.push(Unpacked::Number(if parser.little {
+                    f64::from_le_bytes(bytes)
+                } else {
+                    f64::from_be_bytes(bytes)
+                }));
+            }
+            Opt::Char => values.push(Unpacked::String(pos, pos + size)),
+            Opt::String => {
+                let len = unpack_int(bytes, parser.little, false)? as u64;
+                let start = pos + size;
+                if len > (data.len() - start) as u64 {
+                    return Err(PackError::DataTooShort);
+                }
+                let len = len as usize;
+                values.push(Unpacked::String(start, start + len));
+                pos += len;
+            }
+            Opt::ZStr => {
+                let len = data[pos..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or(PackError::UnfinishedString)?;
+                values.push(Unpacked::String(pos, pos + len));
+                pos += len + 1;
+            }
+            Opt::Padding | Opt::PadAlign | Opt::Nop => {}
+        }
+        pos += size;
+    }
+
+    Ok((values, pos))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Opt {
+    Int,
+    Uint,
+    Float,
+    Double,
+    Char,
+    String,
+    ZStr,
+    Padding,
+    PadAlign,
+    Nop,
+}
+
+struct Parser<'a> {
+    fmt: &'a [u8],
+    little: bool,
+    max_align: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(fmt: &'a [u8]) -> Self {
+        Self {
+            fmt,
+            little: cfg!(target_endian = "little"),
+            max_align: 1,
+        }
+    }
+
+    // Reads a decimal size, if there is one.
+    fn read_num(&mut self) -> Option<usize> {
+        let len = self.fmt.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        let mut n: usize = 0;
+        for &b in &self.fmt[..len] {
+            n = n.saturating_mul(10).saturating_add((b - b'0').into());
+        }
+        self.fmt = &self.fmt[len..];
+        Some(n)
+    }
+
+    fn read_num_limit(&mut self, default: usize) -> Result<usize, PackError> {
+        match self.read_num() {
+            None => Ok(default),
+            Some(n) if (1..=MAX_INT_SIZE).contains(&n) => Ok(n),
+            Some(n) => Err(PackError::IntegralSizeOutOfLimits(n)),
+        }
+    }
+
+    // Reads the next option and its size.
+    fn read_option(&mut self) -> Result<Option<(Opt, usize)>, PackError> {
+        let Some((&c, rest)) = self.fmt.split_first() else {
+            return Ok(None);
+        };
+        self.fmt = rest;
+
+        Ok(Some(match c {
+            b'b' => (Opt::Int, 1),
+            b'B' => (Opt::Uint, 1),
+            b'h' => (Opt::Int, mem::size_of::<c_short>()),
+            b'H' => (Opt::Uint, mem::size_of::<c_short>()),
+            b'l' => (Opt::Int, mem::size_of::<c_long>()),
+            b'L' => (Opt::Uint, mem::size_of::<c_long>()),
+            b'j' => (Opt::Int, LUA_INT_SIZE),
+            b'J' => (Opt::Uint, LUA_INT_SIZE),
+            b'T' => (Opt::Uint, mem::size_of::<usize>()),
+            b'f' => (Opt::Float, mem::size_of::<f32>()),
+            b'd' | b'n' => (Opt::Double, mem::size_of::<f64>()),
+            b'i' => (Opt::Int, self.read_num_limit(mem::size_of::<c_int>())?),
+            b'I' => (Opt::Uint, self.read_num_limit(mem::size_of::<c_int>())?),
+            b's' => (Opt::String, self.read_num_limit(mem::size_of::<usize>())?),
+            b'c' => (
+                Opt::Char,
+                self.read_num().ok_or(PackError::MissingCharSize)?,
+            ),
+            b'z' => (Opt::ZStr, 0),
+            b'x' => (Opt::Padding, 1),
+            b'X' => (Opt::PadAlign, 0),
+            b' ' => (Opt::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (Opt::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (Opt::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (Opt::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.read_num_limit(NATIVE_ALIGN)?;
+                (Opt::Nop, 0)
+            }
+            c => return Err(PackError::InvalidOption(c.into())),
+        }))
+    }
+
+    // Reads the next option, returning the option, its size, and the number of padding bytes
+    // needed to align it given the `total` size so far.
+    fn next_details(&mut self, total: usize) -> Result<Option<(Opt, usize, usize)>, PackError> {
+        let Some((opt, size)) = self.read_option()? else {
+            return Ok(None);
+        };
+
+        let mut align = size;
+        if opt == Opt::PadAlign {
+            // 'X' takes its alignment from the following option, which is otherwise ignored.
+            match self.read_option()? {
+                Some((next, next_size)) if next != Opt::Char && next_size != 0 => align = next_size,
+                _ => return Err(PackError::InvalidAlignOption),
+            }
+        }
+
+        let padding = if align <= 1 || opt == Opt::Char {
+            0
+        } else {
+            let align = align.min(self.max_align);
+            if !align.is_power_of_two() {
+                return Err(PackError::AlignmentNotPowerOfTwo);
+            }
+            (align - (total & (align - 1))) & (align - 1)
+        };
+        Ok(Some((opt, size, padding)))
+    }
+}
+
+fn pack_int(out: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let mut bytes = [0u8; MAX_INT_SIZE];
+    for (i, b) in bytes[..size].iter_mut().enumerate() {
+        *b = if i < LUA_INT_SIZE {
+            (n >> (i * 8)) as u8
+        } else if negative {
+            // Sign extend integers larger than a Lua integer.
+            0xff
+        } else {
+            0
+        };
+    }
+    let bytes = &mut bytes[..size];
+    if !little {
+        bytes.reverse();
+    }
+    out.extend_from_slice(bytes);
+}
+
+fn unpack_int(bytes: &[u8], little: bool, signed: bool) -> Result<i64, PackError> {
+    let size = bytes.len();
+    let byte = |i: usize| {
+        if little {
+            bytes[i]
+        } else {
+            bytes[size - 1 - i]
+        }
+    };
+
+    let limit = size.min(LUA_INT_SIZE);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | u64::from(byte(i));
+    }
+
+    if size < LUA_INT_SIZE {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > LUA_INT_SIZE {
+        // The extra bytes must all be sign extension.
+        let ext = if signed && (res as i64) < 0 { 0xff } else { 0 };
+        if (limit..size).any(|i| byte(i) != ext) {
+            return Err(PackError::IntegerDoesNotFit(size));
+        }
+    }
+    Ok(res as i64)
+}
+
+// Gets the argument at `i`, counting from the first argument after the format.
+fn get_arg<'gc>(
+    args: &[Value<'gc>],
+    i: usize,
+    arg_offset: usize,
+    expected: &'static str,
+) -> (Value<'gc>, impl FnOnce() -> BadArgument) {
+    let found = args.get(i).map_or("no value", |v| v.type_name());
+    let value = args.get(i).copied().unwrap_or(Value::Nil);
+    (value, move || BadArgument {
+        index: arg_offset + i + 1,
+        expected,
+        found,
+    })
+}
+
+fn check_integer(args: &[Value], i: usize, arg_offset: usize) -> Result<i64, BadArgument> {
+    let (v, err) = get_arg(args, i, arg_offset, "integer");
+    v.to_integer().ok_or_else(err)
+}
+
+fn check_number(args: &[Value], i: usize, arg_offset: usize) -> Result<f64, BadArgument> {
+    let (v, err) = get_arg(args, i, arg_offset, "number");
+    v.to_number().ok_or_else(err)
+}
+
+fn check_string(args: &[Value], i: usize, arg_offset: usize) -> Result<Vec<u8>, BadArgument> {
+    match get_arg(args, i, arg_offset, "string") {
+        (Value::String(s), _) => Ok(s.as_bytes().to_vec()),
+        (v @ (Value::Integer(_) | Value::Number(_)), _) => Ok(v.to_string().into_bytes()),
+        (_, err) => Err(err()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_round_trip() {
+        for little in [true, false] {
+            for size in 1..=MAX_INT_SIZE {
+                for n in [0i64, 1, -1, 127, -128] {
+                    let mut out = Vec::new();
+                    pack_int(&mut out, n as u64, little, size, n < 0);
+                    assert_eq!(out.len(), size);
+                    assert_eq!(unpack_int(&out, little, true), Ok(n));
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        pack_int(&mut out, 0x0102, false, 2, false);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(
+            unpack_int(&[0, 0, 0, 0, 0, 0, 0, 0, 1], true, false),
+            Err(PackError::IntegerDoesNotFit(9))
+        );
+    }
+
+    #[test]
+    fn test_packsize() {
+        assert_eq!(packsize(b"i4i8"), Ok(12));
+        assert_eq!(packsize(b"!i1i8"), Ok(16));
+        assert_eq!(packsize(b"!4 i1 i8"), Ok(12));
+        assert_eq!(packsize(b"c3 Xi4"), Ok(3));
+        assert_eq!(packsize(b"!c3 Xi4"), Ok(4));
+        assert_eq!(packsize(b"s4"), Err(PackError::VariableSize));
+        assert_eq!(packsize(b"z"), Err(PackError::VariableSize));
+        assert_eq!(
+            packsize(b"i17"),
+            Err(PackError::IntegralSizeOutOfLimits(17))
+        );
+        assert_eq!(packsize(b"c"), Err(PackError::MissingCharSize));
+        assert_eq!(packsize(b"X"), Err(PackError::InvalidAlignOption));
+        assert_eq!(packsize(b"!i3i3"), Err(PackError::AlignmentNotPowerOfTwo));
+    }
+}
@@ -0,0 +1,301 @@
+use std::{fs, string::String as StdString};
+
+use gc_arena::Collect;
+
+use crate::{
+    meta_ops, BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, IntoValue,
+    Sequence, SequencePoll, Stack, String, Table, Value,
+};
+
+use super::io::strerror;
+
+/// The default value of `package.path`.
+pub const DEFAULT_PATH: &str = "./?.lua;./?/init.lua";
+
+/// Load the `package` library and the global `require` function.
+///
+/// Any standard libraries which are already loaded as globals are added to `package.loaded`, so
+/// this should be loaded after the other libraries.
+pub fn load_package<'gc>(ctx: Context<'gc>) {
+    let package = Table::new(&ctx);
+
+    let loaded = Table::new(&ctx);
+    for name in [
+        "_G",
+        "coroutine",
+        "debug",
+        "io",
+        "math",
+        "os",
+        "string",
+        "table",
+        "utf8",
+    ] {
+        if let Value::Table(lib) = ctx.get_global(name) {
+            loaded.set(ctx, name, lib).unwrap();
+        }
+    }
+    loaded.set(ctx, "package", package).unwrap();
+
+    package.set(ctx, "loaded", loaded).unwrap();
+    package.set(ctx, "preload", Table::new(&ctx)).unwrap();
+    package.set(ctx, "path", DEFAULT_PATH).unwrap();
+
+    package
+        .set(
+            ctx,
+            "searchpath",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let name = stack.check_string(ctx, 0)?;
+                let path = stack.check_string(ctx, 1)?;
+                let sep = opt_string(ctx, &stack, 2, ".")?;
+                let rep = opt_string(ctx, &stack, 3, "/")?;
+                match search_path(&name.to_str_lossy(), &path.to_str_lossy(), &sep, &rep) {
+                    Ok(file) => stack.replace(ctx, ctx.intern(file.as_bytes())),
+                    Err(msg) => stack.replace(ctx, (Value::Nil, ctx.intern(msg.as_bytes()))),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let searchers = Table::new(&ctx);
+
+    searchers
+        .set(
+            ctx,
+            1,
+            Callback::from_fn_with(&ctx, package, |package, ctx, _, mut stack| {
+                let name = stack.check_string(ctx, 0)?;
+                let Value::Table(preload) = package.get(ctx, "preload") else {
+                    return Err("'package.preload' must be a table".into_value(ctx).into());
+                };
+                match preload.get(ctx, name) {
+                    Value::Nil => stack.replace(
+                        ctx,
+                        format!("no field package.preload['{}']", name.to_str_lossy()),
+                    ),
+                    loader => stack.replace(ctx, (loader, ":preload:")),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    searchers
+        .set(
+            ctx,
+            2,
+            Callback::from_fn_with(&ctx, package, |package, ctx, _, mut stack| {
+                let name = stack.check_string(ctx, 0)?;
+                let Value::String(path) = package.get(ctx, "path") else {
+                    return Err("'package.path' must be a string".into_value(ctx).into());
+                };
+                let name = name.to_str_lossy();
+                let file = match search_path(&name, &path.to_str_lossy(), ".", "/") {
+                    Ok(file) => file,
+                    Err(msg) => {
+                        stack.replace(ctx, ctx.intern(msg.as_bytes()));
+                        return Ok(CallbackReturn::Return);
+                    }
+                };
+
+                let loaded = fs::read(&file)
+                    .map_err(|err| strerror(&err))
+                    .and_then(|source| {
+                        Closure::load(ctx, Some(&format!("@{file}")), &source[..])
+                            .map_err(|err| err.to_string())
+                    });
+                match loaded {
+                    Ok(closure) => {
+                        stack.replace(ctx, (closure, ctx.intern(file.as_bytes())));
+                        Ok(CallbackReturn::Return)
+                    }
+                    Err(err) => Err(format!(
+                        "error loading module '{name}' from file '{file}':\n\t{err}"
+                    )
+                    .into_value(ctx)
+                    .into()),
+                }
+            }),
+        )
+        .unwrap();
+
+    package.set(ctx, "searchers", searchers).unwrap();
+
+    #[derive(Collect)]
+    #[collect(no_drop)]
+    struct Require<'gc> {
+        name: String<'gc>,
+        loaded: Table<'gc>,
+        searchers: Table<'gc>,
+        // Modules which are currently being loaded, used to detect circular requires.
+        loading: Table<'gc>,
+        // The index of the searcher that was last called, or `None` once the loader was called.
+        searcher: Option<i64>,
+        // The messages from searchers which did not find the module.
+        messages: Vec<u8>,
+        loader_data: Value<'gc>,
+    }
+
+    impl<'gc> Sequence<'gc> for Require<'gc> {
+        fn poll(
+            &mut self,
+            ctx: Context<'gc>,
+            _exec: Execution<'gc, '_>,
+            mut stack: Stack<'gc, '_>,
+        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+            let Some(index) = self.searcher else {
+                // The loader has returned.
+                let module = stack.get(0);
+                if !module.is_nil() {
+                    self.loaded.set(ctx, self.name, module)?;
+                }
+                if self.loaded.get(ctx, self.name).is_nil() {
+                    self.loaded.set(ctx, self.name, true)?;
+                }
+                self.loading.set(ctx, self.name, Value::Nil)?;
+                stack.replace(ctx, (self.loaded.get(ctx, self.name), self.loader_data));
+                return Ok(SequencePoll::Return);
+            };
+
+            if index > 0 {
+                match stack.get(0) {
+                    Value::Function(loader) => {
+                        self.searcher = None;
+                        self.loader_data = stack.get(1);
+                        stack.replace(ctx, (self.name, self.loader_data));
+                        return Ok(SequencePoll::Call {
+                            function: loader,
+                            is_tail: false,
+                        });
+                    }
+                    Value::String(msg) => {
+                        self.messages.extend(b"\n\t");
+                        self.messages.extend(msg.as_bytes());
+                    }
+                    _ => {}
+                }
+            }
+
+            let index = index + 1;
+            let searcher = self.searchers.get(ctx, index);
+            if searcher.is_nil() {
+                self.loading.set(ctx, self.name, Value::Nil)?;
+                let mut msg = format!("module '{}' not found:", self.name.to_str_lossy());
+                msg.push_str(&StdString::from_utf8_lossy(&self.messages));
+                return Err(msg.into_value(ctx).into());
+            }
+            self.searcher = Some(index);
+            stack.replace(ctx, self.name);
+            Ok(SequencePoll::Call {
+                function: meta_ops::call(ctx, searcher)?,
+                is_tail: false,
+            })
+        }
+
+        fn error(
+            &mut self,
+            ctx: Context<'gc>,
+            _exec: Execution<'gc, '_>,
+            error: Error<'gc>,
+            _stack: Stack<'gc, '_>,
+        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+            // Allow requiring the module again after a failed load.
+            self.loading.set(ctx, self.name, Value::Nil)?;
+            Err(error)
+        }
+    }
+
+    ctx.set_global(
+        "require",
+        Callback::from_fn_with(
+            &ctx,
+            (package, Table::new(&ctx)),
+            |&(package, loading), ctx, _, mut stack| {
+                let name = stack.check_string(ctx, 0)?;
+                let Value::Table(loaded) = package.get(ctx, "loaded") else {
+                    return Err("'package.loaded' must be a table".into_value(ctx).into());
+                };
+
+                let module = loaded.get(ctx, name);
+                if module.to_bool() {
+                    stack.replace(ctx, module);
+                    return Ok(CallbackReturn::Return);
+                }
+
+                let Value::Table(searchers) = package.get(ctx, "searchers") else {
+                    return Err("'package.searchers' must be a table".into_value(ctx).into());
+                };
+
+                if loading.get(ctx, name).to_bool() {
+                    return Err(format!(
+                        "loop or previous error loading module '{}'",
+                        name.to_str_lossy()
+                    )
+                    .into_value(ctx)
+                    .into());
+                }
+                loading.set(ctx, name, true)?;
+
+                stack.clear();
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    Require {
+                        name,
+                        loaded,
+                        searchers,
+                        loading,
+                        searcher: Some(0),
+                        messages: Vec::new(),
+                        loader_data: Value::Nil,
+                    },
+                )))
+            },
+        ),
+    )
+    .unwrap();
+
+    ctx.set_global("package", package).unwrap();
+}
+
+// Get the optional string argument at `i`, or `default` if it is nil or missing.
+fn opt_string<'gc>(
+    ctx: Context<'gc>,
+    stack: &Stack<'gc, '_>,
+    i: usize,
+    default: &str,
+) -> Result<StdString, Error<'gc>> {
+    if stack.get(i).is_nil() {
+        Ok(default.to_owned())
+    } else {
+        Ok(stack.check_string(ctx, i)?.to_str_lossy().into_owned())
+    }
+}
+
+// Search for a readable file for the module `name` in the `;` separated list of templates in
+// `path`, the same as `package.searchpath`.
+//
+// Every occurrence of `sep` in the name is replaced by `rep` (usually the directory separator),
+// and then every `?` in each template is replaced by the name. On failure, returns a message
+// listing all of the files tried.
+fn search_path(name: &str, path: &str, sep: &str, rep: &str) -> Result<StdString, StdString> {
+    let name = if sep.is_empty() {
+        name.to_owned()
+    } else {
+        name.replace(sep, rep)
+    };
+
+    let mut msg = StdString::new();
+    for template in path.split(';').filter(|t| !t.is_empty()) {
+        let file = template.replace('?', &name);
+        if fs::File::open(&file).is_ok() {
+            return Ok(file);
+        }
+        if !msg.is_empty() {
+            msg.push_str("\n\t");
+        }
+        msg.push_str(&format!("no file '{file}'"));
+    }
+    Err(msg)
+}
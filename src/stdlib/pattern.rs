@@ -0,0 +1,575 @@
+//! An implementation of Lua patterns, matching the behavior of PUC-Rio Lua's `lstrlib.c`.
+//!
+//! Patterns operate on bytes, and character classes such as `%a` use the ASCII ("C" locale)
+//! definitions.
+
+use std::mem;
+
+use thiserror::Error;
+
+/// The maximum number of captures in a single pattern.
+pub const MAX_CAPTURES: usize = 32;
+
+/// The maximum recursion depth of the matcher before a match is considered too complex.
+const MAX_MATCH_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+pub enum PatternError {
+    #[error("malformed pattern (ends with '%')")]
+    EndsWithEscape,
+    #[error("malformed pattern (missing ']')")]
+    MissingBracket,
+    #[error("malformed pattern (missing arguments to '%b')")]
+    MissingBalanceArguments,
+    #[error("missing '[' after '%f' in pattern")]
+    MissingFrontierBracket,
+    #[error("invalid capture index %{0}")]
+    InvalidCaptureIndex(u8),
+    #[error("invalid pattern capture")]
+    InvalidPatternCapture,
+    #[error("unfinished capture")]
+    UnfinishedCapture,
+    #[error("too many captures")]
+    TooManyCaptures,
+    #[error("pattern too complex")]
+    TooComplex,
+}
+
+/// A single capture from a successful match.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Capture {
+    /// A substring capture, as a range of byte indexes into the subject.
+    Slice(usize, usize),
+    /// A position capture `()`, as the 0-based byte index into the subject.
+    Position(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The byte index of the start of the whole match.
+    pub start: usize,
+    /// The byte index one past the end of the whole match.
+    pub end: usize,
+    /// Every explicit capture in the pattern, in the order of their opening parentheses.
+    pub captures: Vec<Capture>,
+}
+
+/// Find the first match of `pattern` in `subject`, starting the search at byte index `init`.
+///
+/// A leading `^` anchors the match at `init`.
+pub fn find(subject: &[u8], pattern: &[u8], init: usize) -> Result<Option<Match>, PatternError> {
+    let (pattern, anchored) = match pattern.split_first() {
+        Some((b'^', rest)) => (rest, true),
+        _ => (pattern, false),
+    };
+
+    let mut start = init;
+    loop {
+        if let Some(m) = match_at(subject, pattern, start)? {
+            return Ok(Some(m));
+        }
+        start += 1;
+        if anchored || start > subject.len() {
+            return Ok(None);
+        }
+    }
+}
+
+/// Match `pattern` against `subject` starting exactly at byte index `start`.
+///
+/// Unlike `find`, a leading `^` is not treated specially.
+pub fn match_at(
+    subject: &[u8],
+    pattern: &[u8],
+    start: usize,
+) -> Result<Option<Match>, PatternError> {
+    let mut state = MatchState {
+        src: subject,
+        pat: pattern,
+        depth: 0,
+        captures: Vec::new(),
+    };
+    Ok(match state.do_match(start, 0)? {
+        Some(end) => Some(Match {
+            start,
+            end,
+            captures: state
+                .captures
+                .iter()
+                .map(|c| match c.len {
+                    CaptureLen::Position => Ok(Capture::Position(c.start)),
+                    CaptureLen::Closed(len) => Ok(Capture::Slice(c.start, c.start + len)),
+                    CaptureLen::Unclosed => Err(PatternError::UnfinishedCapture),
+                })
+                .collect::<Result<_, _>>()?,
+        }),
+        None => None,
+    })
+}
+
+/// Returns true if the pattern contains no special characters, so it can only match itself.
+pub fn is_literal(pattern: &[u8]) -> bool {
+    !pattern.iter().any(|b| b"^$*+?.([%-".contains(b))
+}
+
+/// Find the first occurrence of `needle` in `subject` at or after byte index `init`, without any
+/// pattern matching.
+///
+/// This is much faster than `find` for literal patterns: it scans for the first byte of the needle
+/// a machine word at a time and only compares the rest of the needle at candidate positions.
+pub fn find_plain(subject: &[u8], needle: &[u8], init: usize) -> Option<usize> {
+    let Some((&first, rest)) = needle.split_first() else {
+        return (init <= subject.len()).then_some(init);
+    };
+    let last_start = subject.len().checked_sub(needle.len())?;
+
+    let mut pos = init;
+    while pos <= last_start {
+        let start = pos + memchr(first, &subject[pos..=last_start])?;
+        if &subject[start + 1..start + needle.len()] == rest {
+            return Some(start);
+        }
+        pos = start + 1;
+    }
+    None
+}
+
+// Find the first index of `byte` in `haystack`, checking a word at a time.
+fn memchr(byte: u8, haystack: &[u8]) -> Option<usize> {
+    const WORD: usize = mem::size_of::<usize>();
+    const LO: usize = usize::MAX / 0xFF;
+    const HI: usize = LO << 7;
+
+    let repeated = LO * usize::from(byte);
+    let mut chunks = haystack.chunks_exact(WORD);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        // Every byte equal to `byte` becomes zero, and this sets the high bit of the first zero
+        // byte.
+        let word = usize::from_ne_bytes(chunk.try_into().unwrap()) ^ repeated;
+        if word.wrapping_sub(LO) & !word & HI != 0 {
+            return Some(i * WORD + chunk.iter().position(|&b| b == byte).unwrap());
+        }
+    }
+    let offset = haystack.len() - chunks.remainder().len();
+    chunks
+        .remainder()
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| offset + i)
+}
+
+#[derive(Debug, Copy, Clone)]
+enum CaptureLen {
+    Unclosed,
+    Position,
+    Closed(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CaptureState {
+    start: usize,
+    len: CaptureLen,
+}
+
+struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    depth: usize,
+    captures: Vec<CaptureState>,
+}
+
+impl<'a> MatchState<'a> {
+    // Returns the end of the match of the pattern starting at `p` against the subject starting at
+    // `s`, if there is one.
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        if self.depth >= MAX_MATCH_DEPTH {
+            return Err(PatternError::TooComplex);
+        }
+        self.depth += 1;
+        let res = self.do_match_inner(s, p);
+        self.depth -= 1;
+        res
+    }
+
+    fn do_match_inner(
+        &mut self,
+        mut s: usize,
+        mut p: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if p == self.pat.len() {
+                return Ok(Some(s));
+            }
+
+            match (self.pat[p], self.pat.get(p + 1).copied()) {
+                (b'(', Some(b')')) => return self.start_capture(s, p + 2, CaptureLen::Position),
+                (b'(', _) => return self.start_capture(s, p + 1, CaptureLen::Unclosed),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', None) => return Ok((s == self.src.len()).then_some(s)),
+                (b'%', Some(b'b')) => match self.match_balance(s, p + 2)? {
+                    Some(e) => {
+                        s = e;
+                        p += 4;
+                    }
+                    None => return Ok(None),
+                },
+                (b'%', Some(b'f')) => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err(PatternError::MissingFrontierBracket);
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let cur = self.src.get(s).copied().unwrap_or(0);
+                    if self.match_bracket_class(prev, p, ep - 1)
+                        || !self.match_bracket_class(cur, p, ep - 1)
+                    {
+                        return Ok(None);
+                    }
+                    p = ep;
+                }
+                (b'%', Some(l @ b'0'..=b'9')) => match self.match_capture(s, l)? {
+                    Some(e) => {
+                        s = e;
+                        p += 2;
+                    }
+                    None => return Ok(None),
+                },
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let matched = s < self.src.len() && self.single_match(self.src[s], p, ep);
+                    match self.pat.get(ep) {
+                        Some(b'?') => {
+                            if matched {
+                                if let Some(e) = self.do_match(s + 1, ep + 1)? {
+                                    return Ok(Some(e));
+                                }
+                            }
+                            p = ep + 1;
+                        }
+                        Some(b'+') => {
+                            return if matched {
+                                self.max_expand(s + 1, p, ep)
+                            } else {
+                                Ok(None)
+                            };
+                        }
+                        Some(b'*') => return self.max_expand(s, p, ep),
+                        Some(b'-') => return self.min_expand(s, p, ep),
+                        _ => {
+                            if !matched {
+                                return Ok(None);
+                            }
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the index just past the single character class starting at `p`.
+    fn class_end(&self, mut p: usize) -> Result<usize, PatternError> {
+        let c = self.pat[p];
+        p += 1;
+        match c {
+            b'%' => {
+                if p >= self.pat.len() {
+                    return Err(PatternError::EndsWithEscape);
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pat.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // The first character may be a ']' without closing the set.
+                loop {
+                    if p >= self.pat.len() {
+                        return Err(PatternError::MissingBracket);
+                    }
+                    let c = self.pat[p];
+                    p += 1;
+                    if c == b'%' && p < self.pat.len() {
+                        p += 1;
+                    }
+                    if self.pat.get(p) == Some(&b']') {
+                        break;
+                    }
+                }
+                Ok(p + 1)
+            }
+            _ => Ok(p),
+        }
+    }
+
+    // Matches a single character against the class from `p` to `ep`.
+    fn single_match(&self, c: u8, p: usize, ep: usize) -> bool {
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // Matches a character against the set starting with the '[' at `p` and ending with the ']'
+    // at `ec`.
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut positive = true;
+        p += 1;
+        if self.pat[p] == b'^' {
+            positive = false;
+            p += 1;
+        }
+        while p < ec {
+            if self.pat[p] == b'%' {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return positive;
+                }
+                p += 1;
+            } else if self.pat[p + 1] == b'-' && p + 2 < ec {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return positive;
+                }
+                p += 3;
+            } else {
+                if self.pat[p] == c {
+                    return positive;
+                }
+                p += 1;
+            }
+        }
+        !positive
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        if p + 1 >= self.pat.len() {
+            return Err(PatternError::MissingBalanceArguments);
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, PatternError> {
+        let mut count = 0;
+        while s + count < self.src.len() && self.single_match(self.src[s + count], p, ep) {
+            count += 1;
+        }
+        // Try with the maximum number of repetitions first, then back off one at a time.
+        loop {
+            if let Some(e) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        p: usize,
+        ep: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if let Some(e) = self.do_match(s, ep + 1)? {
+                return Ok(Some(e));
+            } else if s < self.src.len() && self.single_match(self.src[s], p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, PatternError> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err(PatternError::TooManyCaptures);
+        }
+        self.captures.push(CaptureState { start: s, len });
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures.pop();
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let l = self
+            .captures
+            .iter()
+            .rposition(|c| matches!(c.len, CaptureLen::Unclosed))
+            .ok_or(PatternError::InvalidPatternCapture)?;
+        self.captures[l].len = CaptureLen::Closed(s - self.captures[l].start);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures[l].len = CaptureLen::Unclosed;
+        }
+        Ok(res)
+    }
+
+    // Matches a back-reference `%1`-`%9` to a previous capture.
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, PatternError> {
+        let capture = (l as usize)
+            .checked_sub(b'1' as usize)
+            .and_then(|i| self.captures.get(i))
+            .filter(|c| !matches!(c.len, CaptureLen::Unclosed))
+            .ok_or(PatternError::InvalidCaptureIndex(l - b'0'))?;
+        // A position capture never matches as a back-reference, the same as PUC-Rio Lua.
+        let CaptureLen::Closed(len) = capture.len else {
+            return Ok(None);
+        };
+        let captured = &self.src[capture.start..capture.start + len];
+        Ok(self.src[s..].starts_with(captured).then_some(s + len))
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // Unlike `u8::is_ascii_whitespace`, this includes the vertical tab, the same as C's
+        // `isspace`.
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_str(subject: &str, pattern: &str) -> Option<(usize, usize)> {
+        find(subject.as_bytes(), pattern.as_bytes(), 0)
+            .unwrap()
+            .map(|m| (m.start, m.end))
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find_str("hello world", "o w"), Some((4, 7)));
+        assert_eq!(find_str("hello world", "^world"), None);
+        assert_eq!(find_str("hello world", "world$"), Some((6, 11)));
+        assert_eq!(find_str("hello", "l+"), Some((2, 4)));
+        assert_eq!(find_str("hello", "x*"), Some((0, 0)));
+        assert_eq!(find_str("<<a>>", "<.->"), Some((0, 4)));
+        assert_eq!(find_str("f(a(b)c)d", "%b()"), Some((1, 8)));
+        assert_eq!(find_str("THE (quick) fox", "%f[%a]%a+"), Some((0, 3)));
+        assert_eq!(find_str("x = [[]]", "[]]"), Some((6, 7)));
+        assert_eq!(find_str("a\x0bb", "%s"), Some((1, 2)));
+    }
+
+    #[test]
+    fn test_find_plain() {
+        assert_eq!(find_plain(b"hello world", b"o w", 0), Some(4));
+        assert_eq!(find_plain(b"hello world", b"o", 5), Some(7));
+        assert_eq!(find_plain(b"hello", b"", 5), Some(5));
+        assert_eq!(find_plain(b"hello", b"", 6), None);
+        assert_eq!(find_plain(b"hello", b"hello!", 0), None);
+        assert_eq!(find_plain(b"a.b", b".", 0), Some(1));
+
+        // Compare against the pattern matcher on a long subject with many partial matches, such
+        // as scanning a log for a message.
+        let mut subject = Vec::new();
+        let mut seed = 12345u32;
+        for _ in 0..20_000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            subject.push(b"abc \n"[(seed >> 16) as usize % 5]);
+        }
+        subject.extend(b"ERROR: disk full\n");
+
+        for needle in [
+            &b"a"[..],
+            b"ab",
+            b"abc",
+            b"cab a",
+            b"aaaa",
+            b"\nERROR",
+            b"full\n",
+            b"x",
+        ] {
+            for init in [0, 1, 7, 1000, 19_999, subject.len()] {
+                let expected = find(&subject, needle, init).unwrap().map(|m| m.start);
+                assert_eq!(find_plain(&subject, needle, init), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_captures() {
+        let m = find(b"key = value", b"(%w+)%s*=%s*()(%w+)()", 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            m.captures,
+            [
+                Capture::Slice(0, 3),
+                Capture::Position(6),
+                Capture::Slice(6, 11),
+                Capture::Position(11),
+            ]
+        );
+
+        let m = find(b"abcabc", b"(abc)%1", 0).unwrap().unwrap();
+        assert_eq!((m.start, m.end), (0, 6));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(find(b"a", b"%", 0), Err(PatternError::EndsWithEscape));
+        assert_eq!(find(b"a", b"[a", 0), Err(PatternError::MissingBracket));
+        assert_eq!(find(b"a", b"(a", 0), Err(PatternError::UnfinishedCapture));
+        assert_eq!(
+            find(b"a", b"a)", 0),
+            Err(PatternError::InvalidPatternCapture)
+        );
+        assert_eq!(
+            find(b"a", b"%1", 0),
+            Err(PatternError::InvalidCaptureIndex(1))
+        );
+        assert_eq!(
+            find(b"a", b"%fa", 0),
+            Err(PatternError::MissingFrontierBracket)
+        );
+        assert_eq!(
+            find(&[b'a'; 1000], &b"a?".repeat(1000), 0),
+            Err(PatternError::TooComplex)
+        );
+    }
+}
@@ -1,4 +1,17 @@
-use crate::{Callback, CallbackReturn, Context, IntoValue, Table, Value};
+use std::cell::Cell;
+
+use crate::{
+    value::display_float, BadArgument, Callback, CallbackReturn, Context, Function, IntoValue,
+    Stack, StringBuilder, Table, Value,
+};
+
+use super::{
+    pack::{self, Unpacked},
+    pattern::{self, Capture, Match},
+};
+
+/// The length of the longest string that `string.rep` will create.
+const MAX_REP_LEN: usize = i32::MAX as usize;
 
 pub fn load_string<'gc>(ctx: Context<'gc>) {
     let string = Table::new(&ctx);
@@ -11,7 +24,7 @@ pub fn load_string<'gc>(ctx: Context<'gc>) {
                 let v: Option<Value> = stack.consume(ctx)?;
                 if let Some(len) = v.and_then(|v| match v {
                     Value::Integer(i) => Some(i.to_string().as_bytes().len().try_into().unwrap()),
-                    Value::Number(n) => Some(n.to_string().as_bytes().len().try_into().unwrap()),
+                    Value::Number(n) => Some(display_float(n).len().try_into().unwrap()),
                     Value::String(s) => Some(s.len()),
                     _ => None,
                 }) {
@@ -24,5 +37,266 @@ pub fn load_string<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    string
+        .set(
+            ctx,
+            "find",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let subject = stack.check_string(ctx, 0)?.as_bytes();
+                let pat = stack.check_string(ctx, 1)?.as_bytes();
+                let Some(init) = start_index(&stack, 2, subject.len())? else {
+                    stack.replace(ctx, Value::Nil);
+                    return Ok(CallbackReturn::Return);
+                };
+                let plain = stack.get(3).to_bool();
+
+                if plain || pattern::is_literal(pat) {
+                    match pattern::find_plain(subject, pat, init) {
+                        Some(start) => {
+                            let end = start + pat.len();
+                            stack.replace(ctx, (start as i64 + 1, end as i64));
+                        }
+                        None => stack.replace(ctx, Value::Nil),
+                    }
+                } else {
+                    match pattern::find(subject, pat, init)? {
+                        Some(m) => {
+                            stack.replace(ctx, (m.start as i64 + 1, m.end as i64));
+                            push_captures(ctx, &mut stack, subject, &m, false);
+                        }
+                        None => stack.replace(ctx, Value::Nil),
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "match",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let subject = stack.check_string(ctx, 0)?.as_bytes();
+                let pat = stack.check_string(ctx, 1)?.as_bytes();
+                let Some(init) = start_index(&stack, 2, subject.len())? else {
+                    stack.replace(ctx, Value::Nil);
+                    return Ok(CallbackReturn::Return);
+                };
+
+                match pattern::find(subject, pat, init)? {
+                    Some(m) => {
+                        stack.clear();
+                        push_captures(ctx, &mut stack, subject, &m, true);
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "gmatch",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let subject = stack.check_string(ctx, 0)?;
+                let pat = stack.check_string(ctx, 1)?;
+                let init = start_index(&stack, 2, subject.as_bytes().len())?
+                    .unwrap_or(subject.as_bytes().len() + 1);
+
+                // The iterator state is the position to start the next search from, and the end
+                // of the last match so that an empty match directly after it is skipped.
+                let state = (subject, pat, Cell::new(init), Cell::new(None::<usize>));
+                stack.replace(
+                    ctx,
+                    Callback::from_fn_with(
+                        &ctx,
+                        state,
+                        |(subject, pat, position, last_match), ctx, _, mut stack| {
+                            let subject = subject.as_bytes();
+                            stack.clear();
+                            for start in position.get()..=subject.len() {
+                                if let Some(m) = pattern::match_at(subject, pat.as_bytes(), start)?
+                                {
+                                    if last_match.get() != Some(m.end) {
+                                        position.set(m.end);
+                                        last_match.set(Some(m.end));
+                                        push_captures(ctx, &mut stack, subject, &m, true);
+                                        return Ok(CallbackReturn::Return);
+                                    }
+                                }
+                            }
+                            position.set(subject.len() + 1);
+                            stack.push_back(Value::Nil);
+                            Ok(CallbackReturn::Return)
+                        },
+                    ),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "pack",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let fmt = stack.check_string(ctx, 0)?.as_bytes();
+                let args = stack.drain(1..).collect::<Vec<_>>();
+                let packed = pack::pack(fmt, &args, 1)?;
+                stack.replace(ctx, ctx.intern(&packed));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "packsize",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let fmt = stack.check_string(ctx, 0)?.as_bytes();
+                let size = pack::packsize(fmt)?;
+                stack.replace(ctx, size as i64);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "unpack",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let fmt = stack.check_string(ctx, 0)?.as_bytes();
+                let data = stack.check_string(ctx, 1)?.as_bytes();
+                let Some(pos) = start_index(&stack, 2, data.len())? else {
+                    return Err(pack::PackError::InitialPositionOutOfString.into());
+                };
+
+                let (values, next) = pack::unpack(fmt, data, pos)?;
+                stack.clear();
+                for value in values {
+                    stack.push_back(match value {
+                        Unpacked::Integer(i) => Value::Integer(i),
+                        Unpacked::Number(n) => Value::Number(n),
+                        Unpacked::String(start, end) => ctx.intern(&data[start..end]).into(),
+                    });
+                }
+                stack.push_back(Value::Integer(next as i64 + 1));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "rep",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let s = stack.check_string(ctx, 0)?;
+                let n = stack.check_integer(1)?;
+                let sep = if stack.get(2).is_nil() {
+                    None
+                } else {
+                    Some(stack.check_string(ctx, 2)?)
+                };
+
+                if n <= 0 {
+                    stack.replace(ctx, ctx.intern(b""));
+                    return Ok(CallbackReturn::Return);
+                }
+
+                let s = s.as_bytes();
+                let sep = sep.map(|sep| sep.as_bytes()).unwrap_or_default();
+                let len = usize::try_from(n)
+                    .ok()
+                    .and_then(|n| (s.len() + sep.len()).checked_mul(n))
+                    .map(|len| len - sep.len())
+                    .filter(|&len| len <= MAX_REP_LEN)
+                    .ok_or_else(|| "resulting string too large".into_value(ctx))?;
+
+                ctx.check_allocation(len)?;
+                let mut builder = StringBuilder::with_capacity(len);
+                for i in 0..n {
+                    if i != 0 {
+                        builder.push_bytes(sep);
+                    }
+                    builder.push_bytes(s);
+                }
+                stack.replace(ctx, builder.build(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "dump",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let strip = stack.get(1).to_bool();
+                match stack.get(0) {
+                    Value::Function(Function::Closure(closure)) => {
+                        let chunk = closure.prototype().dump(strip);
+                        stack.replace(ctx, ctx.intern(&chunk));
+                        Ok(CallbackReturn::Return)
+                    }
+                    Value::Function(Function::Callback(_)) => {
+                        Err("unable to dump given function".into_value(ctx).into())
+                    }
+                    v => Err(BadArgument {
+                        index: 1,
+                        expected: "function",
+                        found: v.type_name(),
+                    }
+                    .into()),
+                }
+            }),
+        )
+        .unwrap();
+
     ctx.set_global("string", string).unwrap();
 }
+
+// Converts the optional 1-based (and possibly negative) start index argument at `i` into a 0-based
+// byte index, returning `None` if it is past the end of the subject.
+fn start_index(stack: &Stack, i: usize, len: usize) -> Result<Option<usize>, BadArgument> {
+    let init = if stack.get(i).is_nil() {
+        1
+    } else {
+        stack.check_integer(i)?
+    };
+    let len = len as i64;
+    let init = if init > 0 {
+        init
+    } else if init == 0 || init < -len {
+        1
+    } else {
+        len + init + 1
+    };
+    Ok((init <= len + 1).then(|| (init - 1) as usize))
+}
+
+// Pushes the captures of `m` onto the stack. If `whole` is true and the pattern has no explicit
+// captures, the whole match is pushed instead.
+fn push_captures<'gc>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    subject: &[u8],
+    m: &Match,
+    whole: bool,
+) {
+    if whole && m.captures.is_empty() {
+        stack.push_back(ctx.intern(&subject[m.start..m.end]).into());
+    }
+    for &capture in &m.captures {
+        stack.push_back(match capture {
+            Capture::Slice(start, end) => ctx.intern(&subject[start..end]).into(),
+            Capture::Position(pos) => Value::Integer(pos as i64 + 1),
+        });
+    }
+}
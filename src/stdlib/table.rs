@@ -1,4 +1,10 @@
-use crate::{Callback, CallbackReturn, Context, Table};
+use gc_arena::Collect;
+use rand::{thread_rng, Rng};
+
+use crate::{
+    raw_ops, BadArgument, BoxSequence, Callback, CallbackReturn, Context, Error, Execution,
+    Function, IntoValue, Sequence, SequencePoll, Stack, StringBuilder, Table, Value,
+};
 
 pub fn load_table<'gc>(ctx: Context<'gc>) {
     let table = Table::new(&ctx);
@@ -8,7 +14,7 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
             ctx,
             "pack",
             Callback::from_fn(&ctx, |ctx, _, mut stack| {
-                let t = Table::new(&ctx);
+                let t = Table::with_capacity(&ctx, stack.len(), 1);
                 for i in 0..stack.len() {
                     t.set(ctx, i as i64 + 1, stack[i]).unwrap();
                 }
@@ -27,7 +33,7 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
                 let (table, start, end): (Table<'gc>, Option<i64>, Option<i64>) =
                     stack.consume(ctx)?;
                 let start = start.unwrap_or(1);
-                let end = end.unwrap_or_else(|| table.length());
+                let end = end.unwrap_or_else(|| table.len());
 
                 if start <= end {
                     stack.resize((end - start + 1) as usize);
@@ -41,5 +47,354 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    table
+        .set(
+            ctx,
+            "concat",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let list = stack.check_table(0)?;
+                let sep = if stack.get(1).is_nil() {
+                    None
+                } else {
+                    Some(stack.check_string(ctx, 1)?)
+                };
+                let start = if stack.get(2).is_nil() {
+                    1
+                } else {
+                    stack.check_integer(2)?
+                };
+                let end = if stack.get(3).is_nil() {
+                    list.len()
+                } else {
+                    stack.check_integer(3)?
+                };
+
+                // Check every element and reserve space for all of the strings up front, so that
+                // the result is built in a single buffer.
+                let mut capacity = 0;
+                for i in start..=end {
+                    match list.get_value(i.into()) {
+                        Value::String(s) => capacity += s.as_bytes().len(),
+                        Value::Integer(_) | Value::Number(_) => {}
+                        _ => {
+                            return Err(format!(
+                                "invalid value (at index {i}) in table for 'concat'"
+                            )
+                            .into_value(ctx)
+                            .into());
+                        }
+                    }
+                    if let Some(sep) = sep {
+                        if i != end {
+                            capacity += sep.as_bytes().len();
+                        }
+                    }
+                }
+
+                ctx.check_allocation(capacity)?;
+                let mut builder = StringBuilder::with_capacity(capacity);
+                for i in start..=end {
+                    builder.push_value(list.get_value(i.into()))?;
+                    if let Some(sep) = sep {
+                        if i != end {
+                            builder.push_bytes(sep.as_bytes());
+                        }
+                    }
+                }
+                stack.replace(ctx, builder.build(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "sort",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let list = stack.check_table(0)?;
+                let comp = match stack.get(1) {
+                    Value::Nil => None,
+                    Value::Function(f) => Some(f),
+                    v => {
+                        return Err(BadArgument {
+                            index: 2,
+                            expected: "function",
+                            found: v.type_name(),
+                        }
+                        .into())
+                    }
+                };
+                stack.clear();
+
+                let mut sort = Sort::new(list);
+                let Some(comp) = comp else {
+                    while let Some((a, b)) = sort.next_comparison() {
+                        let less = raw_ops::less_than(a, b)
+                            .ok_or_else(|| compare_error(a, b).into_value(ctx))?;
+                        sort.advance(less).map_err(|err| err.into_value(ctx))?;
+                    }
+                    sort.finish(ctx)?;
+                    return Ok(CallbackReturn::Return);
+                };
+
+                #[derive(Collect)]
+                #[collect(no_drop)]
+                struct SortSeq<'gc> {
+                    sort: Sort<'gc>,
+                    comp: Function<'gc>,
+                    calling: bool,
+                }
+
+                impl<'gc> Sequence<'gc> for SortSeq<'gc> {
+                    fn poll(
+                        &mut self,
+                        ctx: Context<'gc>,
+                        _exec: Execution<'gc, '_>,
+                        mut stack: Stack<'gc, '_>,
+                    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                        if self.calling {
+                            self.sort
+                                .advance(stack.get(0).to_bool())
+                                .map_err(|err| err.into_value(ctx))?;
+                        }
+
+                        if let Some((a, b)) = self.sort.next_comparison() {
+                            self.calling = true;
+                            stack.replace(ctx, (a, b));
+                            Ok(SequencePoll::Call {
+                                function: self.comp,
+                                is_tail: false,
+                            })
+                        } else {
+                            stack.clear();
+                            self.sort.finish(ctx)?;
+                            Ok(SequencePoll::Return)
+                        }
+                    }
+                }
+
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    SortSeq {
+                        sort,
+                        comp,
+                        calling: false,
+                    },
+                )))
+            }),
+        )
+        .unwrap();
+
     ctx.set_global("table", table).unwrap();
 }
+
+// The quicksort used by PUC-Rio Lua, driven one comparison at a time so that the comparisons can
+// be made by calling a Lua function.
+//
+// Intervals still to be sorted are kept on an explicit stack rather than recursing. An inconsistent
+// comparison can never move the partition scans out of bounds, instead it is detected and reported
+// as "invalid order function for sorting" where PUC-Rio Lua would report it.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Sort<'gc> {
+    list: Table<'gc>,
+    values: Vec<Value<'gc>>,
+    // Inclusive intervals of `values` which are not yet sorted.
+    #[collect(require_static)]
+    intervals: Vec<(usize, usize)>,
+    #[collect(require_static)]
+    step: SortStep,
+    // The interval being sorted, the index of its pivot, and the partition scan positions.
+    lo: usize,
+    up: usize,
+    pivot: usize,
+    i: usize,
+    j: usize,
+    // Set once a partition is badly unbalanced, after which pivots are chosen randomly.
+    randomize: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SortStep {
+    // Start sorting the next interval on the stack.
+    Interval,
+    // Order the first and last values of the interval, then the pivot with each of them.
+    UpLo,
+    PivotLo,
+    UpPivot,
+    // Partition the interval around the pivot, which has been moved to `up - 1`.
+    ScanUp,
+    ScanDown,
+}
+
+// Intervals shorter than this always use the middle value as the pivot.
+const RANDOM_PIVOT_LIMIT: usize = 100;
+
+impl<'gc> Sort<'gc> {
+    fn new(list: Table<'gc>) -> Self {
+        let values: Vec<_> = (1..=list.len()).map(|i| list.get_value(i.into())).collect();
+        let intervals = if values.len() > 1 {
+            vec![(0, values.len() - 1)]
+        } else {
+            Vec::new()
+        };
+        Sort {
+            list,
+            values,
+            intervals,
+            step: SortStep::Interval,
+            lo: 0,
+            up: 0,
+            pivot: 0,
+            i: 0,
+            j: 0,
+            randomize: false,
+        }
+    }
+
+    // Returns the next pair of values to compare with `<`, or `None` if the sort is finished.
+    fn next_comparison(&mut self) -> Option<(Value<'gc>, Value<'gc>)> {
+        let values = &self.values;
+        Some(match self.step {
+            SortStep::Interval => {
+                let (lo, up) = loop {
+                    match self.intervals.pop()? {
+                        (lo, up) if lo < up => break (lo, up),
+                        _ => {}
+                    }
+                };
+                self.lo = lo;
+                self.up = up;
+                self.step = SortStep::UpLo;
+                (values[up], values[lo])
+            }
+            SortStep::UpLo => (values[self.up], values[self.lo]),
+            SortStep::PivotLo => (values[self.pivot], values[self.lo]),
+            SortStep::UpPivot => (values[self.up], values[self.pivot]),
+            SortStep::ScanUp => (values[self.i], values[self.up - 1]),
+            SortStep::ScanDown => (values[self.up - 1], values[self.j]),
+        })
+    }
+
+    // Called with the result of the last comparison.
+    fn advance(&mut self, less: bool) -> Result<(), &'static str> {
+        const INVALID_ORDER: &str = "invalid order function for sorting";
+
+        let (lo, up) = (self.lo, self.up);
+        match self.step {
+            SortStep::Interval => unreachable!("no comparison was requested"),
+            SortStep::UpLo => {
+                if less {
+                    self.values.swap(lo, up);
+                }
+                if up - lo == 1 {
+                    self.step = SortStep::Interval;
+                } else {
+                    self.pivot = self.choose_pivot();
+                    self.step = SortStep::PivotLo;
+                }
+            }
+            SortStep::PivotLo => {
+                if less {
+                    self.values.swap(self.pivot, lo);
+                    self.start_partition();
+                } else {
+                    self.step = SortStep::UpPivot;
+                }
+            }
+            SortStep::UpPivot => {
+                if less {
+                    self.values.swap(self.pivot, up);
+                }
+                self.start_partition();
+            }
+            SortStep::ScanUp => {
+                if less {
+                    if self.i == up - 1 {
+                        return Err(INVALID_ORDER);
+                    }
+                    self.i += 1;
+                } else {
+                    self.j -= 1;
+                    self.step = SortStep::ScanDown;
+                }
+            }
+            SortStep::ScanDown => {
+                if less {
+                    if self.j < self.i {
+                        return Err(INVALID_ORDER);
+                    }
+                    self.j -= 1;
+                } else if self.j < self.i {
+                    self.values.swap(up - 1, self.i);
+                    self.split(self.i);
+                } else {
+                    self.values.swap(self.i, self.j);
+                    self.i += 1;
+                    self.step = SortStep::ScanUp;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn choose_pivot(&self) -> usize {
+        let (lo, up) = (self.lo, self.up);
+        if up - lo < RANDOM_PIVOT_LIMIT || !self.randomize {
+            lo + (up - lo) / 2
+        } else {
+            let quarter = (up - lo) / 4;
+            lo + quarter + thread_rng().gen_range(0..quarter * 2)
+        }
+    }
+
+    // Called once the first, last and pivot values of the interval are in order.
+    fn start_partition(&mut self) {
+        let (lo, up) = (self.lo, self.up);
+        if up - lo == 2 {
+            self.step = SortStep::Interval;
+        } else {
+            self.values.swap(self.pivot, up - 1);
+            self.i = lo + 1;
+            self.j = up - 1;
+            self.step = SortStep::ScanUp;
+        }
+    }
+
+    // Called once the interval is partitioned around the pivot, which is now at `p`.
+    fn split(&mut self, p: usize) {
+        let (lo, up) = (self.lo, self.up);
+        // Sort the smaller side first, which keeps the stack of intervals short.
+        let (smaller, larger) = if p - lo < up - p {
+            ((lo, p - 1), (p + 1, up))
+        } else {
+            ((p + 1, up), (lo, p - 1))
+        };
+        if (up - lo) / 128 > smaller.1 + 1 - smaller.0 {
+            self.randomize = true;
+        }
+        self.intervals.push(larger);
+        self.intervals.push(smaller);
+        self.step = SortStep::Interval;
+    }
+
+    fn finish(&self, ctx: Context<'gc>) -> Result<(), Error<'gc>> {
+        for (i, &value) in self.values.iter().enumerate() {
+            self.list.set(ctx, i as i64 + 1, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_error(a: Value, b: Value) -> String {
+    if a.type_name() == b.type_name() {
+        format!("attempt to compare two {} values", a.type_name())
+    } else {
+        format!(
+            "attempt to compare {} with {}",
+            a.type_name(),
+            b.type_name()
+        )
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{BadArgument, Callback, CallbackReturn, Context, IntoValue, Stack, Table, Value};
+
+/// The largest code point accepted by the `utf8` library.
+const MAX_UNICODE: u32 = 0x10FFFF;
+
+pub fn load_utf8<'gc>(ctx: Context<'gc>) {
+    let utf8 = Table::new(&ctx);
+
+    utf8.set(
+        ctx,
+        "charpattern",
+        ctx.intern_static(b"[\0-\x7F\xC2-\xF4][\x80-\xBF]*"),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "char",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let mut bytes = Vec::new();
+            for i in 0..stack.len() {
+                let code = stack.check_integer(i)?;
+                match u32::try_from(code) {
+                    Ok(code) if code <= MAX_UNICODE => encode(code, &mut bytes),
+                    _ => return Err("value out of range".into_value(ctx).into()),
+                }
+            }
+            stack.replace(ctx, ctx.intern(&bytes));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "codepoint",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let s = stack.check_string(ctx, 0)?.as_bytes();
+            let i = relative_position(opt_integer(&stack, 1, 1)?, s.len());
+            let j = relative_position(opt_integer(&stack, 2, i)?, s.len());
+            if i < 1 || j > s.len() as i64 {
+                return Err("out of bounds".into_value(ctx).into());
+            }
+
+            stack.clear();
+            let mut pos = (i - 1) as usize;
+            while pos < j as usize {
+                let Some((code, len)) = decode(&s[pos..]) else {
+                    return Err("invalid UTF-8 code".into_value(ctx).into());
+                };
+                stack.push_back(Value::Integer(code.into()));
+                pos += len;
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "len",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let s = stack.check_string(ctx, 0)?.as_bytes();
+            let i = relative_position(opt_integer(&stack, 1, 1)?, s.len());
+            let j = relative_position(opt_integer(&stack, 2, -1)?, s.len());
+            if i < 1 || i > s.len() as i64 + 1 {
+                return Err("initial position out of bounds".into_value(ctx).into());
+            }
+            if j > s.len() as i64 {
+                return Err("final position out of bounds".into_value(ctx).into());
+            }
+
+            let mut pos = (i - 1) as usize;
+            let mut count = 0;
+            while (pos as i64) < j {
+                match decode(&s[pos..]) {
+                    Some((_, len)) => pos += len,
+                    None => {
+                        // Return the position of the first invalid byte.
+                        stack.replace(ctx, (Value::Nil, pos as i64 + 1));
+                        return Ok(CallbackReturn::Return);
+                    }
+                }
+                count += 1;
+            }
+            stack.replace(ctx, count);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "offset",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let s = stack.check_string(ctx, 0)?.as_bytes();
+            let mut n = stack.check_integer(1)?;
+            let default_i = if n >= 0 { 1 } else { s.len() as i64 + 1 };
+            let i = relative_position(opt_integer(&stack, 2, default_i)?, s.len());
+            if i < 1 || i > s.len() as i64 + 1 {
+                return Err("position out of bounds".into_value(ctx).into());
+            }
+
+            let is_continuation = |pos: usize| s.get(pos).is_some_and(|&b| b & 0xC0 == 0x80);
+            let mut pos = (i - 1) as usize;
+            if n == 0 {
+                // Find the start of the character containing byte `i`.
+                while pos > 0 && is_continuation(pos) {
+                    pos -= 1;
+                }
+            } else {
+                if is_continuation(pos) {
+                    return Err("initial position is a continuation byte"
+                        .into_value(ctx)
+                        .into());
+                }
+                if n < 0 {
+                    while n < 0 && pos > 0 {
+                        pos -= 1;
+                        while pos > 0 && is_continuation(pos) {
+                            pos -= 1;
+                        }
+                        n += 1;
+                    }
+                } else {
+                    n -= 1;
+                    while n > 0 && pos < s.len() {
+                        pos += 1;
+                        while is_continuation(pos) {
+                            pos += 1;
+                        }
+                        n -= 1;
+                    }
+                }
+            }
+
+            if n == 0 {
+                stack.replace(ctx, pos as i64 + 1);
+            } else {
+                // There is no such character.
+                stack.replace(ctx, Value::Nil);
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    let codes_iter = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        let s = stack.check_string(ctx, 0)?.as_bytes();
+        let control = stack.check_integer(1)?;
+
+        // Skip past the character at the previous (1-based) position, if any.
+        let mut pos = 0;
+        if control > 0 {
+            pos = control as usize;
+            while s.get(pos).is_some_and(|&b| b & 0xC0 == 0x80) {
+                pos += 1;
+            }
+        }
+
+        if pos >= s.len() {
+            stack.clear();
+        } else {
+            let Some((code, _)) = decode(&s[pos..]) else {
+                return Err("invalid UTF-8 code".into_value(ctx).into());
+            };
+            stack.replace(ctx, (pos as i64 + 1, code as i64));
+        }
+        Ok(CallbackReturn::Return)
+    });
+
+    utf8.set(
+        ctx,
+        "codes",
+        Callback::from_fn_with(&ctx, codes_iter, |codes_iter, ctx, _, mut stack| {
+            let s = stack.check_string(ctx, 0)?;
+            stack.replace(ctx, (*codes_iter, s, 0));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    ctx.set_global("utf8", utf8).unwrap();
+}
+
+// Get the optional integer argument at `i`, or `default` if it is nil or missing.
+fn opt_integer(stack: &Stack, i: usize, default: i64) -> Result<i64, BadArgument> {
+    if stack.get(i).is_nil() {
+        Ok(default)
+    } else {
+        stack.check_integer(i)
+    }
+}
+
+// Translates a relative string position, where negative positions count back from the end of the
+// string. Positions before the start of the string become 0.
+fn relative_position(pos: i64, len: usize) -> i64 {
+    let len = len as i64;
+    if pos >= 0 {
+        pos
+    } else if pos < -len {
+        0
+    } else {
+        len + pos + 1
+    }
+}
+
+// Encodes a single code point as UTF-8. Unlike `char::encode_utf8`, this also allows encoding
+// surrogates, the same as PUC-Rio Lua.
+fn encode(code: u32, bytes: &mut Vec<u8>) {
+    if code < 0x80 {
+        bytes.push(code as u8);
+    } else if code < 0x800 {
+        bytes.push(0xC0 | (code >> 6) as u8);
+        bytes.push(0x80 | (code & 0x3F) as u8);
+    } else if code < 0x10000 {
+        bytes.push(0xE0 | (code >> 12) as u8);
+        bytes.push(0x80 | ((code >> 6) & 0x3F) as u8);
+        bytes.push(0x80 | (code & 0x3F) as u8);
+    } else {
+        bytes.push(0xF0 | (code >> 18) as u8);
+        bytes.push(0x80 | ((code >> 12) & 0x3F) as u8);
+        bytes.push(0x80 | ((code >> 6) & 0x3F) as u8);
+        bytes.push(0x80 | (code & 0x3F) as u8);
+    }
+}
+
+// Decodes the UTF-8 sequence at the start of `bytes`, returning the code point and the length of
+// the sequence in bytes.
+//
+// Returns `None` for invalid sequences, including overlong encodings, surrogates, and code points
+// greater than `MAX_UNICODE`.
+fn decode(bytes: &[u8]) -> Option<(u32, usize)> {
+    let first = *bytes.first()?;
+    let (len, mut code, min) = match first {
+        0x00..=0x7F => return Some((first.into(), 1)),
+        0xC0..=0xDF => (2, u32::from(first & 0x1F), 0x80),
+        0xE0..=0xEF => (3, u32::from(first & 0x0F), 0x800),
+        0xF0..=0xF7 => (4, u32::from(first & 0x07), 0x10000),
+        _ => return None,
+    };
+    for &b in bytes.get(1..len)? {
+        if b & 0xC0 != 0x80 {
+            return None;
+        }
+        code = (code << 6) | u32::from(b & 0x3F);
+    }
+    if code < min || code > MAX_UNICODE || (0xD800..=0xDFFF).contains(&code) {
+        return None;
+    }
+    Some((code, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        for c in ['a', 'é', '€', '𝄞'] {
+            let mut bytes = Vec::new();
+            encode(c as u32, &mut bytes);
+            assert_eq!(bytes, c.to_string().as_bytes());
+            assert_eq!(decode(&bytes), Some((c as u32, c.len_utf8())));
+        }
+
+        // Overlong encoding of '/'
+        assert_eq!(decode(b"\xC0\xAF"), None);
+        // Encoded surrogate
+        assert_eq!(decode(b"\xED\xA0\x80"), None);
+        // Beyond `MAX_UNICODE`
+        assert_eq!(decode(b"\xF4\x90\x80\x80"), None);
+        // Truncated sequence
+        assert_eq!(decode(b"\xE2\x82"), None);
+        // Lone continuation byte
+        assert_eq!(decode(b"\x80"), None);
+    }
+}
@@ -2,14 +2,14 @@ use std::{
     alloc,
     borrow::Cow,
     fmt,
-    hash::{BuildHasherDefault, Hash, Hasher},
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
     io::Write,
     ops, slice,
     str::{self, Utf8Error},
     string::String as StdString,
 };
 
-use ahash::AHasher;
+use ahash::{AHasher, RandomState};
 use gc_arena::{
     allocator_api::MetricsAlloc, barrier::Unlock, lock::RefLock, metrics::Metrics, Collect,
     Collection, Gc, GcWeak, Mutation, StaticCollect,
@@ -17,7 +17,7 @@ use gc_arena::{
 use hashbrown::{hash_map, raw::RawTable, HashMap};
 use thiserror::Error;
 
-use crate::{Context, Value};
+use crate::{value::display_float, Context, Value};
 
 // Represents `String` as either a pointer to an external / owned slice pointer or a size prefixed
 // inline array.
@@ -52,7 +52,7 @@ impl<'gc> String<'gc> {
             fn drop(&mut self) {
                 match self.header.buffer {
                     Buffer::Indirect(ptr) => unsafe {
-                        self.metrics.mark_external_deallocation((*ptr).len());
+                        self.metrics.mark_external_deallocation((&(*ptr)).len());
                         drop(Box::from_raw(ptr as *mut [u8]));
                     },
                     Buffer::Inline(_) => unreachable!(),
@@ -136,10 +136,25 @@ impl<'gc> String<'gc> {
         self.0
     }
 
+    /// Intern the given bytes in the string set of the given context, see `InternedStringSet`.
+    ///
+    /// Equivalent to `Context::intern`.
+    pub fn intern(ctx: Context<'gc>, s: &[u8]) -> String<'gc> {
+        ctx.intern(s)
+    }
+
     pub fn stored_hash(self) -> u64 {
         self.0.hash
     }
 
+    /// Returns true if both strings point to the same allocation.
+    ///
+    /// Equal short strings which were both interned are always the same allocation, so this can be
+    /// used as a fast path before comparing string contents.
+    pub fn ptr_eq(a: String<'gc>, b: String<'gc>) -> bool {
+        Gc::ptr_eq(a.0, b.0)
+    }
+
     pub fn as_bytes(self) -> &'gc [u8] {
         // SAFETY: `&'gc [u8]` has the correct lifetime because `Gc::as_ref` also returns `&'gc T`.
         unsafe {
@@ -159,8 +174,23 @@ impl<'gc> String<'gc> {
     }
 }
 
+/// Returns a hasher with fixed keys, for hashes which must be the same across runs of the same
+/// program.
+///
+/// `AHasher::default()` uses keys which are randomly generated once per process, so table hashes
+/// built with it would make table iteration order differ between runs.
+fn fixed_hasher() -> AHasher {
+    const SEEDS: [u64; 4] = [
+        0x243f_6a88_85a3_08d3,
+        0x1319_8a2e_0370_7344,
+        0xa409_3822_299f_31d0,
+        0x082e_fa98_ec4e_6c89,
+    ];
+    RandomState::with_seeds(SEEDS[0], SEEDS[1], SEEDS[2], SEEDS[3]).build_hasher()
+}
+
 fn str_hash(s: &[u8]) -> u64 {
-    let mut state = AHasher::default();
+    let mut state = fixed_hasher();
     state.write(s);
     state.finish()
 }
@@ -186,33 +216,100 @@ pub struct BadConcatType {
     bad_type: &'static str,
 }
 
+/// A buffer for building a `String` out of many pieces.
+///
+/// Building a string with repeated `..` copies everything built so far for every piece added, which
+/// is quadratic in the number of pieces. A `StringBuilder` instead appends every piece to a single
+/// buffer, and `StringBuilder::build` turns that buffer into a flat `String` without copying long
+/// strings again.
+#[derive(Debug, Default, Clone)]
+pub struct StringBuilder {
+    bytes: Vec<u8>,
+}
+
+impl StringBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a builder which can hold `capacity` bytes before it needs to reallocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        StringBuilder {
+            bytes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The number of bytes the builder can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.bytes.reserve(additional);
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Append a value following the rules of the Lua `..` operator, only strings and numbers are
+    /// allowed.
+    pub fn push_value(&mut self, value: Value<'_>) -> Result<(), BadConcatType> {
+        let bad_type = match value {
+            Value::Integer(i) => {
+                write!(&mut self.bytes, "{}", i).unwrap();
+                return Ok(());
+            }
+            Value::Number(n) => {
+                self.bytes.extend(display_float(n).as_bytes());
+                return Ok(());
+            }
+            Value::String(s) => {
+                self.bytes.extend(s.as_bytes());
+                return Ok(());
+            }
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::Thread(_) => "thread",
+            Value::UserData(_) | Value::LightUserData(_) => "userdata",
+        };
+        Err(BadConcatType { bad_type })
+    }
+
+    /// Create the finished string.
+    ///
+    /// Short strings are interned the same as `Context::intern`, longer strings take ownership of
+    /// the builder's buffer.
+    pub fn build<'gc>(self, ctx: Context<'gc>) -> String<'gc> {
+        if self.bytes.len() <= InternedStringSet::MAX_INTERNED_LEN {
+            ctx.intern(&self.bytes)
+        } else {
+            String::from_buffer(&ctx, self.bytes.into_boxed_slice())
+        }
+    }
+}
+
 impl<'gc> String<'gc> {
+    /// Concatenate values following the rules of the Lua `..` operator.
+    ///
+    /// Only strings and numbers may be concatenated, numbers are converted to strings the same way
+    /// as `tostring`, see `value::display_float`.
     pub fn concat(ctx: Context<'gc>, values: &[Value<'gc>]) -> Result<String<'gc>, BadConcatType> {
-        let mut bytes = Vec::new();
-        for value in values {
-            match value {
-                Value::Nil => write!(&mut bytes, "nil").unwrap(),
-                Value::Boolean(b) => write!(&mut bytes, "{}", b).unwrap(),
-                Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
-                Value::String(s) => bytes.extend(s.as_bytes()),
-                Value::Table(_) => return Err(BadConcatType { bad_type: "table" }),
-                Value::Function(_) => {
-                    return Err(BadConcatType {
-                        bad_type: "function",
-                    });
-                }
-                Value::Thread(_) => {
-                    return Err(BadConcatType { bad_type: "thread" });
-                }
-                Value::UserData(_) => {
-                    return Err(BadConcatType {
-                        bad_type: "userdata",
-                    });
-                }
-            }
+        let mut builder = StringBuilder::new();
+        for &value in values {
+            builder.push_value(value)?;
         }
-        Ok(ctx.intern(&bytes))
+        Ok(builder.build(ctx))
     }
 
     pub fn len(self) -> i64 {
@@ -392,6 +489,11 @@ impl<'gc> InternedStaticStrings<'gc> {
 /// string.
 ///
 /// If there is no matching existing live interned string, then a new string is allocated.
+///
+/// Only strings up to `InternedStringSet::MAX_INTERNED_LEN` bytes long are de-duplicated, longer
+/// dynamic strings are always newly allocated. Short strings are the ones most likely to be used
+/// as table keys (identifiers, field names), and for those an equality check with an already
+/// interned string is usually just a pointer comparison.
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct InternedStringSet<'gc> {
@@ -407,8 +509,15 @@ impl<'gc> InternedStringSet<'gc> {
         }
     }
 
+    /// Dynamic strings longer than this are not de-duplicated.
+    pub const MAX_INTERNED_LEN: usize = 40;
+
     pub fn intern(self, mc: &Mutation<'gc>, s: &[u8]) -> String<'gc> {
-        self.dyn_strings.intern(mc, s)
+        if s.len() <= Self::MAX_INTERNED_LEN {
+            self.dyn_strings.intern(mc, s)
+        } else {
+            String::from_slice(mc, s)
+        }
     }
 
     pub fn intern_static(self, mc: &Mutation<'gc>, s: &'static [u8]) -> String<'gc> {
@@ -442,4 +551,30 @@ mod tests {
             assert_eq!(test6.as_bytes(), b"test 666666");
         });
     }
+
+    #[test]
+    fn test_interning() {
+        rootless_arena(|mc| {
+            let set = InternedStringSet::new(mc);
+
+            let a = set.intern(mc, b"identifier");
+            let owned = Vec::from("identifier");
+            let b = set.intern(mc, &owned);
+            assert!(String::ptr_eq(a, b));
+
+            let c = set.intern(mc, b"other");
+            assert!(!String::ptr_eq(a, c));
+
+            let short = [b'x'; InternedStringSet::MAX_INTERNED_LEN];
+            assert!(String::ptr_eq(
+                set.intern(mc, &short),
+                set.intern(mc, &short)
+            ));
+
+            let long = [b'x'; InternedStringSet::MAX_INTERNED_LEN + 1];
+            let (d, e) = (set.intern(mc, &long), set.intern(mc, &long));
+            assert!(!String::ptr_eq(d, e));
+            assert!(d == e);
+        });
+    }
 }
@@ -1,22 +1,17 @@
-use std::{
-    fmt,
-    hash::{Hash, Hasher},
-    i64, mem,
-};
+use std::{cell::Cell, fmt, i64, mem};
 
-use ahash::AHasher;
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, Collect, Mutation};
+use gc_arena::{allocator_api::MetricsAlloc, Collect, Collection, Gc, Mutation};
 use hashbrown::{hash_map, HashMap};
 use thiserror::Error;
 
-use crate::Value;
+use crate::{String, Value};
 
 #[derive(Debug, Copy, Clone, Error)]
 pub enum InvalidTableKey {
-    #[error("table key is NaN")]
+    #[error("table index is NaN")]
     IsNaN,
-    #[error("table key is Nil")]
+    #[error("table index is nil")]
     IsNil,
 }
 
@@ -28,7 +23,14 @@ pub enum NextValue<'gc> {
     NotFound,
 }
 
-#[derive(Collect)]
+/// A Lua table without a metatable, split into an array part and a map part in the same way as
+/// PUC-Rio Lua.
+///
+/// The array part holds the values for the integer keys `1..=n`, where `n` is chosen whenever the
+/// table grows so that more than half of the array part is in use. Every other key, and any integer
+/// key which does not fit in the array part, is stored in the map part. Keys move from the map part
+/// to the array part when the array part grows.
+#[derive(Clone, Collect)]
 #[collect(no_drop)]
 pub struct RawTable<'gc> {
     array: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
@@ -57,7 +59,26 @@ impl<'gc> RawTable<'gc> {
         }
     }
 
+    /// Create a table with space for the sequence keys `1..=array_capacity` in the array part and
+    /// for at least `map_capacity` entries in the map part.
+    pub fn with_capacity(mc: &Mutation<'gc>, array_capacity: usize, map_capacity: usize) -> Self {
+        let mut array = vec::Vec::with_capacity_in(array_capacity, MetricsAlloc::new(mc));
+        array.resize(array_capacity, Value::Nil);
+        Self {
+            array,
+            map: hash_map::HashMap::with_capacity_and_hasher_in(
+                map_capacity,
+                (),
+                MetricsAlloc::new(mc),
+            ),
+        }
+    }
+
     pub fn get(&self, key: Value<'gc>) -> Value<'gc> {
+        if let Value::Integer(i) = key {
+            return self.get_integer(i);
+        }
+
         if let Some(index) = to_array_index(key) {
             if index < self.array.len() {
                 return self.array[index];
@@ -79,11 +100,89 @@ impl<'gc> RawTable<'gc> {
         }
     }
 
+    /// The same as `RawTable::get` with an integer key.
+    ///
+    /// Integer keys are by far the most common, so this goes straight to the array part, and
+    /// otherwise probes the map part comparing only integer keys, without ever building or
+    /// canonicalizing a general `Value` key.
+    pub fn get_integer(&self, key: i64) -> Value<'gc> {
+        if let Some(index) = integer_array_index(key) {
+            if index < self.array.len() {
+                return self.array[index];
+            }
+        }
+
+        match self.map.raw_entry().from_hash(
+            integer_hash(key),
+            |k| matches!(*k, Value::Integer(k) if k == key),
+        ) {
+            Some((_, value)) => *value,
+            None => Value::Nil,
+        }
+    }
+
+    /// The same as `RawTable::get`, but first checks the map bucket at the index held in `slot`,
+    /// and stores the bucket index there whenever the key is found in the map part.
+    ///
+    /// The slot is only a hint: it is always checked against the key, so it stays correct when the
+    /// key is removed, the table is resized, or the slot is used with a different table.
+    pub fn get_cached(&self, key: Value<'gc>, slot: &Cell<u32>) -> Value<'gc> {
+        if to_array_index(key).is_some() {
+            return self.get(key);
+        }
+        let Ok(key) = canonical_key(key) else {
+            return Value::Nil;
+        };
+
+        let raw_table = self.map.raw_table();
+        let cached = slot.get() as usize;
+        if cached < raw_table.buckets() {
+            // SAFETY: The bucket index is in range, and only full buckets are read.
+            unsafe {
+                if raw_table.is_bucket_full(cached) {
+                    let (k, v) = *raw_table.bucket(cached).as_ref();
+                    if key_eq(k, key) {
+                        return v;
+                    }
+                }
+            }
+        }
+
+        match raw_table.find(key_hash(key), |(k, _)| key_eq(*k, key)) {
+            Some(bucket) => {
+                // SAFETY: The bucket was just returned by `find` on the same table.
+                let index = unsafe { raw_table.bucket_index(&bucket) };
+                if let Ok(index) = u32::try_from(index) {
+                    slot.set(index);
+                }
+                // SAFETY: Same as above, the bucket is full.
+                unsafe { bucket.as_ref().1 }
+            }
+            None => Value::Nil,
+        }
+    }
+
     pub fn set(
         &mut self,
         key: Value<'gc>,
         value: Value<'gc>,
     ) -> Result<Value<'gc>, InvalidTableKey> {
+        if let Value::Integer(i) = key {
+            // Assigning to an integer key which is already present never changes the shape of the
+            // table, so it can skip the general path.
+            if let Some(index) = integer_array_index(i) {
+                if index < self.array.len() {
+                    return Ok(mem::replace(&mut self.array[index], value));
+                }
+            }
+            if let hash_map::RawEntryMut::Occupied(occupied) = self.map.raw_entry_mut().from_hash(
+                integer_hash(i),
+                |k| matches!(*k, Value::Integer(k) if k == i),
+            ) {
+                return Ok(mem::replace(occupied.into_mut(), value));
+            }
+        }
+
         let index_key = to_array_index(key);
         if let Some(index) = index_key {
             if index < self.array.len() {
@@ -94,12 +193,16 @@ impl<'gc> RawTable<'gc> {
         let table_key = canonical_key(key)?;
         let hash = key_hash(table_key);
         Ok(if value.is_nil() {
+            // Entries are not removed when they are set to nil, so that they can still be found by
+            // `RawTable::next` when clearing fields during traversal. Dead entries are removed the
+            // next time the table needs to grow. Until then, `Table` does not keep their keys
+            // alive, see `RawTable::trace_weak`.
             if let hash_map::RawEntryMut::Occupied(occupied) = self
                 .map
                 .raw_entry_mut()
                 .from_hash(hash, |k| key_eq(*k, table_key))
             {
-                occupied.remove()
+                mem::replace(occupied.into_mut(), Value::Nil)
             } else {
                 Value::Nil
             }
@@ -119,7 +222,11 @@ impl<'gc> RawTable<'gc> {
             }
         } else {
             // If a new element does not fit in either the array or map part of the table, we need
-            // to grow. First, we find the total count of array candidate elements across the array
+            // to grow. Dead entries with nil values are dropped first, since inserting a new key
+            // during traversal is not allowed anyway.
+            self.map.retain(|_, value| !value.is_nil());
+
+            // Then, we find the total count of array candidate elements across the array
             // part, the map part, and the newly inserted key.
 
             const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
@@ -245,12 +352,7 @@ impl<'gc> RawTable<'gc> {
             // the map part as the max for a binary search.
             let min = array_len;
             let mut max = array_len.checked_add(1).unwrap();
-            while self
-                .map
-                .raw_entry()
-                .from_hash(key_hash(max.into()), |k| key_eq(*k, max.into()))
-                .is_some()
-            {
+            while !self.get(max.into()).is_nil() {
                 if max == i64::MAX {
                     // If we can't find a nil entry by doubling, then the table is pathological. We
                     // return the favor with a pathological answer: i64::MAX + 1 can't exist in the
@@ -265,32 +367,43 @@ impl<'gc> RawTable<'gc> {
             }
 
             // We have found a max where table[max] == nil, so we can now binary search
-            binary_search(min, max, |i| {
-                self.map
-                    .raw_entry()
-                    .from_hash(key_hash(i.into()), |k| key_eq(*k, i.into()))
-                    .is_none()
-            })
+            binary_search(min, max, |i| self.get(i.into()).is_nil())
         }
     }
 
     pub fn next(&self, key: Value<'gc>) -> NextValue<'gc> {
+        // Array keys are accepted even if their entry is currently nil, so that clearing entries
+        // during traversal is allowed.
         let array_result = if let Some(index_key) = to_array_index(key) {
             if index_key < self.array.len() {
-                Some((index_key + 1, self.array[index_key].is_nil()))
+                Some(index_key + 1)
             } else {
                 None
             }
         } else if key.is_nil() {
-            // Nil is never considered missing, it is the "key" before the first key.
-            Some((0, false))
+            // Nil is the "key" before the first key.
+            Some(0)
         } else {
             None
         };
 
         let raw_table = self.map.raw_table();
 
-        if let Some((start_index, is_missing)) = array_result {
+        // Map entries whose values have been set to nil are kept until the table is resized, and
+        // must be skipped.
+        let next_in_map = |start: usize| unsafe {
+            for bucket_index in start..raw_table.buckets() {
+                if raw_table.is_bucket_full(bucket_index) {
+                    let (key, value) = *raw_table.bucket(bucket_index).as_ref();
+                    if !value.is_nil() {
+                        return NextValue::Found { key, value };
+                    }
+                }
+            }
+            NextValue::Last
+        };
+
+        if let Some(start_index) = array_result {
             for i in start_index..self.array.len() {
                 if !self.array[i].is_nil() {
                     return NextValue::Found {
@@ -300,51 +413,144 @@ impl<'gc> RawTable<'gc> {
                 }
             }
 
-            if is_missing {
-                return NextValue::NotFound;
-            }
-
-            unsafe {
-                for bucket_index in 0..raw_table.buckets() {
-                    if raw_table.is_bucket_full(bucket_index) {
-                        let (key, value) = *raw_table.bucket(bucket_index).as_ref();
-                        return NextValue::Found { key, value };
-                    }
-                }
-            }
-
-            return NextValue::Last;
+            return next_in_map(0);
         }
 
         if let Ok(table_key) = canonical_key(key) {
             if let Some(bucket) =
                 raw_table.find(key_hash(table_key), |(k, _)| key_eq(*k, table_key))
             {
-                unsafe {
-                    let bucket_index = raw_table.bucket_index(&bucket);
-                    for i in bucket_index + 1..raw_table.buckets() {
-                        if raw_table.is_bucket_full(i) {
-                            let (key, value) = *raw_table.bucket(i).as_ref();
-                            return NextValue::Found { key, value };
-                        }
-                    }
-                }
-                return NextValue::Last;
+                let bucket_index = unsafe { raw_table.bucket_index(&bucket) };
+                return next_in_map(bucket_index + 1);
             }
         }
 
         NextValue::NotFound
     }
 
+    /// Grow the array part so that it can hold the sequence keys `1..=length() + additional`
+    /// without reallocating.
     pub fn reserve_array(&mut self, additional: usize) {
-        self.array.reserve(additional);
+        let length = usize::try_from(self.length()).unwrap_or(usize::MAX);
+        let size = length.saturating_add(additional);
+        if size > self.array.len() {
+            self.array.reserve_exact(size - self.array.len());
+            self.array.resize(size, Value::Nil);
+
+            // Move any keys that are now in range of the array part out of the map part.
+            let array = &mut self.array;
+            self.map.retain(|&key, &mut value| {
+                if let Some(i) = to_array_index(key) {
+                    if i < array.len() {
+                        array[i] = value;
+                        return false;
+                    }
+                }
+                true
+            });
+        }
+    }
+
+    /// Remove every entry from both parts of the table, keeping their allocations.
+    pub fn clear(&mut self) {
+        for value in &mut self.array {
+            *value = Value::Nil;
+        }
+        self.map.clear();
     }
 
+    /// Reserve space for at least `additional` more entries in the map part.
     pub fn reserve_map(&mut self, additional: usize) {
         self.map
             .raw_table_mut()
             .reserve(additional, |(k, _)| key_hash(*k));
     }
+
+    /// Trace the table, except for the weakly held keys and / or values, and the keys of entries
+    /// which have been set to nil.
+    ///
+    /// With weak keys, the values of entries with a weakly held key are not traced either, they
+    /// must be resurrected with `RawTable::resurrect_values` if their key is found to be alive.
+    ///
+    /// Every untraced key or value must be removed with `RawTable::remove_dead` before the object
+    /// it refers to is freed.
+    pub fn trace_weak(&self, cc: &Collection, weak_keys: bool, weak_values: bool) {
+        let trace = |value: &Value<'gc>, weak: bool| {
+            if !weak || !is_weak_reference(*value) {
+                value.trace(cc);
+            }
+        };
+        for value in &self.array {
+            trace(value, weak_values);
+        }
+        for (key, value) in &self.map {
+            trace(key, weak_keys || value.is_nil());
+            trace(value, weak_values || (weak_keys && is_weak_reference(*key)));
+        }
+    }
+
+    /// Call `resurrect` for every value that `is_dead` whose weakly held key is not, which are the
+    /// values left untraced by `RawTable::trace_weak` with weak keys that must be kept alive.
+    pub fn resurrect_values(
+        &self,
+        is_dead: impl Fn(Value<'gc>) -> bool,
+        mut resurrect: impl FnMut(Value<'gc>),
+    ) {
+        for (&key, &value) in &self.map {
+            if is_weak_reference(key) && !is_dead(key) && is_dead(value) {
+                resurrect(value);
+            }
+        }
+    }
+
+    /// Remove the entries whose weakly held key or value `is_dead`, along with the entries set to
+    /// nil whose key `is_dead`.
+    ///
+    /// Entries with a dead key are removed from the map part entirely, dead values are replaced
+    /// with nil.
+    pub fn remove_dead(
+        &mut self,
+        weak_keys: bool,
+        weak_values: bool,
+        is_dead: impl Fn(Value<'gc>) -> bool,
+    ) {
+        if weak_values {
+            for value in &mut self.array {
+                if is_dead(*value) {
+                    *value = Value::Nil;
+                }
+            }
+        }
+        self.map.retain(|&key, value| {
+            if (weak_keys || value.is_nil()) && is_dead(key) {
+                return false;
+            }
+            if weak_values && is_dead(*value) {
+                *value = Value::Nil;
+            }
+            true
+        });
+    }
+
+    /// The number of sequence keys that the array part can hold, `1..=array_capacity()`.
+    ///
+    /// Integer keys in this range never cause the table to reallocate.
+    pub fn array_capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The number of entries the map part can hold without reallocating.
+    pub fn map_capacity(&self) -> usize {
+        self.map.capacity()
+    }
+}
+
+// Returns true for the values which can be held weakly by a weak table.
+pub(super) fn is_weak_reference(value: Value<'_>) -> bool {
+    matches!(
+        value,
+        Value::Table(_) | Value::Function(_) | Value::Thread(_) | Value::UserData(_)
+    )
 }
 
 fn canonical_key<'gc>(value: Value<'gc>) -> Result<Value<'gc>, InvalidTableKey> {
@@ -371,53 +577,72 @@ fn key_eq<'gc>(a: Value<'gc>, b: Value<'gc>) -> bool {
         (Value::Boolean(a), Value::Boolean(b)) => a == b,
         (Value::Integer(a), Value::Integer(b)) => a == b,
         (Value::Number(a), Value::Number(b)) => a == b,
-        (Value::String(a), Value::String(b)) => a == b,
+        (Value::String(a), Value::String(b)) => {
+            String::ptr_eq(a, b) || (a.stored_hash() == b.stored_hash() && a == b)
+        }
         (Value::Table(a), Value::Table(b)) => a == b,
         (Value::Function(a), Value::Function(b)) => a == b,
         (Value::Thread(a), Value::Thread(b)) => a == b,
         (Value::UserData(a), Value::UserData(b)) => a == b,
+        (Value::LightUserData(a), Value::LightUserData(b)) => a == b,
         _ => false,
     }
 }
 
+// Hash a canonical table key.
+//
+// Rather than running a general purpose hasher over every key, each key is reduced to a single
+// word which is mixed with a per-type seed using one folded multiply, in the style of FxHash. This
+// is much faster for the common cases of string and integer keys, but unlike a randomly seeded
+// hasher it makes no attempt to resist HashDoS: anyone who controls the keys of a table can choose
+// keys that collide. This is an acceptable trade-off for tables created by scripts.
+//
+// Strings are not hashed again at all, their stored hash is used as the word. Together with the
+// pointer equality check in `key_eq`, looking up an interned string costs only a few
+// instructions.
 fn key_hash<'gc>(value: Value<'gc>) -> u64 {
-    let mut state = AHasher::default();
-    match value {
-        Value::Nil => Hash::hash(&0, &mut state),
-        Value::Boolean(b) => {
-            Hash::hash(&1, &mut state);
-            b.hash(&mut state);
-        }
-        Value::Integer(i) => {
-            Hash::hash(&2, &mut state);
-            i.hash(&mut state);
-        }
-        Value::Number(n) => {
-            Hash::hash(&3, &mut state);
-            canonical_float_bytes(n).hash(&mut state);
-        }
-        Value::String(s) => {
-            Hash::hash(&4, &mut state);
-            s.hash(&mut state);
-        }
-        Value::Table(t) => {
-            Hash::hash(&5, &mut state);
-            t.hash(&mut state);
-        }
-        Value::Function(c) => {
-            Hash::hash(&6, &mut state);
-            c.hash(&mut state);
-        }
-        Value::Thread(t) => {
-            Hash::hash(&7, &mut state);
-            t.hash(&mut state);
-        }
-        Value::UserData(u) => {
-            Hash::hash(&8, &mut state);
-            u.hash(&mut state);
-        }
-    }
-    state.finish()
+    let (tag, word) = match value {
+        Value::Nil => (0, 0),
+        Value::Boolean(b) => (1, b as u64),
+        Value::Integer(i) => (2, i as u64),
+        Value::Number(n) => (3, canonical_float_bytes(n)),
+        Value::String(s) => (4, s.stored_hash()),
+        Value::Table(t) => (5, Gc::as_ptr(t.into_inner()) as usize as u64),
+        Value::Function(f) => (6, f.as_ptr() as usize as u64),
+        Value::Thread(t) => (7, Gc::as_ptr(t.into_inner()) as usize as u64),
+        Value::UserData(u) => (8, Gc::as_ptr(u.into_inner()) as usize as u64),
+        Value::LightUserData(u) => (9, u.0 as usize as u64),
+    };
+    hash_word(tag, word)
+}
+
+// The same as `key_hash(Value::Integer(i))`.
+fn integer_hash(i: i64) -> u64 {
+    hash_word(2, i as u64)
+}
+
+// Mix a word with the seed for the given type tag.
+//
+// The full 128-bit product is folded back into 64 bits so that both the low bits (which select
+// the bucket) and the high bits (which select the control byte) depend on every bit of the word.
+// This matters for pointers, whose low bits are always zero.
+fn hash_word(tag: usize, word: u64) -> u64 {
+    const SEEDS: [u64; 10] = [
+        0x243f_6a88_85a3_08d3,
+        0x1319_8a2e_0370_7344,
+        0xa409_3822_299f_31d0,
+        0x082e_fa98_ec4e_6c89,
+        0x4528_21e6_38d0_1377,
+        0xbe54_66cf_34e9_0c6c,
+        0xc0ac_29b7_c97c_50dd,
+        0x3f84_d5b5_b547_0917,
+        0x9216_d5d9_8979_fb1b,
+        0xd131_0ba6_98df_b5ac,
+    ];
+    const MULTIPLIER: u64 = 0x5851_f42d_4c95_7f2d;
+
+    let full = u128::from(word ^ SEEDS[tag]) * u128::from(MULTIPLIER);
+    (full as u64) ^ ((full >> 64) as u64)
 }
 
 // Returns the closest i64 to a given f64 such that casting the i64 back to an f64 results in an
@@ -451,6 +676,10 @@ fn to_array_index<'gc>(key: Value<'gc>) -> Option<usize> {
         _ => return None,
     };
 
+    integer_array_index(i)
+}
+
+fn integer_array_index(i: i64) -> Option<usize> {
     if i > 0 {
         Some(usize::try_from(i).ok()? - 1)
     } else {
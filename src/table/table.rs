@@ -1,16 +1,33 @@
 use std::{
+    cell::Cell,
     hash::{Hash, Hasher},
     i64, mem,
 };
 
-use gc_arena::{lock::RefLock, Collect, Gc, Mutation};
+use gc_arena::{lock::RefLock, Collect, Collection, Gc, Mutation};
+use thiserror::Error;
 
-use crate::{Context, IntoValue, Value};
+use crate::{Context, IntoValue, MetaMethod, VMError, Value};
 
-use super::raw::{InvalidTableKey, NextValue, RawTable};
+use super::raw::{is_weak_reference, InvalidTableKey, NextValue, RawTable};
 
 pub type TableInner<'gc> = RefLock<TableState<'gc>>;
 
+/// An error from `Table::set_path`.
+#[derive(Debug, Copy, Clone, Error)]
+pub enum PathError {
+    /// The path had no keys, so there was nothing to assign.
+    #[error("path is empty")]
+    Empty,
+    /// An intermediate value of the path was not a table.
+    #[error("path element {index} is a {found}, not a table")]
+    NotTable {
+        /// The index into the path of the key whose value is not a table.
+        index: usize,
+        found: &'static str,
+    },
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct Table<'gc>(Gc<'gc, TableInner<'gc>>);
@@ -34,6 +51,55 @@ impl<'gc> Table<'gc> {
         Self::from_parts(mc, RawTable::new(mc), None)
     }
 
+    /// Create a new table with preallocated space for the sequence keys `1..=array_capacity` and
+    /// at least `map_capacity` other entries.
+    pub fn with_capacity(
+        mc: &Mutation<'gc>,
+        array_capacity: usize,
+        map_capacity: usize,
+    ) -> Table<'gc> {
+        Self::from_parts(
+            mc,
+            RawTable::with_capacity(mc, array_capacity, map_capacity),
+            None,
+        )
+    }
+
+    /// Create a new table from an iterator of key-value pairs.
+    ///
+    /// Pairs are assigned in order, so later pairs overwrite earlier pairs with the same key. Fails
+    /// if any key is `nil` or NaN.
+    pub fn from_iter<K, V>(
+        ctx: Context<'gc>,
+        iter: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Table<'gc>, InvalidTableKey>
+    where
+        K: IntoValue<'gc>,
+        V: IntoValue<'gc>,
+    {
+        let table = Table::new(&ctx);
+        table.extend(ctx, iter)?;
+        Ok(table)
+    }
+
+    /// Create a new sequence table, assigning the given values to the keys `1..=n`.
+    pub fn array<V: IntoValue<'gc>>(
+        ctx: Context<'gc>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Table<'gc> {
+        let values = values.into_iter();
+        let table = Table::with_capacity(&ctx, values.size_hint().0, 0);
+        for (i, value) in values.enumerate() {
+            let key: i64 = (i + 1).try_into().unwrap();
+            table.set(ctx, key, value).unwrap();
+        }
+        table
+    }
+
+    /// Create a table from a raw table and an optional metatable.
+    ///
+    /// The table is never weak, even if the metatable has a `__mode` field. Use
+    /// `Table::set_metatable` to create a weak table.
     pub fn from_parts(
         mc: &Mutation<'gc>,
         raw_table: RawTable<'gc>,
@@ -44,6 +110,7 @@ impl<'gc> Table<'gc> {
             RefLock::new(TableState {
                 raw_table,
                 metatable,
+                weak: WeakState::default(),
             }),
         ))
     }
@@ -66,20 +133,163 @@ impl<'gc> Table<'gc> {
         key: K,
         value: V,
     ) -> Result<Value<'gc>, InvalidTableKey> {
-        self.set_value(&ctx, key.into_value(ctx), value.into_value(ctx))
+        self.set_value(ctx, key.into_value(ctx), value.into_value(ctx))
     }
 
     pub fn get_value(self, key: Value<'gc>) -> Value<'gc> {
         self.0.borrow().raw_table.get(key)
     }
 
+    // The same as `Table::get_value`, using `slot` as an inline cache for the location of the key,
+    // see `RawTable::get_cached`.
+    pub(crate) fn get_value_cached(self, key: Value<'gc>, slot: &Cell<u32>) -> Value<'gc> {
+        self.0.borrow().raw_table.get_cached(key, slot)
+    }
+
     pub fn set_value(
         self,
-        mc: &Mutation<'gc>,
+        ctx: Context<'gc>,
         key: Value<'gc>,
         value: Value<'gc>,
     ) -> Result<Value<'gc>, InvalidTableKey> {
-        self.0.borrow_mut(&mc).raw_table.set(key, value)
+        let prev = self.0.borrow_mut(&ctx).raw_table.set(key, value)?;
+        if value.is_nil() && !prev.is_nil() {
+            self.entry_removed(ctx, key);
+        }
+        Ok(prev)
+    }
+
+    /// Remove the given key from the table, returning its previous value.
+    ///
+    /// Returns `nil` if the key was not present, including for keys that can never be present,
+    /// such as `nil` and NaN.
+    pub fn remove(self, ctx: Context<'gc>, key: Value<'gc>) -> Value<'gc> {
+        self.set_value(ctx, key, Value::Nil).unwrap_or(Value::Nil)
+    }
+
+    // Entries set to nil stay in the table until it grows, see `RawTable::set`. Their keys must not
+    // keep the objects they refer to alive, so the table is registered with the finalizers the same
+    // way as a weak table, which removes the entries whose key has been collected.
+    fn entry_removed(self, ctx: Context<'gc>, key: Value<'gc>) {
+        let mut state = self.0.borrow_mut(&ctx);
+        let register = is_weak_reference(key) && !state.weak.registered;
+        if register {
+            state.weak.registered = true;
+        }
+        drop(state);
+
+        if register {
+            ctx.finalizers().register_weak_table(&ctx, self);
+        }
+    }
+
+    /// Remove every entry from the table. The metatable is kept, as is the allocated capacity.
+    ///
+    /// Unlike assigning `nil` to every key, this must not be done while iterating over the table
+    /// with `Table::next`, since the current key is removed as well.
+    pub fn clear(self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).raw_table.clear();
+    }
+
+    /// Create a new table with the same entries as this one.
+    ///
+    /// The copy does not share any entries with this table, but the values themselves are not
+    /// copied, so nested tables are shared. The metatable is not copied.
+    pub fn shallow_copy(self, mc: &Mutation<'gc>) -> Table<'gc> {
+        let mut raw_table = self.0.borrow().raw_table.clone();
+        // Entries set to nil are not copied, since the copy is not registered to have their dead
+        // keys removed.
+        raw_table.remove_dead(false, false, |_| true);
+        Table::from_parts(mc, raw_table, None)
+    }
+
+    /// Reserve space in the array part for at least `additional` more sequence elements after the
+    /// current length of the table, so that appending them does not reallocate.
+    ///
+    /// Fails without reserving anything if this would exceed the memory limit set with
+    /// `GcControl::set_memory_limit`.
+    pub fn reserve(self, ctx: Context<'gc>, additional: usize) -> Result<(), VMError> {
+        ctx.check_allocation(additional.saturating_mul(mem::size_of::<Value>()))?;
+        self.0.borrow_mut(&ctx).raw_table.reserve_array(additional);
+        Ok(())
+    }
+
+    /// The number of sequence keys, `1..=array_capacity()`, that can be set without reallocating.
+    pub fn array_capacity(self) -> usize {
+        self.0.borrow().raw_table.array_capacity()
+    }
+
+    /// The number of non-sequence entries that can be stored without reallocating.
+    pub fn map_capacity(self) -> usize {
+        self.0.borrow().raw_table.map_capacity()
+    }
+
+    /// Assign every key-value pair from the given iterator to this table.
+    ///
+    /// Fails on the first key that is `nil` or NaN, pairs before that key will already have been
+    /// assigned.
+    pub fn extend<K, V>(
+        self,
+        ctx: Context<'gc>,
+        iter: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), InvalidTableKey>
+    where
+        K: IntoValue<'gc>,
+        V: IntoValue<'gc>,
+    {
+        for (key, value) in iter {
+            self.set(ctx, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Get a value nested inside tables, following each key of `path` in turn, so that
+    /// `["server", "port"]` is the same as `table.server.port` (without metamethods).
+    ///
+    /// Returns `nil` if any value along the path is missing or not a table. An empty path returns
+    /// this table.
+    pub fn get_path(self, ctx: Context<'gc>, path: &[&str]) -> Value<'gc> {
+        let mut value = Value::Table(self);
+        for key in path {
+            let Value::Table(table) = value else {
+                return Value::Nil;
+            };
+            value = table.get(ctx, ctx.intern(key.as_bytes()));
+        }
+        value
+    }
+
+    /// Set a value nested inside tables, following each key of `path` but the last in turn, and
+    /// then assigning the last key. Returns the previous value.
+    ///
+    /// Any missing intermediate tables are created, but an intermediate value which exists and is
+    /// not a table is an error, as is an empty path.
+    pub fn set_path<V: IntoValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        path: &[&str],
+        value: V,
+    ) -> Result<Value<'gc>, PathError> {
+        let (last, parents) = path.split_last().ok_or(PathError::Empty)?;
+        let mut table = self;
+        for (index, key) in parents.iter().enumerate() {
+            let key = ctx.intern(key.as_bytes());
+            table = match table.get(ctx, key) {
+                Value::Table(t) => t,
+                Value::Nil => {
+                    let t = Table::new(&ctx);
+                    table.set(ctx, key, t).unwrap();
+                    t
+                }
+                v => {
+                    return Err(PathError::NotTable {
+                        index,
+                        found: v.type_name(),
+                    })
+                }
+            };
+        }
+        Ok(table.set(ctx, ctx.intern(last.as_bytes()), value).unwrap())
     }
 
     /// Returns a 'border' for this table.
@@ -89,10 +299,20 @@ impl<'gc> Table<'gc> {
     ///
     /// If a table has exactly one border, it is called a 'sequence', and this border is the table's
     /// length.
-    pub fn length(self) -> i64 {
+    pub fn len(self) -> i64 {
         self.0.borrow().raw_table.length()
     }
 
+    /// Equivalent to `Table::len`.
+    pub fn length(self) -> i64 {
+        self.len()
+    }
+
+    /// Returns true if the table has no non-nil entries in either its array or map part.
+    pub fn is_empty(self) -> bool {
+        matches!(self.next(Value::Nil), NextValue::Last)
+    }
+
     /// Returns the next value after this key in the table order.
     ///
     /// The table order in the map portion of the table is defined by the incidental order of the
@@ -100,6 +320,11 @@ impl<'gc> Table<'gc> {
     /// as when inserting into the table, so relying on the order while inserting may result in
     /// unspecified (but not unsafe) behavior.
     ///
+    /// The order is deterministic: tables built by the same sequence of operations will always
+    /// iterate in the same order, including across different runs of the same program. The exception
+    /// is keys which are tables, functions, threads, or userdata, which are hashed by their
+    /// address, so the order of these keys may differ between runs.
+    ///
     /// If given Nil, it will return the first pair in the table. If given a key that is present
     /// in the table, it will return the next pair in iteration order. If given a key that is not
     /// present in the table, the behavior is unspecified.
@@ -109,7 +334,14 @@ impl<'gc> Table<'gc> {
 
     /// Iterate over the key-value pairs of the table.
     ///
-    /// Internally uses the `Table::next` method and thus matches the behavior of Lua.
+    /// Internally uses the `Table::next` method and thus matches the behavior of Lua. The array
+    /// part is visited first in index order, followed by the map part in its internal order. Entries
+    /// with nil values are skipped.
+    ///
+    /// The iteration order is stable as long as no new keys are inserted into the table, assigning
+    /// to existing keys (including clearing them by assigning nil) while iterating is allowed and
+    /// will not cause keys to be skipped or repeated. Inserting new keys while iterating may cause
+    /// the table to be resized, after which the remaining order is unspecified (but not unsafe).
     pub fn iter(self) -> Iter<'gc> {
         Iter::new(self)
     }
@@ -118,12 +350,112 @@ impl<'gc> Table<'gc> {
         self.0.borrow().metatable
     }
 
+    /// Set the metatable of this table, returning the previous metatable.
+    ///
+    /// If the new metatable has a `__mode` field containing `k` and/or `v`, the keys and/or values
+    /// of this table become weak, see `WeakMode`. Like PUC-Rio Lua, the `__mode` field is only
+    /// checked here, changing it afterwards has no effect until the metatable is set again.
     pub fn set_metatable(
         self,
-        mc: &Mutation<'gc>,
+        ctx: Context<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Option<Table<'gc>> {
-        mem::replace(&mut self.0.borrow_mut(mc).metatable, metatable)
+        let mode = metatable.and_then(|mt| WeakMode::from_mode(mt.get(ctx, MetaMethod::Mode)));
+
+        let mut state = self.0.borrow_mut(&ctx);
+        state.weak.mode = mode;
+        let register = mode.is_some() && !state.weak.registered;
+        if register {
+            state.weak.registered = true;
+        }
+        let prev = mem::replace(&mut state.metatable, metatable);
+        drop(state);
+
+        if register {
+            ctx.finalizers().register_weak_table(&ctx, self);
+        }
+        prev
+    }
+
+    /// Returns which parts of this table are held weakly, if any.
+    pub fn weak_mode(self) -> Option<WeakMode> {
+        self.0.borrow().weak.mode
+    }
+
+    // Remove every entry whose weakly held key or value is dead, and every entry set to nil whose
+    // key is dead. Dead keys are removed entirely, dead weak values are set to nil so that the key
+    // can still be used with `Table::next`.
+    //
+    // Afterwards, the table is traced strongly until the end of the collection cycle, so that
+    // objects newly stored in it are not freed by the current cycle while still in the table.
+    pub(crate) fn remove_dead(self, mc: &Mutation<'gc>, is_dead: impl Fn(Value<'gc>) -> bool) {
+        let mut state = self.0.borrow_mut(mc);
+        let mode = state.weak.mode;
+        state.raw_table.remove_dead(
+            mode.is_some_and(WeakMode::weak_keys),
+            mode.is_some_and(WeakMode::weak_values),
+            is_dead,
+        );
+        state.weak.strong = true;
+    }
+
+    // Resurrect the values of a table with weak keys whose keys have been found to be alive, see
+    // `RawTable::resurrect_values`. Resurrecting a value can make more keys alive, so this must be
+    // repeated for every weak table until nothing more is resurrected.
+    pub(crate) fn resurrect_values(
+        self,
+        is_dead: impl Fn(Value<'gc>) -> bool,
+        resurrect: impl FnMut(Value<'gc>),
+    ) {
+        let state = self.0.borrow();
+        if state.weak.mode.is_some_and(WeakMode::weak_keys) && !state.weak.strong {
+            state.raw_table.resurrect_values(is_dead, resurrect);
+        }
+    }
+
+    pub(crate) fn set_traced_strongly(self, mc: &Mutation<'gc>, strong: bool) {
+        self.0.borrow_mut(mc).weak.strong = strong;
+    }
+}
+
+/// Which parts of the entries of a weak table are held weakly.
+///
+/// A weakly held key or value does not keep the object it refers to alive. Once such an object is
+/// collected, its entry is removed from the table. As in PUC-Rio Lua, only tables, functions,
+/// threads, and userdata can be weakly held; strings and other values are always held strongly.
+///
+/// As in PUC-Rio Lua, tables with weak keys are ephemeron tables: the value of an entry with a
+/// weakly held key is only kept alive while its key is reachable from outside of the entry, so a
+/// value which refers to its own key does not keep the entry alive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WeakMode {
+    Keys,
+    Values,
+    KeysAndValues,
+}
+
+impl WeakMode {
+    /// Parse the value of a `__mode` metatable field, which is a string containing `k` for weak
+    /// keys and `v` for weak values.
+    pub fn from_mode(mode: Value<'_>) -> Option<WeakMode> {
+        let Value::String(mode) = mode else {
+            return None;
+        };
+        let mode = mode.as_bytes();
+        match (mode.contains(&b'k'), mode.contains(&b'v')) {
+            (true, true) => Some(WeakMode::KeysAndValues),
+            (true, false) => Some(WeakMode::Keys),
+            (false, true) => Some(WeakMode::Values),
+            (false, false) => None,
+        }
+    }
+
+    pub fn weak_keys(self) -> bool {
+        matches!(self, WeakMode::Keys | WeakMode::KeysAndValues)
+    }
+
+    pub fn weak_values(self) -> bool {
+        matches!(self, WeakMode::Values | WeakMode::KeysAndValues)
     }
 }
 
@@ -166,9 +498,37 @@ impl<'gc> IntoIterator for Table<'gc> {
     }
 }
 
-#[derive(Debug, Collect)]
-#[collect(no_drop)]
+#[derive(Debug)]
 pub struct TableState<'gc> {
     pub raw_table: RawTable<'gc>,
     pub metatable: Option<Table<'gc>>,
+    weak: WeakState,
+}
+
+unsafe impl<'gc> Collect for TableState<'gc> {
+    fn trace(&self, cc: &Collection) {
+        self.metatable.trace(cc);
+        // Registered tables have their untraced entries removed by `Table::remove_dead`.
+        if self.weak.registered && !self.weak.strong {
+            let mode = self.weak.mode;
+            self.raw_table.trace_weak(
+                cc,
+                mode.is_some_and(WeakMode::weak_keys),
+                mode.is_some_and(WeakMode::weak_values),
+            );
+        } else {
+            self.raw_table.trace(cc);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WeakState {
+    mode: Option<WeakMode>,
+    // Whether the table has been registered with `Finalizers::register_weak_table`, because it has
+    // become weak or had an entry with a collectable key set to nil. Tables stay registered until
+    // collected, even if they stop being weak.
+    registered: bool,
+    // Set between removing dead entries and the end of the collection cycle.
+    strong: bool,
 }
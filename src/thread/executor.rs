@@ -1,19 +1,30 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    any::Any,
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    string::String as StdString,
+};
 
 use allocator_api2::vec;
 use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
 use thiserror::Error;
 
 use crate::{
+    closure::{UpValue, UpValueState},
     compiler::{FunctionRef, LineNumber},
-    BadThreadMode, CallbackReturn, Context, Error, FromMultiValue, Fuel, Function,
-    FunctionPrototype, IntoMultiValue, SequencePoll, Stack, String, Thread, ThreadMode, Value,
-    Variadic,
+    lua::MemoryCheck,
+    BadThreadMode, Callback, CallbackReturn, Context, Error, FromMultiValue, Fuel, Function,
+    FunctionPrototype, IntoMultiValue, IntoValue, SequencePoll, Stack, String, Thread, ThreadMode,
+    Value, Variadic,
 };
 
 use super::{
-    thread::{Frame, LuaFrame, ThreadState},
+    thread::{
+        frame_infos, frame_local, traceback, Frame, FrameInfo, Hook, HookState, LuaFrame,
+        LuaReturn, MetaReturn, OpenUpValue, ThreadState,
+    },
     vm::run_vm,
+    VMError,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,6 +43,20 @@ pub enum ExecutorMode {
     Running,
 }
 
+/// The outcome of a call to `Executor::step_with_fuel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The main thread has returned or errored, and its result can be taken with
+    /// `Executor::take_result`.
+    Finished,
+    /// The main thread has yielded. The yielded values can be taken with `Executor::take_result`,
+    /// after which the `Executor` may be resumed with `Executor::resume`.
+    Yielded,
+    /// The given fuel was exhausted (or the `Fuel` was interrupted) before any more progress could
+    /// be made. The `Executor` is left in `ExecutorMode::Normal` and can be stepped again.
+    OutOfFuel,
+}
+
 #[derive(Debug, Copy, Clone, Error)]
 #[error("bad executor mode: {found:?}, expected {expected:?}")]
 pub struct BadExecutorMode {
@@ -43,6 +68,9 @@ pub struct BadExecutorMode {
 #[collect(no_drop)]
 pub struct ExecutorState<'gc> {
     thread_stack: vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
+    capture_traceback: bool,
+    error_traceback: Option<StdString>,
+    catch_panics: bool,
 }
 
 pub type ExecutorInner<'gc> = RefLock<ExecutorState<'gc>>;
@@ -91,11 +119,21 @@ impl<'gc> Executor<'gc> {
         Self::run(&ctx, Thread::new(ctx))
     }
 
-    /// Creates a new `Executor` that begins running the given thread.
+    /// Creates a new `Executor` that begins running the given thread, which becomes its main
+    /// thread.
     pub fn run(mc: &Mutation<'gc>, thread: Thread<'gc>) -> Self {
+        thread.set_main(true);
         let mut thread_stack = vec::Vec::new_in(MetricsAlloc::new(mc));
         thread_stack.push(thread);
-        Executor(Gc::new(mc, RefLock::new(ExecutorState { thread_stack })))
+        Executor(Gc::new(
+            mc,
+            RefLock::new(ExecutorState {
+                thread_stack,
+                capture_traceback: false,
+                error_traceback: None,
+                catch_panics: false,
+            }),
+        ))
     }
 
     pub fn from_inner(inner: Gc<'gc, ExecutorInner<'gc>>) -> Self {
@@ -166,7 +204,7 @@ impl<'gc> Executor<'gc> {
                 }
             }
 
-            let mut top_state = top_thread.into_inner().borrow_mut(&ctx);
+            let mut top_state = top_thread.borrow_mut(&ctx);
             let top_state = &mut *top_state;
             if let Some(res_thread) = res_thread {
                 let mode = top_state.mode();
@@ -176,7 +214,7 @@ impl<'gc> Executor<'gc> {
                         ThreadMode::Result => {
                             // Take the results from the res_thread and return them to our top
                             // thread.
-                            let mut res_state = res_thread.into_inner().borrow_mut(&ctx);
+                            let mut res_state = res_thread.borrow_mut(&ctx);
                             match res_state.take_result() {
                                 Ok(vals) => {
                                     let bottom = top_state.stack.len();
@@ -217,6 +255,7 @@ impl<'gc> Executor<'gc> {
                     thread_stack: &mut vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
                     top_state: &mut ThreadState<'gc>,
                     stack_bottom: usize,
+                    callback: Option<Callback<'gc>>,
                     ret: CallbackReturn<'gc>,
                 ) {
                     match ret {
@@ -228,6 +267,7 @@ impl<'gc> Executor<'gc> {
                                 bottom: stack_bottom,
                                 sequence,
                                 pending_error: None,
+                                callback,
                             });
                         }
                         CallbackReturn::Yield { to_thread, then } => {
@@ -236,6 +276,7 @@ impl<'gc> Executor<'gc> {
                                     bottom: stack_bottom,
                                     sequence,
                                     pending_error: None,
+                                    callback,
                                 });
                             }
                             top_state.frames.push(Frame::Yielded);
@@ -261,9 +302,15 @@ impl<'gc> Executor<'gc> {
                                     bottom: stack_bottom,
                                     sequence,
                                     pending_error: None,
+                                    callback,
                                 });
                             }
-                            top_state.push_call(stack_bottom, function);
+                            if let Err(err) = top_state.check_stack_overflow() {
+                                top_state.stack.truncate(stack_bottom);
+                                top_state.frames.push(Frame::Error(err.into()));
+                            } else {
+                                top_state.push_call(stack_bottom, function);
+                            }
                         }
                         CallbackReturn::Resume { thread, then } => {
                             if let Some(sequence) = then {
@@ -271,6 +318,7 @@ impl<'gc> Executor<'gc> {
                                     bottom: stack_bottom,
                                     sequence,
                                     pending_error: None,
+                                    callback,
                                 });
                             }
                             top_state.frames.push(Frame::WaitThread);
@@ -296,16 +344,13 @@ impl<'gc> Executor<'gc> {
                     fuel: &'a mut Fuel,
                     threads: &'a [Thread<'gc>],
                     top_frames: &'a [Frame<'gc>],
-                    top_stack: &[Value<'gc>],
+                    hook: &'a mut Option<HookState<'gc>>,
                 ) -> Execution<'gc, 'a> {
                     let upper_lua = match top_frames.last() {
-                        Some(Frame::Lua { bottom, pc, .. }) => {
-                            let Value::Function(Function::Closure(closure)) = top_stack[*bottom]
-                            else {
-                                panic!("lua frame bottom is not a closure");
-                            };
-                            // Subtract 1 instruction for the Call opcode.
-                            Some((closure.prototype(), *pc - 1))
+                        // Subtract 1 instruction for the Call opcode. A hook can be called before
+                        // the first instruction has run, in which case there is no current line.
+                        Some(Frame::Lua { closure, pc, .. }) => {
+                            pc.checked_sub(1).map(|pc| (closure.prototype(), pc))
                         }
                         _ => None,
                     };
@@ -315,6 +360,34 @@ impl<'gc> Executor<'gc> {
                         fuel,
                         upper_lua,
                         threads,
+                        frames: top_frames,
+                        hook,
+                    }
+                }
+
+                // An error cannot be raised while another one is being handled.
+                let handling_error = matches!(
+                    top_state.frames.last(),
+                    Some(Frame::Error(_))
+                        | Some(Frame::Sequence {
+                            pending_error: Some(_),
+                            ..
+                        })
+                );
+                let memory = if handling_error {
+                    MemoryCheck::Ok
+                } else {
+                    ctx.gc_control()
+                        .check_memory(ctx.metrics().total_allocation())
+                };
+                match memory {
+                    MemoryCheck::Ok => {}
+                    // Return so that `Lua::enter` can perform an emergency collection.
+                    MemoryCheck::NeedsCollection => break false,
+                    MemoryCheck::Exceeded => {
+                        top_state
+                            .frames
+                            .push(Frame::Error(VMError::NotEnoughMemory.into()));
                     }
                 }
 
@@ -326,13 +399,25 @@ impl<'gc> Executor<'gc> {
                             fuel,
                             &state.thread_stack,
                             &top_state.frames,
-                            &top_state.stack,
+                            &mut top_state.hook,
                         );
-                        match callback.call(ctx, exec, Stack::new(&mut top_state.stack, bottom)) {
-                            Ok(ret) => {
-                                callback_ret(ctx, &mut state.thread_stack, top_state, bottom, ret)
-                            }
+                        let stack = Stack::new(&mut top_state.stack, bottom);
+                        match catch_panic(ctx, state.catch_panics, || {
+                            callback.call(ctx, exec, stack)
+                        }) {
+                            Ok(ret) => callback_ret(
+                                ctx,
+                                &mut state.thread_stack,
+                                top_state,
+                                bottom,
+                                Some(callback),
+                                ret,
+                            ),
                             Err(err) => {
+                                if state.capture_traceback {
+                                    state.error_traceback =
+                                        Some(traceback(ctx, &top_state.frames, 1));
+                                }
                                 top_state.stack.truncate(bottom);
                                 top_state.frames.push(Frame::Error(err))
                             }
@@ -342,6 +427,7 @@ impl<'gc> Executor<'gc> {
                         bottom,
                         mut sequence,
                         pending_error,
+                        callback,
                     }) => {
                         fuel.consume(Self::FUEL_PER_SEQ_STEP);
 
@@ -350,13 +436,16 @@ impl<'gc> Executor<'gc> {
                             fuel,
                             &state.thread_stack,
                             &top_state.frames,
-                            &top_state.stack,
+                            &mut top_state.hook,
                         );
-                        let fin = if let Some(err) = pending_error {
-                            sequence.error(ctx, exec, err, Stack::new(&mut top_state.stack, bottom))
-                        } else {
-                            sequence.poll(ctx, exec, Stack::new(&mut top_state.stack, bottom))
-                        };
+                        let stack = Stack::new(&mut top_state.stack, bottom);
+                        let fin = catch_panic(ctx, state.catch_panics, || {
+                            if let Some(err) = pending_error {
+                                sequence.error(ctx, exec, err, stack)
+                            } else {
+                                sequence.poll(ctx, exec, stack)
+                            }
+                        });
 
                         match fin {
                             Ok(ret) => callback_ret(
@@ -364,6 +453,7 @@ impl<'gc> Executor<'gc> {
                                 &mut state.thread_stack,
                                 top_state,
                                 bottom,
+                                callback,
                                 match ret {
                                     SequencePoll::Pending => CallbackReturn::Sequence(sequence),
                                     SequencePoll::Return => CallbackReturn::Return,
@@ -398,13 +488,35 @@ impl<'gc> Executor<'gc> {
 
                         const VM_GRANULARITY: u32 = 64;
 
+                        // Never run more instructions than we have fuel for, so that the VM stops
+                        // at the instruction boundary where the fuel runs out. We always allow at
+                        // least one instruction so that some progress is made.
+                        let max_instructions = VM_GRANULARITY
+                            .min(fuel.remaining().try_into().unwrap_or(0))
+                            .max(1);
+
                         let lua_frame = LuaFrame {
                             state: top_state,
                             thread: top_thread,
                             fuel,
                         };
-                        match run_vm(ctx, lua_frame, VM_GRANULARITY) {
+                        match run_vm(ctx, lua_frame, max_instructions) {
                             Err(err) => {
+                                let err = match top_state.frames.last() {
+                                    Some(Frame::Lua { closure, pc, .. }) => {
+                                        let proto = closure.prototype();
+                                        // The failing instruction is the one before the PC.
+                                        err.with_location(
+                                            &proto.short_src(),
+                                            proto.line_number(pc.saturating_sub(1)),
+                                        )
+                                    }
+                                    _ => err,
+                                };
+                                if state.capture_traceback {
+                                    state.error_traceback =
+                                        Some(traceback(ctx, &top_state.frames, 1));
+                                }
                                 top_state.frames.push(Frame::Error(err.into()));
                             }
                             Ok(instructions_run) => {
@@ -413,29 +525,47 @@ impl<'gc> Executor<'gc> {
                         }
                     }
                     Some(Frame::Error(err)) => {
-                        match top_state
-                            .frames
-                            .pop()
-                            .expect("normal thread must have frame above error")
-                        {
-                            Frame::Lua { bottom, .. } => {
-                                top_state.close_upvalues(&ctx, bottom);
-                                top_state.stack.truncate(bottom);
-                                top_state.frames.push(Frame::Error(err));
-                            }
-                            Frame::Sequence {
-                                bottom,
-                                sequence,
-                                pending_error: error,
-                            } => {
-                                assert!(error.is_none());
-                                top_state.frames.push(Frame::Sequence {
+                        // Any to-be-closed variables in the unwinding Lua frame are closed
+                        // before the frame is popped.
+                        if let Some(err) = top_state.close_on_error(ctx, err) {
+                            match top_state
+                                .frames
+                                .pop()
+                                .expect("normal thread must have frame above error")
+                            {
+                                Frame::Lua {
+                                    bottom,
+                                    expected_return,
+                                    ..
+                                } => {
+                                    if matches!(
+                                        expected_return,
+                                        Some(LuaReturn::Meta(MetaReturn::Hook))
+                                    ) {
+                                        if let Some(hook) = &mut top_state.hook {
+                                            hook.running = false;
+                                        }
+                                    }
+                                    top_state.close_upvalues(&ctx, bottom);
+                                    top_state.stack.truncate(bottom);
+                                    top_state.frames.push(Frame::Error(err));
+                                }
+                                Frame::Sequence {
                                     bottom,
                                     sequence,
-                                    pending_error: Some(err),
-                                });
+                                    pending_error: error,
+                                    callback,
+                                } => {
+                                    assert!(error.is_none());
+                                    top_state.frames.push(Frame::Sequence {
+                                        bottom,
+                                        sequence,
+                                        pending_error: Some(err),
+                                        callback,
+                                    });
+                                }
+                                _ => top_state.frames.push(Frame::Error(err)),
                             }
-                            _ => top_state.frames.push(Frame::Error(err)),
                         }
                     }
                     _ => panic!("tried to step invalid frame type"),
@@ -450,6 +580,80 @@ impl<'gc> Executor<'gc> {
         }
     }
 
+    /// Runs the VM with a fresh `Fuel` budget of `fuel`, roughly the number of VM instructions to
+    /// execute before returning.
+    ///
+    /// This is a convenience wrapper around `Executor::step` for hosts that schedule many
+    /// `Executor`s cooperatively. The VM always stops at an instruction boundary, so if
+    /// `StepResult::OutOfFuel` is returned, the `Executor` may simply be stepped again later to
+    /// continue where it left off.
+    pub fn step_with_fuel(self, ctx: Context<'gc>, fuel: i32) -> StepResult {
+        let mut fuel = Fuel::with(fuel);
+        if !self.step(ctx, &mut fuel) {
+            return StepResult::OutOfFuel;
+        }
+
+        let state = self.0.borrow();
+        let main_thread = state.thread_stack[0].borrow();
+        if main_thread.mode() == ThreadMode::Suspended || main_thread.is_yield_result() {
+            StepResult::Yielded
+        } else {
+            StepResult::Finished
+        }
+    }
+
+    /// Enable or disable capturing a traceback whenever a callback or Lua function raises an
+    /// error.
+    ///
+    /// If the error is never caught, the traceback can be retrieved with
+    /// `Executor::error_traceback` once the executor has finished. `Lua::execute` attaches it to
+    /// the returned error automatically. Capturing is disabled by default, and the setting is kept
+    /// when the executor is reset.
+    pub fn set_capture_traceback(self, mc: &Mutation<'gc>, capture: bool) {
+        let mut state = self.0.borrow_mut(mc);
+        state.capture_traceback = capture;
+        if !capture {
+            state.error_traceback = None;
+        }
+    }
+
+    /// Returns true if tracebacks are captured for raised errors.
+    pub fn captures_traceback(self) -> bool {
+        self.0.borrow().capture_traceback
+    }
+
+    /// Enable or disable catching Rust panics raised by callbacks and sequences run by this
+    /// executor.
+    ///
+    /// When enabled, a panic is caught with `std::panic::catch_unwind` and raised in the running
+    /// thread as the Lua error `"internal error: <panic message>"`, which can be caught by `pcall`
+    /// like any other error, rather than unwinding through the VM and out of `Executor::step`.
+    ///
+    /// This is meant as a safety net for hosts embedding callbacks they do not fully trust, and
+    /// should not be used for normal control flow: the panic hook still runs, any userdata or
+    /// other state the callback was modifying may be left inconsistent, and panics are not caught
+    /// at all when compiled with `panic = "abort"`. Catching is disabled by default, and the
+    /// setting is kept when the executor is reset.
+    pub fn set_catch_panics(self, mc: &Mutation<'gc>, catch: bool) {
+        self.0.borrow_mut(mc).catch_panics = catch;
+    }
+
+    /// Returns true if panics from callbacks are caught and raised as Lua errors.
+    pub fn catches_panics(self) -> bool {
+        self.0.borrow().catch_panics
+    }
+
+    /// If the executor finished with an uncaught error and traceback capturing is enabled, returns
+    /// the traceback of the point where the error was raised.
+    pub fn error_traceback(self) -> Option<StdString> {
+        let state = self.0.borrow();
+        if state.thread_stack.len() == 1 && state.thread_stack[0].borrow().is_error_result() {
+            state.error_traceback.clone()
+        } else {
+            None
+        }
+    }
+
     pub fn take_result<T: FromMultiValue<'gc>>(
         self,
         ctx: Context<'gc>,
@@ -457,7 +661,7 @@ impl<'gc> Executor<'gc> {
         let mode = self.mode();
         if mode == ExecutorMode::Result {
             let state = self.0.borrow();
-            Ok(state.thread_stack[0].take_result(ctx).unwrap())
+            Ok(state.thread_stack[0].take_return_values(ctx))
         } else {
             Err(BadExecutorMode {
                 found: mode,
@@ -504,14 +708,21 @@ impl<'gc> Executor<'gc> {
         let mut state = self.0.borrow_mut(mc);
         state.thread_stack.truncate(1);
         state.thread_stack[0].reset(mc).unwrap();
+        state.error_traceback = None;
     }
 
     /// Reset this `Executor` entirely and begins running the given thread. Equivalent to
     /// creating a new executor with `Executor::run`.
+    ///
+    /// The given thread replaces the previous main thread, which is no longer considered a main
+    /// thread.
     pub fn reset(self, mc: &Mutation<'gc>, thread: Thread<'gc>) {
         let mut state = self.0.borrow_mut(mc);
+        state.thread_stack[0].set_main(false);
+        thread.set_main(true);
         state.thread_stack.clear();
         state.thread_stack.push(thread);
+        state.error_traceback = None;
     }
 
     /// Reset this `Executor` entirely and begins running the given function, equivalent to
@@ -526,6 +737,34 @@ impl<'gc> Executor<'gc> {
         state.thread_stack.truncate(1);
         state.thread_stack[0].reset(&ctx).unwrap();
         state.thread_stack[0].start(ctx, function, args).unwrap();
+        state.error_traceback = None;
+    }
+}
+
+// Call `f`, and if `catch_panics` is set, convert any panic into an "internal error" Lua error.
+fn catch_panic<'gc, R>(
+    ctx: Context<'gc>,
+    catch_panics: bool,
+    f: impl FnOnce() -> Result<R, Error<'gc>>,
+) -> Result<R, Error<'gc>> {
+    if !catch_panics {
+        return f();
+    }
+
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        Err(format!("internal error: {}", panic_message(&*payload))
+            .into_value(ctx)
+            .into())
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<StdString>() {
+        s
+    } else {
+        "callback panicked"
     }
 }
 
@@ -535,9 +774,24 @@ pub struct Execution<'gc, 'a> {
     fuel: &'a mut Fuel,
     upper_lua: Option<(Gc<'gc, FunctionPrototype<'gc>>, usize)>,
     threads: &'a [Thread<'gc>],
+    frames: &'a [Frame<'gc>],
+    hook: &'a mut Option<HookState<'gc>>,
 }
 
 impl<'gc, 'a> Execution<'gc, 'a> {
+    /// Reborrow this `Execution` with a shorter lifetime, so that it can be passed to another
+    /// callback or sequence while still being usable afterwards.
+    pub fn reborrow(&mut self) -> Execution<'gc, '_> {
+        Execution {
+            executor: self.executor,
+            fuel: self.fuel,
+            upper_lua: self.upper_lua,
+            threads: self.threads,
+            frames: self.frames,
+            hook: self.hook,
+        }
+    }
+
     /// The fuel parameter passed to `Executor::step`.
     pub fn fuel(&mut self) -> &mut Fuel {
         self.fuel
@@ -547,10 +801,121 @@ impl<'gc, 'a> Execution<'gc, 'a> {
     pub fn current_thread(&self) -> CurrentThread<'gc> {
         CurrentThread {
             thread: *self.threads.last().unwrap(),
-            is_main: self.threads.len() == 1,
+            is_main: self.threads.last().unwrap().is_main(),
         }
     }
 
+    /// Whether the currently executing thread may yield.
+    ///
+    /// Callbacks never form a yield barrier, since they are executed without using the Rust stack,
+    /// so this is only false when executing in the main thread of the executor. `coroutine.yield`
+    /// raises an error in the main thread, but a Rust callback may still return
+    /// `CallbackReturn::Yield` from it to suspend the entire `Executor`.
+    pub fn is_yieldable(&self) -> bool {
+        !self.current_thread().is_main
+    }
+
+    /// Produce a traceback of the current thread, in the same format as PUC-Rio Lua's
+    /// `debug.traceback`.
+    ///
+    /// Level 1 is the function that called the currently executing callback. Skipped levels are
+    /// omitted, and very deep stacks have their middle levels elided.
+    pub fn traceback(&self, ctx: Context<'gc>, level: usize) -> StdString {
+        traceback(ctx, self.frames, level)
+    }
+
+    /// Information about the call frame at the given level of the current thread.
+    ///
+    /// Level 1 is the function that called the currently executing callback. Returns `None` if
+    /// there is no frame at that level.
+    pub fn frame_info(&self, level: usize) -> Option<FrameInfo<'gc>> {
+        frame_infos(self.frames).nth(level.wrapping_sub(1))
+    }
+
+    /// The name and value of the `n`th (starting at 1) local variable in scope in the Lua function
+    /// at the given level of the current thread.
+    ///
+    /// Level 1 is the function that called the currently executing callback. The `stack` must be
+    /// the stack passed to the currently executing callback, since the values of local variables
+    /// are stored below it.
+    pub fn local_variable(
+        &self,
+        stack: &mut Stack<'gc, '_>,
+        level: usize,
+        n: usize,
+    ) -> Option<(String<'gc>, Value<'gc>)> {
+        let (name, index) = frame_local(self.frames, level, n)?;
+        Some((name, stack.lower()[index]))
+    }
+
+    /// Set the value of the `n`th local variable in scope in the Lua function at the given level
+    /// of the current thread, returning the name of the variable.
+    ///
+    /// The `stack` must be the stack passed to the currently executing callback.
+    pub fn set_local_variable(
+        &self,
+        stack: &mut Stack<'gc, '_>,
+        level: usize,
+        n: usize,
+        value: Value<'gc>,
+    ) -> Option<String<'gc>> {
+        let (name, index) = frame_local(self.frames, level, n)?;
+        stack.lower()[index] = value;
+        Some(name)
+    }
+
+    /// Get the current value of an upvalue.
+    ///
+    /// Upvalues that are still open in the current thread are read from the `stack`, which must be
+    /// the stack passed to the currently executing callback.
+    pub fn get_upvalue(
+        &self,
+        mc: &Mutation<'gc>,
+        stack: &mut Stack<'gc, '_>,
+        upvalue: UpValue<'gc>,
+    ) -> Value<'gc> {
+        match upvalue.get() {
+            UpValueState::Open(open) if self.is_current_thread(open) => {
+                stack.lower()[open.stack_index]
+            }
+            UpValueState::Open(open) => open.get(mc),
+            UpValueState::Closed(v) => v,
+        }
+    }
+
+    /// Set the value of an upvalue.
+    ///
+    /// The `stack` must be the stack passed to the currently executing callback.
+    pub fn set_upvalue(
+        &self,
+        mc: &Mutation<'gc>,
+        stack: &mut Stack<'gc, '_>,
+        upvalue: UpValue<'gc>,
+        value: Value<'gc>,
+    ) {
+        match upvalue.get() {
+            UpValueState::Open(open) if self.is_current_thread(open) => {
+                stack.lower()[open.stack_index] = value;
+            }
+            UpValueState::Open(open) => open.set(mc, value),
+            UpValueState::Closed(_) => upvalue.set(mc, UpValueState::Closed(value)),
+        }
+    }
+
+    fn is_current_thread(&self, open: OpenUpValue<'gc>) -> bool {
+        open.thread.as_ptr() == Gc::as_ptr(self.current_thread().thread.into_inner())
+    }
+
+    /// The debug hook installed on the current thread, if any.
+    pub fn hook(&self) -> Option<Hook<'gc>> {
+        self.hook.as_ref().map(|h| h.hook)
+    }
+
+    /// Install or remove the debug hook for the current thread.
+    pub fn set_hook(&mut self, hook: Option<Hook<'gc>>) {
+        *self.hook = hook.map(HookState::new);
+    }
+
     /// The curently running Executor.
     ///
     /// Do not call methods on this from callbacks! This is provided only for identification
@@ -566,13 +931,7 @@ impl<'gc, 'a> Execution<'gc, 'a> {
         self.upper_lua.map(|(proto, pc)| UpperLuaFrame {
             chunk_name: proto.chunk_name,
             current_function: proto.reference,
-            current_line: match proto
-                .opcode_line_numbers
-                .binary_search_by_key(&pc, |(opi, _)| *opi)
-            {
-                Ok(i) => proto.opcode_line_numbers[i].1,
-                Err(i) => proto.opcode_line_numbers[i - 1].1,
-            },
+            current_line: proto.line_number(pc),
         })
     }
 }
@@ -9,12 +9,16 @@ use crate::TypeError;
 pub use self::{
     executor::{
         BadExecutorMode, CurrentThread, Execution, Executor, ExecutorInner, ExecutorMode,
-        UpperLuaFrame,
+        StepResult, UpperLuaFrame,
     },
-    thread::{BadThreadMode, OpenUpValue, Thread, ThreadInner, ThreadMode},
-    vm::BinaryOperatorError,
+    thread::{
+        BadThreadMode, FrameInfo, Hook, HookMask, OpenUpValue, Thread, ThreadInner, ThreadMode,
+    },
+    vm::{BinaryOperatorError, NotClosableError},
 };
 
+pub(crate) use self::thread::{callback_name, ThreadStacks};
+
 #[derive(Debug, Copy, Clone, Error)]
 pub enum VMError {
     #[error("{}", if *.0 {
@@ -27,4 +31,14 @@ pub enum VMError {
     BadType(#[from] TypeError),
     #[error("_ENV upvalue is only allowed on top-level closure")]
     BadEnvUpValue,
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("not enough memory")]
+    NotEnoughMemory,
+    #[error("'for' step is zero")]
+    ForStepZero,
+    #[error("'for' {0} must be a number")]
+    BadForValue(&'static str),
+    #[error("'for' loop state was not set up by its prep operation")]
+    BadForState,
 }
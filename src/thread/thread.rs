@@ -1,18 +1,25 @@
 use std::{
-    cell::RefMut,
+    cell::{BorrowMutError, Cell, Ref, RefCell, RefMut},
     hash::{Hash, Hasher},
+    mem,
+    string::String as StdString,
 };
 
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, GcWeak, Mutation};
+use gc_arena::{
+    allocator_api::MetricsAlloc, barrier::Write, lock::RefLock, Collect, Gc, GcWeak, Mutation,
+};
 use thiserror::Error;
 
 use crate::{
     closure::{UpValue, UpValueState},
+    compiler::{FunctionRef, LineNumber},
     meta_ops,
+    opcode::Operation,
     types::{RegisterIndex, VarCount},
-    BoxSequence, Callback, Closure, Context, Error, FromMultiValue, Fuel, Function, IntoMultiValue,
-    TypeError, VMError, Value,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, FromMultiValue,
+    Fuel, Function, IntoMultiValue, Sequence, SequencePoll, Stack, String, TypeError, VMError,
+    Value,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,11 +50,18 @@ pub struct BadThreadMode {
     pub expected: Option<ThreadMode>,
 }
 
-pub type ThreadInner<'gc> = RefLock<ThreadState<'gc>>;
+#[derive(Debug, Collect)]
+#[collect(no_drop)]
+pub struct ThreadInner<'gc> {
+    state: RefLock<ThreadState<'gc>>,
+    // Kept outside of the state lock so that it can be read while the thread is running.
+    #[collect(require_static)]
+    is_main: Cell<bool>,
+}
 
 #[derive(Debug, Clone, Copy, Collect)]
 #[collect(no_drop)]
-pub struct Thread<'gc>(Gc<'gc, RefLock<ThreadState<'gc>>>);
+pub struct Thread<'gc>(Gc<'gc, ThreadInner<'gc>>);
 
 impl<'gc> PartialEq for Thread<'gc> {
     fn eq(&self, other: &Thread<'gc>) -> bool {
@@ -64,14 +78,31 @@ impl<'gc> Hash for Thread<'gc> {
 }
 
 impl<'gc> Thread<'gc> {
+    /// The default maximum number of call frames a thread may have, see `Thread::set_max_frames`.
+    pub const DEFAULT_MAX_FRAMES: usize = 200_000;
+
+    /// Create a new thread in the `Stopped` mode.
+    ///
+    /// If a thread has recently been garbage collected, the new thread reuses the allocations of
+    /// its stacks.
     pub fn new(ctx: Context<'gc>) -> Thread<'gc> {
+        let stacks = ctx
+            .finalizers()
+            .take_thread_stacks(&ctx)
+            .unwrap_or_else(|| ThreadStacks::new(&ctx));
         let p = Gc::new(
             &ctx,
-            RefLock::new(ThreadState {
-                frames: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-                stack: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-                open_upvalues: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-            }),
+            ThreadInner {
+                state: RefLock::new(ThreadState {
+                    frames: stacks.frames,
+                    stack: stacks.stack,
+                    open_upvalues: stacks.open_upvalues,
+                    to_be_closed: stacks.to_be_closed,
+                    hook: None,
+                    max_frames: Thread::DEFAULT_MAX_FRAMES,
+                }),
+                is_main: Cell::new(false),
+            },
         );
         ctx.finalizers().register_thread(&ctx, p);
         Thread(p)
@@ -85,8 +116,21 @@ impl<'gc> Thread<'gc> {
         self.0
     }
 
+    /// Returns true if this is the main thread of an `Executor`.
+    ///
+    /// The thread that an `Executor` is created with (or reset to) is its main thread, every other
+    /// thread is a coroutine. Yielding from the main thread with `coroutine.yield` is an error.
+    /// This can be called at any time, including while the thread is running.
+    pub fn is_main(self) -> bool {
+        self.0.is_main.get()
+    }
+
+    pub(crate) fn set_main(self, is_main: bool) {
+        self.0.is_main.set(is_main);
+    }
+
     pub fn mode(self) -> ThreadMode {
-        match self.0.try_borrow() {
+        match self.0.state.try_borrow() {
             Ok(state) => state.mode(),
             Err(_) => ThreadMode::Running,
         }
@@ -129,6 +173,19 @@ impl<'gc> Thread<'gc> {
             .and_then(|vals| Ok(T::from_multi_value(ctx, vals)?)))
     }
 
+    /// If the thread is in the `Result` mode, take the returned (or yielded) values and convert
+    /// them to `T`.
+    ///
+    /// This is a flattened version of `Thread::take_result`: calling this in any mode other than
+    /// `Result` produces a `BadThreadMode` error, and an error raised by the thread itself or by
+    /// the conversion to `T` is returned as is.
+    pub fn take_return_values<T: FromMultiValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+    ) -> Result<T, Error<'gc>> {
+        self.take_result(ctx)?
+    }
+
     /// If the thread is in `Suspended` mode, resume it.
     pub fn resume(
         self,
@@ -153,6 +210,34 @@ impl<'gc> Thread<'gc> {
         Ok(())
     }
 
+    /// If the thread is in `Suspended` mode, resume it with the given arguments.
+    ///
+    /// This is the same as `Thread::resume`, but reports being in the wrong mode as a regular
+    /// `Error`, which is more convenient when driving coroutines from Rust.
+    pub fn resume_with<A: IntoMultiValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        args: A,
+    ) -> Result<(), Error<'gc>> {
+        Ok(self.resume(ctx, args)?)
+    }
+
+    /// Helper for callbacks which yield from the current thread.
+    ///
+    /// Replaces the contents of the callback stack with the given values and returns the
+    /// `CallbackReturn` that yields them to whoever resumed the current thread.
+    pub fn yield_values(
+        ctx: Context<'gc>,
+        mut stack: Stack<'gc, '_>,
+        values: impl IntoMultiValue<'gc>,
+    ) -> CallbackReturn<'gc> {
+        stack.replace(ctx, values);
+        CallbackReturn::Yield {
+            to_thread: None,
+            then: None,
+        }
+    }
+
     /// If the thread is in `Suspended` mode, cause an error wherever the thread was suspended.
     pub fn resume_err(self, mc: &Mutation<'gc>, error: Error<'gc>) -> Result<(), BadThreadMode> {
         let mut state = self.check_mode(mc, ThreadMode::Suspended)?;
@@ -167,7 +252,7 @@ impl<'gc> Thread<'gc> {
     /// If this thread is in any other mode than `Running`, reset the thread completely and restore
     /// it to the `Stopped` state.
     pub fn reset(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
-        match self.0.try_borrow_mut(mc) {
+        match self.try_borrow_mut(mc) {
             Ok(mut state) => {
                 state.reset(mc);
                 Ok(())
@@ -179,13 +264,241 @@ impl<'gc> Thread<'gc> {
         }
     }
 
+    /// Removes the pending to-be-closed variables of a `Suspended` thread and returns their values,
+    /// in the order they were declared.
+    ///
+    /// The variables are not closed, this is for callers which close the thread and then call
+    /// the `__close` metamethod of each value themselves, the most recent first.
+    pub fn take_to_be_closed(self, mc: &Mutation<'gc>) -> Result<Vec<Value<'gc>>, BadThreadMode> {
+        let mut state = self.check_mode(mc, ThreadMode::Suspended)?;
+        let state = &mut *state;
+        Ok(state
+            .to_be_closed
+            .drain(..)
+            .map(|i| state.stack[i])
+            .collect())
+    }
+
+    /// If this thread is `Stopped`, `Suspended`, or `Result`, close it and restore it to the
+    /// `Stopped` state, closing any open upvalues. Pending to-be-closed variables of a suspended
+    /// thread are discarded without calling their `__close` metamethods, unless they were first
+    /// removed with `Thread::take_to_be_closed`.
+    ///
+    /// If the thread was in the `Result` mode because of an error, the error is returned, otherwise
+    /// any pending results are discarded. Threads in the `Normal`, `Waiting`, or `Running` modes
+    /// cannot be closed.
+    pub fn close(self, mc: &Mutation<'gc>) -> Result<Result<(), Error<'gc>>, BadThreadMode> {
+        let mut state = self.try_borrow_mut(mc).map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })?;
+
+        match state.mode() {
+            ThreadMode::Stopped | ThreadMode::Suspended => {
+                state.reset(mc);
+                Ok(Ok(()))
+            }
+            ThreadMode::Result => {
+                let res = state.take_result().map(|_| ());
+                state.reset(mc);
+                Ok(res)
+            }
+            found => Err(BadThreadMode {
+                found,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Produce a traceback of this thread, in the same format as PUC-Rio Lua's `debug.traceback`.
+    ///
+    /// Level 1 is the most recently called function. Skipped levels are omitted, and very deep
+    /// stacks have their middle levels elided. Returns an error if the thread is currently
+    /// running.
+    pub fn traceback(self, ctx: Context<'gc>, level: usize) -> Result<StdString, BadThreadMode> {
+        match self.0.state.try_borrow() {
+            Ok(state) => Ok(traceback(ctx, &state.frames, level)),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Information about the call frame at the given level of this thread, where level 1 is the
+    /// most recently called function. Returns an error if the thread is currently running.
+    pub fn frame_info(self, level: usize) -> Result<Option<FrameInfo<'gc>>, BadThreadMode> {
+        match self.0.state.try_borrow() {
+            Ok(state) => Ok(frame_infos(&state.frames).nth(level.wrapping_sub(1))),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// The name and value of the `n`th (starting at 1) local variable in scope in the Lua function
+    /// at the given call level, where level 1 is the most recent call.
+    ///
+    /// Returns `None` if there is no such local variable, or the frame at that level is not a Lua
+    /// function. Returns an error if the thread is currently running.
+    pub fn local_variable(
+        self,
+        level: usize,
+        n: usize,
+    ) -> Result<Option<(String<'gc>, Value<'gc>)>, BadThreadMode> {
+        match self.0.state.try_borrow() {
+            Ok(state) => Ok(frame_local(&state.frames, level, n)
+                .map(|(name, index)| (name, state.stack[index]))),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Set the value of the `n`th local variable in scope in the Lua function at the given call
+    /// level, returning the name of the variable.
+    ///
+    /// Returns `None` and does nothing if there is no such local variable. Returns an error if the
+    /// thread is currently running.
+    pub fn set_local_variable(
+        self,
+        mc: &Mutation<'gc>,
+        level: usize,
+        n: usize,
+        value: Value<'gc>,
+    ) -> Result<Option<String<'gc>>, BadThreadMode> {
+        match self.try_borrow_mut(mc) {
+            Ok(mut state) => Ok(frame_local(&state.frames, level, n).map(|(name, index)| {
+                state.stack[index] = value;
+                name
+            })),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// The debug hook installed on this thread, if any. Returns an error if the thread is currently
+    /// running.
+    pub fn hook(self) -> Result<Option<Hook<'gc>>, BadThreadMode> {
+        match self.0.state.try_borrow() {
+            Ok(state) => Ok(state.hook.as_ref().map(|h| h.hook)),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Install or remove the debug hook for this thread. Returns an error if the thread is
+    /// currently running.
+    ///
+    /// To set the hook of the currently running thread from a callback, use
+    /// `Execution::set_hook`.
+    pub fn set_hook(
+        self,
+        mc: &Mutation<'gc>,
+        hook: Option<Hook<'gc>>,
+    ) -> Result<(), BadThreadMode> {
+        match self.try_borrow_mut(mc) {
+            Ok(mut state) => {
+                state.hook = hook.map(HookState::new);
+                Ok(())
+            }
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// The maximum number of call frames this thread may have. Returns an error if the thread is
+    /// currently running.
+    pub fn max_frames(self) -> Result<usize, BadThreadMode> {
+        match self.0.state.try_borrow() {
+            Ok(state) => Ok(state.max_frames),
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Set the maximum number of call frames this thread may have. Returns an error if the thread
+    /// is currently running.
+    ///
+    /// Calls never consume host stack, so unbounded recursion would otherwise only be stopped by
+    /// running out of memory. Any call that would exceed this limit instead raises a "stack
+    /// overflow" error, which can be caught with `pcall` like any other error. Tail calls do not
+    /// add a frame and so are not limited.
+    pub fn set_max_frames(
+        self,
+        mc: &Mutation<'gc>,
+        max_frames: usize,
+    ) -> Result<(), BadThreadMode> {
+        match self.try_borrow_mut(mc) {
+            Ok(mut state) => {
+                state.max_frames = max_frames;
+                Ok(())
+            }
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    // Reset the thread and take its (now empty) stacks, leaving it with new stacks that have no
+    // allocations. The stacks taken are shrunk to at most `ThreadStacks::MAX_CAPACITY`.
+    pub(crate) fn take_stacks(self, mc: &Mutation<'gc>) -> ThreadStacks<'gc> {
+        let mut state = self.borrow_mut(mc);
+        state.reset(mc);
+        let state = &mut *state;
+        let empty = ThreadStacks::new(mc);
+        let mut stacks = ThreadStacks {
+            frames: mem::replace(&mut state.frames, empty.frames),
+            stack: mem::replace(&mut state.stack, empty.stack),
+            open_upvalues: mem::replace(&mut state.open_upvalues, empty.open_upvalues),
+            to_be_closed: mem::replace(&mut state.to_be_closed, empty.to_be_closed),
+        };
+        stacks.frames.shrink_to(ThreadStacks::MAX_CAPACITY);
+        stacks.stack.shrink_to(ThreadStacks::MAX_CAPACITY);
+        stacks.open_upvalues.shrink_to(ThreadStacks::MAX_CAPACITY);
+        stacks.to_be_closed.shrink_to(ThreadStacks::MAX_CAPACITY);
+        stacks
+    }
+
+    pub(crate) fn borrow(self) -> Ref<'gc, ThreadState<'gc>> {
+        Gc::as_ref(self.0).state.borrow()
+    }
+
+    pub(crate) fn borrow_mut(self, mc: &Mutation<'gc>) -> RefMut<'gc, ThreadState<'gc>> {
+        self.state_cell(mc).borrow_mut()
+    }
+
+    pub(crate) fn try_borrow_mut(
+        self,
+        mc: &Mutation<'gc>,
+    ) -> Result<RefMut<'gc, ThreadState<'gc>>, BorrowMutError> {
+        self.state_cell(mc).try_borrow_mut()
+    }
+
+    fn state_cell(self, mc: &Mutation<'gc>) -> &'gc RefCell<ThreadState<'gc>> {
+        let inner = Gc::write(mc, self.0);
+        // SAFETY: We have just triggered a write barrier on the `Gc` that owns the state.
+        unsafe { Write::assume(&inner.state) }.unlock()
+    }
+
     fn check_mode(
         &self,
         mc: &Mutation<'gc>,
         expected: ThreadMode,
     ) -> Result<RefMut<ThreadState<'gc>>, BadThreadMode> {
         assert!(expected != ThreadMode::Running);
-        if let Ok(state) = self.0.try_borrow_mut(mc) {
+        if let Ok(state) = self.try_borrow_mut(mc) {
             let found = state.mode();
             if found == expected {
                 Ok(state)
@@ -204,33 +517,114 @@ impl<'gc> Thread<'gc> {
     }
 }
 
+// The empty stacks of a thread, kept after the thread is collected so that their allocations can be
+// reused by a new thread.
+#[derive(Collect)]
+#[collect(no_drop)]
+pub(crate) struct ThreadStacks<'gc> {
+    frames: vec::Vec<Frame<'gc>, MetricsAlloc<'gc>>,
+    stack: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
+    open_upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    to_be_closed: vec::Vec<usize, MetricsAlloc<'gc>>,
+}
+
+impl<'gc> ThreadStacks<'gc> {
+    // The most capacity kept in each stack, so that a collected thread which grew a very deep stack
+    // does not keep all of it allocated while waiting to be reused.
+    const MAX_CAPACITY: usize = 256;
+
+    fn new(mc: &Mutation<'gc>) -> Self {
+        ThreadStacks {
+            frames: vec::Vec::new_in(MetricsAlloc::new(mc)),
+            stack: vec::Vec::new_in(MetricsAlloc::new(mc)),
+            open_upvalues: vec::Vec::new_in(MetricsAlloc::new(mc)),
+            to_be_closed: vec::Vec::new_in(MetricsAlloc::new(mc)),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct OpenUpValue<'gc> {
-    thread: GcWeak<'gc, RefLock<ThreadState<'gc>>>,
-    stack_index: usize,
+    pub(super) thread: GcWeak<'gc, ThreadInner<'gc>>,
+    pub(super) stack_index: usize,
 }
 
 impl<'gc> OpenUpValue<'gc> {
     const UPGRADE_ERR: &'static str = "thread not finalized: upvalues not closed";
 
     pub fn get(self, mc: &Mutation<'gc>) -> Value<'gc> {
-        self.thread
-            .upgrade(mc)
-            .expect(Self::UPGRADE_ERR)
+        Thread(self.thread.upgrade(mc).expect(Self::UPGRADE_ERR))
             .borrow()
             .stack[self.stack_index]
     }
 
     pub fn set(self, mc: &Mutation<'gc>, v: Value<'gc>) {
-        self.thread
-            .upgrade(mc)
-            .expect(Self::UPGRADE_ERR)
+        Thread(self.thread.upgrade(mc).expect(Self::UPGRADE_ERR))
             .borrow_mut(mc)
             .stack[self.stack_index] = v;
     }
 }
 
+/// The events that a debug hook is called for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct HookMask {
+    /// Called when a Lua function is entered.
+    pub call: bool,
+    /// Called just before a Lua function returns.
+    pub ret: bool,
+    /// Called when execution moves to a new source line, or jumps backwards within a line.
+    pub line: bool,
+}
+
+/// A debug hook installed on a thread.
+///
+/// The hook function is called with the name of the event (`"call"`, `"return"`, `"line"`, or
+/// `"count"`) and, for line events, the new line number. If `count` is non-zero, the hook is also
+/// called after every `count` Lua instructions.
+///
+/// Hooks are only triggered by Lua code, not callbacks, and are not triggered while a hook is
+/// already running on the same thread.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Hook<'gc> {
+    pub function: Function<'gc>,
+    pub mask: HookMask,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Collect)]
+#[collect(no_drop)]
+pub(super) struct HookState<'gc> {
+    pub(super) hook: Hook<'gc>,
+    // True while the hook function is being called.
+    pub(super) running: bool,
+    counter: u32,
+    // The frame depth and PC of the instruction events were last generated for.
+    position: Option<(usize, usize)>,
+    // True if events have been generated for the next instruction, which has not yet run. This is
+    // separate from `position` because an instruction that jumps to itself runs again at the same
+    // position.
+    generated: bool,
+    // Events for the instruction at `position` that have not yet been sent.
+    #[collect(require_static)]
+    pending: Vec<&'static str>,
+}
+
+impl<'gc> HookState<'gc> {
+    pub(super) fn new(hook: Hook<'gc>) -> Self {
+        Self {
+            hook,
+            running: false,
+            counter: 0,
+            position: None,
+            generated: false,
+            pending: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(require_static)]
 pub(super) enum MetaReturn {
@@ -240,6 +634,11 @@ pub(super) enum MetaReturn {
     Register(RegisterIndex),
     // Increment the PC by one if the returned value converted to a boolean is equal to this.
     SkipIf(bool),
+    // Returning from a debug hook, no return value is expected.
+    Hook,
+    // Returning from a `__close` metamethod. No return value is expected, and the stack is
+    // restored to exactly where it was before the call, which may be variable.
+    Close,
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -257,6 +656,7 @@ pub(super) enum LuaReturn {
 pub(super) enum Frame<'gc> {
     // A running Lua frame.
     Lua {
+        closure: Closure<'gc>,
         bottom: usize,
         base: usize,
         is_variable: bool,
@@ -283,6 +683,8 @@ pub(super) enum Frame<'gc> {
         // Will be set when unwinding has stopped at this frame. If set, this must be the top frame
         // of the stack.
         pending_error: Option<Error<'gc>>,
+        // The callback which returned this sequence, used to name the frame in tracebacks.
+        callback: Option<Callback<'gc>>,
     },
     // We are waiting on an upper thread to finish. Must be the top frame of the stack.
     WaitThread,
@@ -300,6 +702,10 @@ pub struct ThreadState<'gc> {
     pub(super) frames: vec::Vec<Frame<'gc>, MetricsAlloc<'gc>>,
     pub(super) stack: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     pub(super) open_upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    // The stack indexes of every active to-be-closed variable, in increasing order.
+    pub(super) to_be_closed: vec::Vec<usize, MetricsAlloc<'gc>>,
+    pub(super) hook: Option<HookState<'gc>>,
+    pub(super) max_frames: usize,
 }
 
 impl<'gc> ThreadState<'gc> {
@@ -327,6 +733,29 @@ impl<'gc> ThreadState<'gc> {
         }
     }
 
+    // Returns true if the result waiting to be taken from this thread is an error.
+    pub(super) fn is_error_result(&self) -> bool {
+        matches!(self.frames.as_slice(), [Frame::Error(_)])
+    }
+
+    // Returns true if the result waiting to be taken from this thread was produced by a yield
+    // rather than by returning or erroring.
+    pub(super) fn is_yield_result(&self) -> bool {
+        matches!(
+            self.frames.as_slice(),
+            [.., Frame::Yielded, Frame::Result { .. }]
+        )
+    }
+
+    // Returns an error if pushing another call frame would exceed the maximum number of frames.
+    pub(super) fn check_stack_overflow(&self) -> Result<(), VMError> {
+        if self.frames.len() >= self.max_frames {
+            Err(VMError::StackOverflow)
+        } else {
+            Ok(())
+        }
+    }
+
     // Pushes a function call frame, arguments start at the given stack bottom.
     pub(super) fn push_call(&mut self, bottom: usize, function: Function<'gc>) {
         match function {
@@ -348,6 +777,7 @@ impl<'gc> ThreadState<'gc> {
                 self.stack.resize(base + stack_size, Value::Nil);
 
                 self.frames.push(Frame::Lua {
+                    closure,
                     bottom,
                     base,
                     is_variable: false,
@@ -392,6 +822,9 @@ impl<'gc> ThreadState<'gc> {
                             self.stack.resize(*base + *stack_size, Value::Nil);
                         }
                     }
+                    Some(LuaReturn::Meta(MetaReturn::Close)) => {
+                        self.stack.truncate(bottom);
+                    }
                     Some(LuaReturn::Meta(meta_ret)) => {
                         let meta_val = self.stack.get(bottom).copied().unwrap_or_default();
                         self.stack.truncate(bottom);
@@ -399,14 +832,20 @@ impl<'gc> ThreadState<'gc> {
                         *is_variable = false;
                         match meta_ret {
                             MetaReturn::None => {}
+                            MetaReturn::Hook => {
+                                if let Some(hook) = &mut self.hook {
+                                    hook.running = false;
+                                }
+                            }
                             MetaReturn::Register(reg) => {
                                 self.stack[*base + reg.0 as usize] = meta_val;
                             }
                             MetaReturn::SkipIf(skip_if) => {
-                                if meta_val.to_bool() == skip_if {
+                                if meta_val.is_truthy() == skip_if {
                                     *pc += 1;
                                 }
                             }
+                            MetaReturn::Close => unreachable!(),
                         }
                     }
                     None => panic!("no expected return set for returned to lua frame"),
@@ -419,18 +858,32 @@ impl<'gc> ThreadState<'gc> {
         }
     }
 
+    // Takes the returned values or error from a thread in `Result` mode.
+    //
+    // If the thread is in any other mode, the thread is left untouched and a `BadThreadMode` error
+    // is returned instead.
     pub(super) fn take_result(
         &mut self,
     ) -> Result<impl Iterator<Item = Value<'gc>> + '_, Error<'gc>> {
+        let found = self.mode();
+        if found != ThreadMode::Result {
+            return Err(BadThreadMode {
+                found,
+                expected: Some(ThreadMode::Result),
+            }
+            .into());
+        }
+
         match self.frames.pop() {
             Some(Frame::Result { bottom }) => Ok(self.stack.drain(bottom..)),
             Some(Frame::Error(err)) => {
                 assert!(self.stack.is_empty());
                 assert!(self.frames.is_empty());
                 assert!(self.open_upvalues.is_empty());
+                assert!(self.to_be_closed.is_empty());
                 Err(err)
             }
-            _ => panic!("no results available to take"),
+            _ => unreachable!(),
         }
     }
 
@@ -447,7 +900,7 @@ impl<'gc> ThreadState<'gc> {
         for &upval in &self.open_upvalues[start..] {
             match upval.get() {
                 UpValueState::Open(open_upvalue) => {
-                    assert!(open_upvalue.thread.upgrade(mc).unwrap().as_ptr() == this_ptr);
+                    assert!(open_upvalue.thread.upgrade(mc).unwrap().state.as_ptr() == this_ptr);
                     upval.set(
                         mc,
                         UpValueState::Closed(self.stack[open_upvalue.stack_index]),
@@ -463,8 +916,67 @@ impl<'gc> ThreadState<'gc> {
     fn reset(&mut self, mc: &Mutation<'gc>) {
         self.close_upvalues(mc, 0);
         assert!(self.open_upvalues.is_empty());
+        self.to_be_closed.clear();
         self.stack.clear();
         self.frames.clear();
+        if let Some(hook) = &mut self.hook {
+            *hook = HookState::new(hook.hook);
+        }
+    }
+
+    // Called when an error is unwinding through the top Lua frame. If that frame has any active
+    // to-be-closed variables, closes upvalues in the frame and calls the `__close` metamethod of
+    // the most recent one with the error, after which the error continues unwinding. Returns the
+    // error unchanged if there is nothing left to close.
+    //
+    // If the call to `__close` itself fails, the new error replaces the original one.
+    pub(super) fn close_on_error(
+        &mut self,
+        ctx: Context<'gc>,
+        error: Error<'gc>,
+    ) -> Option<Error<'gc>> {
+        let Some(&Frame::Lua { bottom, .. }) = self.frames.last() else {
+            return Some(error);
+        };
+
+        match self.to_be_closed.last() {
+            Some(&index) if index >= bottom => {}
+            _ => return Some(error),
+        }
+
+        self.close_upvalues(&ctx, bottom);
+        let index = self.to_be_closed.pop().unwrap();
+        let value = self.stack[index];
+        match meta_ops::close(ctx, value, error.to_value(ctx)) {
+            Ok(call) => {
+                #[derive(Collect)]
+                #[collect(no_drop)]
+                struct Unwind<'gc>(Error<'gc>);
+
+                impl<'gc> Sequence<'gc> for Unwind<'gc> {
+                    fn poll(
+                        &mut self,
+                        _ctx: Context<'gc>,
+                        _exec: Execution<'gc, '_>,
+                        _stack: Stack<'gc, '_>,
+                    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                        Err(self.0.clone())
+                    }
+                }
+
+                let top = self.stack.len();
+                self.frames.push(Frame::Sequence {
+                    bottom: top,
+                    sequence: BoxSequence::new(&ctx, Unwind(error)),
+                    pending_error: None,
+                    callback: None,
+                });
+                self.stack.extend(call.args);
+                self.push_call(top, call.function);
+            }
+            Err(err) => self.frames.push(Frame::Error(err.into())),
+        }
+        None
     }
 }
 
@@ -478,17 +990,167 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     const FUEL_PER_CALL: i32 = 4;
     const FUEL_PER_ITEM: i32 = 1;
 
+    // If a debug hook is installed, call it for any events raised by the instruction about to be
+    // executed. Returns true if a call to the hook function has been pushed, in which case the VM
+    // must return and let the hook run before executing the instruction.
+    pub(super) fn run_hook(&mut self, ctx: Context<'gc>) -> Result<bool, VMError> {
+        let depth = self.state.frames.len();
+        let Some(&Frame::Lua {
+            closure,
+            pc,
+            is_variable,
+            ..
+        }) = self.state.frames.last()
+        else {
+            panic!("top frame is not lua frame");
+        };
+        let Some(hook) = &mut self.state.hook else {
+            return Ok(false);
+        };
+        if hook.running {
+            return Ok(false);
+        }
+
+        let proto = closure.prototype();
+        let line = proto.line_number(pc);
+
+        if !hook.generated {
+            hook.pending.clear();
+
+            let mask = hook.hook.mask;
+            if mask.call && pc == 0 {
+                hook.pending.push("call");
+            }
+
+            if hook.hook.count != 0 {
+                hook.counter += 1;
+                if hook.counter >= hook.hook.count {
+                    hook.counter = 0;
+                    hook.pending.push("count");
+                }
+            }
+
+            if mask.line {
+                let new_line = match hook.position {
+                    None => true,
+                    // We have entered a new function.
+                    Some((last_depth, _)) if last_depth < depth => true,
+                    // We have returned from a call, the calling instruction is just before the PC.
+                    Some((last_depth, _)) if last_depth > depth => {
+                        pc == 0 || line != proto.line_number(pc - 1)
+                    }
+                    Some((_, last_pc)) => pc <= last_pc || line != proto.line_number(last_pc),
+                };
+                if new_line {
+                    hook.pending.push("line");
+                }
+            }
+
+            if mask.ret && matches!(proto.opcodes[pc].decode(), Operation::Return { .. }) {
+                hook.pending.push("return");
+            }
+
+            // Events are sent in order from the front of the list.
+            hook.pending.reverse();
+            hook.position = Some((depth, pc));
+            hook.generated = true;
+        }
+
+        // Hooks cannot be called while results are on the stack, skip the events for this
+        // instruction.
+        if is_variable {
+            hook.pending.clear();
+        }
+
+        let Some(event) = hook.pending.pop() else {
+            // Every event has been sent, so the instruction runs next.
+            hook.generated = false;
+            return Ok(false);
+        };
+        hook.running = true;
+        let function = hook.hook.function;
+        let line = if event == "line" {
+            Value::Integer(line.0 as i64 + 1)
+        } else {
+            Value::Nil
+        };
+        LuaFrame {
+            thread: self.thread,
+            state: &mut *self.state,
+            fuel: &mut *self.fuel,
+        }
+        .call_meta_function(
+            ctx,
+            function,
+            &[ctx.intern_static(event.as_bytes()).into(), line],
+            MetaReturn::Hook,
+        )?;
+        Ok(true)
+    }
+
     // Returns the active closure for this Lua frame
     pub(super) fn closure(&self) -> Closure<'gc> {
         match self.state.frames.last() {
-            Some(Frame::Lua { bottom, .. }) => match self.state.stack[*bottom] {
-                Value::Function(Function::Closure(c)) => c,
-                _ => panic!("lua frame bottom is not a closure"),
-            },
+            Some(Frame::Lua { closure, .. }) => *closure,
             _ => panic!("top frame is not lua frame"),
         }
     }
 
+    // Mark the value in the given register as a to-be-closed variable.
+    pub(super) fn mark_to_be_closed(&mut self, reg: RegisterIndex) {
+        let Some(&Frame::Lua { base, .. }) = self.state.frames.last() else {
+            panic!("top frame is not lua frame");
+        };
+        let index = base + reg.0 as usize;
+        debug_assert!(self.state.to_be_closed.last().is_none_or(|&i| i < index));
+        self.state.to_be_closed.push(index);
+    }
+
+    // Close the most recent to-be-closed variable at or above the given register, if there is one,
+    // by calling its `__close` metamethod with a `nil` error. Upvalues at or above the register
+    // are closed first.
+    //
+    // Returns true if a call was pushed, in which case the PC has been moved back so that the
+    // current instruction is executed again once the call returns.
+    pub(super) fn close_to_be_closed(
+        &mut self,
+        ctx: Context<'gc>,
+        bottom_register: RegisterIndex,
+    ) -> Result<bool, VMError> {
+        let Some(&Frame::Lua { base, .. }) = self.state.frames.last() else {
+            panic!("top frame is not lua frame");
+        };
+
+        let bottom = base + bottom_register.0 as usize;
+        match self.state.to_be_closed.last() {
+            Some(&index) if index >= bottom => {}
+            _ => return Ok(false),
+        }
+
+        self.state.close_upvalues(&ctx, bottom);
+        let index = self.state.to_be_closed.pop().unwrap();
+        let call = meta_ops::close(ctx, self.state.stack[index], Value::Nil)?;
+
+        self.fuel.consume(Self::FUEL_PER_CALL);
+
+        let Some(Frame::Lua {
+            pc,
+            expected_return,
+            ..
+        }) = self.state.frames.last_mut()
+        else {
+            unreachable!();
+        };
+        *pc -= 1;
+        *expected_return = Some(LuaReturn::Meta(MetaReturn::Close));
+        // The call is placed at the very top of the stack, so that a variable stack (such as the
+        // results of a `Return`) is preserved.
+        let top = self.state.stack.len();
+        self.state.stack.extend(call.args);
+        self.state.push_call(top, call.function);
+        Ok(true)
+    }
+
     // returns a view of the Lua frame's registers
     pub(super) fn registers<'b>(&'b mut self) -> LuaRegisters<'gc, 'b> {
         match self.state.frames.last_mut() {
@@ -555,7 +1217,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
     pub(super) fn set_table_list(
         &mut self,
-        mc: &Mutation<'gc>,
+        ctx: Context<'gc>,
         table_base: RegisterIndex,
         count: VarCount,
     ) -> Result<(), VMError> {
@@ -605,7 +1267,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
             if let Some(inc) = start.checked_add(1) {
                 start = inc;
                 table
-                    .set_value(mc, inc.into(), self.state.stack[table_ind + 2 + i])
+                    .set_value(ctx, inc.into(), self.state.stack[table_ind + 2 + i])
                     .unwrap();
             } else {
                 break;
@@ -631,6 +1293,8 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         args: VarCount,
         returns: VarCount,
     ) -> Result<(), VMError> {
+        self.state.check_stack_overflow()?;
+
         let Some(Frame::Lua {
             expected_return,
             is_variable,
@@ -674,6 +1338,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 self.state.stack.resize(base + stack_size, Value::Nil);
 
                 self.state.frames.push(Frame::Lua {
+                    closure,
                     bottom: function_index,
                     base,
                     is_variable: false,
@@ -704,6 +1369,8 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         arg_count: u8,
         returns: VarCount,
     ) -> Result<(), VMError> {
+        self.state.check_stack_overflow()?;
+
         let Some(Frame::Lua {
             expected_return,
             is_variable,
@@ -750,6 +1417,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 self.state.stack.resize(base + stack_size, Value::Nil);
 
                 self.state.frames.push(Frame::Lua {
+                    closure,
                     bottom: top,
                     base,
                     is_variable: false,
@@ -783,6 +1451,8 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         args: &[Value<'gc>],
         meta_ret: MetaReturn,
     ) -> Result<(), VMError> {
+        self.state.check_stack_overflow()?;
+
         let Some(Frame::Lua {
             expected_return,
             is_variable,
@@ -825,6 +1495,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 self.state.stack.resize(base + stack_size, Value::Nil);
 
                 self.state.frames.push(Frame::Lua {
+                    closure,
                     bottom: top,
                     base,
                     is_variable: false,
@@ -852,12 +1523,12 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         func: RegisterIndex,
         args: VarCount,
     ) -> Result<(), VMError> {
-        let Some(Frame::Lua {
+        let Some(&Frame::Lua {
             bottom,
             base,
             is_variable,
             ..
-        }) = self.state.frames.pop()
+        }) = self.state.frames.last()
         else {
             panic!("top frame is not lua frame");
         };
@@ -866,9 +1537,13 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
             return Err(VMError::ExpectedVariableStack(args.is_variable()));
         }
 
+        let function_index = base + func.0 as usize;
+        // The calling frame must not be popped until the function is known to be callable, so that
+        // an error is raised from the calling frame.
+        let function = meta_ops::call(ctx, self.state.stack[function_index])?;
+        self.state.frames.pop();
         self.state.close_upvalues(&ctx, bottom);
 
-        let function_index = base + func.0 as usize;
         let arg_count = args
             .to_constant()
             .map(|c| c as usize)
@@ -878,7 +1553,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         self.fuel
             .consume(count_fuel(Self::FUEL_PER_ITEM, arg_count));
 
-        match meta_ops::call(ctx, self.state.stack[function_index])? {
+        match function {
             Function::Closure(closure) => {
                 self.state.stack[bottom] = closure.into();
                 for i in 0..arg_count {
@@ -900,6 +1575,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 self.state.stack.resize(base + stack_size, Value::Nil);
 
                 self.state.frames.push(Frame::Lua {
+                    closure,
                     bottom,
                     base,
                     is_variable: false,
@@ -988,6 +1664,9 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                         *is_variable = false;
                     }
                 }
+                Some(LuaReturn::Meta(MetaReturn::Close)) => {
+                    self.state.stack.truncate(bottom);
+                }
                 Some(LuaReturn::Meta(meta_ret)) => {
                     let meta_val = if count > 0 {
                         self.state.stack[start]
@@ -999,14 +1678,20 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
                     match meta_ret {
                         MetaReturn::None => {}
+                        MetaReturn::Hook => {
+                            if let Some(hook) = &mut self.state.hook {
+                                hook.running = false;
+                            }
+                        }
                         MetaReturn::Register(reg) => {
                             self.state.stack[*base + reg.0 as usize] = meta_val;
                         }
                         MetaReturn::SkipIf(skip_if) => {
-                            if meta_val.to_bool() == skip_if {
+                            if meta_val.is_truthy() == skip_if {
                                 *pc += 1;
                             }
                         }
+                        MetaReturn::Close => unreachable!(),
                     }
                 }
                 None => {
@@ -1129,6 +1814,168 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
     }
 }
 
+/// Information about an active call frame in a thread.
+#[derive(Debug, Copy, Clone)]
+pub enum FrameInfo<'gc> {
+    /// A frame executing Lua code.
+    Lua {
+        closure: Closure<'gc>,
+        current_line: LineNumber,
+    },
+    /// A frame executing a Rust callback or sequence, along with the callback that is running (or
+    /// that returned the sequence), if it is known.
+    Callback { callback: Option<Callback<'gc>> },
+}
+
+// The index of the instruction a Lua frame is currently executing.
+fn current_pc(pc: usize, expected_return: Option<LuaReturn>) -> usize {
+    if matches!(expected_return, Some(LuaReturn::Meta(MetaReturn::Hook))) {
+        // Hooks are called before the instruction at the PC is run.
+        pc
+    } else {
+        // Otherwise, the PC of a Lua frame points at the instruction after the current one.
+        pc.saturating_sub(1)
+    }
+}
+
+// Iterate over the active call frames of a thread, most recent call first.
+fn call_frames<'gc, 'a>(frames: &'a [Frame<'gc>]) -> impl Iterator<Item = &'a Frame<'gc>> + 'a {
+    frames.iter().rev().filter(|frame| {
+        matches!(
+            frame,
+            Frame::Lua { .. } | Frame::Callback { .. } | Frame::Sequence { .. }
+        )
+    })
+}
+
+// Iterate over information about the active call frames of a thread, most recent call first.
+pub(super) fn frame_infos<'gc, 'a>(
+    frames: &'a [Frame<'gc>],
+) -> impl Iterator<Item = FrameInfo<'gc>> + 'a {
+    call_frames(frames).map(|frame| match *frame {
+        Frame::Lua {
+            closure,
+            pc,
+            expected_return,
+            ..
+        } => FrameInfo::Lua {
+            closure,
+            current_line: closure
+                .prototype()
+                .line_number(current_pc(pc, expected_return)),
+        },
+        Frame::Callback { callback, .. } => FrameInfo::Callback {
+            callback: Some(callback),
+        },
+        Frame::Sequence { callback, .. } => FrameInfo::Callback { callback },
+        _ => FrameInfo::Callback { callback: None },
+    })
+}
+
+/// Find the name that a callback is registered under, either as a global variable such as `print`,
+/// or as a field of a global library table such as `table.sort`. Global variables are preferred.
+pub(crate) fn callback_name<'gc>(ctx: Context<'gc>, callback: Callback<'gc>) -> Option<StdString> {
+    let is_callback =
+        |value| matches!(value, Value::Function(Function::Callback(c)) if c == callback);
+    let mut field = None;
+    for (key, value) in ctx.globals() {
+        let Value::String(key) = key else {
+            continue;
+        };
+        if is_callback(value) {
+            return Some(key.to_str_lossy().into_owned());
+        }
+        if let (None, Value::Table(library)) = (&field, value) {
+            field = library.iter().find_map(|(name, value)| match name {
+                Value::String(name) if is_callback(value) => {
+                    Some(format!("{}.{}", key.to_str_lossy(), name.to_str_lossy()))
+                }
+                _ => None,
+            });
+        }
+    }
+    field
+}
+
+// Find the name and absolute stack index of the `n`th local variable in scope in the Lua frame at
+// the given level.
+pub(super) fn frame_local<'gc>(
+    frames: &[Frame<'gc>],
+    level: usize,
+    n: usize,
+) -> Option<(String<'gc>, usize)> {
+    match *call_frames(frames).nth(level.wrapping_sub(1))? {
+        Frame::Lua {
+            closure,
+            base,
+            pc,
+            expected_return,
+            ..
+        } => {
+            let proto = closure.prototype();
+            let local = proto.local_variable(current_pc(pc, expected_return), n)?;
+            Some((local.name, base + local.register.0 as usize))
+        }
+        _ => None,
+    }
+}
+
+// Format a traceback of the given frames, most recent call first, starting at `level` (where level
+// 1 is the topmost call frame).
+pub(super) fn traceback<'gc>(ctx: Context<'gc>, frames: &[Frame<'gc>], level: usize) -> StdString {
+    const LEVELS_START: usize = 10;
+    const LEVELS_END: usize = 11;
+
+    let lines = frame_infos(frames)
+        .map(|info| match info {
+            FrameInfo::Lua {
+                closure,
+                current_line,
+            } => {
+                let proto = closure.prototype();
+                let src = proto.short_src();
+                match &proto.reference {
+                    FunctionRef::Named(name, _) => format!(
+                        "{src}:{current_line}: in function '{}'",
+                        name.to_str_lossy()
+                    ),
+                    FunctionRef::Expression(line) => {
+                        format!("{src}:{current_line}: in function <{src}:{line}>")
+                    }
+                    FunctionRef::Chunk => format!("{src}:{current_line}: in main chunk"),
+                }
+            }
+            FrameInfo::Callback { callback } => {
+                match callback.and_then(|c| callback_name(ctx, c)) {
+                    Some(name) => format!("[C]: in function '{name}'"),
+                    None => "[C]: in ?".to_owned(),
+                }
+            }
+        })
+        .skip(level.saturating_sub(1))
+        .collect::<Vec<_>>();
+
+    let mut traceback = StdString::from("stack traceback:");
+    if lines.len() > LEVELS_START + LEVELS_END {
+        for line in &lines[..LEVELS_START] {
+            traceback.push_str("\n\t");
+            traceback.push_str(line);
+        }
+        let skipped = lines.len() - LEVELS_START - LEVELS_END;
+        traceback.push_str(&format!("\n\t...\t(skipping {skipped} levels)"));
+        for line in &lines[lines.len() - LEVELS_END..] {
+            traceback.push_str("\n\t");
+            traceback.push_str(line);
+        }
+    } else {
+        for line in &lines {
+            traceback.push_str("\n\t");
+            traceback.push_str(line);
+        }
+    }
+    traceback
+}
+
 fn count_fuel(per_item: i32, len: usize) -> i32 {
     i32::try_from(len)
         .unwrap_or(i32::MAX)
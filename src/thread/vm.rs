@@ -1,3 +1,5 @@
+use std::{cell::Cell, string::String as StdString};
+
 use allocator_api2::vec;
 use gc_arena::allocator_api::MetricsAlloc;
 use thiserror::Error;
@@ -9,7 +11,7 @@ use crate::{
     table::RawTable,
     thread::thread::MetaReturn,
     types::{RegisterIndex, UpValueDescriptor, VarCount},
-    Closure, Constant, Context, Function, RuntimeError, String, Table, Value,
+    Closure, Constant, Context, Function, RuntimeError, String, Table, TypeError, Value,
 };
 
 use super::{thread::LuaFrame, VMError};
@@ -28,6 +30,10 @@ pub enum BinaryOperatorError {
     FloorDivide,
     #[error("cannot modulo values")]
     Modulo,
+    #[error("attempt to perform 'n//0'")]
+    FloorDivideByZero,
+    #[error("attempt to perform 'n%0'")]
+    ModuloByZero,
     #[error("cannot exponentiate values")]
     Exponentiate,
     #[error("cannot negate value")]
@@ -50,6 +56,10 @@ pub enum BinaryOperatorError {
     LessEqual,
 }
 
+#[derive(Debug, Clone, Error)]
+#[error("variable '{0}' got a non-closable value")]
+pub struct NotClosableError(pub StdString);
+
 // Runs the VM for the given number of instructions or until the current LuaFrame may have been
 // changed.
 //
@@ -57,12 +67,20 @@ pub enum BinaryOperatorError {
 pub(super) fn run_vm<'gc>(
     ctx: Context<'gc>,
     mut lua_frame: LuaFrame<'gc, '_>,
-    max_instructions: u32,
+    mut max_instructions: u32,
 ) -> Result<u32, RuntimeError> {
     if max_instructions == 0 {
         return Ok(0);
     }
 
+    if lua_frame.state.hook.is_some() {
+        if lua_frame.run_hook(ctx)? {
+            return Ok(0);
+        }
+        // Run a single instruction at a time so that the hook sees every instruction.
+        max_instructions = 1;
+    }
+
     let current_function = lua_frame.closure();
     let current_prototype = current_function.prototype();
     let current_upvalues = current_function.upvalues();
@@ -80,6 +98,25 @@ pub(super) fn run_vm<'gc>(
         }
     }
 
+    // Index a table, using the inline cache slot of the current operation when the key is a
+    // constant. The slot is only used to find a raw value in the table, so the metatable is still
+    // consulted as normal when the raw value is nil.
+    fn index_cached<'gc>(
+        ctx: Context<'gc>,
+        table: Value<'gc>,
+        key: Value<'gc>,
+        is_constant: bool,
+        slot: &Cell<u32>,
+    ) -> Result<MetaResult<'gc, 2>, TypeError> {
+        if let (Value::Table(t), true) = (table, is_constant) {
+            let v = t.get_value_cached(key, slot);
+            if !v.is_nil() {
+                return Ok(MetaResult::Value(v));
+            }
+        }
+        meta_ops::index(ctx, table, key)
+    }
+
     loop {
         let op = current_prototype.opcodes[*registers.pc].decode();
         *registers.pc += 1;
@@ -106,9 +143,8 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::LoadNil { dest, count } => {
-                for i in dest.0..dest.0 + count {
-                    registers.stack_frame[i as usize] = Value::Nil;
-                }
+                let dest = dest.0 as usize;
+                registers.stack_frame[dest..dest + count as usize].fill(Value::Nil);
             }
 
             Operation::NewTable {
@@ -116,17 +152,18 @@ pub(super) fn run_vm<'gc>(
                 array_size,
                 map_size,
             } => {
-                let mut raw_table = RawTable::new(&ctx);
-                raw_table.reserve_array(array_size as usize);
-                raw_table.reserve_map(map_size as usize);
+                let raw_table =
+                    RawTable::with_capacity(&ctx, array_size as usize, map_size as usize);
                 let table = Table::from_parts(&ctx, raw_table, None);
                 registers.stack_frame[dest.0 as usize] = Value::Table(table);
             }
 
             Operation::GetTable { dest, table, key } => {
                 let table = registers.stack_frame[table.0 as usize];
+                let is_constant = matches!(key, RCIndex::Constant(_));
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                match meta_ops::index(ctx, table, key)? {
+                let slot = &current_prototype.inline_cache[*registers.pc - 1];
+                match index_cached(ctx, table, key, is_constant, slot)? {
                     MetaResult::Value(v) => {
                         registers.stack_frame[dest.0 as usize] = v;
                     }
@@ -158,9 +195,11 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::GetUpTable { dest, table, key } => {
-                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
+                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize].get());
+                let is_constant = matches!(key, RCIndex::Constant(_));
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                match meta_ops::index(ctx, table, key)? {
+                let slot = &current_prototype.inline_cache[*registers.pc - 1];
+                match index_cached(ctx, table, key, is_constant, slot)? {
                     MetaResult::Value(v) => {
                         registers.stack_frame[dest.0 as usize] = v;
                     }
@@ -177,7 +216,7 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetUpTable { table, key, value } => {
-                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
+                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize].get());
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
                 let value = get_rc(&registers.stack_frame, &current_prototype.constants, value);
                 if let Some(call) = meta_ops::new_index(ctx, table, key, value)? {
@@ -192,7 +231,7 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetList { base, count } => {
-                lua_frame.set_table_list(&ctx, base, count)?;
+                lua_frame.set_table_list(ctx, base, count)?;
                 registers = lua_frame.registers();
             }
 
@@ -211,7 +250,9 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Return { start, count } => {
-                lua_frame.return_upper(&ctx, start, count)?;
+                if !lua_frame.close_to_be_closed(ctx, RegisterIndex(0))? {
+                    lua_frame.return_upper(&ctx, start, count)?;
+                }
                 break;
             }
 
@@ -220,19 +261,41 @@ pub(super) fn run_vm<'gc>(
                 registers = lua_frame.registers();
             }
 
+            Operation::ToBeClosed { value } => {
+                let v = registers.stack_frame[value.0 as usize];
+                if v.is_truthy() {
+                    if meta_ops::close(ctx, v, Value::Nil).is_err() {
+                        let pc = *registers.pc - 1;
+                        let name = current_prototype
+                            .local_variables
+                            .iter()
+                            .find(|l| l.register == value && l.start_pc <= pc && pc < l.end_pc)
+                            .map(|l| l.name.to_str_lossy().into_owned())
+                            .unwrap_or_else(|| "?".to_owned());
+                        return Err(NotClosableError(name).into());
+                    }
+                    lua_frame.mark_to_be_closed(value);
+                    registers = lua_frame.registers();
+                }
+            }
+
             Operation::Jump {
                 offset,
                 close_upvalues,
             } => {
-                *registers.pc = add_offset(*registers.pc, offset);
                 if let Some(r) = close_upvalues.to_u8() {
                     registers.close_upvalues(&ctx, RegisterIndex(r));
+                    if lua_frame.close_to_be_closed(ctx, RegisterIndex(r))? {
+                        break;
+                    }
+                    registers = lua_frame.registers();
                 }
+                *registers.pc = add_offset(*registers.pc, offset);
             }
 
             Operation::Test { value, is_true } => {
                 let value = registers.stack_frame[value.0 as usize];
-                if value.to_bool() == is_true {
+                if value.is_truthy() == is_true {
                     *registers.pc += 1;
                 }
             }
@@ -243,7 +306,7 @@ pub(super) fn run_vm<'gc>(
                 is_true,
             } => {
                 let value = registers.stack_frame[value.0 as usize];
-                if value.to_bool() == is_true {
+                if value.is_truthy() == is_true {
                     *registers.pc += 1;
                 } else {
                     registers.stack_frame[dest.0 as usize] = value;
@@ -262,7 +325,7 @@ pub(super) fn run_vm<'gc>(
                             upvalues.push(registers.open_upvalue(&ctx, reg));
                         }
                         UpValueDescriptor::Outer(uvindex) => {
-                            upvalues.push(current_upvalues[uvindex.0 as usize]);
+                            upvalues.push(current_upvalues[uvindex.0 as usize].get());
                         }
                     }
                 }
@@ -273,54 +336,60 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::NumericForPrep { base, jump } => {
-                registers.stack_frame[base.0 as usize] = raw_ops::subtract(
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 2],
-                )
-                .ok_or(BinaryOperatorError::Subtract)?;
-                *registers.pc = add_offset(*registers.pc, jump);
+                let base = base.0 as usize;
+                match for_prep(
+                    registers.stack_frame[base],
+                    registers.stack_frame[base + 1],
+                    registers.stack_frame[base + 2],
+                )? {
+                    Some([index, limit, step]) => {
+                        registers.stack_frame[base] = index;
+                        registers.stack_frame[base + 1] = limit;
+                        registers.stack_frame[base + 2] = step;
+                        registers.stack_frame[base + 3] = index;
+                    }
+                    None => {
+                        // Skip the loop body and the `NumericForLoop` instruction.
+                        *registers.pc = add_offset(*registers.pc, jump) + 1;
+                    }
+                }
             }
 
             Operation::NumericForLoop { base, jump } => {
+                let base = base.0 as usize;
                 match (
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 1],
-                    registers.stack_frame[base.0 as usize + 2],
+                    registers.stack_frame[base],
+                    registers.stack_frame[base + 1],
+                    registers.stack_frame[base + 2],
                 ) {
-                    (Value::Integer(index), Value::Integer(limit), Value::Integer(step)) => {
-                        let index = index + step;
-                        registers.stack_frame[base.0 as usize] = Value::Integer(index);
-
-                        let past_end = if step < 0 {
-                            index < limit
-                        } else {
-                            limit < index
-                        };
-                        if !past_end {
+                    (Value::Integer(index), Value::Integer(count), Value::Integer(step)) => {
+                        // For integer loops, the limit register holds the number of remaining
+                        // iterations as an unsigned integer, so the index can never wrap past
+                        // the limit.
+                        let count = count as u64;
+                        if count > 0 {
+                            let index = index.wrapping_add(step);
+                            registers.stack_frame[base] = Value::Integer(index);
+                            registers.stack_frame[base + 1] = Value::Integer((count - 1) as i64);
+                            registers.stack_frame[base + 3] = Value::Integer(index);
                             *registers.pc = add_offset(*registers.pc, jump);
-                            registers.stack_frame[base.0 as usize + 3] = Value::Integer(index);
                         }
                     }
-                    (index, limit, step) => {
-                        if let (Some(index), Some(limit), Some(step)) =
-                            (index.to_number(), limit.to_number(), step.to_number())
-                        {
-                            let index = index + step;
-                            registers.stack_frame[base.0 as usize] = Value::Number(index);
-
-                            let past_end = if step < 0.0 {
-                                index < limit
-                            } else {
-                                limit < index
-                            };
-                            if !past_end {
-                                *registers.pc = add_offset(*registers.pc, jump);
-                                registers.stack_frame[base.0 as usize + 3] = Value::Number(index);
-                            }
+                    (Value::Number(index), Value::Number(limit), Value::Number(step)) => {
+                        let index = index + step;
+                        let in_range = if step > 0.0 {
+                            index <= limit
                         } else {
-                            return Err(BinaryOperatorError::Add.into());
+                            limit <= index
+                        };
+                        if in_range {
+                            registers.stack_frame[base] = Value::Number(index);
+                            registers.stack_frame[base + 3] = Value::Number(index);
+                            *registers.pc = add_offset(*registers.pc, jump);
                         }
                     }
+                    // Only possible in malformed bytecode loaded from a binary chunk.
+                    _ => return Err(VMError::BadForState.into()),
                 }
             }
 
@@ -330,7 +399,7 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::GenericForLoop { base, jump } => {
-                if registers.stack_frame[base.0 as usize + 1].to_bool() {
+                if registers.stack_frame[base.0 as usize + 1].is_truthy() {
                     registers.stack_frame[base.0 as usize] =
                         registers.stack_frame[base.0 as usize + 1];
                     *registers.pc = add_offset(*registers.pc, jump);
@@ -370,13 +439,13 @@ pub(super) fn run_vm<'gc>(
 
             Operation::GetUpValue { source, dest } => {
                 registers.stack_frame[dest.0 as usize] =
-                    registers.get_upvalue(&ctx, current_upvalues[source.0 as usize]);
+                    registers.get_upvalue(&ctx, current_upvalues[source.0 as usize].get());
             }
 
             Operation::SetUpValue { source, dest } => {
                 registers.set_upvalue(
                     &ctx,
-                    current_upvalues[dest.0 as usize],
+                    current_upvalues[dest.0 as usize].get(),
                     registers.stack_frame[source.0 as usize],
                 );
             }
@@ -407,7 +476,7 @@ pub(super) fn run_vm<'gc>(
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
                 match meta_ops::equal(ctx, left, right)? {
                     MetaResult::Value(v) => {
-                        if v.to_bool() == skip_if {
+                        if v.is_truthy() == skip_if {
                             *registers.pc += 1;
                         }
                     }
@@ -479,7 +548,7 @@ pub(super) fn run_vm<'gc>(
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
                 registers.stack_frame[dest.0 as usize] =
-                    raw_ops::subtract(left, right).ok_or(BinaryOperatorError::Add)?;
+                    raw_ops::subtract(left, right).ok_or(BinaryOperatorError::Subtract)?;
             }
 
             Operation::Mul { dest, left, right } => {
@@ -499,15 +568,27 @@ pub(super) fn run_vm<'gc>(
             Operation::IDiv { dest, left, right } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::floor_divide(left, right).ok_or(BinaryOperatorError::FloorDivide)?;
+                registers.stack_frame[dest.0 as usize] = raw_ops::floor_divide(left, right)
+                    .ok_or_else(|| {
+                        if is_integer_zero_division(left, right) {
+                            BinaryOperatorError::FloorDivideByZero
+                        } else {
+                            BinaryOperatorError::FloorDivide
+                        }
+                    })?;
             }
 
             Operation::Mod { dest, left, right } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
                 registers.stack_frame[dest.0 as usize] =
-                    raw_ops::modulo(left, right).ok_or(BinaryOperatorError::Modulo)?;
+                    raw_ops::modulo(left, right).ok_or_else(|| {
+                        if is_integer_zero_division(left, right) {
+                            BinaryOperatorError::ModuloByZero
+                        } else {
+                            BinaryOperatorError::Modulo
+                        }
+                    })?;
             }
 
             Operation::Pow { dest, left, right } => {
@@ -562,6 +643,109 @@ pub(super) fn run_vm<'gc>(
     Ok(instructions_run)
 }
 
+// Returns true if an integer `//` or `%` of these operands failed because the divisor is zero.
+fn is_integer_zero_division<'gc>(left: Value<'gc>, right: Value<'gc>) -> bool {
+    matches!(
+        (left.to_numeric(), right.to_numeric()),
+        (Some(Value::Integer(_)), Some(Value::Integer(0)))
+    )
+}
+
+// Prepares the internal state of a numeric for loop, returning `None` if the loop should not run
+// at all.
+//
+// If both the initial value and the step are integers, the loop is an integer loop and the
+// returned limit is replaced by the unsigned iteration count (stored as an `i64`). Otherwise, all
+// three values are converted to floats.
+fn for_prep<'gc>(
+    init: Value<'gc>,
+    limit: Value<'gc>,
+    step: Value<'gc>,
+) -> Result<Option<[Value<'gc>; 3]>, VMError> {
+    if let (Value::Integer(init), Value::Integer(step)) = (init, step) {
+        if step == 0 {
+            return Err(VMError::ForStepZero);
+        }
+
+        let Some(limit) = for_limit(init, limit, step)? else {
+            return Ok(None);
+        };
+
+        let count = if step > 0 {
+            (limit as u64).wrapping_sub(init as u64) / step as u64
+        } else {
+            // Avoid negating `i64::MIN`.
+            (init as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1)
+        };
+
+        Ok(Some([
+            Value::Integer(init),
+            Value::Integer(count as i64),
+            Value::Integer(step),
+        ]))
+    } else {
+        let init = init
+            .to_number()
+            .ok_or(VMError::BadForValue("initial value"))?;
+        let limit = limit.to_number().ok_or(VMError::BadForValue("limit"))?;
+        let step = step.to_number().ok_or(VMError::BadForValue("step"))?;
+        if step == 0.0 {
+            return Err(VMError::ForStepZero);
+        }
+
+        let runs = if step > 0.0 {
+            init <= limit
+        } else {
+            limit <= init
+        };
+        Ok(runs.then_some([
+            Value::Number(init),
+            Value::Number(limit),
+            Value::Number(step),
+        ]))
+    }
+}
+
+// Converts the limit of an integer for loop to an integer, clipping float limits that are out of
+// range. Returns `None` if the loop should not run at all.
+fn for_limit<'gc>(init: i64, limit: Value<'gc>, step: i64) -> Result<Option<i64>, VMError> {
+    let limit = match limit {
+        Value::Integer(limit) => limit,
+        limit => {
+            let limit = limit.to_number().ok_or(VMError::BadForValue("limit"))?;
+            // Round the limit towards the loop direction, so that e.g. `for i = 1, 2.5` runs
+            // exactly twice.
+            let limit = if step > 0 {
+                limit.floor()
+            } else {
+                limit.ceil()
+            };
+            if limit.is_nan() {
+                return Ok(None);
+            } else if limit >= -(i64::MIN as f64) {
+                if step < 0 {
+                    return Ok(None);
+                }
+                i64::MAX
+            } else if limit < i64::MIN as f64 {
+                if step > 0 {
+                    return Ok(None);
+                }
+                i64::MIN
+            } else {
+                limit as i64
+            }
+        }
+    };
+
+    let runs = if step > 0 {
+        init <= limit
+    } else {
+        limit <= init
+    };
+    Ok(runs.then_some(limit))
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
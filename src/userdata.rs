@@ -42,6 +42,24 @@ impl<'gc> Hash for UserData<'gc> {
     }
 }
 
+/// An opaque host pointer, the equivalent of "light userdata" in PUC-Rio Lua.
+///
+/// Unlike `UserData`, this holds no garbage collected data and has no metatable of its own. It
+/// compares and hashes by pointer identity, and the pointer is never dereferenced by Lua.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Collect)]
+#[collect(require_static)]
+pub struct LightUserData(pub *const ());
+
+impl LightUserData {
+    pub fn new<T>(ptr: *const T) -> Self {
+        LightUserData(ptr.cast())
+    }
+
+    pub fn as_ptr(self) -> *const () {
+        self.0
+    }
+}
+
 #[derive(Collect)]
 #[collect(require_static)]
 struct StaticRoot<R> {
@@ -2,9 +2,12 @@ use std::{f64, fmt, i64, io, string::String as StdString};
 
 use gc_arena::{Collect, Gc};
 
-use crate::{Callback, Closure, Constant, Function, String, Table, Thread, UserData};
+use crate::{
+    constant::float_to_integer, Callback, Closure, Constant, Function, LightUserData, String,
+    Table, Thread, UserData,
+};
 
-#[derive(Debug, Copy, Clone, Collect)]
+#[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub enum Value<'gc> {
     Nil,
@@ -16,6 +19,7 @@ pub enum Value<'gc> {
     Function(Function<'gc>),
     Thread(Thread<'gc>),
     UserData(UserData<'gc>),
+    LightUserData(LightUserData),
 }
 
 impl<'gc> Default for Value<'gc> {
@@ -34,26 +38,27 @@ impl<'gc> Value<'gc> {
             Value::Table(_) => "table",
             Value::Function(_) => "function",
             Value::Thread(_) => "thread",
-            Value::UserData(_) => "userdata",
+            Value::UserData(_) | Value::LightUserData(_) => "userdata",
         }
     }
 
+    /// Write the value the same way as the Lua `tostring` function would, without calling any
+    /// `__tostring` or `__name` metamethods.
+    ///
+    /// Values without a natural string representation are written as their type name followed by
+    /// their address, like `table: 0x55d0c1a0b2c0`.
     pub fn display<W: io::Write>(self, mut w: W) -> Result<(), io::Error> {
         match self {
             Value::Nil => write!(w, "nil"),
             Value::Boolean(b) => write!(w, "{}", b),
             Value::Integer(i) => write!(w, "{}", i),
-            Value::Number(f) => write!(w, "{}", f),
+            Value::Number(f) => w.write_all(display_float(f).as_bytes()),
             Value::String(s) => w.write_all(s.as_bytes()),
-            Value::Table(t) => write!(w, "<table {:p}>", Gc::as_ptr(t.into_inner())),
-            Value::Function(Function::Closure(c)) => {
-                write!(w, "<function {:p}>", Gc::as_ptr(c.into_inner()))
-            }
-            Value::Function(Function::Callback(c)) => {
-                write!(w, "<function {:p}>", Gc::as_ptr(c.into_inner()))
-            }
-            Value::Thread(t) => write!(w, "<thread {:p}>", Gc::as_ptr(t.into_inner())),
-            Value::UserData(u) => write!(w, "<userdata {:p}>", Gc::as_ptr(u.into_inner())),
+            Value::Table(t) => write!(w, "table: {:p}", Gc::as_ptr(t.into_inner())),
+            Value::Function(f) => write!(w, "function: {:p}", f.as_ptr()),
+            Value::Thread(t) => write!(w, "thread: {:p}", Gc::as_ptr(t.into_inner())),
+            Value::UserData(u) => write!(w, "userdata: {:p}", Gc::as_ptr(u.into_inner())),
+            Value::LightUserData(u) => write!(w, "userdata: {:p}", u.as_ptr()),
         }
     }
 
@@ -62,16 +67,26 @@ impl<'gc> Value<'gc> {
     }
 
     /// Lua `nil` and `false` are false, anything else is true.
+    pub fn is_truthy(self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// Equivalent to `Value::is_truthy`.
     pub fn to_bool(self) -> bool {
-        match self {
-            Value::Nil => false,
-            Value::Boolean(false) => false,
-            _ => true,
-        }
+        self.is_truthy()
     }
 
     pub fn not(self) -> Value<'gc> {
-        Value::Boolean(!self.to_bool())
+        Value::Boolean(!self.is_truthy())
+    }
+
+    /// Interprets Numbers, Integers, and Strings as an Integer or a Number value, if possible.
+    ///
+    /// Strings are coerced following Lua rules, see `Constant::to_numeric`.
+    pub fn to_numeric(self) -> Option<Value<'gc>> {
+        self.to_constant()
+            .and_then(|c| c.to_numeric())
+            .map(Value::from)
     }
 
     /// Interprets Numbers, Integers, and Strings as a Number, if possible.
@@ -96,6 +111,81 @@ impl<'gc> Value<'gc> {
     }
 }
 
+/// Format a float the same way as C's `printf("%.14g", n)`, which is how PUC-Rio Lua writes floats
+/// with `io.write`.
+pub fn format_float(n: f64) -> StdString {
+    const PRECISION: i32 = 14;
+
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_owned();
+    } else if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_owned();
+    } else if n == 0.0 {
+        return if n.is_sign_negative() { "-0" } else { "0" }.to_owned();
+    }
+
+    // Determine the decimal exponent after rounding to the requested precision.
+    let sci = format!("{:.*e}", (PRECISION - 1) as usize, n);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+
+    fn trim_zeros(s: &str) -> &str {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            s
+        }
+    }
+
+    if !(-4..PRECISION).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exp.abs())
+    } else {
+        let fixed = format!("{:.*}", (PRECISION - 1 - exp) as usize, n);
+        trim_zeros(&fixed).to_owned()
+    }
+}
+
+/// Convert a float with no fractional part (such as the result of `f64::floor`) into an Integer if
+/// it is within the range of an `i64`, and otherwise leave it as a Number.
+pub fn float_to_lua<'gc>(n: f64) -> Value<'gc> {
+    match float_to_integer(n) {
+        Some(i) => Value::Integer(i),
+        None => Value::Number(n),
+    }
+}
+
+/// Convert a float to a string the way Lua does for `tostring`, `print` and concatenation.
+///
+/// This is `format_float`, except that floats which would look like integers get a `.0` suffix so
+/// that they can be told apart from Integer values.
+pub fn display_float(n: f64) -> StdString {
+    let mut s = format_float(n);
+    if s.bytes().all(|c| c.is_ascii_digit() || c == b'-') {
+        s.push_str(".0");
+    }
+    s
+}
+
+// Unlike the derived implementation, this never prints the contents of tables or other objects
+// (which may be self-referential), only their addresses.
+impl<'gc> fmt::Debug for Value<'gc> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Nil => write!(fmt, "Nil"),
+            Value::Boolean(b) => fmt.debug_tuple("Boolean").field(&b).finish(),
+            Value::Integer(i) => fmt.debug_tuple("Integer").field(&i).finish(),
+            Value::Number(n) => fmt.debug_tuple("Number").field(&n).finish(),
+            Value::String(s) => fmt.debug_tuple("String").field(&s.to_str_lossy()).finish(),
+            Value::Table(t) => write!(fmt, "Table({:p})", Gc::as_ptr(t.into_inner())),
+            Value::Function(f) => write!(fmt, "Function({:p})", f.as_ptr()),
+            Value::Thread(t) => write!(fmt, "Thread({:p})", Gc::as_ptr(t.into_inner())),
+            Value::UserData(u) => write!(fmt, "UserData({:p})", Gc::as_ptr(u.into_inner())),
+            Value::LightUserData(u) => write!(fmt, "LightUserData({:p})", u.as_ptr()),
+        }
+    }
+}
+
 impl<'gc> fmt::Display for Value<'gc> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         let mut buf = Vec::new();
@@ -179,3 +269,40 @@ impl<'gc> From<UserData<'gc>> for Value<'gc> {
         Value::UserData(v)
     }
 }
+
+impl<'gc> From<LightUserData> for Value<'gc> {
+    fn from(v: LightUserData) -> Value<'gc> {
+        Value::LightUserData(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(format_float(1.0), "1");
+        assert_eq!(format_float(-2.5), "-2.5");
+        assert_eq!(format_float(0.1), "0.1");
+        assert_eq!(format_float(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format_float(1e15), "1e+15");
+        assert_eq!(format_float(12345678901234567.0), "1.2345678901235e+16");
+        assert_eq!(format_float(1e-5), "1e-05");
+        assert_eq!(format_float(0.0001), "0.0001");
+        assert_eq!(format_float(f64::INFINITY), "inf");
+        assert_eq!(format_float(-0.0), "-0");
+    }
+
+    #[test]
+    fn test_display_float() {
+        assert_eq!(display_float(1.0), "1.0");
+        assert_eq!(display_float(-3.0), "-3.0");
+        assert_eq!(display_float(-0.0), "-0.0");
+        assert_eq!(display_float(2.5), "2.5");
+        assert_eq!(display_float(1e15), "1e+15");
+        assert_eq!(display_float(1e100), "1e+100");
+        assert_eq!(display_float(f64::NEG_INFINITY), "-inf");
+        assert_eq!(display_float(f64::NAN), "nan");
+    }
+}
@@ -1,7 +1,16 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{self, Poll},
+};
+
 use gc_arena::Collect;
 use piccolo::{
-    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor, Function,
-    IntoValue, Lua, Sequence, SequencePoll, Stack, StaticError, String, Thread, Value,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor,
+    ExecutorMode, Fuel, Function, IntoValue, Lua, Sequence, SequencePoll, Stack, StaticError,
+    String, Thread, Value, Variadic,
 };
 
 #[test]
@@ -291,3 +300,374 @@ fn resume_with_err() {
         },
     );
 }
+
+#[test]
+fn typed_callback() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn_typed(&ctx, |_, (a, b, c): (i64, f64, Option<bool>)| {
+            let sum = a as f64 + b;
+            Ok((sum, c.unwrap_or(false)))
+        });
+        ctx.set_global("callback", callback)?;
+        let sum = Callback::from_fn_typed(&ctx, |_, (a, rest): (i64, Variadic<Vec<i64>>)| {
+            Ok(a + rest.iter().sum::<i64>())
+        });
+        ctx.set_global("sum", sum)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local a, b = callback(1, 2.5, true)
+                assert(a == 3.5 and b == true)
+                local c, d = callback(2, 3)
+                assert(c == 5 and d == false)
+
+                local ok, err = pcall(callback, 1, {}, true)
+                assert(not ok and tostring(err) == "bad argument #2 (number expected, got table)")
+                local ok, err = pcall(callback, 1, 2, 3)
+                assert(not ok and tostring(err) == "bad argument #3 (boolean expected, got number)")
+                local ok, err = pcall(callback)
+                assert(not ok and tostring(err) == "bad argument #1 (integer expected, got nil)")
+
+                assert(sum(1, 2, 3) == 6)
+                local ok, err = pcall(sum, 1, 2, {}, 4)
+                assert(not ok and tostring(err) == "bad argument #3 (integer expected, got table)")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn call_then_continue() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            #[derive(Collect)]
+            #[collect(require_static)]
+            struct Double;
+
+            impl<'gc> Sequence<'gc> for Double {
+                fn poll(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    let i: i64 = stack.consume(ctx)?;
+                    stack.replace(ctx, i * 2);
+                    Ok(SequencePoll::Return)
+                }
+            }
+
+            let function: Function = stack.from_front(ctx)?;
+            Ok(CallbackReturn::Call {
+                function,
+                then: Some(BoxSequence::new(&ctx, Double)),
+            })
+        });
+        ctx.set_global("callback", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function add(a, b)
+                    return a + b
+                end
+                return callback(add, 3, 4)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 14);
+    Ok(())
+}
+
+#[test]
+fn resume_then_continue() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            #[derive(Collect)]
+            #[collect(require_static)]
+            struct Increment;
+
+            impl<'gc> Sequence<'gc> for Increment {
+                fn poll(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    let i: i64 = stack.consume(ctx)?;
+                    stack.replace(ctx, i + 1);
+                    Ok(SequencePoll::Return)
+                }
+            }
+
+            let (thread, then): (Thread, bool) = stack.consume(ctx)?;
+            Ok(CallbackReturn::Resume {
+                thread,
+                then: then.then(|| BoxSequence::new(&ctx, Increment)),
+            })
+        });
+        ctx.set_global("callback", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local co = coroutine.create(function()
+                    coroutine.yield(1)
+                    return 10
+                end)
+                assert(callback(co, false) == 1)
+                assert(callback(co, true) == 11)
+                assert(coroutine.status(co) == "dead")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn future_sequence() -> Result<(), StaticError> {
+    // A future which stays pending until the host fills in its value.
+    struct Manual(Rc<Cell<Option<i64>>>);
+
+    impl Future for Manual {
+        type Output = Result<i64, StaticError>;
+
+        fn poll(self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<Self::Output> {
+            match self.0.get() {
+                Some(i) => Poll::Ready(Ok(i)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    let mut lua = Lua::core();
+    let slot = Rc::new(Cell::new(None));
+
+    let executor = lua.try_enter(|ctx| {
+        let slot = slot.clone();
+        let callback = Callback::from_fn(&ctx, move |ctx, _, _| {
+            Ok(CallbackReturn::Sequence(BoxSequence::from_future(
+                &ctx,
+                Manual(slot.clone()),
+            )))
+        });
+        ctx.set_global("await_value", callback)?;
+
+        let closure = Closure::load(ctx, None, &b"return await_value() + 1"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    // While the future is pending, every step is interrupted without finishing.
+    for _ in 0..3 {
+        let finished = lua.enter(|ctx| {
+            let executor = ctx.fetch(&executor);
+            executor.step(ctx, &mut Fuel::with(1024));
+            executor.mode() != ExecutorMode::Normal
+        });
+        assert!(!finished);
+    }
+
+    slot.set(Some(41));
+    assert_eq!(lua.execute::<i64>(&executor)?, 42);
+    Ok(())
+}
+
+#[test]
+fn sequence_combinators() -> Result<(), StaticError> {
+    // Calls the function at the bottom of the stack with the rest of the stack as arguments.
+    #[derive(Collect)]
+    #[collect(require_static)]
+    struct CallFirst;
+
+    impl<'gc> Sequence<'gc> for CallFirst {
+        fn poll(
+            &mut self,
+            ctx: Context<'gc>,
+            _exec: Execution<'gc, '_>,
+            mut stack: Stack<'gc, '_>,
+        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+            let function: Function = stack.from_front(ctx)?;
+            Ok(SequencePoll::Call {
+                function,
+                is_tail: true,
+            })
+        }
+    }
+
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let call_and_double = Callback::from_fn(&ctx, |ctx, _, _| {
+            let sequence = BoxSequence::new(&ctx, CallFirst).map(&ctx, |ctx, mut stack| {
+                let i: i64 = stack.consume(ctx)?;
+                stack.replace(ctx, i * 2);
+                Ok(())
+            });
+            Ok(CallbackReturn::Sequence(sequence))
+        });
+        ctx.set_global("call_and_double", call_and_double)?;
+
+        let chained = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (f, g): (Function, Function) = stack.consume(ctx)?;
+            stack.replace(ctx, (f, 1));
+            let sequence = BoxSequence::new(&ctx, CallFirst)
+                .and_then(&ctx, |ctx, mut stack| {
+                    let i: i64 = stack.consume(ctx)?;
+                    stack.replace(ctx, (ctx.get_global("add_one"), i + 1));
+                    Ok(BoxSequence::new(&ctx, CallFirst))
+                })
+                .then_call(&ctx, g);
+            Ok(CallbackReturn::Sequence(sequence))
+        });
+        ctx.set_global("chained", chained)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                function add_one(i) return i + 1 end
+                local function ten_times(i) return i * 10 end
+
+                assert(call_and_double(add_one, 20) == 42)
+                assert(chained(add_one, ten_times) == 40)
+
+                local ok, err = pcall(call_and_double, error, "boom")
+                assert(not ok and err == "boom")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute(&executor)
+}
+
+#[test]
+fn yield_receives_resume_values() -> Result<(), StaticError> {
+    #[derive(Collect)]
+    #[collect(require_static)]
+    struct Received;
+
+    impl<'gc> Sequence<'gc> for Received {
+        fn poll(
+            &mut self,
+            ctx: Context<'gc>,
+            _exec: Execution<'gc, '_>,
+            mut stack: Stack<'gc, '_>,
+        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+            let reply: String = stack.consume(ctx)?;
+            stack.replace(ctx, format!("received {}", reply.to_str_lossy()));
+            Ok(SequencePoll::Return)
+        }
+    }
+
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let ask = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            stack.replace(ctx, "ping");
+            Ok(CallbackReturn::Yield {
+                to_thread: None,
+                then: Some(BoxSequence::new(&ctx, Received)),
+            })
+        });
+        ctx.set_global("ask", ask)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local co = coroutine.create(function()
+                    return ask()
+                end)
+
+                local ok, question = coroutine.resume(co)
+                assert(ok and question == "ping")
+                local ok, answer = coroutine.resume(co, "pong")
+                assert(ok and answer == "received pong")
+                assert(coroutine.status(co) == "dead")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute(&executor)
+}
+
+#[test]
+fn catch_callback_panics() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |_, _, _| panic!("deliberate panic"));
+        ctx.set_global("panicking", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local ok, err = pcall(panicking, 1, 2)
+                assert(not ok and err == "internal error: deliberate panic")
+                return "still running"
+            "#[..],
+        )?;
+
+        let executor = Executor::start(ctx, closure.into(), ());
+        assert!(!executor.catches_panics());
+        executor.set_catch_panics(&ctx, true);
+        assert!(executor.catches_panics());
+        Ok(ctx.stash(executor))
+    })?;
+
+    assert_eq!(
+        lua.execute::<std::string::String>(&executor)?,
+        "still running"
+    );
+
+    // Without opting in, the panic unwinds out of the executor as normal.
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"pcall(panicking)"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = lua.execute::<()>(&executor);
+    }));
+    assert!(result.is_err());
+
+    Ok(())
+}
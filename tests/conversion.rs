@@ -57,3 +57,30 @@ fn test_conversions() {
         assert_eq!((a, b, c), (2, false, "goodbye".to_owned()));
     });
 }
+
+#[test]
+fn test_coercions() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert!(!Value::Nil.is_truthy());
+        assert!(!Value::Boolean(false).is_truthy());
+        assert!(Value::Integer(0).is_truthy());
+        assert!("".into_value(ctx).is_truthy());
+
+        assert!(matches!(
+            "0x10".into_value(ctx).to_numeric(),
+            Some(Value::Integer(16))
+        ));
+        assert!(matches!(
+            "  3.5  ".into_value(ctx).to_numeric(),
+            Some(Value::Number(n)) if n == 3.5
+        ));
+        assert!("hello".into_value(ctx).to_numeric().is_none());
+        assert!(Value::Boolean(true).to_numeric().is_none());
+
+        assert_eq!("0x10".into_value(ctx).to_integer(), Some(16));
+        assert_eq!("  3.5  ".into_value(ctx).to_integer(), None);
+        assert_eq!(" 4.0 ".into_value(ctx).to_integer(), Some(4));
+        assert_eq!("hello".into_value(ctx).to_integer(), None);
+    });
+}
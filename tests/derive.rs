@@ -0,0 +1,111 @@
+#![cfg(feature = "derive")]
+
+use piccolo::{Closure, Executor, FromValue, IntoValue, Lua, StaticError, Table, TypeError, Value};
+
+#[derive(Debug, Clone, PartialEq, IntoValue, FromValue)]
+struct Config {
+    name: String,
+    #[lua(rename = "max-size")]
+    max_size: i64,
+    ratio: Option<f64>,
+    #[lua(default)]
+    tags: Vec<String>,
+}
+
+#[derive(IntoValue, FromValue)]
+struct WithTable<'gc> {
+    table: Table<'gc>,
+    count: i64,
+}
+
+#[test]
+fn derive_round_trip() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let config = Config {
+        name: "piccolo".to_owned(),
+        max_size: 64,
+        ratio: None,
+        tags: vec!["a".to_owned(), "b".to_owned()],
+    };
+
+    lua.try_enter(|ctx| {
+        ctx.set_global("config", config.clone())?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(config.name == "piccolo")
+                assert(config["max-size"] == 64 and config.max_size == nil)
+                assert(config.ratio == nil)
+                assert(#config.tags == 2 and config.tags[2] == "b")
+                return {name = "other", ["max-size"] = 1, ratio = 0.5}
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let other = lua.execute::<Config>(&executor)?;
+    assert_eq!(
+        other,
+        Config {
+            name: "other".to_owned(),
+            max_size: 1,
+            ratio: Some(0.5),
+            tags: Vec::new(),
+        }
+    );
+
+    lua.enter(|ctx| {
+        let value = config.clone().into_value(ctx);
+        assert_eq!(Config::from_value(ctx, value).unwrap(), config);
+
+        let inner = Table::new(&ctx);
+        let value = WithTable {
+            table: inner,
+            count: 3,
+        }
+        .into_value(ctx);
+        let with_table = WithTable::from_value(ctx, value).unwrap();
+        assert_eq!(with_table.table, inner);
+        assert_eq!(with_table.count, 3);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn derive_errors() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, "name", "n").unwrap();
+        let err = Config::from_value(ctx, table.into()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "type error, expected field 'max-size', found nil"
+        );
+
+        table.set(ctx, "max-size", "big").unwrap();
+        let TypeError { expected, found } = Config::from_value(ctx, table.into()).unwrap_err();
+        assert_eq!((expected, found), ("field 'max-size'", "string"));
+
+        table.set(ctx, "max-size", 1).unwrap();
+        table.set(ctx, "tags", 1).unwrap();
+        let err = Config::from_value(ctx, table.into()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "type error, expected field 'tags', found number"
+        );
+        table.set(ctx, "tags", Value::Nil).unwrap();
+        assert!(Config::from_value(ctx, table.into()).is_ok());
+
+        let err = Config::from_value(ctx, Value::Integer(1)).unwrap_err();
+        assert_eq!(err.to_string(), "type error, expected table, found number");
+    });
+}
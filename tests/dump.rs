@@ -0,0 +1,147 @@
+use std::fs;
+
+use piccolo::{
+    dump, Closure, Executor, Fuel, FunctionPrototype, Lua, StaticError, Table, UndumpError,
+};
+
+#[test]
+fn dump_round_trip() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("round_trip"),
+            &br#"
+                local function fib(n)
+                    if n < 2 then return n end
+                    return fib(n - 1) + fib(n - 2)
+                end
+                local t = {}
+                for i = 1, 10 do
+                    t[#t + 1] = fib(i)
+                end
+                return t[10], #t, "done"
+            "#[..],
+        )?;
+        let chunk = closure.prototype().dump(false);
+        let loaded = Closure::load_binary(ctx, &chunk, ctx.globals())?;
+        assert_eq!(loaded.prototype().chunk_name.as_bytes(), b"round_trip");
+        Ok(ctx.stash(Executor::start(ctx, loaded.into(), ())))
+    })?;
+
+    let (a, b, c): (i64, i64, String) = lua.execute(&executor)?;
+    assert_eq!((a, b, c.as_str()), (55, 10, "done"));
+    Ok(())
+}
+
+#[test]
+fn undump_errors() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return 1"[..])?;
+        let chunk = closure.prototype().dump(true);
+        assert!(dump::is_binary_chunk(&chunk));
+
+        let mut bad_version = chunk.clone();
+        bad_version[dump::SIGNATURE.len()] = dump::FORMAT_VERSION + 1;
+        assert!(matches!(
+            FunctionPrototype::undump(ctx, &bad_version),
+            Err(UndumpError::VersionMismatch { found, expected })
+                if found == dump::FORMAT_VERSION + 1 && expected == dump::FORMAT_VERSION
+        ));
+
+        assert!(matches!(
+            FunctionPrototype::undump(ctx, &chunk[..chunk.len() - 1]),
+            Err(UndumpError::Truncated)
+        ));
+        assert!(matches!(
+            FunctionPrototype::undump(ctx, b"\x1bLuaT"),
+            Err(UndumpError::Truncated | UndumpError::BadSignature)
+        ));
+
+        let mut trailing = chunk.clone();
+        trailing.push(0);
+        assert!(matches!(
+            FunctionPrototype::undump(ctx, &trailing),
+            Err(UndumpError::Malformed(_))
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn undump_compiled_scripts() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    // Everything the compiler produces must pass the checks made when loading a binary chunk.
+    for entry in fs::read_dir("./tests/scripts").unwrap() {
+        let path = entry.unwrap().path();
+        lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, fs::File::open(&path).unwrap())?;
+            for strip in [false, true] {
+                let chunk = closure.prototype().dump(strip);
+                if let Err(err) = FunctionPrototype::undump(ctx, &chunk) {
+                    panic!("could not undump {:?}: {}", path, err);
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn undump_mutated_chunks() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| ctx.gc_control().set_memory_limit(Some(8 * 1024 * 1024)));
+
+    let chunk = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function sum(a, b, ...)
+                    local t = {a, b, ...}
+                    local s = 0
+                    for i = 1, #t do
+                        s = s + t[i]
+                    end
+                    return s, ...
+                end
+                local n = 0
+                for i = 1, 3 do
+                    n = n + sum(i, i * 2, i * 3)
+                end
+                return n
+            "#[..],
+        )?;
+        Ok(closure.prototype().dump(true))
+    })?;
+
+    // Malformed bytecode must either be rejected when loaded or raise an error when run, but it
+    // must never panic.
+    for i in 0..chunk.len() {
+        for byte in [0, 1, 0x7f, 0xff, chunk[i] ^ 1, chunk[i].wrapping_add(2)] {
+            let mut mutated = chunk.clone();
+            mutated[i] = byte;
+            let executor = lua.enter(|ctx| {
+                let closure = Closure::load_binary(ctx, &mutated, Table::new(&ctx)).ok()?;
+                Some(ctx.stash(Executor::start(ctx, closure.into(), ())))
+            });
+            if let Some(executor) = executor {
+                for _ in 0..100 {
+                    let finished =
+                        lua.enter(|ctx| ctx.fetch(&executor).step(ctx, &mut Fuel::with(1000)));
+                    if finished {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,6 +1,11 @@
 mod sizes;
 
-use piccolo::{error::LuaError, Callback, Closure, Error, Executor, Lua, StaticError, Value};
+use std::io;
+
+use piccolo::{
+    closure::short_src, error::LuaError, Callback, CallbackReturn, Closure, Error, Executor, Lua,
+    PrototypeError, StaticError, Thread, Value,
+};
 use thiserror::Error;
 
 #[test]
@@ -59,3 +64,384 @@ fn error_tostring() -> Result<(), StaticError> {
 
     lua.execute(&executor)
 }
+
+#[test]
+fn error_context() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    #[derive(Debug, Error)]
+    #[error("file not found")]
+    struct TestError;
+
+    let executor = lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, _, _| {
+            let err: Error = TestError.into();
+            Err(err.with_context(ctx.intern_static(b"could not load config")))
+        });
+        ctx.set_global("callback", callback)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local r, e = pcall(callback)
+                assert(not r)
+                assert(tostring(e) == "could not load config: file not found")
+                callback()
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor);
+    lua.try_enter(|ctx| {
+        match ctx.fetch(&executor).take_result::<()>(ctx)? {
+            Err(err @ Error::Context(_)) => {
+                let cause = err.cause().unwrap();
+                assert!(matches!(cause, Error::Runtime(e) if e.is::<TestError>()));
+                assert_eq!(
+                    err.to_string(),
+                    "could not load config: runtime error: file not found"
+                );
+            }
+            _ => panic!("wrong error returned"),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn error_from_std() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let as_string = Callback::from_fn(&ctx, |ctx, _, _| {
+            let err = io::Error::new(io::ErrorKind::NotFound, "no such file");
+            Err(Error::from_std(ctx, err))
+        });
+        ctx.set_global("as_string", as_string)?;
+
+        let with_question_mark = Callback::from_fn(&ctx, |_, _, _| {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such file"))?;
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("with_question_mark", with_question_mark)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local r, e = pcall(as_string)
+                assert(not r and e == "no such file")
+                local r, e = pcall(with_question_mark)
+                assert(not r and type(e) == "userdata" and tostring(e) == "no such file")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute(&executor)
+}
+
+#[test]
+fn error_downcast() -> Result<(), StaticError> {
+    #[derive(Debug, Error, PartialEq)]
+    #[error("custom error {0}")]
+    struct CustomError(i64);
+
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let custom = Callback::from_fn(&ctx, |_, _, _| {
+            Err(CustomError(42))?;
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("custom", custom)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local r, e = pcall(custom)
+                assert(not r and tostring(e) == "custom error 42")
+                error(e)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    // The error keeps its type after being caught and raised again by Lua code.
+    let err = lua.execute::<()>(&executor).unwrap_err();
+    assert!(err.is::<CustomError>());
+    assert!(!err.is::<io::Error>());
+    assert_eq!(err.downcast_ref::<CustomError>(), Some(&CustomError(42)));
+    assert!(err.downcast_ref::<io::Error>().is_none());
+    let err = err.downcast::<io::Error>().unwrap_err();
+    // Taking ownership requires that the Lua copy of the error has been collected.
+    drop(executor);
+    lua.gc_collect();
+    assert_eq!(err.downcast::<CustomError>().unwrap(), CustomError(42));
+
+    lua.enter(|ctx| {
+        let err = Error::from(CustomError(1)).with_context(ctx.intern(b"while testing"));
+        assert_eq!(err.downcast_ref::<CustomError>(), Some(&CustomError(1)));
+        assert_eq!(err.downcast::<CustomError>().unwrap(), CustomError(1));
+
+        let err = Error::from_value(ctx.intern(b"a plain lua error").into());
+        assert!(!err.is::<CustomError>());
+        assert!(err.downcast::<CustomError>().is_err());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn error_location() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local r, e = pcall(function()
+                    local f = nil
+                    f()
+                end)
+                assert(not r)
+
+                local r2, e2 = pcall(function() return {} + 1 end)
+                return tostring(e), tostring(e2)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (call_error, arith_error) = lua.execute::<(String, String)>(&executor)?;
+    assert!(call_error.starts_with("test:4: "), "{call_error}");
+    assert!(arith_error.starts_with("test:8: "), "{arith_error}");
+    Ok(())
+}
+
+#[test]
+fn tail_call_error() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    // Tail calling a value that is not callable raises the error from the calling frame.
+    let function = lua.load(Some("=test"), &b"local f = nil\nreturn f(1)"[..])?;
+    let error = lua.call::<()>(&function).unwrap_err().to_string();
+    assert!(error.contains("test:2: "), "{error}");
+    Ok(())
+}
+
+#[test]
+fn short_src_chunk_names() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    // Errors are prefixed with the short form of the chunk name for each of the three forms.
+    let error = |lua: &mut Lua, name: &str| {
+        let function = lua.load(Some(name), &b"\nlocal x = nil + 1"[..]).unwrap();
+        lua.call::<()>(&function).unwrap_err().to_string()
+    };
+    assert!(error(&mut lua, "@scripts/file.lua").contains(" scripts/file.lua:2: "));
+    assert!(error(&mut lua, "=name").contains(" name:2: "));
+    assert!(error(&mut lua, "x = 1").contains(" [string \"x = 1\"]:2: "));
+
+    // A string chunk passed to `load` is named after its source.
+    let function = lua.load(
+        None,
+        &br#"
+            local _, err = pcall(load("local x = nil + 1\nreturn x"))
+            return tostring(err)
+        "#[..],
+    )?;
+    assert_eq!(
+        lua.call::<String>(&function)?,
+        "[string \"local x = nil + 1...\"]:1: cannot add values"
+    );
+
+    // Tracebacks use the same form.
+    let function = lua.load(
+        Some("@file.lua"),
+        &b"local t = debug.traceback()\nreturn t"[..],
+    )?;
+    assert_eq!(
+        lua.call::<String>(&function)?,
+        "stack traceback:\n\tfile.lua:1: in main chunk"
+    );
+
+    // Compile errors from `load` are prefixed in the same way.
+    let function = lua.load(
+        None,
+        &br#"
+            local function error(...)
+                local _, err = load(...)
+                return err
+            end
+            return error("\nx = = 1", "@file.lua"), error("\nbreak", "=name"), error("break")
+        "#[..],
+    )?;
+    let (file, name, string) = lua.call::<(String, String, String)>(&function)?;
+    assert!(file.starts_with("file.lua:2: "), "{file}");
+    assert_eq!(name, "name:2: break outside a loop");
+    assert_eq!(string, "[string \"break\"]:1: break outside a loop");
+
+    // Long names are shortened.
+    let long = "x".repeat(100);
+    assert_eq!(short_src(format!("={long}").as_bytes()), "x".repeat(59));
+    assert_eq!(
+        short_src(format!("@{long}.lua").as_bytes()),
+        format!("...{}.lua", "x".repeat(52))
+    );
+    assert_eq!(
+        short_src(long.as_bytes()),
+        format!("[string \"{}...\"]", "x".repeat(45))
+    );
+    assert_eq!(
+        short_src("x".repeat(44).as_bytes()),
+        format!("[string \"{}\"]", "x".repeat(44))
+    );
+    Ok(())
+}
+
+#[test]
+fn for_loop_errors() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local _, zero = pcall(function() for i = 1, 10, 0 do end end)
+                local _, limit = pcall(function() for i = 1, {} do end end)
+                return tostring(zero), tostring(limit)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (zero, limit) = lua.execute::<(String, String)>(&executor)?;
+    assert_eq!(zero, "test:2: 'for' step is zero");
+    assert_eq!(limit, "test:3: 'for' limit must be a number");
+    Ok(())
+}
+
+#[test]
+fn to_be_closed_errors() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local _, e = pcall(function() local value <close> = {} end)
+                return tostring(e)
+            "#[..],
+        )?;
+
+        for source in ["local x <frozen> = 1", "local a <close>, b <close> = nil"] {
+            assert!(matches!(
+                Closure::load(ctx, None, source.as_bytes()),
+                Err(PrototypeError::Parser { .. })
+            ));
+        }
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let error = lua.execute::<String>(&executor)?;
+    assert_eq!(error, "test:2: variable 'value' got a non-closable value");
+    Ok(())
+}
+
+#[test]
+fn stack_overflow() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local function recurse(n)
+                    return 1 + recurse(n + 1)
+                end
+                local function depth(n, max)
+                    if n == max then
+                        return n
+                    end
+                    return 0 + depth(n + 1, max)
+                end
+
+                local r, e = pcall(recurse, 1)
+                assert(not r)
+                -- The thread is still usable after the overflow.
+                return tostring(e), depth(1, 50)
+            "#[..],
+        )?;
+
+        let thread = Thread::new(ctx);
+        assert_eq!(thread.max_frames()?, Thread::DEFAULT_MAX_FRAMES);
+        thread.set_max_frames(&ctx, 100)?;
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(Executor::run(&ctx, thread)))
+    })?;
+
+    let (error, depth) = lua.execute::<(String, i64)>(&executor)?;
+    assert_eq!(error, "test:3: stack overflow");
+    assert_eq!(depth, 50);
+    Ok(())
+}
+
+#[test]
+fn compile_errors() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let compile_error = |source: &str| match Closure::load(ctx, None, source.as_bytes()) {
+            Err(PrototypeError::Compiler { error, .. }) => error.kind.to_string(),
+            Err(err) => panic!("unexpected error {err}"),
+            Ok(_) => panic!("{source:?} should not compile"),
+        };
+
+        assert_eq!(
+            compile_error("goto missing"),
+            "no visible label 'missing' for goto"
+        );
+        assert_eq!(
+            compile_error("do ::inner:: end goto inner"),
+            "no visible label 'inner' for goto"
+        );
+        assert_eq!(
+            compile_error("goto later local y = 1 ::later:: print(y)"),
+            "<goto later> jumps into the scope of local 'y'"
+        );
+        assert_eq!(
+            compile_error("::dup:: ::dup::"),
+            "label 'dup' already defined"
+        );
+        assert_eq!(compile_error("break"), "break outside a loop");
+        assert_eq!(
+            compile_error("local x <const> = 1 x = 2"),
+            "attempt to assign to const variable 'x'"
+        );
+        assert_eq!(
+            compile_error("local x <const> = 1 local function f() x = 2 end"),
+            "attempt to assign to const variable 'x'"
+        );
+        assert_eq!(
+            compile_error("local y, x <close> = 1, nil x, y = 2, 3"),
+            "attempt to assign to const variable 'x'"
+        );
+
+        // Jumping to a trailing label over a local is allowed
+        assert!(Closure::load(ctx, None, &b"do goto e local x = 1 ::e:: end"[..]).is_ok());
+    });
+}
@@ -1,4 +1,6 @@
-use piccolo::{Callback, CallbackReturn, Closure, Executor, ExecutorMode, Fuel, Lua, StaticError};
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, ExecutorMode, Fuel, Lua, StaticError, StepResult,
+};
 
 #[test]
 fn test_interrupt() -> Result<(), StaticError> {
@@ -28,3 +30,91 @@ fn test_interrupt() -> Result<(), StaticError> {
 
     Ok(())
 }
+
+#[test]
+fn test_step_with_fuel() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        // Yielding from the main thread suspends the whole executor.
+        let suspend = Callback::from_fn(&ctx, |_, _, _| {
+            Ok(CallbackReturn::Yield {
+                to_thread: None,
+                then: None,
+            })
+        });
+        ctx.set_global("suspend", suspend)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local i = 0
+                while true do
+                    i = i + 1
+                    if i == 100000 then
+                        suspend(i)
+                    end
+                    if i == 200000 then
+                        return i
+                    end
+                end
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let mut out_of_fuel = 0;
+    loop {
+        let result = lua.enter(|ctx| ctx.fetch(&executor).step_with_fuel(ctx, 1000));
+        match result {
+            StepResult::OutOfFuel => out_of_fuel += 1,
+            StepResult::Yielded => break,
+            StepResult::Finished => panic!("finished before yielding"),
+        }
+    }
+    // The loop cannot run 100000 iterations without exhausting its fuel many times over.
+    assert!(out_of_fuel > 100);
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert_eq!(executor.take_result::<i64>(ctx).unwrap().unwrap(), 100000);
+        executor.resume(ctx, ()).unwrap();
+    });
+
+    loop {
+        let result = lua.enter(|ctx| ctx.fetch(&executor).step_with_fuel(ctx, 1000));
+        match result {
+            StepResult::OutOfFuel => {}
+            StepResult::Yielded => panic!("unexpected yield"),
+            StepResult::Finished => break,
+        }
+    }
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 200000);
+
+    Ok(())
+}
+
+#[test]
+fn test_fuel_instruction_boundary() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"while true do end"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        for _ in 0..100 {
+            let mut fuel = Fuel::with(10);
+            assert!(!executor.step(ctx, &mut fuel));
+            // The VM must not run far past the fuel it was given.
+            assert!(fuel.remaining() > -10);
+            assert_eq!(executor.mode(), ExecutorMode::Normal);
+        }
+    });
+
+    Ok(())
+}
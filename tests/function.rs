@@ -1,4 +1,7 @@
-use piccolo::{Callback, CallbackReturn, Executor, Function, Lua, StaticError, Variadic};
+use piccolo::{
+    compiler::LineNumber, opcode::Operation, Callback, CallbackReturn, Closure, Constant, Executor,
+    Function, Lua, StaticError, Table, Variadic,
+};
 
 #[test]
 fn function_compose_bind() -> Result<(), StaticError> {
@@ -42,3 +45,193 @@ fn function_compose_bind() -> Result<(), StaticError> {
     assert_eq!(lua.execute::<i64>(&executor)?, 33);
     Ok(())
 }
+
+#[test]
+fn function_from_fn() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let funcs = Table::new(&ctx);
+        funcs.set(
+            ctx,
+            "double",
+            Function::from_fn(&ctx, |ctx, _, mut stack| {
+                let i: i64 = stack.consume(ctx)?;
+                stack.replace(ctx, i * 2);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let counter = Table::new(&ctx);
+        funcs.set(
+            ctx,
+            "count",
+            Function::from_fn_with(&ctx, counter, |counter, ctx, _, mut stack| {
+                let n = counter.get(ctx, "n").to_integer().unwrap_or(0) + 1;
+                counter.set(ctx, "n", n)?;
+                stack.replace(ctx, n);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        ctx.set_global("funcs", funcs)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(type(funcs.double) == "function")
+                assert(funcs.count() == 1 and funcs.count() == 2)
+                return funcs.double(21)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 42);
+    Ok(())
+}
+
+#[test]
+fn prototype_introspection() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let chunk = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local up = 1
+                local function f(a, b)
+                    return up + a + b, "constant"
+                end
+                return f
+            "#[..],
+        )?;
+
+        let proto = chunk.prototype();
+        assert_eq!(proto.param_count(), 0);
+        assert!(proto.is_vararg());
+        assert_eq!(proto.line_defined(), None);
+        assert_eq!(proto.last_line_defined(), None);
+        assert_eq!(proto.prototypes().len(), 1);
+
+        let f = proto.prototypes()[0];
+        assert_eq!(f.param_count(), 2);
+        assert!(!f.is_vararg());
+        assert_eq!(f.upvalue_count(), 1);
+        assert_eq!(f.upvalue_names()[0].as_bytes(), b"up");
+        assert!(f
+            .constants()
+            .iter()
+            .any(|c| matches!(c, Constant::String(s) if s.as_bytes() == b"constant")));
+        assert_eq!(f.line_defined(), Some(LineNumber(2)));
+        assert_eq!(f.last_line_defined(), Some(LineNumber(4)));
+        Ok(())
+    })
+}
+
+#[test]
+fn constant_folding() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        // Compile `return <source>`, returning its operations and any constant loaded by its
+        // first operation.
+        let compile = |source: &str| {
+            let chunk = Closure::load(ctx, None, format!("return {source}").as_bytes()).unwrap();
+            let proto = chunk.prototype();
+            let ops: Vec<Operation> = proto.opcodes.iter().map(|op| op.decode()).collect();
+            let constant = match ops[0] {
+                Operation::LoadConstant { constant, .. } => {
+                    Some(proto.constants()[constant.0 as usize])
+                }
+                _ => None,
+            };
+            (ops, constant)
+        };
+
+        let folded = |source: &str, expected: Constant<&[u8]>| {
+            let (ops, constant) = compile(source);
+            // A single load followed by the return, and the implicit return at the end of the
+            // chunk.
+            assert_eq!(ops.len(), 3, "{source} was not folded: {ops:?}");
+            assert!(matches!(
+                ops[0],
+                Operation::LoadConstant { .. }
+                    | Operation::LoadBool { .. }
+                    | Operation::LoadNil { .. }
+            ));
+            if let Some(constant) = constant {
+                let constant = constant.map_string(|s| s.as_bytes());
+                assert!(
+                    constant.is_equal(&expected)
+                        && matches!(constant, Constant::Integer(_))
+                            == matches!(expected, Constant::Integer(_)),
+                    "{source} folded to {constant:?}"
+                );
+            }
+        };
+
+        folded("2 + 3", Constant::Integer(5));
+        folded("2 * 3.0", Constant::Number(6.0));
+        folded("7 // 2", Constant::Integer(3));
+        folded("7 / 2", Constant::Number(3.5));
+        folded("0x7fffffffffffffff + 1", Constant::Integer(i64::MIN));
+        folded("1 << 64", Constant::Integer(0));
+        folded("0xF0 | 0x0F", Constant::Integer(0xFF));
+        folded("'a' .. 'b' .. 1 .. 2.0", Constant::String(b"ab12.0"));
+        folded("1 < 2", Constant::Boolean(true));
+        folded("'a' >= 'b'", Constant::Boolean(false));
+        folded("1 ~= 1.0", Constant::Boolean(false));
+        folded("nil and x", Constant::Nil);
+        folded("false or 4", Constant::Integer(4));
+        folded("not 1", Constant::Boolean(false));
+
+        // Operations which raise an error at runtime are left to the VM.
+        for source in ["1 // 0", "1 % 0", "1 < '2'", "{} .. 'a'", "1 .. true"] {
+            let (ops, _) = compile(source);
+            assert!(
+                !matches!(
+                    ops[..],
+                    [Operation::LoadConstant { .. }, Operation::Return { .. }, _]
+                ),
+                "{source} was folded"
+            );
+        }
+    })
+}
+
+#[test]
+fn stack_size() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let stack_size = |source: &str| {
+            let chunk = Closure::load(ctx, None, source.as_bytes()).unwrap();
+            chunk.prototype().prototypes()[0].stack_size
+        };
+
+        // The parameter, and one register for each operand of the flattened concatenation.
+        let size = stack_size(
+            r#"
+            return function(a)
+                local x = (a .. a) .. (a .. (a .. a))
+                return x
+            end
+            "#,
+        );
+        assert!(size <= 6, "stack size is {size}");
+
+        let size = stack_size(
+            r#"
+            return function(a, b)
+                local s = a .. b .. (a .. b) .. a .. (b .. a .. b)
+                local n = #s + #a * (#b - #s) + #(a .. b) * #(b .. a)
+                return s, n
+            end
+            "#,
+        );
+        assert!(size <= 10, "stack size is {size}");
+    })
+}
@@ -0,0 +1,182 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use piccolo::{
+    stdlib::{load_base_with, load_file_functions_with, load_io_with},
+    Lua, StaticError,
+};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn io_write() -> Result<(), StaticError> {
+    let stdout = SharedBuffer::default();
+    let stderr = SharedBuffer::default();
+
+    let mut lua = Lua::core();
+    lua.enter(|ctx| load_io_with(ctx, stdout.clone(), stderr.clone()));
+
+    let function = lua.load(
+        None,
+        &br#"
+            assert(io.write("a", 1, " ", 2.5, " ", 1/3, " ", 2^63) == io.stdout)
+            io.stdout:write("b"):write("c")
+            assert(io.stderr:write("err") == io.stderr)
+            assert(not pcall(io.write, {}))
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+
+    assert_eq!(
+        stdout.0.borrow().as_slice(),
+        b"a1 2.5 0.33333333333333 9.2233720368548e+18bc"
+    );
+    assert_eq!(stderr.0.borrow().as_slice(), b"err");
+    Ok(())
+}
+
+#[test]
+fn print_output() -> Result<(), StaticError> {
+    let output = SharedBuffer::default();
+
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| load_base_with(ctx, output.clone()));
+
+    let function = lua.load(None, &b"print(1, 'x', true)"[..])?;
+    lua.call::<()>(&function)?;
+    assert_eq!(output.0.borrow().as_slice(), b"1\tx\ttrue\n");
+
+    output.0.borrow_mut().clear();
+    let function = lua.load(
+        None,
+        &br#"
+            local t = setmetatable({}, { __tostring = function() return "custom" end })
+            print(t)
+            print()
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+    assert_eq!(output.0.borrow().as_slice(), b"custom\n\n");
+    Ok(())
+}
+
+#[test]
+fn number_formatting() -> Result<(), StaticError> {
+    // Each pair is a number and its output from PUC-Rio Lua 5.4, first from `print` and then from
+    // `io.write`, which formats floats with "%.14g" without adding ".0".
+    const CASES: &[(&str, &str, &str)] = &[
+        ("1", "1", "1"),
+        (
+            "math.mininteger",
+            "-9223372036854775808",
+            "-9223372036854775808",
+        ),
+        ("100.0", "100.0", "100"),
+        ("-0.0", "-0.0", "-0"),
+        ("2^53", "9.007199254741e+15", "9.007199254741e+15"),
+        ("12345678901234.5", "12345678901234.0", "12345678901234"),
+        (
+            "123456789012345.0",
+            "1.2345678901234e+14",
+            "1.2345678901234e+14",
+        ),
+        ("1e-5", "1e-05", "1e-05"),
+        ("5e-324", "4.9406564584125e-324", "4.9406564584125e-324"),
+        (
+            "1.7976931348623157e308",
+            "1.7976931348623e+308",
+            "1.7976931348623e+308",
+        ),
+        ("math.huge", "inf", "inf"),
+        ("-math.huge", "-inf", "-inf"),
+        ("-(0/0)", "nan", "nan"),
+    ];
+
+    let output = SharedBuffer::default();
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        load_base_with(ctx, output.clone());
+        load_io_with(ctx, output.clone(), io::sink());
+    });
+
+    for &(number, printed, written) in CASES {
+        output.0.borrow_mut().clear();
+        let source = format!("print({number}) io.write({number}, '|', {number} .. '')");
+        let function = lua.load(None, source.as_bytes())?;
+        lua.call::<()>(&function)?;
+        assert_eq!(
+            String::from_utf8_lossy(&output.0.borrow()),
+            format!("{printed}\n{written}|{printed}"),
+            "formatting {number}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn loadfile_stdin() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.load_debug();
+    lua.enter(|ctx| {
+        load_file_functions_with(
+            ctx,
+            io::Cursor::new(&b"local a = ... return (a or 0) + 1"[..]),
+        )
+    });
+
+    let function = lua.load(
+        None,
+        &br#"
+            local chunk = assert(loadfile())
+            assert(chunk(41) == 42)
+            assert(debug.getinfo(chunk).source == "=stdin")
+
+            -- The input has been consumed, so the next chunk is empty.
+            assert(dofile() == nil)
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+    Ok(())
+}
+
+#[test]
+fn closed_file_finalizer() -> Result<(), StaticError> {
+    let path = std::env::temp_dir().join(format!("piccolo-closed-file-{}", std::process::id()));
+    std::fs::write(&path, b"contents").unwrap();
+
+    let mut lua = Lua::full();
+    lua.enter(|ctx| {
+        let path = ctx.intern(path.to_str().unwrap().as_bytes());
+        ctx.set_global("path", path).unwrap();
+    });
+
+    let function = lua.load(
+        None,
+        &br#"
+            local closed = assert(io.open(path))
+            closed:close()
+            local open = assert(io.open(path))
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+    std::fs::remove_file(&path).unwrap();
+
+    // Finalizing a file which was already closed is not an error.
+    lua.gc_collect();
+    assert!(lua.take_finalizer_errors().is_empty());
+    Ok(())
+}
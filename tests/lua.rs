@@ -0,0 +1,224 @@
+use piccolo::{Lua, StaticError, Table, Value};
+
+#[test]
+fn load_and_call() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let function = lua.load(Some("=test"), &b"return 1 + 2"[..])?;
+    assert_eq!(lua.call::<i64>(&function)?, 3);
+
+    // Functions can be called multiple times, and see changes to globals.
+    let function = lua.load(None, &b"counter = (counter or 0) + 1; return counter"[..])?;
+    assert_eq!(lua.call::<i64>(&function)?, 1);
+    assert_eq!(lua.call::<i64>(&function)?, 2);
+
+    assert!(lua.load(None, &b"return +"[..]).is_err());
+    Ok(())
+}
+
+#[test]
+fn global_table() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let function = lua.load(None, &b"x = 5; return _G.x"[..])?;
+    assert_eq!(lua.call::<i64>(&function)?, 5);
+    Ok(())
+}
+
+#[test]
+fn memory_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let limit = lua.total_memory() + 1024 * 1024;
+    lua.enter(|ctx| ctx.gc_control().set_memory_limit(Some(limit)));
+
+    // Garbage does not count against the limit, it is collected when the limit is reached.
+    let function = lua.load(
+        None,
+        &br#"
+            for i = 1, 100 do
+                local t = {}
+                for j = 1, 1000 do
+                    t[j] = j
+                end
+            end
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+
+    let function = lua.load(
+        None,
+        &br#"
+            local tables = {}
+            local ok, err = pcall(function()
+                while true do
+                    local t = {}
+                    for j = 1, 1000 do
+                        t[j] = j
+                    end
+                    tables[#tables + 1] = t
+                end
+            end)
+            assert(not ok)
+            tables = nil
+            return tostring(err)
+        "#[..],
+    )?;
+    assert_eq!(lua.call::<String>(&function)?, "not enough memory");
+    lua.gc_collect();
+    assert!(lua.total_memory() < limit);
+
+    lua.enter(|ctx| ctx.gc_control().set_memory_limit(None));
+    Ok(())
+}
+
+#[test]
+fn memory_limit_large_allocation() -> Result<(), StaticError> {
+    let path = std::env::temp_dir().join(format!("piccolo-memory-limit-{}", std::process::id()));
+    std::fs::write(&path, vec![b'x'; 2 * 1024 * 1024]).unwrap();
+
+    let mut lua = Lua::full();
+
+    let limit = lua.total_memory() + 1024 * 1024;
+    lua.enter(|ctx| {
+        ctx.gc_control().set_memory_limit(Some(limit));
+        let path = ctx.intern(path.to_str().unwrap().as_bytes());
+        ctx.set_global("path", path).unwrap();
+    });
+
+    // Buffers are checked against the limit before they are allocated, rather than after.
+    let function = lua.load(
+        None,
+        &br#"
+            local ok, err = pcall(string.rep, "x", 2^31 - 1)
+            assert(not ok and tostring(err) == "not enough memory")
+
+            local s = string.rep("x", 400 * 1024)
+            local ok, err = pcall(table.concat, {s, s, s})
+            assert(not ok and tostring(err) == "not enough memory")
+
+            local f = assert(io.open(path, "rb"))
+            local ok, err = pcall(f.read, f, "a")
+            assert(not ok and tostring(err) == "not enough memory")
+            local ok, err = pcall(f.read, f, 2^40)
+            assert(not ok and tostring(err) == "not enough memory")
+            assert(#f:read(1024) == 1024)
+            f:close()
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+    std::fs::remove_file(&path).unwrap();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        assert!(table.reserve(ctx, 1024 * 1024).is_err());
+        assert_eq!(table.array_capacity(), 0);
+        table.reserve(ctx, 16).unwrap();
+        assert_eq!(table.array_capacity(), 16);
+
+        ctx.gc_control().set_memory_limit(None);
+    });
+    Ok(())
+}
+
+#[test]
+fn memory_limit_while_unwinding() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| ctx.gc_control().set_memory_limit(Some(8 * 1024 * 1024)));
+
+    // The memory limit error is raised once, and not again while it unwinds and the memory is
+    // still in use.
+    let function = lua.load(
+        None,
+        &br#"
+            local ok, err = pcall(string.pack, "c" .. (1 << 30), "")
+            assert(not ok and tostring(err) == "not enough memory")
+            local ok, err = pcall(function() return table.unpack({}, 1, 1e8) end)
+            assert(not ok and tostring(err) == "not enough memory")
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+
+    lua.enter(|ctx| ctx.gc_control().set_memory_limit(None));
+    Ok(())
+}
+
+#[test]
+fn registry_survives_collection() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let stashed = lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, "key", "stashed").unwrap();
+
+        let registered = Table::new(&ctx);
+        registered.set(ctx, 1, "registered").unwrap();
+        ctx.set_registry("my_table", registered).unwrap();
+
+        ctx.stash(table)
+    });
+
+    // Make garbage, and then collect everything that is unreachable.
+    lua.enter(|ctx| {
+        for i in 0..1000 {
+            Table::new(&ctx).set(ctx, i, i).unwrap();
+        }
+    });
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        let table = ctx.fetch(&stashed);
+        assert!(matches!(table.get(ctx, "key"), Value::String(s) if s == "stashed"));
+
+        let Value::Table(registered) = ctx.get_registry("my_table") else {
+            panic!("registry entry missing");
+        };
+        assert!(matches!(registered.get(ctx, 1), Value::String(s) if s == "registered"));
+
+        // Registry keys are separate from globals.
+        assert!(ctx.get_global("my_table").is_nil());
+        ctx.set_registry("my_table", Value::Nil).unwrap();
+        assert!(ctx.get_registry("my_table").is_nil());
+    });
+    Ok(())
+}
+
+#[test]
+fn restricted_os() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.load_os(false);
+
+    let function = lua.load(
+        None,
+        &br#"
+            return os.execute == nil and os.exit == nil and os.remove == nil
+                and os.rename == nil and os.time ~= nil
+        "#[..],
+    )?;
+    assert!(lua.call::<bool>(&function)?);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn os_execute() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let function = lua.load(
+        None,
+        &br#"
+            assert(os.execute() == true)
+
+            local ok, reason, code = os.execute("exit 0")
+            assert(ok == true and reason == "exit" and code == 0)
+
+            local ok, reason, code = os.execute("exit 3")
+            assert(ok == nil and reason == "exit" and code == 3)
+
+            local ok, reason, code = os.execute("kill -9 $$")
+            assert(ok == nil and reason == "signal" and code == 9)
+        "#[..],
+    )?;
+    lua.call::<()>(&function)?;
+    Ok(())
+}
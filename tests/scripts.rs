@@ -25,8 +25,8 @@ fn test_scripts() {
 
                 if let Err(err) = lua
                     .try_enter(|ctx| {
-                        let closure =
-                            Closure::load(ctx, Some(path.to_string_lossy().as_ref()), file)?;
+                        let name = format!("@{}", path.to_string_lossy());
+                        let closure = Closure::load(ctx, Some(&name), file)?;
                         Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
                     })
                     .and_then(|executor| lua.execute::<()>(&executor))
@@ -63,3 +63,14 @@ assert(
     test6() and
     test7()
 )
+
+do
+    -- Shifts by 64 or more bits, and negative shifts, at runtime and when constant folded.
+    local one, n = 1, 64
+    assert(one << n == 0 and one >> n == 0 and 1 << 64 == 0)
+    assert(one << -1 == 0 and 8 >> -1 == 16 and 8 << -1 == 4)
+    assert(-1 >> 63 == 1 and (-1 >> 1) == math.maxinteger)
+    assert(("a" .. 1 .. 2.0) == "a12.0" and not (1 ~= 1.0))
+    assert((nil and error("unreachable")) == nil and (false or 3) == 3)
+    assert(select('#', nil and print()) == 1 and select('#', 1 and (function() end)()) == 1)
+end
@@ -0,0 +1,182 @@
+local log = {}
+
+local function closable(name)
+    return setmetatable({}, {
+        __close = function(self, err)
+            log[#log + 1] = name
+            if err ~= nil then
+                log[#log + 1] = "error"
+            end
+        end
+    })
+end
+
+local function check(...)
+    local expected = { ... }
+    assert(#log == #expected)
+    for i = 1, #expected do
+        assert(log[i] == expected[i])
+    end
+    log = {}
+end
+
+do
+    local a <close> = closable("a")
+    local b <close> = closable("b")
+    local c <close> = nil
+    local d <close> = false
+    assert(#log == 0)
+end
+check("b", "a")
+
+for i = 1, 3 do
+    local x <close> = closable(i)
+    if i == 2 then
+        break
+    end
+end
+check(1, 2)
+
+local i = 0
+while true do
+    local x <close> = closable("loop")
+    i = i + 1
+    if i == 2 then
+        goto done
+    end
+end
+::done::
+check("loop", "loop")
+
+local function returns()
+    local x <close> = closable("return")
+    local y = "value"
+    return y, x
+end
+local r1, r2 = returns()
+assert(r1 == "value" and getmetatable(r2) ~= nil)
+check("return")
+
+local function varargs(...)
+    local x <close> = closable("varargs")
+    return ...
+end
+local v1, v2, v3 = varargs(1, 2, 3)
+assert(v1 == 1 and v2 == 2 and v3 == 3)
+check("varargs")
+
+local function tailcall()
+    local x <close> = closable("tailcall")
+    return returns()
+end
+tailcall()
+check("return", "tailcall")
+
+local ok, err = pcall(function()
+    local a <close> = closable("a")
+    local b <close> = closable("b")
+    error("boom")
+end)
+assert(not ok and err == "boom")
+check("b", "error", "a", "error")
+
+local ok, err = pcall(function()
+    local a <close> = closable("a")
+    local b <close> = setmetatable({}, {
+        __close = function()
+            error("in close")
+        end
+    })
+end)
+assert(not ok and err == "in close")
+check("a", "error")
+
+local ok = pcall(function()
+    local x <close> = {}
+end)
+assert(not ok)
+
+local co = coroutine.create(function()
+    local x <close> = closable("coroutine")
+    coroutine.yield()
+end)
+coroutine.resume(co)
+check()
+coroutine.resume(co)
+check("coroutine")
+
+-- Closing a suspended coroutine closes its pending variables, the most recent first.
+local co = coroutine.create(function()
+    local a <close> = closable("a")
+    local function inner()
+        local b <close> = closable("b")
+        coroutine.yield()
+    end
+    inner()
+end)
+coroutine.resume(co)
+check()
+assert(coroutine.close(co) == true)
+check("b", "a")
+assert(coroutine.status(co) == "dead")
+
+-- A failing close handler is reported, and the remaining handlers still run with its error.
+local co = coroutine.create(function()
+    local a <close> = closable("a")
+    local b <close> = setmetatable({}, {
+        __close = function()
+            error("in close")
+        end
+    })
+    local c <close> = closable("c")
+    coroutine.yield()
+end)
+coroutine.resume(co)
+local ok, err = coroutine.close(co)
+assert(ok == false and err == "in close")
+check("c", "a", "error")
+
+-- The fourth value of a generic for is closed when the loop ends, however it ends.
+local function iterate(n, name)
+    local i = 0
+    return function()
+        i = i + 1
+        if i <= n then
+            return i
+        end
+    end, nil, nil, closable(name)
+end
+
+for i in iterate(3, "end") do
+    check()
+end
+check("end")
+
+for i in iterate(3, "break") do
+    if i == 2 then
+        break
+    end
+end
+check("break")
+
+local ok = pcall(function()
+    for i in iterate(3, "error") do
+        error("boom")
+    end
+end)
+assert(not ok)
+check("error", "error")
+
+local function returns_first()
+    for i in iterate(3, "return") do
+        return i
+    end
+end
+assert(returns_first() == 1)
+check("return")
+
+local ok, err = pcall(function()
+    for i in next, {}, nil, 42 do
+    end
+end)
+assert(not ok and string.find(tostring(err), "variable '(for state)' got a non-closable value", 1, true))
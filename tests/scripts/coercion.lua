@@ -0,0 +1,55 @@
+do
+    -- Integer-looking strings coerce to integers, others to floats.
+    assert("10" + 5 == 15 and math.type("10" + 5) == "integer")
+    assert("3.5" * 2 == 7.0 and math.type("3.5" * 2) == "float")
+    assert("10" // "3" == 3 and math.type("10" // "3") == "integer")
+    assert("1e1" - 0 == 10 and math.type("1e1" - 0) == "float")
+    assert("0x10" | 1 == 17)
+    assert(2 ^ "3" == 8.0)
+
+    local ok, err = pcall(function() return "abc" + 1 end)
+    assert(not ok and string.find(tostring(err), "cannot add values", 1, true))
+    local ok, err = pcall(function() return 1 - "1x" end)
+    assert(not ok and string.find(tostring(err), "cannot subtract values", 1, true))
+    assert(not pcall(function() return {} * 2 end))
+
+    -- Numbers coerce to strings in concatenation.
+    assert(1 .. 2 == "12")
+    assert(1.5 .. "" == "1.5")
+    assert("n" .. -3 == "n-3")
+end
+
+do
+    -- `tonumber` uses the same conversion as arithmetic.
+    assert(tonumber("10") == 10 and math.type(tonumber("10")) == "integer")
+    assert(tonumber(" 3.5 ") == 3.5)
+    assert(tonumber("0x1p4") == 16.0)
+    assert(tonumber(7) == 7 and tonumber(7.5) == 7.5)
+    assert(tonumber("abc") == nil and tonumber("") == nil)
+    assert(tonumber({}) == nil and tonumber(nil) == nil)
+    assert(not pcall(tonumber))
+
+    assert(tonumber("ff", 16) == 255)
+    assert(tonumber(" -zz ", 36) == -1295)
+    assert(tonumber("777", 8) == 511)
+    assert(tonumber("8", 8) == nil)
+    assert(tonumber("1.5", 10) == nil)
+    assert(tonumber(10, 16) == 16)
+    assert(not pcall(tonumber, "1", 1))
+    assert(not pcall(tonumber, "1", 37))
+end
+
+do
+    assert(0xff == 255 and math.type(0xff) == "integer")
+    assert(0x1p4 == 16.0 and math.type(0x1p4) == "float")
+    assert(0x.8 == 0.5 and 0xA.8p-1 == 5.25)
+    assert(0xffffffffffffffff == -1 and 0x7fffffffffffffff1 == -15)
+    assert(math.type(9223372036854775807) == "integer")
+    assert(math.type(9223372036854775808) == "float" and 9223372036854775808 == 2^63)
+    assert(tonumber("0xffffffffffffffff") == -1)
+
+    for _, source in ipairs({ "0x", "1e", "3..2", "12abc" }) do
+        local f, err = load("return " .. source)
+        assert(f == nil and string.find(err, "malformed number near '" .. source .. "'", 1, true))
+    end
+end
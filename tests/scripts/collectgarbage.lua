@@ -0,0 +1,34 @@
+do
+    local kb, bytes = collectgarbage("count")
+    assert(type(kb) == "number" and kb > 0)
+    assert(math.type(bytes) == "integer" and bytes >= 0 and bytes < 1024)
+
+    local t = {}
+    for i = 1, 100 do
+        t[i] = { i }
+    end
+    t = nil
+
+    assert(collectgarbage("collect") == 0)
+    assert(collectgarbage() == 0)
+    assert(type(collectgarbage("step")) == "boolean")
+
+    -- Stepping the collector eventually finishes a cycle, which "step" reports.
+    local finished = false
+    for i = 1, 100000 do
+        t = { i }
+        if collectgarbage("step") then
+            finished = true
+            break
+        end
+    end
+    assert(finished)
+
+    assert(collectgarbage("isrunning") == true)
+    collectgarbage("stop")
+    assert(collectgarbage("isrunning") == false)
+    collectgarbage("restart")
+    assert(collectgarbage("isrunning") == true)
+
+    assert(pcall(collectgarbage, "bogus") == false)
+end
@@ -0,0 +1,33 @@
+local x <const> = 10
+
+-- Const locals can be captured as upvalues and read.
+local function get_x()
+    return x
+end
+assert(get_x() == 10)
+
+local function nested()
+    return function()
+        return x + 1
+    end
+end
+assert(nested()() == 11)
+
+-- Redeclaring the name in an inner scope makes a new, assignable variable.
+do
+    local x = 1
+    x = x + 1
+    assert(x == 2)
+end
+assert(x == 10)
+
+local function shadow()
+    local x = 5
+    x = 6
+    return x
+end
+assert(shadow() == 6)
+
+local a <const>, b = 1, 2
+b = 3
+assert(a == 1 and b == 3)
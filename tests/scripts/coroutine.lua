@@ -103,3 +103,126 @@ do
         coroutine.yieldto(co)
     end) == false)
 end
+
+do
+    local f = coroutine.wrap(function(a, b)
+        local c = coroutine.yield(a + b)
+        local d, e = coroutine.yield(c * 2)
+        return d + e
+    end)
+
+    assert(f(1, 2) == 3)
+    assert(f(5) == 10)
+    assert(f(3, 4) == 7)
+    assert(pcall(f) == false)
+end
+
+do
+    local f = coroutine.wrap(function()
+        coroutine.yield(1)
+        error("wrap error")
+    end)
+
+    assert(f() == 1)
+    local s, e = pcall(f)
+    assert(s == false and e == "wrap error")
+    assert(pcall(f) == false)
+end
+
+do
+    local main, is_main = coroutine.running()
+    assert(type(main) == "thread" and is_main == true)
+    assert(coroutine.isyieldable() == false)
+
+    local co
+    co = coroutine.create(function()
+        local t, m = coroutine.running()
+        coroutine.yield(t == co, m, coroutine.isyieldable())
+    end)
+
+    local s, same, m, y = coroutine.resume(co)
+    assert(s == true and same == true and m == false and y == true)
+end
+
+do
+    local closed = false
+    local co = coroutine.create(function()
+        coroutine.yield(1)
+        closed = true
+    end)
+
+    assert(coroutine.resume(co))
+    assert(coroutine.status(co) == "suspended")
+    assert(coroutine.close(co) == true)
+    assert(coroutine.status(co) == "dead")
+    assert(closed == false)
+    assert(coroutine.resume(co) == false)
+
+    -- Closing a dead coroutine succeeds
+    assert(coroutine.close(co) == true)
+end
+
+do
+    local co
+    co = coroutine.create(function()
+        return pcall(coroutine.close, co)
+    end)
+
+    local s, ok, err = coroutine.resume(co)
+    assert(s == true and ok == false and err == "cannot close a running coroutine")
+
+    local outer
+    outer = coroutine.create(function()
+        local inner = coroutine.create(function()
+            return pcall(coroutine.close, outer)
+        end)
+        return coroutine.resume(inner)
+    end)
+
+    local s, s2, ok, err = coroutine.resume(outer)
+    assert(s == true and s2 == true and ok == false and err == "cannot close a normal coroutine")
+end
+
+do
+    local co = coroutine.create(function(a, b, c)
+        local d, e = coroutine.yield(a, b, c)
+        local f = coroutine.yield(d + e)
+        return f
+    end)
+
+    local s, a, b, c = coroutine.resume(co, 1, "two", 3)
+    assert(s == true and a == 1 and b == "two" and c == 3)
+    local s, r = coroutine.resume(co, 4, 5)
+    assert(s == true and r == 9)
+    local s, r = coroutine.resume(co, "last")
+    assert(s == true and r == "last" and coroutine.status(co) == "dead")
+end
+
+do
+    -- Every yielded value is surfaced by `resume`, including trailing nils.
+    local function count(...)
+        return select("#", ...), ...
+    end
+
+    local co = coroutine.create(function()
+        coroutine.yield()
+        coroutine.yield(nil, nil, nil)
+        local function nested()
+            coroutine.yield(1, 2)
+        end
+        nested()
+    end)
+
+    local n, s = count(coroutine.resume(co))
+    assert(n == 1 and s == true)
+    local n, s, a, b, c = count(coroutine.resume(co))
+    assert(n == 4 and s == true and a == nil and b == nil and c == nil)
+    local n, s, a, b = count(coroutine.resume(co))
+    assert(n == 3 and s == true and a == 1 and b == 2)
+
+    local gen = coroutine.wrap(function()
+        coroutine.yield(1, 2, 3)
+    end)
+    local n, a, b, c = count(gen())
+    assert(n == 3 and a == 1 and b == 2 and c == 3)
+end
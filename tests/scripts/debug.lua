@@ -0,0 +1,251 @@
+local function add(a, b)
+    return a + b
+end
+
+do
+    local info = debug.getinfo(add)
+    assert(info.what == "Lua")
+    assert(info.source == "@./tests/scripts/debug.lua")
+    assert(info.short_src == "./tests/scripts/debug.lua")
+    assert(info.linedefined == 1)
+    assert(info.lastlinedefined == 3)
+    assert(info.nparams == 2 and info.isvararg == false)
+    assert(info.func == add)
+    assert(info.name == "add")
+
+    local info = debug.getinfo(add, "S")
+    assert(info.what == "Lua" and info.nparams == nil and info.func == nil)
+end
+
+do
+    local info = debug.getinfo(math.abs)
+    assert(info.what == "C")
+    assert(info.short_src == "[C]")
+    assert(info.linedefined == -1)
+    assert(info.func == math.abs)
+    assert(info.name == "math.abs")
+
+    -- Callback frames are named after the global they are registered under.
+    local _, info = pcall(function()
+        local info = debug.getinfo(2, "nf")
+        return info
+    end)
+    assert(info.name == "pcall" and info.func == pcall)
+end
+
+do
+    local function where()
+        local info = debug.getinfo(1, "Sl")
+        return info
+    end
+
+    local info = where()
+    assert(info.what == "Lua" and info.currentline == 38)
+
+    local info = debug.getinfo(1, "Sl")
+    assert(info.what == "main" and info.currentline == 45)
+
+    assert(debug.getinfo(0).what == "C")
+    assert(debug.getinfo(100) == nil)
+end
+
+do
+    local co = coroutine.create(function()
+        coroutine.yield()
+    end)
+    coroutine.resume(co)
+    local info = debug.getinfo(co, 1, "Sl")
+    assert(info.what == "Lua" and info.currentline == 54)
+end
+
+do
+    local count = 0
+    local function hook(event, line)
+        assert(event == "line" and type(line) == "number")
+        count = count + 1
+    end
+    debug.sethook(hook, "l")
+    local total = 0
+    for i = 1, 3 do
+        total = total + i
+    end
+    debug.sethook()
+    assert(total == 6)
+    -- The loop body runs three times, so there must be at least that many line events.
+    assert(count >= 4)
+    assert(debug.gethook() == nil)
+end
+
+do
+    local events = {}
+    local function hook(event)
+        events[#events + 1] = event
+    end
+    local function f()
+        return 1
+    end
+    debug.sethook(hook, "cr")
+    f()
+    debug.sethook()
+    assert(#events == 2 and events[1] == "call" and events[2] == "return")
+end
+
+do
+    -- Callbacks can be hooks, even before the first instruction of a function.
+    local function f()
+        return 1
+    end
+    debug.sethook(type, "cl")
+    local n = f() + f()
+    debug.sethook()
+    assert(n == 2)
+end
+
+do
+    local count = 0
+    local function hook(event)
+        assert(event == "count")
+        count = count + 1
+    end
+    debug.sethook(hook, "", 1)
+    local a = 1
+    a = a + 1
+    debug.sethook()
+    assert(count >= 2)
+end
+
+do
+    -- Every instruction is counted, including one that jumps to itself.
+    local count = 0
+    debug.sethook(function()
+        count = count + 1
+    end, "", 1)
+    for i = 1, 100 do
+    end
+    debug.sethook()
+    assert(count >= 100)
+
+    -- So a count hook can interrupt a runaway loop.
+    local ok, err = pcall(function()
+        local n = 0
+        debug.sethook(function()
+            n = n + 1
+            if n == 10 then
+                error("interrupted")
+            end
+        end, "", 100)
+        while true do
+        end
+    end)
+    debug.sethook()
+    assert(not ok and string.find(tostring(err), "interrupted", 1, true))
+
+    -- Jumping back is a new line, even to the same line.
+    local lines = 0
+    debug.sethook(function()
+        lines = lines + 1
+    end, "l")
+    for i = 1, 5 do end
+    debug.sethook()
+    assert(lines >= 5)
+end
+
+do
+    local function hook() end
+    debug.sethook(hook, "lc", 3)
+    local f, mask, count = debug.gethook()
+    debug.sethook()
+    assert(f == hook and mask == "cl" and count == 3)
+end
+
+do
+    local ok, err = pcall(function()
+        debug.sethook(function()
+            debug.sethook()
+            error("in hook")
+        end, "l")
+        local a = 1
+    end)
+    assert(not ok and err == "in hook")
+    assert(debug.gethook() == nil)
+end
+
+do
+    local co = coroutine.create(function() end)
+    local function hook() end
+    debug.sethook(co, hook, "l")
+    assert(debug.gethook(co) == hook)
+    assert(debug.gethook() == nil)
+end
+
+do
+    local seen_name, seen_value
+    local function f(a, b)
+        local total = a + b
+        total = total * 2
+        return total
+    end
+    local return_line = debug.getinfo(1, "l").currentline - 2
+    debug.sethook(function(event, line)
+        if line == return_line then
+            seen_name, seen_value = debug.getlocal(2, 3)
+            assert(debug.getlocal(2, 1) == "a")
+            assert(debug.getlocal(2, 4) == nil)
+        end
+    end, "l")
+    local result = f(1, 2)
+    debug.sethook()
+    assert(result == 6)
+    assert(seen_name == "total" and seen_value == 6)
+end
+
+do
+    local function f(a, b)
+        local c
+    end
+    assert(debug.getlocal(f, 1) == "a")
+    assert(debug.getlocal(f, 2) == "b")
+    assert(debug.getlocal(f, 3) == nil)
+    assert(debug.getlocal(print, 1) == nil)
+end
+
+do
+    local function f()
+        local x = 10
+        local y = 20
+        local name, value = debug.getlocal(1, 2)
+        assert(name == "y" and value == 20)
+        assert(debug.getlocal(1, 0) == nil and debug.getlocal(1, 10) == nil)
+        assert(debug.setlocal(1, 1, 5) == "x")
+        assert(x == 5)
+        assert(debug.setlocal(1, 10, 5) == nil)
+    end
+    f()
+    assert(not pcall(debug.getlocal, 100, 1))
+end
+
+do
+    local a, b = 1, 2
+    local function f()
+        return a + b
+    end
+    local name, value = debug.getupvalue(f, 2)
+    assert(name == "b" and value == 2)
+    assert(debug.getupvalue(f, 3) == nil)
+    assert(debug.getupvalue(print, 1) == nil)
+
+    assert(debug.setupvalue(f, 1, 10) == "a")
+    assert(a == 10 and f() == 12)
+    assert(debug.setupvalue(f, 3, 10) == nil)
+
+    local function g()
+        return b
+    end
+    assert(debug.upvalueid(f, 2) == debug.upvalueid(g, 1))
+    assert(debug.upvalueid(f, 1) ~= debug.upvalueid(g, 1))
+    assert(type(debug.upvalueid(f, 1)) == "userdata")
+
+    debug.upvaluejoin(f, 2, f, 1)
+    assert(f() == 20)
+    assert(debug.upvalueid(f, 1) == debug.upvalueid(f, 2))
+end
@@ -0,0 +1,50 @@
+do
+    local function f(a, b, ...)
+        local t = { a, b, ... }
+        local sum = 0
+        for i = 1, #t do
+            sum = sum + t[i]
+        end
+        local function g(x) return x * 2, "str", 1.5, true, nil end
+        return g(sum)
+    end
+
+    for _, strip in ipairs({ false, true }) do
+        local chunk = string.dump(f, strip)
+        assert(type(chunk) == "string")
+        local loaded = assert(load(chunk, "dumped", "b"))
+        local a, b, c, d, e = loaded(1, 2, 3, 4)
+        assert(a == 20 and b == "str" and c == 1.5 and d == true and e == nil)
+        assert(loaded ~= f)
+    end
+end
+
+do
+    -- The first upvalue is set to the environment, any others start as nil.
+    local function f()
+        return x
+    end
+    local loaded = load(string.dump(f), nil, "bt", { x = "env" })
+    assert(loaded() == "env")
+
+    local up, other = 10, 20
+    local function g()
+        return up, other
+    end
+    local a, b = load(string.dump(g), nil, "b", { x = "env" })()
+    assert(a.x == "env" and b == nil)
+
+    local chunk = load("x = (x or 0) + 1 return x")
+    local loaded = load(string.dump(chunk))
+    x = nil
+    assert(loaded() == 1 and loaded() == 2)
+end
+
+do
+    local f, err = load(string.dump(function() end), "chunk", "t")
+    assert(f == nil and err == "attempt to load a binary chunk (mode is 't')")
+
+    local ok, err = pcall(string.dump, print)
+    assert(not ok and string.find(err, "unable to dump given function", 1, true))
+    assert(not pcall(string.dump, {}))
+end
@@ -17,4 +17,12 @@ end
 assert(
     test1() and
     test2()
-)
\ No newline at end of file
+)
+
+do
+    x = 5
+    assert(_G.x == 5)
+    _G.y = 6
+    assert(y == 6)
+    assert(_G == _ENV and _G._G == _G)
+end
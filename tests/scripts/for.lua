@@ -81,10 +81,160 @@ function test_break_scope()
     return true
 end
 
+function test_numeric_descending()
+    local t = {}
+    for i = 10, 1, -3 do
+        t[#t + 1] = i
+    end
+    return #t == 4 and t[1] == 10 and t[2] == 7 and t[3] == 4 and t[4] == 1 and
+        math.type(t[1]) == "integer"
+end
+
+function test_numeric_float()
+    local count = 0
+    local last
+    for i = 0, 1, 0.25 do
+        assert(math.type(i) == "float")
+        count = count + 1
+        last = i
+    end
+
+    local float_init = 0
+    for i = 1.0, 3 do
+        assert(math.type(i) == "float")
+        float_init = float_init + 1
+    end
+
+    local float_limit = 0
+    for i = 1, 2.5 do
+        assert(math.type(i) == "integer")
+        float_limit = float_limit + 1
+    end
+
+    return count == 5 and last == 1.0 and float_init == 3 and float_limit == 2
+end
+
+function test_numeric_no_iterations()
+    for i = 1, 0 do
+        return false
+    end
+    for i = 0, 1, -1 do
+        return false
+    end
+    for i = 1, 0.5 do
+        return false
+    end
+    return true
+end
+
+function test_numeric_no_wrap()
+    local count = 0
+    for i = math.maxinteger - 2, math.maxinteger do
+        count = count + 1
+    end
+
+    local down = 0
+    for i = math.mininteger + 2, math.mininteger, -1 do
+        down = down + 1
+    end
+
+    local big_step = 0
+    for i = 1, math.maxinteger, math.maxinteger // 2 do
+        big_step = big_step + 1
+    end
+
+    local huge_limit = 0
+    for i = math.maxinteger - 1, math.huge do
+        huge_limit = huge_limit + 1
+    end
+
+    return count == 3 and down == 3 and big_step == 3 and huge_limit == 2
+end
+
+function test_numeric_zero_step()
+    local ok, err = pcall(function()
+        for i = 1, 10, 0 do end
+    end)
+    local float_ok = pcall(function()
+        for i = 1, 10, 0.0 do end
+    end)
+    local bad_limit = pcall(function()
+        for i = 1, {} do end
+    end)
+    return not ok and err ~= nil and not float_ok and not bad_limit
+end
+
+function test_generic_ipairs()
+    local t = { 10, 20, 30, nil, 50 }
+    local sum = 0
+    local last
+    for i, v in ipairs(t) do
+        sum = sum + v
+        last = i
+    end
+    return sum == 60 and last == 3
+end
+
+function test_generic_stateful()
+    local function range(n)
+        local i = 0
+        return function()
+            i = i + 1
+            if i <= n then
+                return i, i * i
+            end
+        end
+    end
+
+    local count = 0
+    local squares = 0
+    for i, sq in range(4) do
+        count = count + 1
+        squares = squares + sq
+    end
+    return count == 4 and squares == 30
+end
+
+function test_generic_explist()
+    local function iter(t, i)
+        i = i + 1
+        if t[i] then
+            return i, t[i]
+        end
+    end
+    local function state_and_control()
+        return { "a", "b" }, 0
+    end
+
+    local n = 0
+    for i, v in iter, state_and_control() do
+        n = n + i
+    end
+
+    -- The closing value and any extra values are still evaluated.
+    local evaluated = 0
+    local function touch()
+        evaluated = evaluated + 1
+    end
+    for i in iter, { "x" }, 0, touch(), touch() do
+        n = n + 10
+    end
+
+    return n == 13 and evaluated == 2
+end
+
 assert(
     test_generic() and
     test_numeric() and
     test_numeric_closure() and
     test_generic_closure() and
-    test_break_scope()
+    test_break_scope() and
+    test_numeric_descending() and
+    test_numeric_float() and
+    test_numeric_no_iterations() and
+    test_numeric_no_wrap() and
+    test_numeric_zero_step() and
+    test_generic_ipairs() and
+    test_generic_stateful() and
+    test_generic_explist()
 )
@@ -34,3 +34,28 @@ assert(
     test1() and
     test2()
 )
+
+do
+    -- Backward goto forming a loop
+    local i, sum = 1, 0
+    ::top::
+    sum = sum + i
+    i = i + 1
+    if i <= 10 then
+        goto top
+    end
+    assert(sum == 55)
+end
+
+do
+    -- Forward goto skipping code, and continue-style goto in a loop
+    local odd = 0
+    for i = 1, 10 do
+        if i % 2 == 0 then
+            goto continue
+        end
+        odd = odd + 1
+        ::continue::
+    end
+    assert(odd == 5)
+end
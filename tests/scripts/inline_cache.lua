@@ -0,0 +1,70 @@
+do
+    -- A field that is added while the lookup is being repeated is found.
+    local t = {}
+    local seen = 0
+    for i = 1, 10 do
+        if t.x then
+            seen = seen + t.x
+        end
+        if i == 5 then
+            t.x = 1
+        end
+    end
+    assert(seen == 5)
+end
+
+do
+    -- Growing the table moves its entries, which must not confuse the cache.
+    local t = { x = 1 }
+    for i = 1, 200 do
+        assert(t.x == i)
+        t["k" .. i] = i
+        t.x = i + 1
+    end
+end
+
+do
+    -- The same operation used with different tables finds each table's own field.
+    local function get(t)
+        return t.x
+    end
+    local a = { x = "a" }
+    local b = { y = 1, z = 2, x = "b" }
+    for _ = 1, 3 do
+        assert(get(a) == "a")
+        assert(get(b) == "b")
+        assert(get({}) == nil)
+    end
+end
+
+do
+    -- Once a cached field is removed, the metatable is consulted again.
+    local t = setmetatable({ x = 1 }, { __index = function() return "meta" end })
+    local results = {}
+    for i = 1, 4 do
+        results[i] = t.x
+        if i == 2 then
+            t.x = nil
+        elseif i == 3 then
+            setmetatable(t, { __index = { x = "other" } })
+        end
+    end
+    assert(results[1] == 1 and results[2] == 1)
+    assert(results[3] == "meta" and results[4] == "other")
+end
+
+do
+    -- Globals are looked up the same way.
+    local function get()
+        return cached_global
+    end
+    assert(get() == nil)
+    cached_global = 1
+    assert(get() == 1)
+    for i = 1, 100 do
+        _ENV["global" .. i] = i
+    end
+    assert(get() == 1)
+    cached_global = nil
+    assert(get() == nil)
+end
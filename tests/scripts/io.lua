@@ -0,0 +1,89 @@
+local name = os.tmpname()
+
+do
+    local f = assert(io.open(name, "w"))
+    assert(f:write("first line\n", 2, "\n", 3.5, "\n") == f)
+    assert(f:write("last") == f)
+    assert(f:close() == true)
+    assert(not pcall(f.write, f, "closed"))
+    assert(tostring(f) == "file (closed)")
+end
+
+do
+    local lines = {}
+    for line in io.lines(name) do
+        lines[#lines + 1] = line
+    end
+    assert(#lines == 4)
+    assert(lines[1] == "first line" and lines[2] == "2" and lines[3] == "3.5" and lines[4] == "last")
+end
+
+do
+    local f = assert(io.open(name))
+    assert(f:read("a") == "first line\n2\n3.5\nlast")
+    assert(f:read("a") == "")
+    assert(f:read("l") == nil)
+    assert(f:read(0) == nil)
+    f:close()
+end
+
+do
+    local f = assert(io.open(name, "r"))
+    assert(f:read("L") == "first line\n")
+    local a, b = f:read("n", "n")
+    assert(a == 2 and math.type(a) == "integer")
+    assert(b == 3.5)
+    assert(f:read(2) == "\nl")
+    assert(f:seek() == 18)
+    assert(f:seek("set", 6) == 6)
+    assert(f:read("l") == "line")
+    assert(f:seek("end") == 21)
+    f:close()
+end
+
+do
+    local f = assert(io.open(name))
+    local count = 0
+    for a, b in f:lines(1, "l") do
+        count = count + 1
+        if count == 1 then
+            assert(a == "f" and b == "irst line")
+        end
+    end
+    assert(count == 4)
+    -- `file:lines` does not close the file.
+    assert(f:seek("set") == 0)
+    f:close()
+end
+
+do
+    local f = assert(io.open(name, "a+"))
+    f:write("\nappended")
+    f:seek("set")
+    assert(f:read("a") == "first line\n2\n3.5\nlast\nappended")
+    f:close()
+end
+
+do
+    local f, err, code = io.open(name .. "/does/not/exist")
+    assert(f == nil and type(err) == "string" and type(code) == "number")
+    assert(not pcall(io.open, name, "x"))
+    assert(not pcall(io.lines, name .. "/does/not/exist"))
+end
+
+do
+    assert(io.stdout:seek() == nil)
+    local ok, err = io.stdout:close()
+    assert(ok == nil and err == "cannot close standard file")
+end
+
+do
+    -- Errors are reported like `strerror`, with the error code separately.
+    local f, err, code = io.open("surely/missing/file.txt")
+    assert(f == nil and code == 2)
+    assert(string.find(err, "surely/missing/file.txt: ", 1, true) == 1)
+    assert(not string.find(err, "os error", 1, true))
+
+    local ok, lines_err = pcall(io.lines, "surely/missing/file.txt")
+    assert(not ok and tostring(lines_err) == err)
+end
@@ -0,0 +1,74 @@
+local function is_border(t, n)
+    return (n == 0 or rawget(t, n) ~= nil) and rawget(t, n + 1) == nil
+end
+
+do
+    assert(#"" == 0)
+    assert(#"hello" == 5)
+    assert(#"\0\0" == 2)
+end
+
+do
+    local t = {1, 2, 3}
+    assert(#t == 3)
+    t[#t + 1] = 4
+    assert(#t == 4)
+    t[4] = nil
+    assert(#t == 3)
+end
+
+do
+    -- A trailing nil in the constructor may give any valid border.
+    local t = {1, 2, 3, nil}
+    assert(is_border(t, #t))
+
+    local u = {1, nil, 3}
+    assert(is_border(u, #u))
+
+    local v = {nil, nil, 3}
+    assert(is_border(v, #v))
+end
+
+do
+    local t = {[1] = 1, [2] = 2, [3] = 3, [4] = 4}
+    assert(#t == 4)
+    t[4] = nil
+    assert(#t == 3)
+    t[2] = nil
+    assert(is_border(t, #t))
+
+    local u = {}
+    for i = 1, 100 do
+        u[i] = i
+    end
+    u[50] = nil
+    assert(is_border(u, #u))
+    u[1000] = true
+    assert(is_border(u, #u))
+end
+
+do
+    local t = setmetatable({1, 2, 3}, {
+        __len = function(self)
+            return 42
+        end
+    })
+    assert(#t == 42)
+    assert(rawlen == nil or rawlen(t) == 3)
+
+    local mt = {}
+    mt.__len = function(self)
+        return "not a number"
+    end
+    local u = setmetatable({}, mt)
+    assert(#u == "not a number")
+
+    local got
+    local v = setmetatable({}, {
+        __len = function(self)
+            got = self
+            return #"abc"
+        end
+    })
+    assert(#v == 3 and got == v)
+end
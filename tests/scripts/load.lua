@@ -0,0 +1,79 @@
+do
+    local f = load("return 1 + 2")
+    assert(f() == 3)
+
+    local f = load("local a, b = ... return a * b", "mul")
+    assert(f(3, 4) == 12)
+end
+
+do
+    local f, err = load("return +")
+    assert(f == nil and type(err) == "string")
+
+    local f, err = load("return 1", "chunk", "b")
+    assert(f == nil and err == "attempt to load a text chunk (mode is 'b')")
+    assert(load("return 1", "chunk", "t")() == 1)
+end
+
+do
+    local env = { x = 5 }
+    local f = load("y = x * 2 return x", "env", "t", env)
+    assert(f() == 5)
+    assert(env.y == 10)
+    assert(y == nil)
+end
+
+do
+    local parts = { "return ", "1 ", "+ ", "41" }
+    local i = 0
+    local f = load(function()
+        i = i + 1
+        return parts[i]
+    end)
+    assert(f() == 42)
+
+    local f, err = load(function() return {} end)
+    assert(f == nil and type(err) == "string")
+end
+
+do
+    local name = os.tmpname()
+    local f = assert(io.open(name, "w"))
+    f:write("#!/usr/bin/env lua\nlocal a, b = ...\ncounter = (counter or 0) + 1\nreturn a, b, counter\n")
+    f:close()
+
+    local chunk = assert(loadfile(name))
+    assert(debug.getinfo(chunk).source == "@" .. name)
+    local a, b, c = chunk(1, 2)
+    assert(a == 1 and b == 2 and c == 1)
+
+    local a, b, c = dofile(name)
+    assert(a == nil and b == nil and c == 2)
+
+    local env = {}
+    assert(select(3, loadfile(name, "t", env)()) == 1)
+    assert(env.counter == 1 and counter == 2)
+    local chunk, err = loadfile(name, "b")
+    assert(chunk == nil and err == "attempt to load a text chunk (mode is 'b')")
+
+    f = assert(io.open(name, "w"))
+    f:write("\nerror('from file')")
+    f:close()
+    local ok, err = pcall(dofile, name)
+    assert(not ok and string.find(err, "from file", 1, true))
+
+    f = assert(io.open(name, "w"))
+    f:write("return +")
+    f:close()
+    local chunk, err = loadfile(name)
+    assert(chunk == nil and type(err) == "string")
+    assert(not pcall(dofile, name))
+end
+
+do
+    local chunk, err = loadfile("surely/missing/file.lua")
+    assert(chunk == nil and string.find(err, "cannot open surely/missing/file.lua", 1, true))
+    assert(not string.find(err, "os error", 1, true))
+    local ok, err = pcall(dofile, "surely/missing/file.lua")
+    assert(not ok and string.find(err, "cannot open surely/missing/file.lua", 1, true))
+end
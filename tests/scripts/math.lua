@@ -60,6 +60,10 @@ function test5()
            math.abs(math.atan( 1, -1) - 3*math.pi/4)  < 1e-7 and
            math.abs(math.atan(-1, -1) + 3*math.pi/4)  < 1e-7 and
            math.abs(math.atan(math.huge, 1) - math.pi/2) < 1e-7 and
+           math.atan(0, -1) == math.pi and
+           math.atan(-0.0, -1) == -math.pi and
+           math.atan(-1, 0) == -math.pi/2 and
+           math.atan(1, nil) == math.atan(1) and
        not is_integer(math.atan(0, 0))
 end
 
@@ -115,7 +119,14 @@ function test12()
     return math.log(0) == -math.huge and
            math.log(1) == 0.0 and
            math.abs(math.log(10) - 2.302585092994) < 1e-7 and
-           is_nan(math.log(-1))
+           is_nan(math.log(-1)) and
+           math.log(8, 2) == 3 and
+           math.log(2^52, 2) == 52 and
+           math.abs(math.log(1000, 10) - 3) < 1e-12 and
+           math.abs(math.log(81, 3) - 4) < 1e-12 and
+           math.log(0, 2) == -math.huge and
+           is_nan(math.log(-8, 2)) and
+           math.log(math.exp(2)) == 2
 end
 
 function test13()
@@ -282,6 +293,75 @@ function test24()
                math.ult(1, 2)
 end
 
+function test25()
+    -- The signed and unsigned orderings differ whenever exactly one argument is negative.
+    return math.ult(1, -1) and
+               not math.ult(-1, 1) and
+               math.ult(math.maxinteger, math.mininteger) and
+               not math.ult(math.mininteger, math.maxinteger) and
+               math.ult(0, math.mininteger) and
+               not math.ult(-1, -1) and
+               math.ult(1.0, 2) and
+               not pcall(math.ult, 1.5, 2) and
+               not pcall(math.ult, 1, "x") and
+               not pcall(math.ult, 1) and
+               select(2, pcall(math.ult, 1, "x")) == "bad argument #2 to 'ult' (integer expected, got string)"
+end
+
+function test26()
+    -- Results outside the range of an integer are returned as floats.
+    return math.floor(2^63) == 2^63 and
+               math.type(math.floor(2^63)) == "float" and
+               math.type(math.ceil(2^63)) == "float" and
+               math.type(math.floor(-2^63)) == "integer" and
+               math.floor(-2^63) == math.mininteger and
+               math.type(math.floor(-2^63 - 2048)) == "float" and
+               math.type(math.ceil(2^63 - 1024)) == "integer" and
+               math.ceil(2^63 - 1024) == 9223372036854774784 and
+               math.floor(math.maxinteger) == math.maxinteger and
+               math.ceil(math.mininteger) == math.mininteger and
+               math.floor(1/0) == 1/0 and
+               math.ceil(-1/0) == -1/0 and
+               math.floor(0/0) ~= math.floor(0/0) and
+               math.tointeger(2^63) == nil and
+               math.tointeger(-2^63) == math.mininteger
+end
+
+function test27()
+    -- Floor division rounds towards negative infinity.
+    return -7 // 2 == -4 and
+               7 // -2 == -4 and
+               -7 // -2 == 3 and
+               7 // 2 == 3 and
+               -8 // 2 == -4 and
+               math.mininteger // -1 == math.mininteger and
+               math.type(7 // 2) == "integer" and
+               7.5 // 2 == 3.0 and
+               math.type(7.5 // 2) == "float" and
+               -7.5 // 2 == -4.0
+end
+
+function test28()
+    local ip, fp = math.modf(3.7)
+    local nip, nfp = math.modf(-3.7)
+    local iip, ifp = math.modf(5)
+    local hip, hfp = math.modf(math.huge)
+    return math.fmod(-5, 3) == -2 and
+               -5 % 3 == 1 and
+               math.fmod(5, -3) == 2 and
+               5 % -3 == -1 and
+               math.type(math.fmod(-5, 3)) == "integer" and
+               math.fmod(-5.5, 2) == -1.5 and
+               math.fmod(math.mininteger, -1) == 0 and
+               not pcall(math.fmod, 1, 0) and
+               math.fmod(1, 0.0) ~= math.fmod(1, 0.0) and
+               ip == 3.0 and math.type(ip) == "float" and
+               math.abs(fp - 0.7) < 1e-12 and
+               nip == -3.0 and math.abs(nfp + 0.7) < 1e-12 and
+               iip == 5 and ifp == 0.0 and math.type(ifp) == "float" and
+               hip == math.huge and hfp == 0.0
+end
+
 assert(
     test1() and
     test2() and
@@ -306,5 +386,9 @@ assert(
     test21() and
     test22() and
     test23() and
-    test24()
+    test24() and
+    test25() and
+    test26() and
+    test27() and
+    test28()
 )
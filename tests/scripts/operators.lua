@@ -161,3 +161,47 @@ assert(
     test16() and
     test17()
 )
+
+do
+    assert("10" + 1 == 11 and math.type("10" + 1) == "integer")
+    assert(" 0x10 " * 2 == 32)
+    assert("3.5" + 0 == 3.5)
+    assert(-"2" == -2)
+    assert(not pcall(function() return "abc" + 1 end))
+end
+
+do
+    -- Floor division and modulo round towards negative infinity for every sign combination.
+    local function check(a, b, q, m)
+        assert(a // b == q and math.type(a // b) == math.type(q))
+        assert(a % b == m and math.type(a % b) == math.type(m))
+    end
+    check(5, 3, 1, 2)
+    check(-5, 3, -2, 1)
+    check(5, -3, -2, -1)
+    check(-5, -3, 1, -2)
+    check(6, -3, -2, 0)
+    check(5.0, 3, 1.0, 2.0)
+    check(-5.0, 3, -2.0, 1.0)
+    check(5, -3.0, -2.0, -1.0)
+    check(-5.5, -3.0, 1.0, -2.5)
+
+    -- Integer overflow wraps around rather than raising an error.
+    check(math.mininteger, -1, math.mininteger, 0)
+    assert(1 % math.maxinteger == 1 and -1 % math.maxinteger == math.maxinteger - 1)
+
+    -- Modulo by infinity keeps the sign of the divisor.
+    assert(5 % math.huge == 5.0 and -5 % math.huge == math.huge)
+    assert(5 % -math.huge == -math.huge and -5 % -math.huge == -5.0)
+
+    -- Integer division and modulo by zero are errors, float ones are not.
+    local zero = 0
+    local ok, err = pcall(function() return 5 % zero end)
+    assert(not ok and string.find(tostring(err), "attempt to perform 'n%0'", 1, true))
+    ok, err = pcall(function() return 5 // zero end)
+    assert(not ok and string.find(tostring(err), "attempt to perform 'n//0'", 1, true))
+    ok, err = pcall(function() return "5" % zero end)
+    assert(not ok and string.find(tostring(err), "attempt to perform 'n%0'", 1, true))
+    assert(is_nan(5.0 % zero) and is_nan(5 % 0.0))
+    assert(5.0 // zero == math.huge and -5 // 0.0 == -math.huge)
+end
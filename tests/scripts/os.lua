@@ -0,0 +1,79 @@
+do
+    assert(type(os.exit) == "function")
+    assert(os.getenv("PICCOLO_SURELY_UNSET_VARIABLE") == nil)
+    local path = os.getenv("PATH")
+    assert(path == nil or type(path) == "string")
+end
+
+do
+    local a = os.tmpname()
+    local b = os.tmpname()
+    assert(type(a) == "string" and type(b) == "string")
+    assert(a ~= b)
+    assert(os.remove(a) == true)
+    assert(os.remove(b) == true)
+end
+
+do
+    assert(math.type(os.time()) == "integer")
+    assert(os.difftime(10, 4) == 6.0 and math.type(os.difftime(10, 4)) == "float")
+    assert(os.difftime(4, 10) == -6.0)
+    assert(not pcall(os.difftime, 1))
+
+    local t = os.time({ year = 2000, month = 1, day = 1, hour = 0 })
+    assert(t == 946684800)
+    assert(os.time({ year = 2000, month = 1, day = 1 }) == t + 12 * 3600)
+    assert(os.difftime(os.time({ year = 2000, month = 1, day = 2, hour = 0 }), t) == 86400)
+
+    local d = os.date("!*t", t)
+    assert(d.year == 2000 and d.month == 1 and d.day == 1)
+    assert(d.hour == 0 and d.min == 0 and d.sec == 0)
+    -- January 1st 2000 was a Saturday.
+    assert(d.wday == 7 and d.yday == 1 and d.isdst == false)
+
+    local d = os.date("*t", os.time({ year = 2024, month = 12, day = 31 }))
+    assert(d.wday == 3 and d.yday == 366)
+
+    -- Out of range fields are normalized, and the table is updated to match.
+    local date = { year = 2023, month = 13, day = 1, hour = 0 }
+    assert(os.time(date) == os.time({ year = 2024, month = 1, day = 1, hour = 0 }))
+    assert(date.year == 2024 and date.month == 1 and date.day == 1 and date.wday == 2)
+    assert(os.time({ year = 2024, month = 3, day = 0, hour = 0 })
+        == os.time({ year = 2024, month = 2, day = 29, hour = 0 }))
+    assert(os.time({ year = 2024, month = 1, day = 1, hour = 0, sec = -1 })
+        == os.time({ year = 2023, month = 12, day = 31, hour = 23, min = 59, sec = 59 }))
+    assert(os.time({ year = 2024, month = 0, day = 1, hour = 0 })
+        == os.time({ year = 2023, month = 12, day = 1, hour = 0 }))
+
+    assert(not pcall(os.time, { year = 2000, month = 1 }))
+    assert(not pcall(os.time, { year = 2000, month = 1, day = 1.5 }))
+
+    assert(os.date("!%Y-%m-%d %H:%M:%S", t) == "2000-01-01 00:00:00")
+    assert(os.date("%A %B %j", t) == "Saturday January 001")
+    assert(os.date("!%c", 0) == "Thu Jan  1 00:00:00 1970")
+    assert(not pcall(os.date, "%Q"))
+    assert(type(os.date()) == "string")
+end
+
+do
+    local name = os.tmpname()
+    local file = io.open(name, "w")
+    file:write("contents")
+    file:close()
+
+    local renamed = name .. ".renamed"
+    assert(os.rename(name, renamed) == true)
+    assert(io.open(name) == nil)
+    local file = io.open(renamed)
+    assert(file:read("a") == "contents")
+    file:close()
+
+    assert(os.remove(renamed) == true)
+    assert(io.open(renamed) == nil)
+
+    -- Failures return nil, an error message, and the error code.
+    local ok, err, code = os.remove(renamed)
+    assert(ok == nil and string.find(err, renamed, 1, true) == 1 and math.type(code) == "integer")
+    local ok, err, code = os.rename(renamed, name)
+    assert(ok == nil and type(err) == "string" and math.type(code) == "integer")
+end
@@ -0,0 +1,105 @@
+do
+    -- Round trips across endianness and integer widths
+    for _, endian in ipairs({"<", ">", "="}) do
+        for _, fmt in ipairs({"b", "h", "i", "i3", "l", "j", "i16"}) do
+            for _, n in ipairs({0, 1, -1, 100, -100}) do
+                local packed = string.pack(endian .. fmt, n)
+                assert(#packed == string.packsize(endian .. fmt))
+                local v, next = string.unpack(endian .. fmt, packed)
+                assert(v == n and next == #packed + 1)
+            end
+        end
+        for _, fmt in ipairs({"B", "H", "I", "I5", "J", "T"}) do
+            local v = string.unpack(endian .. fmt, string.pack(endian .. fmt, 200))
+            assert(v == 200)
+        end
+    end
+
+    assert(string.pack("<i4", 0x01020304) == "\x04\x03\x02\x01")
+    assert(string.pack(">i4", 0x01020304) == "\x01\x02\x03\x04")
+    assert(string.pack(">I2", 0xfffe) == "\xff\xfe")
+    assert(string.unpack("<i2", "\xff\xff") == -1)
+    assert(string.unpack("<I2", "\xff\xff") == 0xffff)
+    assert(string.pack("<j", math.mininteger) == "\0\0\0\0\0\0\0\x80")
+    assert(string.unpack(">J", string.pack(">J", -1)) == -1)
+
+    assert(not pcall(string.pack, "b", 128))
+    assert(not pcall(string.pack, "B", -1))
+    assert(not pcall(string.pack, "i17", 1))
+    assert(not pcall(string.unpack, "<i9", "\0\0\0\0\0\0\0\0\1"))
+end
+
+do
+    -- Floats
+    for _, endian in ipairs({"<", ">"}) do
+        assert(string.unpack(endian .. "d", string.pack(endian .. "d", 1.5)) == 1.5)
+        assert(string.unpack(endian .. "n", string.pack(endian .. "n", -0.25)) == -0.25)
+        assert(string.unpack(endian .. "f", string.pack(endian .. "f", 0.5)) == 0.5)
+    end
+    assert(string.packsize("fdn") == 20)
+end
+
+do
+    -- Strings
+    local packed = string.pack("<s1", "hello")
+    assert(packed == "\5hello")
+    assert(string.unpack("<s1", packed) == "hello")
+    assert(string.unpack("s", string.pack("s", "")) == "")
+
+    packed = string.pack("z", "abc")
+    assert(packed == "abc\0")
+    assert(string.unpack("z", packed) == "abc")
+    assert(not pcall(string.pack, "z", "a\0b"))
+    assert(not pcall(string.unpack, "z", "abc"))
+
+    assert(string.pack("c5", "ab") == "ab\0\0\0")
+    assert(string.unpack("c2", "abc") == "ab")
+    assert(not pcall(string.pack, "c2", "abc"))
+    assert(not pcall(string.pack, "c", "abc"))
+
+    local a, b, c, next = string.unpack("<s1zc2", string.pack("<s1zc2", "x", "yy", "zz"))
+    assert(a == "x" and b == "yy" and c == "zz" and next == 8)
+end
+
+do
+    -- Alignment and padding
+    assert(string.packsize("!i1i8") == 16)
+    assert(string.packsize("i1i8") == 9)
+    assert(string.packsize("!4i1i8") == 12)
+    assert(string.pack("!<i1i2", 1, 2) == "\1\0\2\0")
+    assert(string.pack("<i1xi1", 1, 2) == "\1\0\2")
+    assert(string.pack("!<i1Xi4i1", 1, 2) == "\1\0\0\0\2")
+    assert(string.packsize("!c3Xi4") == 4)
+
+    local a, b, next = string.unpack("!<i1i2", "\1\0\2\0")
+    assert(a == 1 and b == 2 and next == 5)
+end
+
+do
+    -- Unpack from a position
+    local data = string.pack("<i2i2i2", 1, 2, 3)
+    assert(string.unpack("<i2", data, 3) == 2)
+    assert(string.unpack("<i2", data, -2) == 3)
+    assert(not pcall(string.unpack, "<i2", data, 6))
+    assert(not pcall(string.unpack, "<i2", data, 8))
+
+    local ok, err = pcall(string.packsize, "s")
+    assert(not ok and tostring(err) == "variable-size format in packsize")
+    assert(not pcall(string.packsize, "z"))
+    assert(not pcall(string.pack, "y"))
+    assert(not pcall(string.pack, "i"))
+end
+
+do
+    -- Sizes too large to pack are an error before anything is allocated
+    local huge = "c" .. math.maxinteger
+    local ok, err = pcall(string.pack, huge, "")
+    assert(not ok and tostring(err) == "resulting string too large")
+    ok, err = pcall(string.pack, "i1" .. huge, 1, "")
+    assert(not ok and tostring(err) == "resulting string too large")
+    assert(string.packsize(huge) == math.maxinteger)
+    ok, err = pcall(string.packsize, huge .. "i1")
+    assert(not ok and tostring(err) == "format result too large")
+    ok, err = pcall(string.unpack, "!8i1Xi8" .. huge, "\1")
+    assert(not ok and tostring(err) == "data string too short")
+end
@@ -0,0 +1,103 @@
+do
+    assert(type(require) == "function")
+    assert(package.loaded.package == package)
+    assert(package.loaded.math == math and package.loaded.string == string)
+    assert(package.loaded._G == _G)
+    assert(require("math") == math)
+end
+
+do
+    local calls = 0
+    package.preload.counter = function(name, data)
+        calls = calls + 1
+        assert(name == "counter" and data == ":preload:")
+        return { value = 42 }
+    end
+
+    local m, data = require("counter")
+    assert(m.value == 42 and data == ":preload:")
+    assert(calls == 1)
+    assert(package.loaded.counter == m)
+    assert(require("counter") == m)
+    assert(calls == 1)
+end
+
+do
+    package.preload.nothing = function() end
+    assert(require("nothing") == true)
+    assert(package.loaded.nothing == true)
+
+    -- A module may set its own `package.loaded` entry.
+    package.preload.self_loading = function(name)
+        package.loaded[name] = "loaded"
+    end
+    assert(require("self_loading") == "loaded")
+end
+
+do
+    package.preload.circular = function()
+        return require("circular")
+    end
+    local ok, err = pcall(require, "circular")
+    assert(not ok and string.find(err, "loop or previous error loading module 'circular'", 1, true))
+
+    -- A failed load may be retried.
+    package.preload.circular = function() return "fixed" end
+    assert(require("circular") == "fixed")
+end
+
+do
+    package.preload.failing = function() error("failed") end
+    assert(not pcall(require, "failing"))
+    package.preload.failing = function() return 1 end
+    assert(require("failing") == 1)
+end
+
+do
+    local ok, err = pcall(require, "surely.missing.module")
+    assert(not ok)
+    assert(string.find(err, "module 'surely.missing.module' not found:", 1, true))
+    assert(string.find(err, "no field package.preload['surely.missing.module']", 1, true))
+    assert(string.find(err, "no file './surely/missing/module.lua'", 1, true))
+end
+
+do
+    assert(package.searchpath("surely_missing", "a/?.lua;b/?.lua") == nil)
+    local _, err = package.searchpath("x.y", "a/?.lua;b/?.lua")
+    assert(err == "no file 'a/x/y.lua'\n\tno file 'b/x/y.lua'")
+    local _, err = package.searchpath("x.y", "?", "", "")
+    assert(err == "no file 'x.y'")
+end
+
+do
+    local name = os.tmpname()
+    local f = assert(io.open(name, "w"))
+    f:write("local name, file = ... return { name = name, file = file }")
+    f:close()
+
+    assert(package.searchpath("anything", "surely/missing/?.lua;" .. name) == name)
+
+    local path = package.path
+    package.path = "surely/missing/?.lua;" .. name
+    local m, file = require("from_file")
+    package.path = path
+
+    assert(m.name == "from_file" and m.file == name and file == name)
+    assert(package.loaded.from_file == m)
+end
+
+do
+    -- Custom searchers are called in order.
+    local searchers = package.searchers
+    searchers[3], searchers[2] = searchers[2], searchers[1]
+    searchers[1] = function(name)
+        if name == "custom" then
+            return function(name, data) return data end, "custom data"
+        end
+        return "not custom"
+    end
+    assert(require("custom") == "custom data")
+    local _, err = pcall(require, "surely_missing")
+    assert(string.find(err, "not found:\n\tnot custom\n\tno field", 1, true))
+    searchers[1], searchers[2], searchers[3] = searchers[2], searchers[3], nil
+end
@@ -60,4 +60,16 @@ do
   for i = 1,10 do
     assert(t2[i] == i, i)
   end
-end
\ No newline at end of file
+end
+
+do
+  -- Clearing existing fields during traversal is allowed
+  local t = {1, 2, 3, a = 1, b = 2, c = 3, d = 4}
+  local count = 0
+  for k in pairs(t) do
+    t[k] = nil
+    count = count + 1
+  end
+  assert(count == 7)
+  assert(next(t) == nil)
+end
@@ -0,0 +1,106 @@
+local function check(...)
+    local n = select("#", ...)
+    local t = {...}
+    return function(...)
+        if select("#", ...) ~= n then
+            return false
+        end
+        for i = 1, n do
+            if t[i] ~= select(i, ...) then
+                return false
+            end
+        end
+        return true
+    end
+end
+
+do
+    assert(check(5, 7)(string.find("hello world", "o w")))
+    assert(check(7, 11)(string.find("hello world", "world", 1, true)))
+    assert(check(nil)(string.find("hello world", "xyz")))
+    assert(check(1, 0)(string.find("abc", "")))
+    assert(check(4, 3)(string.find("abc", "", 10 - 6)))
+    assert(check(nil)(string.find("abc", "", 5)))
+    assert(check(9, 11)(string.find("a.b a.b a.b", "a.b", -3, true)))
+    assert(check(3, 5)(string.find("a+b+c", "b+c", 1, true)))
+    assert(check(nil)(string.find("hello", "^ello")))
+    assert(check(2, 5)(string.find("hello", "^ello", 2)))
+end
+
+do
+    -- Position captures are interleaved with string captures in capture order.
+    assert(check(1, 11, "key", 7, "value", 12)(string.find("key = value", "(%w+)%s*=%s*()(%w+)()")))
+    assert(check(1, "hello", 6)(string.match("hello world", "()(%a+)()")))
+    assert(check(3)(string.match("abc", "()c")))
+    assert(check(4)(string.match("abc", "()$")))
+    assert(check(2, 2, 3)(string.find("abc", "b()")))
+
+    -- Without explicit captures, `match` returns the whole match.
+    assert(check("123")(string.match("abc 123 def", "%d+")))
+    assert(check("def")(string.match("abc 123 def", "%a+", 4)))
+    assert(check(nil)(string.match("abc", "%d")))
+
+    assert(check("a", "b")(string.match(" a = b ", "(%S+)%s*=%s*(%S+)")))
+    assert(check("(foo(bar))")(string.match("x(foo(bar))y", "%b()")))
+end
+
+do
+    -- Frontier patterns
+    assert(check("THE")(string.match("THE (quick) fox", "%f[%a]%a+")))
+    assert(check("quick")(string.match("THE (quick) fox", "%f[%l]%a+")))
+    assert(check(5, 7)(string.find("the cat sat", "%f[%w]cat%f[%W]")))
+    assert(check(nil)(string.find("concatenate", "%f[%w]cat%f[%W]")))
+    assert(check(5)(string.match("foo bar", "%f[%w]()bar")))
+end
+
+do
+    assert(not pcall(string.find, "a", "%"))
+    assert(not pcall(string.find, "a", "[a"))
+    assert(not pcall(string.match, "a", "(a"))
+    assert(not pcall(string.match, "a", "%fa"))
+end
+
+do
+    local words = {}
+    for word in string.gmatch("The quick brown fox, jumps!", "%a+") do
+        words[#words + 1] = word
+    end
+    assert(#words == 5 and words[1] == "The" and words[5] == "jumps")
+
+    local keys, values = {}, {}
+    for k, v in string.gmatch("a=1, b=2, c=3", "(%w+)=(%w+)") do
+        keys[#keys + 1] = k
+        values[#values + 1] = v
+    end
+    assert(#keys == 3 and keys[3] == "c" and values[2] == "2")
+
+    for pos in string.gmatch("abc", "()b") do
+        assert(pos == 2)
+    end
+
+    -- Empty matches advance the iterator, and an empty match directly after a non-empty match is
+    -- skipped.
+    local matches = {}
+    for m in string.gmatch("hello world", "%a*") do
+        matches[#matches + 1] = m
+    end
+    assert(#matches == 2 and matches[1] == "hello" and matches[2] == "world")
+
+    local count = 0
+    for m in string.gmatch("abc", "") do
+        assert(m == "")
+        count = count + 1
+    end
+    assert(count == 4)
+
+    local rest = {}
+    for word in string.gmatch("one two three", "%a+", 5) do
+        rest[#rest + 1] = word
+    end
+    assert(#rest == 2 and rest[1] == "two")
+
+    local iter = string.gmatch("x", "x")
+    assert(iter() == "x")
+    assert(iter() == nil)
+    assert(iter() == nil)
+end
@@ -39,3 +39,53 @@ assert(
     test_concat() and
     test_len()
 )
+
+do
+    assert(1 .. "" == "1")
+    assert(1.0 .. "" == "1.0")
+    assert(-0.5 .. "" == "-0.5")
+    assert(2^53 .. "" == "9.007199254741e+15")
+    assert(tostring(1e15) == "1e+15")
+    assert(tostring(10 // 1) == "10")
+    assert(tostring(10 / 2) == "5.0")
+end
+
+do
+    assert(string.rep("ab", 3) == "ababab")
+    assert(string.rep("ab", 3, ",") == "ab,ab,ab")
+    assert(string.rep("ab", 1, ",") == "ab")
+    assert(string.rep("ab", 0) == "" and string.rep("ab", -1, ",") == "")
+    assert(string.rep(12, 2) == "1212")
+    assert(#string.rep("x", 1000) == 1000)
+    assert(not pcall(string.rep, "x", 1 << 62))
+end
+
+do
+    local s = [[
+first line
+second line]]
+    assert(s == "first line\nsecond line")
+
+    assert([==[a]]b]=]c]==] == "a]]b]=]c")
+    assert([=[
+\n]=] == "\\n")
+    --[==[ a long comment
+    containing ]] and ]=] ]==]
+    local ok, err = load("return [[ unfinished")
+    assert(not ok and string.find(err, "unfinished long string", 1, true))
+    local ok, err = load("--[=[ unfinished ]]")
+    assert(not ok and string.find(err, "unfinished long comment", 1, true))
+end
+
+do
+    assert("\u{1F600}" == "\xF0\x9F\x98\x80")
+    assert("\u{7F}\u{80}\u{FFFF}\u{10FFFF}" == "\x7F\xC2\x80\xEF\xBF\xBF\xF4\x8F\xBF\xBF")
+    assert("\255" == "\xFF" and "\0659" == "A9" and #"\0\00\000" == 3)
+    assert("a\z
+          b" == "ab")
+    assert("\a\b\f\n\r\t\v\\\"\'" == "\7\8\12\10\13\9\11\92\34\39")
+
+    for _, source in ipairs({ [["\256"]], [["\u{}"]], [["\u{110000}"]], [["\xg"]], [["\q"]] }) do
+        assert(load("return " .. source) == nil)
+    end
+end
@@ -122,3 +122,115 @@ do
     assert(table.unpack(t, 4, 4) == nil)
     assert(table.unpack(t, 4, 2) == nil)
 end
+
+do
+    local t = {}
+    local ok, err = pcall(function() t[nil] = 1 end)
+    assert(not ok and string.find(tostring(err), "table index is nil", 1, true))
+    local ok, err = pcall(function() t[0/0] = 1 end)
+    assert(not ok and string.find(tostring(err), "table index is NaN", 1, true))
+    assert(not pcall(rawset, t, nil, 1))
+    assert(next(t) == nil)
+
+    -- Reading with an invalid key is not an error.
+    assert(t[nil] == nil and t[0/0] == nil)
+
+    t[2.0] = "two"
+    assert(t[2] == "two" and math.type(next(t)) == "integer")
+    t[2] = nil
+    assert(t[2.0] == nil and next(t) == nil)
+
+    t[-0.0] = "zero"
+    assert(t[0] == "zero")
+    t[1.5] = "float"
+    assert(t[1.5] == "float" and t[1] == nil)
+end
+
+do
+    assert(table.concat({}) == "")
+    assert(table.concat({1, "a", 2.5}) == "1a2.5")
+    assert(table.concat({"a", "b", "c"}, ", ") == "a, b, c")
+    assert(table.concat({"a", "b", "c", "d"}, "-", 2, 3) == "b-c")
+    assert(table.concat({"a", "b"}, "-", 3) == "")
+    assert(not pcall(table.concat, {"a", {}, "c"}))
+    local ok, err = pcall(table.concat, {1, 2}, "", 1, 3)
+    assert(not ok and string.find(tostring(err), "invalid value (at index 3)", 1, true))
+
+    local t = {}
+    for i = 1, 1000 do
+        t[i] = i
+    end
+    local s = table.concat(t, ",")
+    assert(#s == 3892 and string.find(s, "^1,2,3,") and string.find(s, ",999,1000$"))
+end
+
+do
+    local nan = 0 / 0
+    assert(not (nan == nan))
+    assert(nan ~= nan)
+    assert(not (nan < 1) and not (1 < nan))
+    assert(not (nan <= 1) and not (1 <= nan))
+    assert(not (nan < nan) and not (nan <= nan))
+    assert(not (nan > 1) and not (nan >= 1))
+    assert(not (nan < math.huge) and not (-math.huge < nan))
+end
+
+do
+    local t = { 5, 2, 8, 1, 9, 3 }
+    table.sort(t)
+    assert(table.concat(t, " ") == "1 2 3 5 8 9")
+
+    table.sort(t, function(a, b) return a > b end)
+    assert(table.concat(t, " ") == "9 8 5 3 2 1")
+
+    local t = { "b", "c", "a" }
+    table.sort(t)
+    assert(table.concat(t) == "abc")
+
+    local t = { 2.5, 1, -3, 2 }
+    table.sort(t)
+    assert(t[1] == -3 and t[2] == 1 and t[3] == 2 and t[4] == 2.5)
+
+    local t = {}
+    table.sort(t)
+    assert(#t == 0)
+
+    -- Sorting with NaN finishes and keeps every element, in some order.
+    local nan = 0 / 0
+    local t = { 3, nan, 1, nan, 2 }
+    table.sort(t)
+    local nans, sum = 0, 0
+    for i = 1, 5 do
+        if t[i] ~= t[i] then
+            nans = nans + 1
+        else
+            sum = sum + t[i]
+        end
+    end
+    assert(#t == 5 and nans == 2 and sum == 6)
+
+    -- An inconsistent comparator is an error rather than looping or reading out of bounds.
+    local ok, err = pcall(table.sort, { 4, 3, 2, 1 }, function() return true end)
+    assert(not ok and string.find(tostring(err), "invalid order function for sorting", 1, true))
+    local t = { 5, 1, 4, 2, 3, 9, 8, 7, 6 }
+    pcall(table.sort, t, function() return math.random() < 0.5 end)
+    assert(#t == 9)
+
+    local t = {}
+    for i = 1, 1000 do
+        t[i] = (i * 7919) % 1000
+    end
+    table.sort(t)
+    for i = 2, #t do
+        assert(t[i - 1] <= t[i])
+    end
+    table.sort(t, function(a, b) return a > b end)
+    for i = 2, #t do
+        assert(t[i - 1] >= t[i])
+    end
+
+    local ok, err = pcall(table.sort, { 1, "x" })
+    assert(not ok and string.find(tostring(err), "attempt to compare", 1, true))
+    assert(not pcall(table.sort, { 1, 2 }, 3))
+    assert(not pcall(table.sort, { 2, 1 }, function() error("fail") end))
+end
@@ -24,7 +24,86 @@ function test2()
     return a == 1 and b == 2 and c == 3
 end
 
+function test3()
+    local depth
+    local function loop(n, acc)
+        if n == 0 then
+            depth = 0
+            while debug.getinfo(depth + 1) do
+                depth = depth + 1
+            end
+            return acc
+        end
+        return loop(n - 1, acc + 1)
+    end
+
+    -- A tail-recursive loop runs in constant stack space.
+    return loop(1000000, 0) == 1000000 and depth < 10
+end
+
+function test4()
+    local callable = setmetatable({}, {
+        __call = function(self, n)
+            if n == 0 then
+                return "done"
+            end
+            return self(n - 1)
+        end
+    })
+
+    local function method_loop(t, n)
+        if n == 0 then
+            return true
+        end
+        return t:method(n - 1)
+    end
+    local t = { method = method_loop }
+
+    return callable(100000) == "done" and method_loop(t, 100000)
+end
+
+function test5()
+    local function multi()
+        return 1, 2, 3
+    end
+
+    -- A parenthesized call is truncated to one value, so it is not a tail call.
+    local function grouped()
+        return (multi())
+    end
+    local function grouped_varargs(...)
+        return (...)
+    end
+
+    return select("#", grouped()) == 1 and select("#", (multi())) == 1 and
+        select("#", grouped_varargs(1, 2, 3)) == 1
+end
+
+function test6()
+    -- Tail calling a value which is not callable raises the error from the calling function, also
+    -- when the calling function is the body of a coroutine.
+    local function call_nil()
+        local f = nil
+        return f(1)
+    end
+    local ok, err = pcall(call_nil)
+    if ok or not string.find(tostring(err), "tailcall.lua:%d+:") then
+        return false
+    end
+
+    local co = coroutine.wrap(function()
+        local g = nil
+        return g()
+    end)
+    ok, err = pcall(co)
+    return not ok and string.find(tostring(err), "tailcall.lua:%d+:") ~= nil
+end
+
 assert(
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6()
 )
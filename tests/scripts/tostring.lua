@@ -0,0 +1,66 @@
+do
+    local a, b = {}, {}
+    assert(string.match(tostring(a), "^table: 0x%x+$"))
+    assert(tostring(a) ~= tostring(b))
+    assert(tostring(a) == tostring(a))
+
+    local f = function() end
+    assert(string.match(tostring(f), "^function: 0x%x+$"))
+    assert(tostring(f) == tostring(f))
+    assert(string.match(tostring(coroutine.create(f)), "^thread: 0x%x+$"))
+end
+
+do
+    local t = setmetatable({}, { __name = "MyType" })
+    local address = string.match(tostring(t), "^MyType: (0x%x+)$")
+    assert(address)
+    assert(tostring(setmetatable(t, nil)) == "table: " .. address)
+
+    -- `__tostring` takes precedence, and a non-string `__name` is ignored.
+    assert(tostring(setmetatable({}, { __name = "MyType", __tostring = function() return "x" end })) == "x")
+    assert(string.match(tostring(setmetatable({}, { __name = 1 })), "^table: 0x%x+$"))
+end
+
+do
+    -- Numbers format the same as PUC-Rio Lua, floats with "%.14g" plus ".0" if they would
+    -- otherwise look like an integer.
+    local cases = {
+        { 1, "1" },
+        { math.mininteger, "-9223372036854775808" },
+        { 100.0, "100.0" },
+        { -0.0, "-0.0" },
+        { 0.1, "0.1" },
+        { 0.1 + 0.2, "0.3" },
+        { -1 / 3, "-0.33333333333333" },
+        { 2 ^ 24, "16777216.0" },
+        { 2 ^ 53, "9.007199254741e+15" },
+        { 2 ^ 63, "9.2233720368548e+18" },
+        { 1e14, "1e+14" },
+        { 99999999999999.99, "1e+14" },
+        { 12345678901234.5, "12345678901234.0" },
+        { 123456789012345.0, "1.2345678901234e+14" },
+        { 1e100, "1e+100" },
+        { 0.0001, "0.0001" },
+        { 0.00001, "1e-05" },
+        { 9.5e-5, "9.5e-05" },
+        { 1e-310, "1e-310" },
+        { 5e-324, "4.9406564584125e-324" },
+        { 1.7976931348623157e308, "1.7976931348623e+308" },
+        { math.huge, "inf" },
+        { -math.huge, "-inf" },
+    }
+    for _, case in ipairs(cases) do
+        local value, expected = case[1], case[2]
+        assert(tostring(value) == expected, tostring(value) .. " ~= " .. expected)
+        assert(value .. "" == expected)
+        assert("" .. value == expected)
+    end
+
+    -- The sign of a NaN depends on the platform, so either spelling is accepted.
+    for _, value in ipairs({ 0 / 0, -(0 / 0) }) do
+        assert(value ~= value)
+        local s = tostring(value)
+        assert(s == "nan" or s == "-nan", s)
+        assert(value .. "" == s)
+    end
+end
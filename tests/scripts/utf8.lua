@@ -0,0 +1,70 @@
+local s = "h\u{E9}llo \u{20AC}\u{1D11E}"
+
+do
+    assert(utf8.char(72, 233, 0x20AC, 0x1D11E) == "H\u{E9}\u{20AC}\u{1D11E}")
+    assert(utf8.char() == "")
+    assert(not pcall(utf8.char, -1))
+    assert(not pcall(utf8.char, 0x110000))
+end
+
+do
+    assert(utf8.len(s) == 8)
+    assert(#s == 14)
+    assert(utf8.len(s, 4) == 6 and utf8.len(s, 3) == nil)
+    assert(utf8.len(s, -4) == 1)
+    assert(utf8.len("") == 0)
+
+    local a, b, c = utf8.codepoint(s, 1, 4)
+    assert(a == 104 and b == 233 and c == 108)
+    assert(utf8.codepoint(s, -4) == 0x1D11E)
+    assert(select("#", utf8.codepoint(s, 1, 0)) == 0)
+    assert(not pcall(utf8.codepoint, s, 15))
+end
+
+do
+    assert(utf8.offset(s, 1) == 1)
+    assert(utf8.offset(s, 3) == 4)
+    assert(utf8.offset(s, -1) == 11)
+    assert(utf8.offset(s, -2) == 8)
+    assert(utf8.offset(s, 9) == 15)
+    assert(utf8.offset(s, 10) == nil)
+    assert(utf8.offset(s, 0, 3) == 2)
+    assert(not pcall(utf8.offset, s, 1, 3))
+end
+
+do
+    local positions, codes = {}, {}
+    for p, c in utf8.codes(s) do
+        positions[#positions + 1] = p
+        codes[#codes + 1] = c
+    end
+    assert(#codes == 8)
+    assert(positions[2] == 2 and codes[2] == 233)
+    assert(positions[3] == 4)
+    assert(positions[8] == 11 and codes[8] == 0x1D11E)
+
+    local chars = {}
+    for c in string.gmatch(s, utf8.charpattern) do
+        chars[#chars + 1] = c
+    end
+    assert(#chars == 8 and chars[7] == "\u{20AC}")
+end
+
+do
+    -- Malformed sequences
+    local bad = "ab\xFFcd"
+    local n, pos = utf8.len(bad)
+    assert(n == nil and pos == 3)
+    n, pos = utf8.len("\xE2\x82")
+    assert(n == nil and pos == 1)
+    assert(utf8.len("\xED\xA0\x80") == nil)
+
+    local ok, err = pcall(utf8.codepoint, bad, 1, -1)
+    assert(not ok and err == "invalid UTF-8 code")
+
+    ok, err = pcall(function()
+        for _ in utf8.codes(bad) do
+        end
+    end)
+    assert(not ok and err == "invalid UTF-8 code")
+end
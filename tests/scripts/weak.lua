@@ -0,0 +1,97 @@
+do
+    local weak = setmetatable({}, { __mode = "v" })
+    local kept = {}
+    weak[1] = {}
+    weak[2] = kept
+    weak.x = function() end
+    weak.s = "string"
+
+    collectgarbage()
+    assert(weak[1] == nil)
+    assert(weak[2] == kept)
+    assert(weak.x == nil)
+    assert(weak.s == "string")
+
+    kept = nil
+    collectgarbage()
+    assert(weak[2] == nil)
+end
+
+do
+    local weak = setmetatable({}, { __mode = "k" })
+    local key = {}
+    weak[key] = 1
+    weak[{}] = 2
+
+    collectgarbage()
+    local count = 0
+    for k, v in pairs(weak) do
+        count = count + 1
+        assert(k == key and v == 1)
+    end
+    assert(count == 1)
+end
+
+do
+    -- Removing the metatable makes the table strong again.
+    local t = setmetatable({}, { __mode = "kv" })
+    setmetatable(t, nil)
+    t[1] = {}
+    collectgarbage()
+    assert(t[1] ~= nil)
+end
+
+do
+    -- Keys set to nil are not kept alive, even by a table which is not weak.
+    local t = {}
+    local probe = setmetatable({}, { __mode = "v" })
+    local key = {}
+    t[key] = 1
+    probe[1] = key
+    t[key] = nil
+    key = nil
+    collectgarbage()
+    assert(probe[1] == nil)
+    assert(next(t) == nil)
+end
+
+do
+    -- Tables with weak keys are ephemeron tables, a value only stays alive while its key does.
+    local eph = setmetatable({}, { __mode = "k" })
+    local kept = {}
+    eph[kept] = {}
+    eph[{}] = {}
+    local k = {}
+    eph[k] = { k }
+    k = nil
+
+    collectgarbage()
+    local count = 0
+    for _ in pairs(eph) do
+        count = count + 1
+    end
+    assert(count == 1)
+    assert(type(eph[kept]) == "table")
+end
+
+do
+    -- A key which is only reachable through the value of another live key is kept.
+    local eph = setmetatable({}, { __mode = "k" })
+    local other = setmetatable({}, { __mode = "k" })
+    local root = {}
+    eph[root] = { other }
+    local key = {}
+    other[root] = { key }
+    eph[key] = "alive"
+    local probe = setmetatable({ key }, { __mode = "v" })
+    key = nil
+
+    collectgarbage()
+    assert(probe[1] ~= nil)
+    assert(eph[probe[1]] == "alive")
+
+    root = nil
+    collectgarbage()
+    assert(probe[1] == nil)
+    assert(next(eph) == nil)
+end
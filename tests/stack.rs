@@ -0,0 +1,141 @@
+use allocator_api2::vec;
+use gc_arena::allocator_api::MetricsAlloc;
+use std::iter;
+
+use piccolo::{Callback, CallbackReturn, Closure, Executor, Lua, Stack, StaticError, Table, Value};
+
+#[test]
+fn check_arguments() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        let mut stack = Stack::new(&mut values, 0);
+        stack.push_back(Value::Integer(3));
+        stack.push_back(Value::Number(4.0));
+        stack.push_back(Value::Table(Table::new(&ctx)));
+        stack.push_back(Value::Nil);
+
+        assert_eq!(stack.check_integer(0).unwrap(), 3);
+        assert_eq!(stack.check_integer(1).unwrap(), 4);
+        assert_eq!(stack.check_number(0).unwrap(), 3.0);
+        assert_eq!(stack.check_string(ctx, 0).unwrap().as_bytes(), b"3");
+        assert!(stack.check_table(2).is_ok());
+
+        assert_eq!(
+            stack.check_integer(2).unwrap_err().to_string(),
+            "bad argument #3 (integer expected, got table)"
+        );
+        assert_eq!(
+            stack.check_table(0).unwrap_err().to_string(),
+            "bad argument #1 (table expected, got number)"
+        );
+        assert_eq!(
+            stack.check_string(ctx, 3).unwrap_err().to_string(),
+            "bad argument #4 (string expected, got nil)"
+        );
+    });
+}
+
+#[test]
+fn check_missing_arguments() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        let mut stack = Stack::new(&mut values, 0);
+        stack.push_back(Value::Integer(1));
+
+        assert!(stack.get(1).is_nil());
+        assert!(stack.get(7).is_nil());
+
+        let err = stack.check_integer(1).unwrap_err();
+        assert_eq!(err.index, 2);
+        assert_eq!(err.found, "no value");
+        assert_eq!(
+            err.to_string(),
+            "bad argument #2 (integer expected, got no value)"
+        );
+    });
+}
+
+#[test]
+fn take_and_replace() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        values.push(Value::Integer(0));
+        let mut stack = Stack::new(&mut values, 1);
+        stack.into_back(ctx, (1, 2, 3));
+
+        let taken = stack.take();
+        assert_eq!(taken.len(), 3);
+        assert!(matches!(taken[..], [Value::Integer(1), _, Value::Integer(3)]));
+        assert!(stack.is_empty());
+
+        stack.into_back(ctx, (4, 5));
+        stack.replace(ctx, (true, "six"));
+        assert_eq!(stack.len(), 2);
+        assert!(matches!(stack.get(0), Value::Boolean(true)));
+        assert!(matches!(stack.get(1), Value::String(s) if s == b"six"));
+
+        // Values below the stack bottom are untouched.
+        assert_eq!(values.len(), 3);
+        assert!(matches!(values[0], Value::Integer(0)));
+    });
+}
+
+#[test]
+fn args_and_results() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        // Returns the sum of every argument followed by the arguments in reverse order.
+        let callback = Callback::from_fn(&ctx, |_, _, mut stack| {
+            let args = stack.args();
+            let sum = args.iter().filter_map(|v| v.to_integer()).sum::<i64>();
+            let results: Vec<Value> = iter::once(Value::Integer(sum))
+                .chain(args.iter().rev().copied())
+                .collect();
+            stack.set_results(results);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("callback", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local sum, a, b, c = callback(1, 2, 3)
+                assert(sum == 6 and a == 3 and b == 2 and c == 1)
+                assert(select('#', callback()) == 1)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        values.push(Value::Integer(0));
+        let mut stack = Stack::new(&mut values, 1);
+        stack.push_back(Value::Integer(1));
+        stack.push_back(Value::Integer(2));
+        assert_eq!(stack.args().len(), 2);
+
+        // Only the values above the bottom of the stack are replaced.
+        stack.set_results([Value::Boolean(true)]);
+        assert_eq!(stack.len(), 1);
+        assert!(matches!(stack.args(), [Value::Boolean(true)]));
+        assert!(matches!(
+            values[..],
+            [Value::Integer(0), Value::Boolean(true)]
+        ));
+    });
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use gc_arena::Gc;
+use piccolo::{IntoValue, Lua, String, StringBuilder, Value};
+
+#[test]
+fn test_concat() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let s = String::concat(
+            ctx,
+            &[
+                "a".into_value(ctx),
+                Value::Integer(1),
+                Value::Number(2.5),
+                Value::Number(3.0),
+                Value::Integer(-4),
+                Value::Number(1e100),
+                Value::Number(0.1),
+            ],
+        )
+        .unwrap();
+        assert_eq!(s, "a12.53.0-41e+1000.1");
+
+        assert_eq!(String::concat(ctx, &[]).unwrap(), "");
+        assert_eq!(
+            String::concat(ctx, &[Value::Number(1.0 / 3.0)]).unwrap(),
+            "0.33333333333333"
+        );
+        assert!(String::concat(ctx, &[Value::Integer(1), Value::Nil]).is_err());
+        assert!(String::concat(ctx, &[ctx.globals().into()]).is_err());
+    });
+}
+
+#[test]
+fn test_builder_single_allocation() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let pieces: Vec<_> = (0..1000).map(|i| format!("piece {i};")).collect();
+        let len = pieces.iter().map(|p| p.len()).sum::<usize>();
+
+        let mut builder = StringBuilder::with_capacity(len);
+        for piece in &pieces {
+            builder.push_bytes(piece.as_bytes());
+        }
+        assert_eq!(builder.capacity(), len);
+
+        // Only the final string, which owns the builder's buffer, is allocated outside of the gc.
+        let external = ctx.metrics().total_external_allocation();
+        let s = builder.build(ctx);
+        assert_eq!(ctx.metrics().total_external_allocation() - external, len);
+        assert_eq!(s.as_bytes(), pieces.concat().as_bytes());
+
+        let mut builder = StringBuilder::new();
+        builder.push_value("short ".into_value(ctx)).unwrap();
+        builder.push_value(Value::Integer(1)).unwrap();
+        assert!(builder.push_value(Value::Nil).is_err());
+        let short = builder.build(ctx);
+        assert_eq!(short, "short 1");
+        assert!(Gc::ptr_eq(
+            short.into_inner(),
+            ctx.intern(b"short 1").into_inner()
+        ));
+    });
+}
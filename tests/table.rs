@@ -1,6 +1,9 @@
 use std::cmp::Ordering;
 
-use piccolo::{Lua, Table, Value};
+use piccolo::{
+    table::NextValue, Closure, Context, Executor, IntoValue, InvalidTableKey, Lua, PathError,
+    StaticError, Table, Value,
+};
 
 #[test]
 fn test_table_iter() {
@@ -34,3 +37,547 @@ fn test_table_iter() {
         assert!(matches!(pairs[5], (Value::String(s), Value::Integer(3)) if s == "3" ));
     });
 }
+
+fn is_border<'gc>(ctx: Context<'gc>, table: Table<'gc>, n: i64) -> bool {
+    (n == 0 || !table.get(ctx, n).is_nil()) && table.get(ctx, n + 1).is_nil()
+}
+
+#[test]
+fn test_table_length() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        assert_eq!(table.length(), 0);
+
+        for i in 1..=8 {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(table.length(), 8);
+
+        table.set(ctx, 8, Value::Nil).unwrap();
+        assert_eq!(table.length(), 7);
+
+        table.set(ctx, 4, Value::Nil).unwrap();
+        assert!(is_border(ctx, table, table.length()));
+
+        table.set(ctx, 20, true).unwrap();
+        assert!(is_border(ctx, table, table.length()));
+
+        let map = Table::new(&ctx);
+        for i in [3, 2, 1] {
+            map.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(map.length(), 3);
+        map.set(ctx, 3, Value::Nil).unwrap();
+        assert_eq!(map.length(), 2);
+    });
+}
+
+#[test]
+fn test_table_iter_mixed() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        assert!(table.is_empty());
+        assert_eq!(table.len(), 0);
+
+        for i in 1..=5 {
+            table.set(ctx, i, i * 10).unwrap();
+        }
+        table.set(ctx, "name", "mixed").unwrap();
+        table.set(ctx, 1.5, true).unwrap();
+        assert!(!table.is_empty());
+        assert_eq!(table.len(), 5);
+
+        let mut integers = Vec::new();
+        let mut others = 0;
+        for (key, value) in table.iter() {
+            assert!(!value.is_nil());
+            match key {
+                Value::Integer(i) => {
+                    assert!(matches!(value, Value::Integer(v) if v == i * 10));
+                    integers.push(i);
+                }
+                Value::String(s) => {
+                    assert_eq!(s, "name");
+                    assert!(matches!(value, Value::String(v) if v == "mixed"));
+                    others += 1;
+                }
+                Value::Number(n) => {
+                    assert_eq!(n, 1.5);
+                    assert!(matches!(value, Value::Boolean(true)));
+                    others += 1;
+                }
+                _ => unreachable!(),
+            }
+        }
+        // The array part is always visited first and in order.
+        assert_eq!(integers, [1, 2, 3, 4, 5]);
+        assert_eq!(others, 2);
+
+        // Clearing existing keys while iterating does not disturb the iteration.
+        let mut count = 0;
+        for (key, _) in table.iter() {
+            table.set_value(ctx, key, Value::Nil).unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        assert!(table.is_empty());
+        assert_eq!(table.iter().count(), 0);
+        assert_eq!(table.len(), 0);
+    });
+}
+
+#[test]
+fn test_table_construction() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let map = Table::from_iter(ctx, [("a", 1), ("b", 2), ("c", 3), ("a", 4)]).unwrap();
+        assert_eq!(map.iter().count(), 3);
+        assert_eq!(map.len(), 0);
+        assert!(matches!(map.get(ctx, "a"), Value::Integer(4)));
+        assert!(matches!(map.get(ctx, "b"), Value::Integer(2)));
+        assert!(matches!(map.get(ctx, "c"), Value::Integer(3)));
+
+        map.extend(ctx, [(1, "one"), (2, "two")]).unwrap();
+        assert_eq!(map.iter().count(), 5);
+        assert_eq!(map.len(), 2);
+        assert!(matches!(map.get(ctx, 2), Value::String(s) if s == "two"));
+
+        assert!(Table::from_iter(ctx, [(Value::Nil, 1)]).is_err());
+        assert!(map.extend(ctx, [(f64::NAN, 1)]).is_err());
+
+        let array = Table::array(ctx, ["x", "y", "z"]);
+        assert_eq!(array.len(), 3);
+        assert!(matches!(array.get(ctx, 1), Value::String(s) if s == "x"));
+        assert!(matches!(array.get(ctx, 3), Value::String(s) if s == "z"));
+        assert!(array.get(ctx, 4).is_nil());
+
+        let empty = Table::array(ctx, Vec::<i64>::new());
+        assert!(empty.is_empty());
+    });
+}
+
+#[test]
+fn test_table_iter_deterministic() {
+    fn key_order(lua: &mut Lua) -> std::string::String {
+        let function = lua
+            .load(
+                None,
+                &br#"
+                    local t = {}
+                    for i = 1, 100 do
+                        t["key" .. i] = i
+                        t[i * 1000] = i
+                        t[i + 0.5] = i
+                    end
+                    t[true] = 1
+                    t[false] = 2
+                    t.key50 = nil
+
+                    local order = ""
+                    for k in pairs(t) do
+                        order = order .. tostring(k) .. ","
+                    end
+                    return order
+                "#[..],
+            )
+            .unwrap();
+        lua.call::<std::string::String>(&function).unwrap()
+    }
+
+    let mut lua = Lua::core();
+    let order = key_order(&mut lua);
+    assert_eq!(order, key_order(&mut lua));
+    assert_eq!(order, key_order(&mut Lua::core()));
+    assert!(!order.contains("key50,"));
+}
+
+#[test]
+fn test_table_reserve() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::with_capacity(&ctx, 100, 4);
+        assert_eq!(table.array_capacity(), 100);
+        assert!(table.map_capacity() >= 4);
+        assert_eq!(table.len(), 0);
+        assert!(table.is_empty());
+
+        for i in 1..=100 {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(table.array_capacity(), 100);
+        assert_eq!(table.len(), 100);
+
+        // Reserving is relative to the current length.
+        table.reserve(ctx, 1000).unwrap();
+        assert_eq!(table.array_capacity(), 1100);
+        let map_capacity = table.map_capacity();
+        for i in 101..=1100 {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(table.array_capacity(), 1100);
+        assert_eq!(table.map_capacity(), map_capacity);
+        assert_eq!(table.len(), 1100);
+
+        // Reserving less than the existing capacity does nothing.
+        table.reserve(ctx, 0).unwrap();
+        assert_eq!(table.array_capacity(), 1100);
+
+        // Growing past the reservation still works, and keys in the map part move into the array
+        // part when it grows.
+        table.set(ctx, 1102, 1102).unwrap();
+        table.set(ctx, 1101, 1101).unwrap();
+        assert_eq!(table.len(), 1102);
+        table.reserve(ctx, 10).unwrap();
+        assert_eq!(table.array_capacity(), 1112);
+        assert!(matches!(table.get(ctx, 1102), Value::Integer(1102)));
+        assert_eq!(table.iter().count(), 1102);
+    });
+}
+
+#[test]
+fn test_table_array_part() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        // Sequential keys are stored in the array part, the map part is never allocated.
+        let table = Table::new(&ctx);
+        for i in 1..=1000 {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert!(table.array_capacity() >= 1000);
+        assert_eq!(table.map_capacity(), 0);
+        assert_eq!(table.len(), 1000);
+
+        // Creating a hole leaves the other keys in place, and the length is still a border.
+        table.set(ctx, 500, Value::Nil).unwrap();
+        assert!(table.get(ctx, 500).is_nil());
+        assert!(matches!(table.get(ctx, 501), Value::Integer(501)));
+        assert!(is_border(ctx, table, table.len()));
+        assert_eq!(table.iter().count(), 999);
+        table.set(ctx, 500, 500).unwrap();
+        assert_eq!(table.len(), 1000);
+
+        // Keys inserted in reverse start out in the map part, and migrate to the array part once
+        // it is dense enough.
+        let table = Table::new(&ctx);
+        for i in (1..=1000).rev() {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert!(table.array_capacity() >= 1000);
+        assert_eq!(table.len(), 1000);
+        for i in 1..=1000 {
+            assert!(matches!(table.get(ctx, i), Value::Integer(v) if v == i));
+        }
+
+        // Sparse integer keys stay in the map part.
+        let table = Table::new(&ctx);
+        for i in 0..100 {
+            table.set(ctx, i * 1000 + 1, i).unwrap();
+        }
+        assert!(table.array_capacity() < 100);
+        assert_eq!(table.iter().count(), 100);
+    });
+}
+
+#[test]
+fn test_table_array_workload() {
+    const N: i64 = 200_000;
+
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        for i in 1..=N {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(table.len(), N);
+
+        let mut sum = 0;
+        for i in 1..=N {
+            let Value::Integer(v) = table.get(ctx, i) else {
+                panic!("missing value");
+            };
+            sum += v;
+        }
+        assert_eq!(sum, N * (N + 1) / 2);
+        assert_eq!(table.map_capacity(), 0);
+    });
+}
+
+#[test]
+fn test_table_invalid_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+
+        let err = table.set(ctx, Value::Nil, 1).unwrap_err();
+        assert!(matches!(err, InvalidTableKey::IsNil));
+        assert_eq!(err.to_string(), "table index is nil");
+        let err = table.set(ctx, f64::NAN, 1).unwrap_err();
+        assert!(matches!(err, InvalidTableKey::IsNaN));
+        assert_eq!(err.to_string(), "table index is NaN");
+        assert!(table.set(ctx, Value::Nil, Value::Nil).is_err());
+        assert!(table.is_empty());
+
+        // Integer-valued float keys are normalized to integer keys.
+        table.set(ctx, 2.0, "two").unwrap();
+        assert!(matches!(table.get(ctx, 2), Value::String(s) if s == "two"));
+        assert!(matches!(
+            table.next(Value::Nil),
+            NextValue::Found {
+                key: Value::Integer(2),
+                ..
+            }
+        ));
+        table.set(ctx, 3, "three").unwrap();
+        assert!(matches!(table.get(ctx, 3.0), Value::String(s) if s == "three"));
+        table.set(ctx, 2.5, "float").unwrap();
+        assert!(!table.get(ctx, 2).is_nil() && !table.get(ctx, 3).is_nil());
+        assert!(matches!(table.get(ctx, 2.5), Value::String(s) if s == "float"));
+
+        // Setting a key to nil removes it.
+        table.set(ctx, 2, Value::Nil).unwrap();
+        table.set(ctx, 3.0, Value::Nil).unwrap();
+        table.set(ctx, 2.5, Value::Nil).unwrap();
+        assert!(table.get(ctx, 2.0).is_nil());
+        assert!(table.is_empty());
+        assert_eq!(table.iter().count(), 0);
+    });
+}
+
+#[test]
+fn test_table_numeric_key_aliasing() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+
+        // Integral floats are the same key as the equal integer, in both the array and map parts.
+        for key in [1, 2, -3, 1 << 40, i64::MIN] {
+            table.set(ctx, key, key).unwrap();
+            assert!(matches!(table.get(ctx, key as f64), Value::Integer(v) if v == key));
+        }
+        table.set(ctx, 4.0, "four").unwrap();
+        assert!(matches!(table.get(ctx, 4), Value::String(s) if s == "four"));
+        table.set(ctx, 4, Value::Nil).unwrap();
+        assert!(table.get(ctx, 4.0).is_nil());
+
+        // Non-integral floats are distinct from every integer.
+        table.set(ctx, 1.5, "float").unwrap();
+        assert!(matches!(table.get(ctx, 1), Value::Integer(1)));
+        assert!(matches!(table.get(ctx, 2), Value::Integer(2)));
+        assert!(matches!(table.get(ctx, 1.5), Value::String(s) if s == "float"));
+
+        // Keys are stored in their integer form.
+        let mut float_keys = 0;
+        for (key, _) in table.iter() {
+            if let Value::Number(n) = key {
+                assert_eq!(n, 1.5);
+                float_keys += 1;
+            }
+        }
+        assert_eq!(float_keys, 1);
+    });
+}
+
+#[test]
+fn test_table_many_string_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        for i in 0..10_000 {
+            table.set(ctx, format!("key{i}"), i).unwrap();
+        }
+        // Keys which are equal but not interned as the same string are still found.
+        for i in 0..10_000 {
+            let key = piccolo::String::from_slice(&ctx, format!("key{i}"));
+            assert!(matches!(table.get(ctx, key), Value::Integer(v) if v == i));
+        }
+        assert!(table.get(ctx, "key10000").is_nil());
+
+        for i in (0..10_000).step_by(2) {
+            table.set(ctx, format!("key{i}"), Value::Nil).unwrap();
+        }
+        let mut count = 0;
+        for (key, value) in table {
+            let Value::Integer(v) = value else {
+                panic!("unexpected value {value:?}");
+            };
+            assert!(v % 2 == 1);
+            assert_eq!(key.to_string(), format!("key{v}"));
+            count += 1;
+        }
+        assert_eq!(count, 5_000);
+
+        // Table keys only differ in their pointers.
+        let keys: Vec<Table> = (0..1_000).map(|_| Table::new(&ctx)).collect();
+        for (i, &key) in keys.iter().enumerate() {
+            table.set(ctx, key, i as i64).unwrap();
+        }
+        for (i, &key) in keys.iter().enumerate() {
+            assert!(matches!(table.get(ctx, key), Value::Integer(v) if v == i as i64));
+        }
+    });
+}
+
+#[test]
+fn test_table_integer_and_other_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        for i in -100..=100 {
+            table.set(ctx, i, i * 2).unwrap();
+        }
+        table.set(ctx, "a", 1).unwrap();
+        table.set(ctx, 0.5, 2).unwrap();
+        table.set(ctx, true, 3).unwrap();
+        table.set(ctx, table, 4).unwrap();
+
+        for i in -100..=100 {
+            assert!(matches!(table.get(ctx, i), Value::Integer(v) if v == i * 2));
+            // Floats with an integer value find the integer key and vice versa.
+            assert!(matches!(table.get(ctx, i as f64), Value::Integer(v) if v == i * 2));
+        }
+        assert!(matches!(table.get(ctx, "a"), Value::Integer(1)));
+        assert!(matches!(table.get(ctx, 0.5), Value::Integer(2)));
+        assert!(matches!(table.get(ctx, true), Value::Integer(3)));
+        assert!(matches!(table.get(ctx, table), Value::Integer(4)));
+        assert!(table.get(ctx, 101).is_nil());
+
+        // Overwriting, removing and then re-adding integer keys in the map part.
+        assert!(matches!(
+            table.set(ctx, -50, "x").unwrap(),
+            Value::Integer(-100)
+        ));
+        assert!(matches!(table.set(ctx, -50.0, Value::Nil).unwrap(), Value::String(s) if s == "x"));
+        assert!(table.get(ctx, -50).is_nil());
+        assert!(table.set(ctx, -50, 7).unwrap().is_nil());
+        assert!(matches!(table.get(ctx, -50.0), Value::Integer(7)));
+        assert_eq!(table.iter().count(), 205);
+    });
+}
+
+#[test]
+fn test_table_paths() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return {
+                    server = {
+                        http = {port = 8080, host = "localhost"},
+                        name = "test",
+                    },
+                }
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.finish(&executor);
+
+    lua.try_enter(|ctx| {
+        let config = ctx.fetch(&executor).take_result::<Table>(ctx)??;
+        assert!(matches!(
+            config.get_path(ctx, &["server", "http", "port"]),
+            Value::Integer(8080)
+        ));
+        assert!(
+            matches!(config.get_path(ctx, &["server", "name"]), Value::String(s) if s == "test")
+        );
+        assert!(matches!(config.get_path(ctx, &[]), Value::Table(t) if t == config));
+        assert!(config
+            .get_path(ctx, &["server", "missing", "port"])
+            .is_nil());
+        // Stops cleanly at a non-table value.
+        assert!(config.get_path(ctx, &["server", "name", "len"]).is_nil());
+
+        // Missing intermediate tables are created.
+        let table = Table::new(&ctx);
+        assert!(table.set_path(ctx, &["a", "b", "c"], 1).unwrap().is_nil());
+        assert!(matches!(
+            table.get_path(ctx, &["a", "b", "c"]),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            table.set_path(ctx, &["a", "b", "c"], 2).unwrap(),
+            Value::Integer(1)
+        ));
+        table.set_path(ctx, &["a", "d"], "x").unwrap();
+        let Value::Table(a) = table.get(ctx, "a") else {
+            panic!("expected a table");
+        };
+        assert_eq!(a.iter().count(), 2);
+
+        let err = table.set_path(ctx, &["a", "d", "e"], 3).unwrap_err();
+        assert!(matches!(err, PathError::NotTable { index: 1, .. }));
+        assert_eq!(err.to_string(), "path element 1 is a string, not a table");
+
+        // An empty path is an error rather than assigning anything.
+        let err = table.set_path(ctx, &[], 4).unwrap_err();
+        assert!(matches!(err, PathError::Empty));
+        assert_eq!(table.iter().count(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_table_remove_clear_copy() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::array(ctx, [1, 2, 3]);
+        table.set(ctx, "key", "value").unwrap();
+        let nested = Table::new(&ctx);
+        table.set(ctx, "nested", nested).unwrap();
+
+        assert!(matches!(
+            table.remove(ctx, Value::Integer(3)),
+            Value::Integer(3)
+        ));
+        assert!(table.get(ctx, 3).is_nil());
+        assert_eq!(table.length(), 2);
+        assert!(
+            matches!(table.remove(ctx, "key".into_value(ctx)), Value::String(s) if s == "value")
+        );
+        assert!(table.remove(ctx, "key".into_value(ctx)).is_nil());
+        assert!(table.remove(ctx, Value::Nil).is_nil());
+        assert!(table.remove(ctx, Value::Number(f64::NAN)).is_nil());
+
+        let metatable = Table::new(&ctx);
+        table.set_metatable(ctx, Some(metatable));
+        let copy = table.shallow_copy(&ctx);
+        assert!(copy != table);
+        assert!(copy.metatable().is_none());
+        assert_eq!(copy.length(), 2);
+        assert!(matches!(copy.get(ctx, "nested"), Value::Table(t) if t == nested));
+
+        // The copy is independent of the original.
+        copy.set(ctx, 1, 10).unwrap();
+        copy.set(ctx, "new", true).unwrap();
+        assert!(matches!(table.get(ctx, 1), Value::Integer(1)));
+        assert!(table.get(ctx, "new").is_nil());
+
+        table.clear(&ctx);
+        assert_eq!(table.length(), 0);
+        assert!(table.is_empty());
+        assert!(table.metatable() == Some(metatable));
+        assert_eq!(copy.length(), 2);
+        assert!(matches!(copy.get(ctx, 1), Value::Integer(10)));
+
+        // A cleared table can still be used normally.
+        table.set(ctx, 1, "a").unwrap();
+        assert_eq!(table.length(), 1);
+    });
+}
@@ -0,0 +1,261 @@
+use piccolo::{
+    Callback, CallbackReturn, Closure, Error, Executor, Function, Hook, HookMask, Lua, StaticError,
+    Thread, ThreadMode,
+};
+
+#[test]
+fn take_return_values() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return 3, 'three'"[..])?;
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok((ctx.stash(thread), ctx.stash(Executor::run(&ctx, thread))))
+    })?;
+
+    lua.finish(&executor);
+
+    lua.try_enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        assert_eq!(thread.mode(), ThreadMode::Result);
+        let (i, s): (i64, String) = thread.take_return_values(ctx)?;
+        assert_eq!(i, 3);
+        assert_eq!(s, "three");
+        assert_eq!(thread.mode(), ThreadMode::Stopped);
+        Ok(())
+    })
+}
+
+#[test]
+fn take_return_values_bad_mode() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let thread = Thread::new(ctx);
+        match thread.take_return_values::<()>(ctx) {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err.to_string(), "bad thread mode: Stopped, expected Result");
+            }
+            _ => panic!("expected a bad thread mode error"),
+        }
+        assert_eq!(thread.mode(), ThreadMode::Stopped);
+        Ok(())
+    })
+}
+
+#[test]
+fn resume_with_yield_values() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (a, b): (i64, i64) = stack.consume(ctx)?;
+            Ok(Thread::yield_values(ctx, stack, a + b))
+        });
+
+        let thread = Thread::new(ctx);
+        thread.start_suspended(&ctx, Function::Callback(callback))?;
+        thread.resume_with(ctx, (1, 2))?;
+        Ok((ctx.stash(thread), ctx.stash(Executor::run(&ctx, thread))))
+    })?;
+
+    lua.finish(&executor);
+
+    lua.try_enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        assert_eq!(thread.take_return_values::<i64>(ctx)?, 3);
+        assert_eq!(thread.mode(), ThreadMode::Suspended);
+
+        // Resuming a thread which is not suspended is an error rather than a panic.
+        let thread = Thread::new(ctx);
+        assert!(thread.resume_with(ctx, (1, 2)).is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn reuse_collected_threads() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let run_coroutines = |lua: &mut Lua| -> Result<(), StaticError> {
+        let executor = lua.try_enter(|ctx| {
+            let closure = Closure::load(
+                ctx,
+                None,
+                &br#"
+                    for i = 1, 100 do
+                        local co = coroutine.create(function(a)
+                            local b = coroutine.yield(a + i)
+                            return b * 2
+                        end)
+                        local _, r = coroutine.resume(co, 1)
+                        assert(r == i + 1)
+                        local _, r = coroutine.resume(co, i)
+                        assert(r == i * 2)
+                        assert(coroutine.status(co) == "dead")
+                    end
+                "#[..],
+            )?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        lua.execute::<()>(&executor)
+    };
+
+    // Every finished coroutine is collected, so the next coroutines created reuse their stacks.
+    run_coroutines(&mut lua)?;
+    lua.gc_collect();
+    let reused = lua.enter(|ctx| ctx.finalizers().reused_threads());
+    run_coroutines(&mut lua)?;
+    assert!(lua.enter(|ctx| ctx.finalizers().reused_threads()) > reused);
+
+    // Reused threads start out completely empty.
+    lua.enter(|ctx| {
+        let thread = Thread::new(ctx);
+        assert_eq!(thread.mode(), ThreadMode::Stopped);
+        assert!(thread.frame_info(1).unwrap().is_none());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn pooled_thread_stacks_are_shrunk() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+    lua.gc_collect();
+    let before = lua.total_memory();
+
+    // Collected threads keep their stacks for reuse, but not all of a very deep stack.
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function recurse(n)
+                    if n > 0 then
+                        return 1 + recurse(n - 1)
+                    end
+                    return 0
+                end
+                for i = 1, 32 do
+                    local co = coroutine.create(recurse)
+                    assert(select(2, coroutine.resume(co, 5000)) == 5000)
+                end
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+    drop(executor);
+    lua.gc_collect();
+    lua.gc_collect();
+
+    assert!(lua.total_memory() - before < 4 << 20);
+    Ok(())
+}
+
+#[test]
+fn main_thread() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let (thread, executor) = lua.try_enter(|ctx| {
+        // The running thread can be checked from inside a callback.
+        let callback = Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+            stack.replace(ctx, exec.current_thread().thread.is_main());
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("is_main", callback)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(is_main())
+                assert(select(2, coroutine.running()))
+
+                local co = coroutine.create(function()
+                    assert(not is_main())
+                    assert(not select(2, coroutine.running()))
+                    coroutine.yield(1)
+                    return 2
+                end)
+                assert(select(2, coroutine.resume(co)) == 1)
+                assert(select(2, coroutine.resume(co)) == 2)
+
+                local ok, err = pcall(coroutine.yield, 3)
+                assert(not ok and err == "attempt to yield from outside a coroutine")
+                return co
+            "#[..],
+        )?;
+        let thread = Thread::new(ctx);
+        assert!(!thread.is_main());
+        thread.start(ctx, closure.into(), ())?;
+        Ok((ctx.stash(thread), ctx.stash(Executor::run(&ctx, thread))))
+    })?;
+
+    lua.finish(&executor);
+
+    lua.try_enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        assert!(thread.is_main());
+        let co: Thread = thread.take_return_values(ctx)?;
+        assert!(!co.is_main());
+
+        // Resetting the executor moves the designation to the new thread.
+        let new_thread = Thread::new(ctx);
+        ctx.fetch(&executor).reset(&ctx, new_thread);
+        assert!(new_thread.is_main());
+        assert!(!thread.is_main());
+        Ok(())
+    })
+}
+
+#[test]
+fn callback_hook() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        // Call and line hooks run before the first instruction of a function.
+        let hook = Callback::from_fn(&ctx, |ctx, exec, _| {
+            if exec.upper_lua_frame().is_none() {
+                let count = ctx.get_global("no_line").to_integer().unwrap_or(0);
+                ctx.set_global("no_line", count + 1)?;
+            }
+            Ok(CallbackReturn::Return)
+        });
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function f()
+                    return 1
+                end
+                return f() + f()
+            "#[..],
+        )?;
+        let thread = Thread::new(ctx);
+        thread
+            .set_hook(
+                &ctx,
+                Some(Hook {
+                    function: hook.into(),
+                    mask: HookMask {
+                        call: true,
+                        line: true,
+                        ..Default::default()
+                    },
+                    count: 0,
+                }),
+            )
+            .unwrap();
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(Executor::run(&ctx, thread)))
+    })?;
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 2);
+    lua.enter(|ctx| {
+        assert!(ctx.get_global("no_line").to_integer().unwrap() >= 2);
+    });
+    Ok(())
+}
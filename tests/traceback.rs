@@ -0,0 +1,183 @@
+use piccolo::{Closure, Executor, Lua, StaticError};
+
+#[test]
+fn traceback() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local function c()
+                    local t1, t2 = debug.traceback("message"), debug.traceback(nil, 2)
+                    return t1, t2
+                end
+                local function b()
+                    local t1, t2 = c()
+                    return t1, t2
+                end
+                local function a()
+                    local t1, t2 = b()
+                    return t1, t2
+                end
+                local t1, t2 = a()
+                return t1, t2, type(debug.traceback({})) == "table"
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (full, skipped, non_string) = lua.execute::<(String, String, bool)>(&executor)?;
+    assert_eq!(
+        full,
+        "message\n\
+         stack traceback:\n\
+         \ttest:3: in function 'c'\n\
+         \ttest:7: in function 'b'\n\
+         \ttest:11: in function 'a'\n\
+         \ttest:14: in main chunk"
+    );
+    assert_eq!(
+        skipped,
+        "stack traceback:\n\
+         \ttest:7: in function 'b'\n\
+         \ttest:11: in function 'a'\n\
+         \ttest:14: in main chunk"
+    );
+    assert!(non_string);
+    Ok(())
+}
+
+#[test]
+fn traceback_elision() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local function recurse(n)
+                    if n == 0 then
+                        local t = debug.traceback()
+                        return t
+                    end
+                    local t = recurse(n - 1)
+                    return t
+                end
+                local t = recurse(40)
+                return t
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let traceback = lua.execute::<String>(&executor)?;
+    let lines = traceback.lines().collect::<Vec<_>>();
+    // The header, 10 leading levels, the elision marker, then 11 trailing levels.
+    assert_eq!(lines.len(), 23);
+    assert_eq!(lines[11], "\t...\t(skipping 21 levels)");
+    assert_eq!(lines[1], "\ttest:4: in function 'recurse'");
+    assert_eq!(lines[22], "\ttest:10: in main chunk");
+    Ok(())
+}
+
+#[test]
+fn uncaught_error_traceback() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local function b()
+                    error("boom")
+                end
+                local function a()
+                    b()
+                end
+                a()
+            "#[..],
+        )?;
+
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.set_capture_traceback(&ctx, true);
+        Ok(ctx.stash(executor))
+    })?;
+
+    let err = lua.execute::<()>(&executor).unwrap_err();
+    assert_eq!(
+        err.traceback(),
+        Some(
+            "stack traceback:\n\
+             \ttest:3: in function 'b'\n\
+             \ttest:6: in function 'a'\n\
+             \ttest:8: in main chunk"
+        )
+    );
+    assert!(err.to_string().ends_with(err.traceback().unwrap()));
+
+    // Errors caught by `pcall` do not leave a traceback behind.
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("=test"), &b"pcall(error, 'caught')"[..])?;
+        ctx.fetch(&executor).restart(ctx, closure.into(), ());
+        Ok(())
+    })?;
+    lua.execute::<()>(&executor)?;
+    lua.enter(|ctx| assert!(ctx.fetch(&executor).error_traceback().is_none()));
+
+    // Runtime errors carry their traceback as well.
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("=test"), &b"\nlocal x = nil + 1"[..])?;
+        ctx.fetch(&executor).restart(ctx, closure.into(), ());
+        Ok(())
+    })?;
+    let err = lua.execute::<()>(&executor).unwrap_err();
+    assert_eq!(
+        err.traceback(),
+        Some("stack traceback:\n\ttest:2: in main chunk")
+    );
+    Ok(())
+}
+
+#[test]
+fn callback_frame_names() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("=test"),
+            &br#"
+                local t
+                local function compare(a, b)
+                    t = t or debug.traceback()
+                    return a < b
+                end
+                local function f()
+                    table.sort({ 3, 2, 1 }, compare)
+                end
+                pcall(f)
+                return t
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    // Callbacks are named after the global or library field they are registered under.
+    assert_eq!(
+        lua.execute::<String>(&executor)?,
+        "stack traceback:\n\
+         \ttest:4: in function 'compare'\n\
+         \t[C]: in function 'table.sort'\n\
+         \ttest:8: in function 'f'\n\
+         \t[C]: in function 'pcall'\n\
+         \ttest:10: in main chunk"
+    );
+    Ok(())
+}
@@ -1,5 +1,10 @@
+use std::future;
+
 use gc_arena::{lock::Lock, Collect, Gc, Rootable};
-use piccolo::{Callback, CallbackReturn, Closure, Executor, Lua, StaticError, UserData, Value};
+use piccolo::{
+    meta_ops::MetaMethod, BoxSequence, Callback, CallbackReturn, Closure, Executor, IntoValue,
+    LightUserData, Lua, StaticError, Table, UserData, Value,
+};
 
 #[derive(Collect)]
 #[collect(no_drop)]
@@ -60,3 +65,340 @@ fn userdata() -> Result<(), StaticError> {
         Ok(())
     })
 }
+
+#[test]
+fn userdata_index() -> Result<(), StaticError> {
+    #[derive(Collect)]
+    #[collect(require_static)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let metatable = Table::new(&ctx);
+        metatable.set(
+            ctx,
+            MetaMethod::Index,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (point, field): (UserData, piccolo::String) = stack.consume(ctx)?;
+                let point = point.downcast_static::<Point>()?;
+                let value = match field.as_bytes() {
+                    b"x" => Value::Integer(point.x),
+                    b"y" => Value::Integer(point.y),
+                    _ => Value::Nil,
+                };
+                stack.replace(ctx, value);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let point = UserData::new_static(&ctx, Point { x: 3, y: 4 });
+        point.set_metatable(&ctx, Some(metatable));
+        ctx.set_global("point", point)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return point.x * point.x + point.y * point.y, point.z == nil
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (dist, no_z) = lua.execute::<(i64, bool)>(&executor)?;
+    assert_eq!(dist, 25);
+    assert!(no_z);
+    Ok(())
+}
+
+#[test]
+fn userdata_gc() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    // Finish any collection cycle in progress, userdata registered after finalization has already
+    // happened in the current cycle are only finalized in the next one.
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        let metatable = Table::new(&ctx);
+        metatable.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, stack| {
+                assert!(matches!(stack.get(0), Value::UserData(_)));
+                let count = ctx.get_global("finalized").to_integer().unwrap_or(0);
+                ctx.set_global("finalized", count + 1)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        for _ in 0..3 {
+            let userdata = UserData::new_static(&ctx, ());
+            userdata.set_metatable(&ctx, Some(metatable));
+            ctx.finalizers().register_userdata(&ctx, userdata);
+        }
+
+        let kept = UserData::new_static(&ctx, ());
+        kept.set_metatable(&ctx, Some(metatable));
+        ctx.finalizers().register_userdata(&ctx, kept);
+        ctx.set_global("kept", kept)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        assert_eq!(ctx.get_global("finalized").to_integer(), Some(3));
+    });
+
+    lua.enter(|ctx| {
+        ctx.set_global("kept", Value::Nil).unwrap();
+    });
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        assert_eq!(ctx.get_global("finalized").to_integer(), Some(4));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn userdata_gc_error() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        let failing = Table::new(&ctx);
+        failing.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, _| {
+                Err("finalizer failed".into_value(ctx).into())
+            }),
+        )?;
+
+        let flagging = Table::new(&ctx);
+        flagging.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, _| {
+                ctx.set_global("flag", true)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        for metatable in [failing, flagging, failing] {
+            let userdata = UserData::new_static(&ctx, ());
+            userdata.set_metatable(&ctx, Some(metatable));
+            ctx.finalizers().register_userdata(&ctx, userdata);
+        }
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    // An erroring finalizer does not prevent the others from running.
+    lua.enter(|ctx| {
+        assert!(ctx.get_global("flag").to_bool());
+    });
+
+    let errors = lua.take_finalizer_errors();
+    assert_eq!(errors.len(), 2);
+    for err in errors {
+        assert_eq!(err.to_string(), "lua error: finalizer failed");
+    }
+    assert!(lua.take_finalizer_errors().is_empty());
+
+    // Finalizers are only ever called once.
+    lua.gc_collect();
+    assert!(lua.take_finalizer_errors().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn userdata_gc_never_finishes() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        let looping = Table::new(&ctx);
+        looping.set(
+            ctx,
+            MetaMethod::Gc,
+            Closure::load(ctx, None, &b"while true do end"[..])?,
+        )?;
+
+        let waiting = Table::new(&ctx);
+        waiting.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, _| {
+                Ok(CallbackReturn::Sequence(BoxSequence::from_future(
+                    &ctx,
+                    future::pending::<Result<(), StaticError>>(),
+                )))
+            }),
+        )?;
+
+        // Memory is over the limit while the finalizer runs, but finalizers cannot wait for a
+        // collection.
+        let allocating = Table::new(&ctx);
+        allocating.set(
+            ctx,
+            MetaMethod::Gc,
+            Closure::load(
+                ctx,
+                None,
+                &b"local t = {} for i = 1, 100 do t[i] = {} end flag = true"[..],
+            )?,
+        )?;
+
+        for metatable in [looping, waiting, allocating] {
+            let userdata = UserData::new_static(&ctx, ());
+            userdata.set_metatable(&ctx, Some(metatable));
+            ctx.finalizers().register_userdata(&ctx, userdata);
+        }
+        ctx.gc_control().set_memory_limit(Some(0));
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        ctx.gc_control().set_memory_limit(None);
+        assert!(ctx.get_global("flag").to_bool());
+    });
+
+    let errors = lua.take_finalizer_errors();
+    assert_eq!(errors.len(), 2);
+    for err in errors {
+        assert_eq!(
+            err.to_string(),
+            "runtime error: finalizer did not finish and was aborted"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn userdata_gc_error_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        let failing = Table::new(&ctx);
+        failing.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, _| {
+                Err("finalizer failed".into_value(ctx).into())
+            }),
+        )?;
+
+        for _ in 0..Lua::MAX_FINALIZER_ERRORS + 10 {
+            let userdata = UserData::new_static(&ctx, ());
+            userdata.set_metatable(&ctx, Some(failing));
+            ctx.finalizers().register_userdata(&ctx, userdata);
+        }
+        Ok(())
+    })?;
+
+    // Errors which are never taken do not accumulate forever.
+    lua.gc_collect();
+    assert_eq!(lua.take_finalizer_errors().len(), Lua::MAX_FINALIZER_ERRORS);
+
+    Ok(())
+}
+
+#[test]
+fn userdata_tostring() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let named = UserData::new_static(&ctx, ());
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, MetaMethod::Name, "Point")?;
+        named.set_metatable(&ctx, Some(metatable));
+        ctx.set_global("named", named)?;
+        ctx.set_global("unnamed", UserData::new_static(&ctx, ()))?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(string.match(tostring(named), "^Point: 0x%x+$"))
+                assert(string.match(tostring(unnamed), "^userdata: 0x%x+$"))
+                assert(tostring(named) ~= tostring(unnamed))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn light_userdata() -> Result<(), StaticError> {
+    let a = 1u8;
+    let b = 2u8;
+
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        ctx.set_global("a", LightUserData::new(&a))?;
+        ctx.set_global("a2", LightUserData::new(&a))?;
+        ctx.set_global("b", LightUserData::new(&b))?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(type(a) == "userdata")
+                assert(a == a2 and a ~= b)
+
+                local t = {}
+                t[a] = "a"
+                t[b] = "b"
+                assert(t[a2] == "a" and t[b] == "b")
+
+                local count = 0
+                for k in pairs(t) do
+                    count = count + 1
+                end
+                assert(count == 2)
+
+                t[a2] = nil
+                assert(t[a] == nil)
+                return b, next(t)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor);
+
+    lua.try_enter(|ctx| {
+        let (b_out, key) = ctx
+            .fetch(&executor)
+            .take_result::<(LightUserData, LightUserData)>(ctx)??;
+        assert_eq!(b_out, LightUserData::new(&b));
+        assert_eq!(key.as_ptr(), &b as *const u8 as *const ());
+        assert_ne!(b_out, LightUserData::new(&a));
+        Ok(())
+    })
+}
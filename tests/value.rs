@@ -0,0 +1,79 @@
+use gc_arena::Gc;
+use piccolo::{
+    Callback, CallbackReturn, IntoValue, LightUserData, Lua, Table, Thread, UserData, Value,
+};
+
+#[test]
+fn test_display() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+        assert_eq!(Value::Boolean(false).to_string(), "false");
+        assert_eq!(Value::Integer(-17).to_string(), "-17");
+        assert_eq!(Value::Number(2.0).to_string(), "2.0");
+        assert_eq!(Value::Number(0.1).to_string(), "0.1");
+        assert_eq!(Value::Number(1e300).to_string(), "1e+300");
+        assert_eq!("hello".into_value(ctx).to_string(), "hello");
+
+        let table = Table::new(&ctx);
+        assert_eq!(
+            Value::Table(table).to_string(),
+            format!("table: {:p}", Gc::as_ptr(table.into_inner()))
+        );
+
+        let callback = Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return));
+        assert_eq!(
+            callback.into_value(ctx).to_string(),
+            format!("function: {:p}", Gc::as_ptr(callback.into_inner()))
+        );
+
+        let thread = Thread::new(ctx);
+        assert_eq!(
+            Value::Thread(thread).to_string(),
+            format!("thread: {:p}", Gc::as_ptr(thread.into_inner()))
+        );
+
+        let userdata = UserData::new_static(&ctx, 1);
+        assert_eq!(
+            Value::UserData(userdata).to_string(),
+            format!("userdata: {:p}", Gc::as_ptr(userdata.into_inner()))
+        );
+
+        let x = 5;
+        let light = LightUserData::new(&x);
+        assert_eq!(
+            Value::LightUserData(light).to_string(),
+            format!("userdata: {:p}", &x)
+        );
+
+        // `Display` is stable for the same object
+        assert_eq!(
+            Value::Table(table).to_string(),
+            Value::Table(table).to_string()
+        );
+    });
+}
+
+#[test]
+fn test_debug() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert_eq!(format!("{:?}", Value::Nil), "Nil");
+        assert_eq!(format!("{:?}", Value::Boolean(true)), "Boolean(true)");
+        assert_eq!(format!("{:?}", Value::Integer(3)), "Integer(3)");
+        assert_eq!(format!("{:?}", Value::Number(3.0)), "Number(3.0)");
+        assert_eq!(
+            format!("{:?}", "a\"b".into_value(ctx)),
+            "String(\"a\\\"b\")"
+        );
+
+        // Self-referential tables must not recurse
+        let table = Table::new(&ctx);
+        table.set(ctx, "self", table).unwrap();
+        assert_eq!(
+            format!("{:?}", Value::Table(table)),
+            format!("Table({:p})", Gc::as_ptr(table.into_inner()))
+        );
+    });
+}
@@ -0,0 +1,235 @@
+use piccolo::{
+    table::WeakMode, Callback, CallbackReturn, Lua, MetaMethod, StaticError, Table, UserData, Value,
+};
+
+fn weak_metatable<'gc>(ctx: piccolo::Context<'gc>, mode: &'static str) -> Table<'gc> {
+    let metatable = Table::new(&ctx);
+    metatable.set(ctx, MetaMethod::Mode, mode).unwrap();
+    metatable
+}
+
+#[test]
+fn weak_values() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let weak = Table::new(&ctx);
+        weak.set_metatable(ctx, Some(weak_metatable(ctx, "v")));
+        assert_eq!(weak.weak_mode(), Some(WeakMode::Values));
+
+        let kept = Table::new(&ctx);
+        weak.set(ctx, 1, Table::new(&ctx))?;
+        weak.set(ctx, 2, kept)?;
+        weak.set(ctx, "dropped", Table::new(&ctx))?;
+        weak.set(ctx, "string", "strings are never weak")?;
+
+        ctx.set_global("weak", weak)?;
+        ctx.set_global("kept", kept)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        let Value::Table(weak) = ctx.get_global("weak") else {
+            panic!("weak table was collected");
+        };
+        assert!(weak.get(ctx, 1).is_nil());
+        assert!(matches!(
+            (weak.get(ctx, 2), ctx.get_global("kept")),
+            (Value::Table(a), Value::Table(b)) if a == b
+        ));
+        assert!(weak.get(ctx, "dropped").is_nil());
+        assert!(matches!(weak.get(ctx, "string"), Value::String(_)));
+
+        // Once the only other reference is gone, the value is dropped by the next collection.
+        ctx.set_global("kept", Value::Nil)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        let Value::Table(weak) = ctx.get_global("weak") else {
+            panic!("weak table was collected");
+        };
+        assert!(weak.get(ctx, 2).is_nil());
+        assert!(matches!(weak.get(ctx, "string"), Value::String(_)));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn weak_keys() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let weak = Table::new(&ctx);
+        weak.set_metatable(ctx, Some(weak_metatable(ctx, "k")));
+        assert_eq!(weak.weak_mode(), Some(WeakMode::Keys));
+
+        let kept = Table::new(&ctx);
+        weak.set(ctx, kept, 1)?;
+        weak.set(ctx, Table::new(&ctx), 2)?;
+        // Values are held strongly when their keys can never be weak.
+        weak.set(ctx, "value", Table::new(&ctx))?;
+
+        ctx.set_global("weak", weak)?;
+        ctx.set_global("kept", kept)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        let Value::Table(weak) = ctx.get_global("weak") else {
+            panic!("weak table was collected");
+        };
+        let entries = weak.iter().count();
+        assert_eq!(entries, 2);
+        assert_eq!(weak.get(ctx, ctx.get_global("kept")).to_integer(), Some(1));
+        assert!(matches!(weak.get(ctx, "value"), Value::Table(_)));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn weak_table_resurrected_by_finalizer() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        // A weak table which is only reachable from a finalized userdata must still have its dead
+        // entries removed once the userdata is resurrected.
+        let weak = Table::new(&ctx);
+        weak.set_metatable(ctx, Some(weak_metatable(ctx, "kv")));
+        weak.set(ctx, 1, Table::new(&ctx))?;
+
+        let metatable = Table::new(&ctx);
+        metatable.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn_with(&ctx, weak, |weak, ctx, _, _| {
+                ctx.set_global("weak", *weak)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let userdata = UserData::new_static(&ctx, ());
+        userdata.set_metatable(&ctx, Some(metatable));
+        ctx.finalizers().register_userdata(&ctx, userdata);
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        let Value::Table(weak) = ctx.get_global("weak") else {
+            panic!("finalizer was not called");
+        };
+        assert!(weak.get(ctx, 1).is_nil());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn removed_keys_are_not_kept_alive() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        // Removed entries stay in the table until it grows, but must not keep their keys alive.
+        let table = Table::new(&ctx);
+        let probe = Table::new(&ctx);
+        probe.set_metatable(ctx, Some(weak_metatable(ctx, "v")));
+        for i in 1..=2 {
+            let key = Table::new(&ctx);
+            table.set(ctx, key, i)?;
+            probe.set(ctx, i, key)?;
+        }
+
+        let Value::Table(first) = probe.get(ctx, 1) else {
+            panic!("expected a table");
+        };
+        table.remove(ctx, first.into());
+        let Value::Table(second) = probe.get(ctx, 2) else {
+            panic!("expected a table");
+        };
+        table.set(ctx, second, Value::Nil)?;
+
+        ctx.set_global("table", table)?;
+        ctx.set_global("probe", probe)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        let Value::Table(probe) = ctx.get_global("probe") else {
+            panic!("probe table was collected");
+        };
+        assert!(probe.get(ctx, 1).is_nil());
+        assert!(probe.get(ctx, 2).is_nil());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn ephemeron_value_not_finalized() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        // A userdata which is only reachable through a live key of a weak key table is alive, and
+        // must not be finalized.
+        let metatable = Table::new(&ctx);
+        metatable.set(
+            ctx,
+            MetaMethod::Gc,
+            Callback::from_fn(&ctx, |ctx, _, _| {
+                ctx.set_global("finalized", true)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        let userdata = UserData::new_static(&ctx, ());
+        userdata.set_metatable(&ctx, Some(metatable));
+        ctx.finalizers().register_userdata(&ctx, userdata);
+
+        let weak = Table::new(&ctx);
+        weak.set_metatable(ctx, Some(weak_metatable(ctx, "k")));
+        let key = Table::new(&ctx);
+        weak.set(ctx, key, userdata)?;
+
+        ctx.set_global("weak", weak)?;
+        ctx.set_global("key", key)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+    lua.gc_collect();
+
+    lua.try_enter(|ctx| {
+        assert!(ctx.get_global("finalized").is_nil());
+        let Value::Table(weak) = ctx.get_global("weak") else {
+            panic!("weak table was collected");
+        };
+        assert!(matches!(
+            weak.get(ctx, ctx.get_global("key")),
+            Value::UserData(_)
+        ));
+
+        ctx.set_global("key", Value::Nil)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.enter(|ctx| {
+        assert!(ctx.get_global("finalized").to_bool());
+    });
+
+    Ok(())
+}
@@ -54,9 +54,13 @@ impl<'gc> de::Deserializer<'gc> for Deserializer<'gc> {
                     self.deserialize_map(visitor)
                 }
             }
+            Value::UserData(ud) if is_unit(ud) => visitor.visit_unit(),
+            Value::UserData(ud) if is_none(ud) => visitor.visit_none(),
             Value::Function(_) => Err(de::Error::custom("cannot deserialize from function")),
             Value::Thread(_) => Err(de::Error::custom("cannot deserialize from thread")),
-            Value::UserData(_) => Err(de::Error::custom("cannot deserialize from userdata")),
+            Value::UserData(_) | Value::LightUserData(_) => {
+                Err(de::Error::custom("cannot deserialize from userdata"))
+            }
         }
     }
 
@@ -64,7 +68,7 @@ impl<'gc> de::Deserializer<'gc> for Deserializer<'gc> {
     where
         V: de::Visitor<'gc>,
     {
-        visitor.visit_bool(self.value.to_bool())
+        visitor.visit_bool(self.value.is_truthy())
     }
 
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Error>
@@ -596,7 +600,7 @@ impl<'de> de::VariantAccess<'de> for UnitVariant {
     }
 }
 
-fn is_sequence<'gc>(table: Table<'gc>) -> bool {
+pub(super) fn is_sequence<'gc>(table: Table<'gc>) -> bool {
     let mut key = match table.next(Value::Nil) {
         NextValue::Found { key, value: _ } => key,
         NextValue::Last => return true,
@@ -1,12 +1,14 @@
 pub mod de;
 pub mod markers;
 pub mod ser;
+pub mod value;
 
 use piccolo::Lua;
 
 pub use self::{
     de::from_value,
     ser::{to_value, to_value_with, Options as SerOptions},
+    value::{SerializeValue, ValueSeed},
 };
 
 pub trait LuaSerdeExt {
@@ -15,6 +17,6 @@ pub trait LuaSerdeExt {
 
 impl LuaSerdeExt for Lua {
     fn load_serde(&mut self) {
-        self.enter(|ctx| markers::set_globals(ctx));
+        self.enter(markers::set_globals);
     }
 }
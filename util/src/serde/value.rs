@@ -0,0 +1,214 @@
+//! Serialization of arbitrary `Value`s to and from any serde format.
+//!
+//! Values are serialized with the following policy:
+//!
+//! * `nil` and the `none` marker serialize as a none option, the `unit` marker serializes as a
+//!   unit (both are `null` in JSON).
+//! * Booleans, integers and floats serialize as themselves.
+//! * Strings serialize as `str` if they are valid UTF-8, and as bytes otherwise.
+//! * Tables whose keys are exactly the integers `1..=n` serialize as a sequence, every other table
+//!   serializes as a map. An empty table is an empty sequence. Metatables are ignored.
+//! * Functions, threads and any other userdata cannot be serialized, and cause an error.
+//!
+//! A table may appear more than once in the serialized value, but a table which contains itself
+//! causes an error rather than recursing forever.
+//!
+//! Values are deserialized with `ValueSeed`, which creates new strings and tables for any
+//! self-describing deserializer. Units become the `unit` marker and missing options become the
+//! `none` marker, so that a `null` inside of a sequence or map is kept rather than leaving a hole.
+
+use std::{cell::RefCell, fmt};
+
+use piccolo::{Context, Table, Value};
+use serde::{
+    de::{self, DeserializeSeed},
+    ser::{self, SerializeMap, SerializeSeq},
+};
+
+use super::{
+    de::is_sequence,
+    markers::{is_none, is_unit, none, unit},
+};
+
+/// Wraps a `Value` so that it can be serialized with serde.
+#[derive(Copy, Clone)]
+pub struct SerializeValue<'gc>(pub Value<'gc>);
+
+impl<'gc> ser::Serialize for SerializeValue<'gc> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let parents = RefCell::new(Vec::new());
+        Serializing {
+            value: self.0,
+            parents: &parents,
+        }
+        .serialize(serializer)
+    }
+}
+
+// A value being serialized along with every table that contains it, which are checked to detect
+// cycles.
+struct Serializing<'a, 'gc> {
+    value: Value<'gc>,
+    parents: &'a RefCell<Vec<Table<'gc>>>,
+}
+
+impl<'a, 'gc> Serializing<'a, 'gc> {
+    fn child(&self, value: Value<'gc>) -> Self {
+        Serializing {
+            value,
+            parents: self.parents,
+        }
+    }
+}
+
+impl<'a, 'gc> ser::Serialize for Serializing<'a, 'gc> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Nil => serializer.serialize_none(),
+            Value::Boolean(b) => serializer.serialize_bool(b),
+            Value::Integer(i) => serializer.serialize_i64(i),
+            Value::Number(n) => serializer.serialize_f64(n),
+            Value::String(s) => match s.to_str() {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(s.as_bytes()),
+            },
+            Value::Table(table) => {
+                if self.parents.borrow().contains(&table) {
+                    return Err(ser::Error::custom("cannot serialize a cyclic table"));
+                }
+
+                self.parents.borrow_mut().push(table);
+                let res = if is_sequence(table) {
+                    let len = table.length();
+                    let mut seq = serializer.serialize_seq(usize::try_from(len).ok())?;
+                    for i in 1..=len {
+                        seq.serialize_element(&self.child(table.get_value(Value::Integer(i))))?;
+                    }
+                    seq.end()
+                } else {
+                    let mut map = serializer.serialize_map(None)?;
+                    for (key, value) in table {
+                        map.serialize_entry(&self.child(key), &self.child(value))?;
+                    }
+                    map.end()
+                };
+                self.parents.borrow_mut().pop();
+                res
+            }
+            Value::UserData(ud) if is_unit(ud) => serializer.serialize_unit(),
+            Value::UserData(ud) if is_none(ud) => serializer.serialize_none(),
+            v => Err(ser::Error::custom(format_args!(
+                "cannot serialize a {}",
+                v.type_name()
+            ))),
+        }
+    }
+}
+
+// The most sequence elements that will be preallocated based on a size hint.
+const MAX_PREALLOCATION: usize = 4096;
+
+/// A `DeserializeSeed` which deserializes any self-describing format into a `Value`.
+///
+/// Sequences become tables with the keys `1..=n`, and maps become tables with the deserialized
+/// keys. Map keys which cannot be table keys (`nil` or NaN) are an error.
+#[derive(Copy, Clone)]
+pub struct ValueSeed<'gc> {
+    ctx: Context<'gc>,
+}
+
+impl<'gc> ValueSeed<'gc> {
+    pub fn new(ctx: Context<'gc>) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<'gc, 'de> DeserializeSeed<'de> for ValueSeed<'gc> {
+    type Value = Value<'gc>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value<'gc>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'gc, 'de> de::Visitor<'de> for ValueSeed<'gc> {
+    type Value = Value<'gc>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value<'gc>, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value<'gc>, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value<'gc>, E> {
+        Ok(match i64::try_from(v) {
+            Ok(i) => Value::Integer(i),
+            Err(_) => Value::Number(v as f64),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value<'gc>, E> {
+        Ok(Value::Number(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value<'gc>, E> {
+        self.visit_bytes(v.as_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value<'gc>, E> {
+        Ok(self.ctx.intern(v).into())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value<'gc>, E> {
+        Ok(unit(self.ctx).into())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value<'gc>, E> {
+        Ok(none(self.ctx).into())
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value<'gc>, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value<'gc>, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value<'gc>, A::Error> {
+        let table = Table::new(&self.ctx);
+        // The size hint comes from the input, so only trust it up to a point.
+        let len = seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATION);
+        table.reserve(self.ctx, len).map_err(de::Error::custom)?;
+        let mut i = 1;
+        while let Some(value) = seq.next_element_seed(self)? {
+            table
+                .set_value(self.ctx, Value::Integer(i), value)
+                .map_err(de::Error::custom)?;
+            i += 1;
+        }
+        Ok(table.into())
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value<'gc>, A::Error> {
+        let table = Table::new(&self.ctx);
+        while let Some((key, value)) = map.next_entry_seed(self, self)? {
+            table
+                .set_value(self.ctx, key, value)
+                .map_err(de::Error::custom)?;
+        }
+        Ok(table.into())
+    }
+}
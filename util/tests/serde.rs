@@ -0,0 +1,419 @@
+#![cfg(feature = "serde")]
+
+use std::fmt;
+
+use piccolo::{Closure, Executor, Lua, StaticError, Table, Value};
+use piccolo_util::serde::{
+    markers::{is_none, is_unit},
+    SerializeValue, ValueSeed,
+};
+use serde::{
+    de::{self, value::MapDeserializer, value::SeqDeserializer, DeserializeSeed, IntoDeserializer},
+    forward_to_deserialize_any,
+    ser::{self, Impossible, Serialize},
+};
+
+#[test]
+fn serialize_mixed_table() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return {
+                    name = "piccolo",
+                    list = {1, 2.5, "three", true},
+                    nested = {empty = {}, sparse = {[1] = "a", [3] = "c"}},
+                }
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.finish(&executor);
+
+    let data = lua.try_enter(|ctx| {
+        let value = ctx.fetch(&executor).take_result::<Value>(ctx)??;
+        Ok(to_data(value).unwrap())
+    })?;
+
+    let str = |s: &str| Data::Str(s.to_owned());
+    assert_eq!(
+        data,
+        Data::Map(vec![
+            (
+                str("list"),
+                Data::Seq(vec![
+                    Data::Int(1),
+                    Data::Float(2.5),
+                    str("three"),
+                    Data::Bool(true)
+                ]),
+            ),
+            (str("name"), str("piccolo")),
+            (
+                str("nested"),
+                Data::Map(vec![
+                    (str("empty"), Data::Seq(Vec::new())),
+                    (
+                        str("sparse"),
+                        Data::Map(vec![(Data::Int(1), str("a")), (Data::Int(3), str("c"))]),
+                    ),
+                ]),
+            ),
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn null_round_trip() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let str = |s: &str| Data::Str(s.to_owned());
+        let data = Data::Map(vec![
+            (
+                str("list"),
+                Data::Seq(vec![
+                    Data::Int(1),
+                    Data::Unit,
+                    Data::None,
+                    Data::Bool(false),
+                ]),
+            ),
+            (str("map"), Data::Map(vec![(str("a"), Data::Unit)])),
+        ]);
+        let value = ValueSeed::new(ctx).deserialize(data.clone()).unwrap();
+
+        // Nulls are kept as markers rather than leaving holes.
+        let Value::Table(table) = value else {
+            panic!("expected a table");
+        };
+        let Value::Table(list) = table.get(ctx, "list") else {
+            panic!("expected a table");
+        };
+        assert_eq!(list.length(), 4);
+        assert!(matches!(list.get(ctx, 2), Value::UserData(ud) if is_unit(ud)));
+        assert!(matches!(list.get(ctx, 3), Value::UserData(ud) if is_none(ud)));
+
+        assert_eq!(to_data(value).unwrap(), data);
+    });
+}
+
+#[test]
+fn serialize_errors() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        let inner = Table::new(&ctx);
+        table.set(ctx, "inner", inner).unwrap();
+        inner.set(ctx, "outer", table).unwrap();
+        let err = to_data(table.into()).unwrap_err();
+        assert_eq!(err.0, "cannot serialize a cyclic table");
+
+        // The same table appearing twice is not a cycle.
+        let shared = Table::new(&ctx);
+        let table = Table::new(&ctx);
+        table.set(ctx, 1, shared).unwrap();
+        table.set(ctx, 2, shared).unwrap();
+        assert_eq!(
+            to_data(table.into()).unwrap(),
+            Data::Seq(vec![Data::Seq(Vec::new()), Data::Seq(Vec::new())])
+        );
+
+        let table = Table::new(&ctx);
+        let function = Closure::load(ctx, None, &b""[..]).unwrap();
+        table.set(ctx, "f", function).unwrap();
+        let err = to_data(table.into()).unwrap_err();
+        assert_eq!(err.0, "cannot serialize a function");
+
+        let err = ValueSeed::new(ctx)
+            .deserialize(Data::Map(vec![(Data::Float(f64::NAN), Data::Int(1))]))
+            .unwrap_err();
+        assert_eq!(err.0, "table index is NaN");
+    });
+}
+
+// A minimal self-describing data format, just enough to test `Value` serialization. Maps are
+// sorted by key so that they can be compared.
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+enum Data {
+    Unit,
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Seq(Vec<Data>),
+    Map(Vec<(Data, Data)>),
+}
+
+#[derive(Debug)]
+struct DataError(String);
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DataError {}
+
+impl ser::Error for DataError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DataError(msg.to_string())
+    }
+}
+
+impl de::Error for DataError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DataError(msg.to_string())
+    }
+}
+
+fn to_data(value: Value) -> Result<Data, DataError> {
+    SerializeValue(value).serialize(DataSerializer)
+}
+
+struct DataSerializer;
+
+fn unsupported<T>() -> Result<T, DataError> {
+    Err(DataError("unsupported".to_owned()))
+}
+
+impl ser::Serializer for DataSerializer {
+    type Ok = Data;
+    type Error = DataError;
+
+    type SerializeSeq = SerializeSeq;
+    type SerializeTuple = Impossible<Data, DataError>;
+    type SerializeTupleStruct = Impossible<Data, DataError>;
+    type SerializeTupleVariant = Impossible<Data, DataError>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = Impossible<Data, DataError>;
+    type SerializeStructVariant = Impossible<Data, DataError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Data, DataError> {
+        Ok(Data::Bool(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Data, DataError> {
+        Ok(Data::Int(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Data, DataError> {
+        Ok(Data::Float(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Data, DataError> {
+        Ok(Data::Str(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Data, DataError> {
+        Ok(Data::Bytes(v.to_owned()))
+    }
+
+    fn serialize_unit(self) -> Result<Data, DataError> {
+        Ok(Data::Unit)
+    }
+
+    fn serialize_none(self) -> Result<Data, DataError> {
+        Ok(Data::None)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<SerializeSeq, DataError> {
+        Ok(SerializeSeq(Vec::new()))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<SerializeMap, DataError> {
+        Ok(SerializeMap(Vec::new(), None))
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_char(self, _: char) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Data, DataError> {
+        unsupported()
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, DataError> {
+        unsupported()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, DataError> {
+        unsupported()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, DataError> {
+        unsupported()
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, DataError> {
+        unsupported()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, DataError> {
+        unsupported()
+    }
+}
+
+struct SerializeSeq(Vec<Data>);
+
+impl ser::SerializeSeq for SerializeSeq {
+    type Ok = Data;
+    type Error = DataError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        self.0.push(value.serialize(DataSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Data, DataError> {
+        Ok(Data::Seq(self.0))
+    }
+}
+
+struct SerializeMap(Vec<(Data, Data)>, Option<Data>);
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Data;
+    type Error = DataError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), DataError> {
+        self.1 = Some(key.serialize(DataSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        let key = self.1.take().unwrap();
+        self.0.push((key, value.serialize(DataSerializer)?));
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<Data, DataError> {
+        self.0.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        Ok(Data::Map(self.0))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Data {
+    type Error = DataError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self {
+            Data::Unit => visitor.visit_unit(),
+            Data::None => visitor.visit_none(),
+            Data::Bool(b) => visitor.visit_bool(b),
+            Data::Int(i) => visitor.visit_i64(i),
+            Data::Float(f) => visitor.visit_f64(f),
+            Data::Str(s) => visitor.visit_string(s),
+            Data::Bytes(b) => visitor.visit_byte_buf(b),
+            Data::Seq(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter())),
+            Data::Map(entries) => visitor.visit_map(MapDeserializer::new(entries.into_iter())),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DataError> for Data {
+    type Deserializer = Data;
+
+    fn into_deserializer(self) -> Data {
+        self
+    }
+}